@@ -0,0 +1,26 @@
+// Compares a fresh agreement with a static peer against one served by `AgreementCache`.
+// Run with `cargo bench --bench agreement`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diffie_hellman::{generate_keypair, AgreementCache, DhParameters, Group};
+
+fn agreement(c: &mut Criterion) {
+    let params = DhParameters::from(Group::Modp2048);
+    let (private_key, _) = generate_keypair(&params).unwrap();
+    let (_, peer_public) = generate_keypair(&params).unwrap();
+
+    let mut group = c.benchmark_group("agreement_modp2048");
+    group.sample_size(10);
+    group.bench_function("uncached", |b| {
+        b.iter(|| {
+            peer_public.validate_in_group(&params).unwrap();
+            private_key.agree(&peer_public, &params)
+        })
+    });
+    let mut cache = AgreementCache::new(private_key, params.clone());
+    group.bench_function("cached", |b| b.iter(|| cache.agree(&peer_public).is_ok()));
+    group.finish();
+}
+
+criterion_group!(benches, agreement);
+criterion_main!(benches);
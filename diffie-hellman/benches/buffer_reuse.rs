@@ -0,0 +1,62 @@
+// Compares `encrypt_gcm`, which returns a fresh Vec per message, with `encrypt_gcm_into` reusing one buffer. A counting
+// allocator reports how many allocations each makes per message before the timings run.
+// Run with `cargo bench --bench buffer_reuse`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diffie_hellman::{encrypt_gcm, encrypt_gcm_into, generate_random_key};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const MESSAGES: usize = 1000;
+
+fn allocations_per_message(mut encrypt: impl FnMut()) -> f64 {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..MESSAGES {
+        encrypt();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / MESSAGES as f64
+}
+
+fn buffer_reuse(c: &mut Criterion) {
+    let secret = generate_random_key();
+    let payload = vec![0x5au8; 1024];
+    let mut out = Vec::new();
+
+    println!(
+        "allocations per 1 KiB message: encrypt_gcm {:.1}, encrypt_gcm_into {:.1}",
+        allocations_per_message(|| drop(encrypt_gcm(&payload, &secret).unwrap())),
+        allocations_per_message(|| encrypt_gcm_into(&payload, &secret, &mut out).unwrap())
+    );
+
+    let mut group = c.benchmark_group("gcm_1024");
+    group.bench_function("fresh_vec", |b| b.iter(|| encrypt_gcm(&payload, &secret).unwrap()));
+    group.bench_function("reused_buffer", |b| b.iter(|| encrypt_gcm_into(&payload, &secret, &mut out).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, buffer_reuse);
+criterion_main!(benches);
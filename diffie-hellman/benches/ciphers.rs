@@ -0,0 +1,31 @@
+// Compares encryption throughput of AES-128 in ECB, CBC and GCM mode for 1 KiB and 1 MiB payloads, to show what
+// authentication costs. Run with `cargo bench --bench ciphers`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use diffie_hellman::{encrypt_gcm, encrypt_with_padding, generate_random_key, CipherMode, Padding};
+
+const PAYLOAD_LENS: [usize; 2] = [1024, 1024 * 1024];
+
+fn ciphers(c: &mut Criterion) {
+    let secret = generate_random_key();
+    let iv = [0u8; 16];
+
+    let mut group = c.benchmark_group("encrypt");
+    for len in PAYLOAD_LENS {
+        let payload = vec![0x5au8; len];
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("ecb", len), &payload, |b, payload| {
+            b.iter(|| encrypt_with_padding(payload, &secret, CipherMode::Ecb, Padding::Pkcs7, &[]).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("cbc", len), &payload, |b, payload| {
+            b.iter(|| encrypt_with_padding(payload, &secret, CipherMode::Cbc, Padding::Pkcs7, &iv).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("gcm", len), &payload, |b, payload| {
+            b.iter(|| encrypt_gcm(payload, &secret).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ciphers);
+criterion_main!(benches);
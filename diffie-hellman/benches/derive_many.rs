@@ -0,0 +1,33 @@
+// Compares validating and agreeing with a batch of peer keys one after another with `GroupContext::derive_many`.
+// Run with `cargo bench --bench derive_many --features parallel`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diffie_hellman::{generate_keypair, DhParameters, Group, GroupContext, PublicKey};
+
+const BATCH: usize = 32;
+
+fn derive_many(c: &mut Criterion) {
+    let params = DhParameters::from(Group::Modp2048);
+    let (private_key, _) = generate_keypair(&params).unwrap();
+    let peers: Vec<PublicKey> = (0..BATCH).map(|_| generate_keypair(&params).unwrap().1).collect();
+    let context = GroupContext::new(&params);
+
+    let mut group = c.benchmark_group("derive_modp2048_x32");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            peers
+                .iter()
+                .map(|peer_public| {
+                    peer_public.validate_in_group(&params).unwrap();
+                    private_key.agree(peer_public, &params)
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("derive_many", |b| b.iter(|| context.derive_many(&private_key, &peers)));
+    group.finish();
+}
+
+criterion_group!(benches, derive_many);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+// Compares keypair generation with and without a precomputed `GroupContext`, and fails if the context isn't at least
+// `MIN_SPEEDUP` times faster, so a change that loses the gain shows up here. The check runs before the timings.
+// Run with `cargo bench --bench group_context`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diffie_hellman::{generate_keypair, DhParameters, GroupContext};
+
+// The gain is around five times on a 2048-bit group; anything under this means the table isn't being used.
+const MIN_SPEEDUP: f64 = 2.0;
+const KEYPAIRS: u32 = 100;
+
+fn time_keypairs(mut keygen: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..KEYPAIRS {
+        keygen();
+    }
+    start.elapsed()
+}
+
+fn group_context(c: &mut Criterion) {
+    let params = DhParameters::ffdhe2048();
+    let context = GroupContext::new(&params);
+
+    let plain = time_keypairs(|| drop(black_box(generate_keypair(&params).unwrap())));
+    let precomputed = time_keypairs(|| drop(black_box(context.generate_keypair().unwrap())));
+    let speedup = plain.as_secs_f64() / precomputed.as_secs_f64();
+    println!("keypairs with a GroupContext are {:.1} times as fast", speedup);
+    assert!(speedup >= MIN_SPEEDUP, "GroupContext speedup {:.1} is below {}", speedup, MIN_SPEEDUP);
+
+    let mut group = c.benchmark_group("keygen_ffdhe2048");
+    group.bench_function("plain", |b| b.iter(|| generate_keypair(&params).unwrap()));
+    group.bench_function("group_context", |b| b.iter(|| context.generate_keypair().unwrap()));
+    group.finish();
+    c.bench_function("group_context_setup_ffdhe2048", |b| b.iter(|| GroupContext::new(&params)));
+}
+
+criterion_group!(benches, group_context);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+// Compares key generation, agreement and encryption with the agreed secret across the MODP groups, and X25519 with
+// the `x25519` feature, plus the constant-time exponentiation of the `ct` feature against plain `BigUint::modpow`.
+// Run with `cargo bench --bench groups --features x25519`, and with `--no-default-features` to see the variable-time
+// path alone.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use diffie_hellman::{encrypt_gcm, DhParameters, FfDh, Group, KeyExchange};
+#[cfg(feature = "x25519")]
+use diffie_hellman::X25519Dh;
+use num_bigint::BigUint;
+
+const MODP_GROUPS: [Group; 3] = [Group::Modp2048, Group::Modp3072, Group::Modp4096];
+const PAYLOAD_LEN: usize = 64 * 1024;
+
+// Keygen, agreement (including the check of the peer's key) and sealing one payload, under `name`.
+fn bench_exchange<K: KeyExchange>(c: &mut Criterion, name: &str, exchange: &K) {
+    let (private_key, _) = exchange.keypair().unwrap();
+    let (_, peer_public) = exchange.keypair().unwrap();
+    let secret = BigUint::from_bytes_be(&exchange.agree(&private_key, &peer_public).unwrap().dangerous_into_bytes());
+    let payload = vec![0x5au8; PAYLOAD_LEN];
+
+    let mut group = c.benchmark_group(format!("exchange_{}", name));
+    group.sample_size(10);
+    group.bench_function("keygen", |b| b.iter(|| exchange.keypair().unwrap()));
+    group.bench_function("agree", |b| b.iter(|| exchange.agree(&private_key, &peer_public).unwrap()));
+    group.throughput(Throughput::Bytes(PAYLOAD_LEN as u64));
+    group.bench_function("encrypt_64k", |b| b.iter(|| encrypt_gcm(&payload, &secret).unwrap()));
+    group.finish();
+}
+
+fn exchanges(c: &mut Criterion) {
+    for group in MODP_GROUPS {
+        bench_exchange(c, group.name(), &FfDh::new(group.parameters()));
+    }
+    #[cfg(feature = "x25519")]
+    bench_exchange(c, "x25519", &X25519Dh);
+}
+
+// g^x mod p for one private key, through `PrivateKey::public_key` (constant time unless the `ct` feature is off) and
+// through `BigUint::modpow` directly.
+fn modpow_backends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("modpow_backend");
+    group.sample_size(10);
+    for modp in MODP_GROUPS {
+        let params = DhParameters::from(modp);
+        let (private_key, _) = FfDh::new(params.clone()).keypair().unwrap();
+        let exponent = BigUint::from_bytes_be(&private_key.to_bytes_be());
+        group.bench_with_input(BenchmarkId::new("public_key", modp.name()), &params, |b, params| {
+            b.iter(|| private_key.public_key(params))
+        });
+        group.bench_with_input(BenchmarkId::new("biguint_modpow", modp.name()), &params, |b, params| {
+            b.iter(|| params.generator().modpow(&exponent, params.modulus()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, exchanges, modpow_backends);
+criterion_main!(benches);
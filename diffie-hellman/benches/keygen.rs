@@ -0,0 +1,21 @@
+// Compares generating a batch of keypairs one after another with `generate_keypairs_parallel`.
+// Run with `cargo bench --features parallel`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diffie_hellman::{generate_keypair, generate_keypairs_parallel, DhParameters, Group};
+
+const BATCH: usize = 32;
+
+fn keygen(c: &mut Criterion) {
+    let params = DhParameters::from(Group::Modp2048);
+    let mut group = c.benchmark_group("keygen_modp2048_x32");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| (0..BATCH).map(|_| generate_keypair(&params).unwrap()).collect::<Vec<_>>())
+    });
+    group.bench_function("parallel", |b| b.iter(|| generate_keypairs_parallel(BATCH, &params).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, keygen);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+// A line-based encrypted chat between two terminals over TCP.
+//
+// One end listens and the other connects. They run a handshake with key confirmation, so a mismatched secret fails
+// before any message is sent, and each side prints a short authentication string. Read it aloud to the other person:
+// if both terminals show the same words, nobody is in the middle. After that every line typed is sent as one record
+// of a `RecordLayer`, padded to hide its exact length. The record layer changes keys every few records in each
+// direction, far sooner than a real deployment would, so the key changes show up in a short conversation.
+//
+// Run one end with: cargo run --example chat -- listen 127.0.0.1:7000
+// and the other with: cargo run --example chat -- connect 127.0.0.1:7000
+// Type /quit or end standard input to leave.
+
+use std::env;
+use std::io::{self, BufRead, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use diffie_hellman::{
+    confirmed_handshake, connect, ConnectOptions, DhError, DhParameters, RecordLayer, Role, DEFAULT_HANDSHAKE_TIMEOUT,
+};
+
+// Change keys after this many records in a direction. Both ends must agree on it.
+const REKEY_RECORDS: u64 = 4;
+// Pad each record to a multiple of this many bytes.
+const PADDING_BLOCK: usize = 32;
+// How long to wait for the peer before checking standard input again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// The lines typed on standard input, from a thread of their own so that reading them doesn't hold up the socket.
+// The channel closes when standard input ends.
+fn read_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// Whether the peer has sent anything, waiting at most `POLL_INTERVAL`. A record is only read once its first byte has
+// arrived, so a read never stops partway through one.
+fn peer_has_sent(stream: &TcpStream) -> Result<bool, DhError> {
+    match stream.peek(&mut [0u8]) {
+        Ok(_) => Ok(true),
+        Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn chat(mut stream: TcpStream, role: Role) -> Result<(), DhError> {
+    let params = DhParameters::ffdhe2048();
+    stream.set_read_timeout(Some(DEFAULT_HANDSHAKE_TIMEOUT))?;
+    let (secret, _) = confirmed_handshake(&mut stream, &params)?;
+    println!("key confirmed, secret {}", secret.fingerprint());
+    println!("check the other end reads: {}", secret.sas());
+
+    let mut records = RecordLayer::new(stream, &secret, role)
+        .with_padding(PADDING_BLOCK)?
+        .with_rekey_limits(REKEY_RECORDS, u64::MAX);
+    let lines = read_lines();
+    let mut epochs = records.epochs();
+    loop {
+        match lines.try_recv() {
+            Ok(line) if line == "/quit" => break,
+            Ok(line) => records.send(line.as_bytes())?,
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+
+        records.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+        if peer_has_sent(records.get_ref())? {
+            records.get_ref().set_read_timeout(None)?;
+            match records.receive()? {
+                Some(message) => println!("peer: {}", String::from_utf8_lossy(&message)),
+                None => {
+                    println!("the peer left");
+                    return Ok(());
+                }
+            }
+        }
+
+        if records.epochs() != epochs {
+            epochs = records.epochs();
+            println!("(keys changed: sending epoch {}, receiving epoch {})", epochs.0, epochs.1);
+        }
+    }
+    println!("leaving");
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["listen", addr] => TcpListener::bind(addr).map_err(DhError::from).and_then(|listener| {
+            println!("waiting for the peer on {}", addr);
+            let (stream, peer) = listener.accept()?;
+            println!("{} connected", peer);
+            chat(stream, Role::Responder)
+        }),
+        ["connect", addr] => connect(addr, &ConnectOptions::default()).and_then(|stream| chat(stream, Role::Initiator)),
+        _ => {
+            eprintln!("usage: chat listen <address> | chat connect <address>");
+            process::exit(2);
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,50 @@
+// Forward secrecy with ephemeral keys.
+//
+// Alice and Bob run two separate sessions, generating fresh keypairs for each one. An attacker who later steals
+// everything from the first session (its private keys and the secret it agreed on) still learns nothing about the
+// second session: its secret was derived from different private keys that were never exposed.
+//
+// Run with: cargo run --example forward_secrecy
+
+use diffie_hellman::{generate_keypair, generate_session_salt, DhError, Group, Role, Session, SharedSecret};
+
+// One exchange with freshly generated keys on both sides, returning the secret each party arrived at.
+fn ephemeral_exchange(group: Group) -> Result<(SharedSecret, SharedSecret), DhError> {
+    let params = group.parameters();
+    let (alice_private, alice_public) = generate_keypair(&params)?;
+    let (bob_private, bob_public) = generate_keypair(&params)?;
+    Ok((alice_private.agree(&bob_public, &params), bob_private.agree(&alice_public, &params)))
+}
+
+fn main() -> Result<(), DhError> {
+    let group = Group::Modp2048;
+
+    // Session 1: a fresh exchange, then one message from Alice to Bob.
+    let (alice_secret_1, bob_secret_1) = ephemeral_exchange(group)?;
+    let salt_1 = generate_session_salt();
+    let mut alice_session_1 = Session::new(&alice_secret_1, &salt_1, Role::Initiator);
+    let mut bob_session_1 = Session::new(&bob_secret_1, &salt_1, Role::Responder);
+    let message_1 = alice_session_1.encrypt_next("Meet at the old bridge at noon.")?;
+    println!("Session 1 secret: {}", alice_secret_1.fingerprint());
+    println!("Bob reads session 1: {}", bob_session_1.decrypt_next(&message_1)?);
+
+    // Session 2: new keypairs on both sides, so a new secret.
+    let (alice_secret_2, bob_secret_2) = ephemeral_exchange(group)?;
+    let salt_2 = generate_session_salt();
+    let mut alice_session_2 = Session::new(&alice_secret_2, &salt_2, Role::Initiator);
+    let mut bob_session_2 = Session::new(&bob_secret_2, &salt_2, Role::Responder);
+    let message_2 = alice_session_2.encrypt_next("Change of plan: the train station at six.")?;
+    println!("Session 2 secret: {}", alice_secret_2.fingerprint());
+    println!("Bob reads session 2: {}", bob_session_2.decrypt_next(&message_2)?);
+
+    println!("The two secrets are different: {}", alice_secret_1 != alice_secret_2);
+
+    // Eve has compromised session 1 and knows its secret and salt. Armed with them she tries the second ciphertext,
+    // even assuming she also knows session 2's salt, which travels in the clear.
+    let mut eve_session = Session::new(&alice_secret_1, &salt_2, Role::Responder);
+    match eve_session.decrypt_next(&message_2) {
+        Ok(plain_text) => println!("Eve decrypted session 2: {}", plain_text),
+        Err(error) => println!("Eve can't read session 2 with session 1's key: {}", error),
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+// An exchange between this crate and the `openssl` command-line tool.
+//
+// The example writes the ffdhe2048 parameters as PKCS#3, has OpenSSL generate a keypair in that group, and exchanges
+// public keys with it as PEM files. Both ends then agree on a secret: this crate with `PrivateKey::agree`, OpenSSL
+// with `openssl pkeyutl -derive`. The two have to match byte for byte once the secret is padded to the modulus
+// length as RFC 2631 specifies. OpenSSL also derives the secret from our private key file, which checks that it reads
+// the PKCS#8 this crate writes. Any mismatch makes the example fail.
+//
+// Needs `openssl` on the PATH. Run with: cargo run --example openssl_interop --features pkcs8
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use diffie_hellman::{generate_keypair, Group, PublicKey};
+
+// Run `openssl` with the given arguments in `dir` and return what it wrote to stdout.
+fn openssl(dir: &Path, args: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = Command::new("openssl").args(args).current_dir(dir).output()?;
+    if !output.status.success() {
+        return Err(format!("openssl {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr)).into());
+    }
+    Ok(output.stdout)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let params = Group::Ffdhe2048.parameters();
+    let dir = std::env::temp_dir().join(format!("diffie-hellman-openssl-interop-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("params.pem"), params.to_pkcs3_pem())?;
+
+    // OpenSSL's side: a keypair in the same group, with the public key exported for us.
+    openssl(&dir, &["genpkey", "-paramfile", "params.pem", "-out", "openssl_private.pem"])?;
+    openssl(&dir, &["pkey", "-in", "openssl_private.pem", "-pubout", "-out", "openssl_public.pem"])?;
+    let openssl_pem = fs::read_to_string(dir.join("openssl_public.pem"))?;
+    let (openssl_public, openssl_params) = PublicKey::from_spki_pem(&openssl_pem)?;
+    if !openssl_params.is_compatible_with(&params) {
+        return Err("OpenSSL's key is in a different group".into());
+    }
+
+    // Our side: a keypair written out for OpenSSL.
+    let (private_key, public_key) = generate_keypair(&params)?;
+    fs::write(dir.join("rust_public.pem"), public_key.to_spki_pem(&params))?;
+    fs::write(dir.join("rust_private.pem"), private_key.to_pkcs8_pem(&params))?;
+
+    let ours = private_key.agree(&openssl_public, &params).to_padded_bytes(&params);
+    let theirs = openssl(
+        &dir,
+        &["pkeyutl", "-derive", "-inkey", "openssl_private.pem", "-peerkey", "rust_public.pem", "-pkeyopt", "dh_pad:1"],
+    )?;
+    let from_our_key = openssl(
+        &dir,
+        &["pkeyutl", "-derive", "-inkey", "rust_private.pem", "-peerkey", "openssl_public.pem", "-pkeyopt", "dh_pad:1"],
+    )?;
+    fs::remove_dir_all(&dir)?;
+
+    println!("Our public key:     {}...", &hex(&public_key.to_padded_bytes(&params))[..32]);
+    println!("OpenSSL public key: {}...", &hex(&openssl_public.to_padded_bytes(&params))[..32]);
+    println!("Our secret:         {}... ({} bytes)", &hex(&ours)[..32], ours.len());
+    println!("OpenSSL's secret:   {}... ({} bytes)", &hex(&theirs)[..32], theirs.len());
+    if ours[..] != theirs[..] || ours[..] != from_our_key[..] {
+        return Err("the secrets don't match".into());
+    }
+    println!("Both sides agree, and OpenSSL reads our private key too.");
+    Ok(())
+}
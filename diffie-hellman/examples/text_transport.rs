@@ -0,0 +1,69 @@
+// The handshake and encrypted payloads over transports that carry text: an HTTP API and an MQTT broker.
+//
+// A device registers with a server by POSTing its datagram hello as the request body; the response body is the
+// server's reply. From then on the device publishes readings to an MQTT topic as sealed text messages, and the
+// server answers commands on another topic the same way. Both transports are in-process stand-ins here, a function
+// for the HTTP handler and a map of topics for the broker, so the example runs on its own; with a real HTTP client
+// or MQTT library only the send and receive calls change.
+//
+// Run with: cargo run --example text_transport
+
+use std::collections::{HashMap, VecDeque};
+
+use diffie_hellman::{DatagramChannel, DatagramInitiator, DatagramResponder, DhError, DhParameters, Group};
+
+// An MQTT broker reduced to queues of text messages by topic.
+#[derive(Default)]
+struct Broker {
+    topics: HashMap<String, VecDeque<String>>,
+}
+
+impl Broker {
+    fn publish(&mut self, topic: &str, payload: String) {
+        self.topics.entry(topic.to_string()).or_default().push_back(payload);
+    }
+
+    fn receive(&mut self, topic: &str) -> Option<String> {
+        self.topics.get_mut(topic)?.pop_front()
+    }
+}
+
+// The server's handler for `POST /register`: the body is the device's hello, the response body the reply.
+fn register(params: &DhParameters, body: &str) -> Result<(String, DatagramChannel), DhError> {
+    let (responder, channel) = DatagramResponder::respond_text(params, body)?;
+    Ok((responder.reply_text(), channel))
+}
+
+fn main() -> Result<(), DhError> {
+    let params = DhParameters::from(Group::Modp2048);
+    let mut broker = Broker::default();
+
+    // Registration over HTTP.
+    let device = DatagramInitiator::new(&params)?;
+    let request_body = device.hello_text();
+    println!("POST /register\n{request_body}\n");
+    let (response_body, mut server) = register(&params, &request_body)?;
+    println!("200 OK\n{response_body}\n");
+    let mut device = device.finish_text(&response_body)?;
+
+    // Telemetry and commands over MQTT.
+    for reading in ["temperature=21.5", "temperature=21.7"] {
+        broker.publish("devices/42/telemetry", device.seal_text(reading.as_bytes())?);
+    }
+    while let Some(payload) = broker.receive("devices/42/telemetry") {
+        println!("devices/42/telemetry: {payload}");
+        println!("  server reads: {}", String::from_utf8_lossy(&server.open_text(&payload)?));
+    }
+
+    broker.publish("devices/42/commands", server.seal_text(b"interval=60")?);
+    while let Some(payload) = broker.receive("devices/42/commands") {
+        println!("devices/42/commands: {payload}");
+        println!("  device reads: {}", String::from_utf8_lossy(&device.open_text(&payload)?));
+    }
+
+    // A payload mangled on the way is rejected rather than decrypted.
+    let mut tampered = device.seal_text(b"temperature=99.9")?;
+    tampered.replace_range(tampered.len() - 4.., "AAAA");
+    println!("tampered payload rejected: {}", server.open_text(&tampered).is_err());
+    Ok(())
+}
@@ -0,0 +1,23 @@
+// The decryption paths, fed arbitrary ciphertexts under a fixed secret. None may panic, whatever the input.
+
+#![no_main]
+
+use diffie_hellman::{
+    decrypt_data, decrypt_gcm, decrypt_gcm_limited, open, RatchetState, Role, SecureChannel, Session, SharedSecret,
+    StreamOpener,
+};
+use libfuzzer_sys::fuzz_target;
+use num_bigint::BigUint;
+
+fuzz_target!(|data: &[u8]| {
+    let secret_key = BigUint::from(0x5eed_u32);
+    let shared = SharedSecret::from_bytes(&[0x5e; 32]);
+    let _ = decrypt_data(data, &secret_key);
+    let _ = decrypt_gcm(data, &secret_key);
+    let _ = decrypt_gcm_limited(data, &secret_key, 64);
+    let _ = open(data, &secret_key);
+    let _ = SecureChannel::new(&shared, Role::Responder).open(data);
+    let _ = Session::new(&shared, b"fuzz salt", Role::Responder).receive(data);
+    let _ = RatchetState::new(&shared, Role::Responder).decrypt(data);
+    let _ = StreamOpener::new(&shared, b"fuzz salt").open_chunk(data);
+});
@@ -0,0 +1,18 @@
+// Splitting arbitrary bytes into an envelope, and opening it, must fail cleanly rather than panic.
+
+#![no_main]
+
+use diffie_hellman::{EncryptedMessage, EnvelopeOpener, Role, SequencedMessage};
+use libfuzzer_sys::fuzz_target;
+use num_bigint::BigUint;
+
+fuzz_target!(|data: &[u8]| {
+    let secret = BigUint::from(0x5eed_u32);
+    if let Ok(message) = EncryptedMessage::from_bytes(data) {
+        assert_eq!(message.to_bytes(), data);
+        let _ = message.open(&secret);
+    }
+    if let Ok(message) = SequencedMessage::from_bytes(data) {
+        let _ = EnvelopeOpener::new(&secret, Role::Responder).open(&message);
+    }
+});
@@ -0,0 +1,17 @@
+// PEM and DER parsing of parameters and keys, as read from files other tools wrote.
+
+#![no_main]
+
+use diffie_hellman::{DhParameters, PrivateKey, PublicKey};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DhParameters::from_pkcs3_der(data);
+    let _ = PrivateKey::from_pkcs8_der(data);
+    let _ = PublicKey::from_spki_der(data);
+    if let Ok(pem) = std::str::from_utf8(data) {
+        let _ = DhParameters::from_pkcs3_pem(pem);
+        let _ = PrivateKey::from_pkcs8_pem(pem);
+        let _ = PublicKey::from_spki_pem(pem);
+    }
+});
@@ -0,0 +1,21 @@
+// Every way of reading a public key or parameters from untrusted bytes, with the input split between the encodings.
+
+#![no_main]
+
+use diffie_hellman::{DhParameters, Group, HandshakeMessage, PublicKey};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let params = Group::Ffdhe2048.parameters();
+    if let Ok(key) = PublicKey::from_wire_bytes(data, &params) {
+        assert_eq!(key.to_wire_bytes(), data);
+    }
+    let _ = PublicKey::from_padded_bytes(data, &params);
+    let _ = PublicKey::from_ssh_mpint(data);
+    let _ = DhParameters::from_bytes(data);
+    let _ = HandshakeMessage::decode(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = PublicKey::from_hex(text);
+        let _ = PublicKey::from_compact_string(text);
+    }
+});
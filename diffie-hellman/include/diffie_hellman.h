@@ -0,0 +1,46 @@
+#ifndef DIFFIE_HELLMAN_H
+#define DIFFIE_HELLMAN_H
+
+/* Generated by cbindgen from src/ffi.rs; regenerate rather than editing by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum DhStatus {
+  DH_STATUS_OK = 0,
+  DH_STATUS_INVALID_ARGUMENT = 1,
+  DH_STATUS_UNKNOWN_GROUP = 2,
+  DH_STATUS_ERROR = 3,
+} DhStatus;
+
+typedef struct DhBuffer {
+  uint8_t *data;
+  size_t len;
+} DhBuffer;
+
+const char *dh_last_error(void);
+
+void dh_buffer_free(struct DhBuffer *buffer);
+
+enum DhStatus dh_keygen(const char *group, struct DhBuffer *private_key, struct DhBuffer *public_key);
+
+enum DhStatus dh_derive(const char *group,
+                        const uint8_t *private_key,
+                        size_t private_key_len,
+                        const uint8_t *peer_public_key,
+                        size_t peer_public_key_len,
+                        struct DhBuffer *secret);
+
+enum DhStatus dh_encrypt(const uint8_t *secret,
+                         size_t secret_len,
+                         const uint8_t *plaintext,
+                         size_t plaintext_len,
+                         struct DhBuffer *ciphertext);
+
+enum DhStatus dh_decrypt(const uint8_t *secret,
+                         size_t secret_len,
+                         const uint8_t *ciphertext,
+                         size_t ciphertext_len,
+                         struct DhBuffer *plaintext);
+
+#endif /* DIFFIE_HELLMAN_H */
@@ -0,0 +1,82 @@
+// Memoized agreements for a party with a static private key. A server that keeps talking to the same static-key
+// peers would otherwise redo the same validation and exponentiation for every conversation; the cache does them once
+// per peer public key.
+
+use std::collections::HashMap;
+
+use crate::error::DhError;
+use crate::keys::{PrivateKey, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+
+pub struct AgreementCache {
+    private_key: PrivateKey,
+    params: DhParameters,
+    secrets: HashMap<PublicKey, SharedSecret>,
+}
+
+impl AgreementCache {
+    pub fn new(private_key: PrivateKey, params: DhParameters) -> Self {
+        AgreementCache { private_key, params, secrets: HashMap::new() }
+    }
+
+    // The shared secret with `peer_public`, computed and validated on first use and served from the cache after
+    // that. Peer keys that fail validation are not cached, so they are rejected again each time.
+    pub fn agree(&mut self, peer_public: &PublicKey) -> Result<&SharedSecret, DhError> {
+        if !self.secrets.contains_key(peer_public) {
+            peer_public.validate_in_group(&self.params)?;
+            let secret = self.private_key.agree(peer_public, &self.params);
+            self.secrets.insert(peer_public.clone(), secret);
+        }
+        Ok(&self.secrets[peer_public])
+    }
+
+    // How many peers have a cached secret. The cache grows with every new peer; call `clear` to bound it.
+    pub fn len(&self) -> usize {
+        self.secrets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.secrets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    #[test]
+    fn cached_secrets_match_fresh_agreements() {
+        let params = Group::Ffdhe2048.parameters();
+        let private_key = PrivateKey::from_bytes_be(&[0x31; 28]);
+        let peers: Vec<PublicKey> =
+            [0x42u8, 0x43].iter().map(|&byte| PrivateKey::from_bytes_be(&[byte; 28]).public_key(&params)).collect();
+        let fresh: Vec<SharedSecret> = peers.iter().map(|peer| private_key.agree(peer, &params)).collect();
+
+        let mut cache = AgreementCache::new(PrivateKey::from_bytes_be(&[0x31; 28]), params);
+        assert!(cache.is_empty());
+        for _ in 0..2 {
+            for (peer, expected) in peers.iter().zip(&fresh) {
+                assert!(cache.agree(peer).unwrap() == expected);
+            }
+        }
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!(cache.agree(&peers[0]).unwrap() == &fresh[0]);
+    }
+
+    #[test]
+    fn invalid_peer_keys_are_rejected_every_time() {
+        let params = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        let mut cache = AgreementCache::new(PrivateKey::from_bytes_be(&[6]), params);
+        for _ in 0..2 {
+            assert!(cache.agree(&PublicKey::from_biguint(1u32.into())).is_err());
+        }
+        assert!(cache.is_empty());
+    }
+}
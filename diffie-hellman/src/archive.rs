@@ -0,0 +1,151 @@
+// Storing many messages encrypted under one session. An archive is written by one end of a session and read back by
+// a session with the opposite role (or a restored copy of one), so the stored records are exactly the messages the
+// session would have sent, each authenticated on its own.
+//
+// Archive layout: magic "DHAR" || version (1 byte) || session binding (32 bytes) || records, where each record is a
+// 4-byte big-endian length followed by a session message. The binding is keying material exported from the session,
+// so an archive opened with the wrong session is turned away before any record is tried. The last record is the
+// session's close message, so an archive cut short is reported as truncated.
+
+use crate::error::DhError;
+use crate::session::{Record, Session};
+
+const MAGIC: &[u8; 4] = b"DHAR";
+const ARCHIVE_VERSION: u8 = 1;
+const BINDING_LEN: usize = 32;
+const LENGTH_LEN: usize = 4;
+
+fn binding(session: &Session) -> Vec<u8> {
+    session.export_keying_material("message archive", &[], BINDING_LEN)
+}
+
+// An encrypted archive of messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive(Vec<u8>);
+
+impl Archive {
+    // Encrypt `messages` in order into a new archive. This uses up the session's sending direction: it is closed
+    // afterwards.
+    pub fn write(session: &mut Session, messages: &[&str]) -> Result<Archive, DhError> {
+        let mut archive = MAGIC.to_vec();
+        archive.push(ARCHIVE_VERSION);
+        archive.extend_from_slice(&binding(session));
+        for message in messages {
+            push_record(&mut archive, &session.encrypt_next(message)?);
+        }
+        push_record(&mut archive, &session.close()?);
+        Ok(Archive(archive))
+    }
+
+    // Decrypt every message in the archive, in the order they were written. Fails if any record has been tampered
+    // with, reordered or removed, or if the archive doesn't belong to this session.
+    pub fn read(&self, session: &mut Session) -> Result<Vec<String>, DhError> {
+        let header_len = MAGIC.len() + 1 + BINDING_LEN;
+        if self.0.len() < header_len || !self.0.starts_with(MAGIC) {
+            return Err(DhError::MalformedMessage);
+        }
+        let version = self.0[MAGIC.len()];
+        if version != ARCHIVE_VERSION {
+            return Err(DhError::UnsupportedVersion { version });
+        }
+        if self.0[MAGIC.len() + 1..header_len] != binding(session)[..] {
+            return Err(DhError::DecryptionFailed);
+        }
+
+        let mut messages = Vec::new();
+        let mut rest = &self.0[header_len..];
+        let mut expected_counter = None;
+        while !rest.is_empty() {
+            let (length_bytes, tail) = rest.split_first_chunk::<LENGTH_LEN>().ok_or(DhError::MalformedMessage)?;
+            let length = u32::from_be_bytes(*length_bytes) as usize;
+            if length > tail.len() {
+                return Err(DhError::MalformedMessage);
+            }
+            let (record, tail) = tail.split_at(length);
+            rest = tail;
+            // Sessions accept messages out of order, but an archive has to come back exactly as written: each record
+            // must carry the counter after the previous one. The first may be anything, as the writing session may
+            // have sent other messages before.
+            let counter = record.first_chunk::<8>().map(|counter| u64::from_be_bytes(*counter));
+            if counter.is_none() || (expected_counter.is_some() && counter != expected_counter) {
+                return Err(DhError::DecryptionFailed);
+            }
+            expected_counter = counter.map(|counter| counter + 1);
+            match session.receive(record)? {
+                Record::Data(message) => messages.push(message),
+                Record::Close if rest.is_empty() => {}
+                Record::Close => return Err(DhError::MalformedMessage),
+            }
+        }
+        session.end_of_stream()?;
+        Ok(messages)
+    }
+
+    // Load an archive saved with `as_bytes`. Nothing is checked until `read`.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Archive(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+fn push_record(archive: &mut Vec<u8>, record: &[u8]) {
+    archive.extend_from_slice(&(record.len() as u32).to_be_bytes());
+    archive.extend_from_slice(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SharedSecret;
+    use crate::session::Role;
+
+    fn session_pair() -> (Session, Session) {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let salt = b"archive test";
+        (Session::new(&secret, salt, Role::Initiator), Session::new(&secret, salt, Role::Responder))
+    }
+
+    #[test]
+    fn messages_come_back_in_order() {
+        let (mut writer, mut reader) = session_pair();
+        let messages = ["first", "", "third message", "fourth"];
+        let archive = Archive::write(&mut writer, &messages).unwrap();
+        let stored = Archive::from_bytes(archive.as_bytes().to_vec());
+        assert_eq!(stored.read(&mut reader).unwrap(), messages);
+    }
+
+    #[test]
+    fn empty_archive_reads_as_no_messages() {
+        let (mut writer, mut reader) = session_pair();
+        let archive = Archive::write(&mut writer, &[]).unwrap();
+        assert!(archive.read(&mut reader).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tampered_or_truncated_archives_are_rejected() {
+        let (mut writer, _) = session_pair();
+        let archive = Archive::write(&mut writer, &["one", "two"]).unwrap();
+
+        let mut tampered = archive.as_bytes().to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(Archive::from_bytes(tampered).read(&mut session_pair().1).is_err());
+
+        // Cut after the first record, so the second and the close are gone.
+        let bytes = archive.as_bytes();
+        let first_len = u32::from_be_bytes(bytes[37..41].try_into().unwrap()) as usize;
+        let truncated = Archive::from_bytes(bytes[..41 + first_len].to_vec());
+        assert!(truncated.read(&mut session_pair().1).is_err());
+    }
+
+    #[test]
+    fn archive_from_another_session_is_rejected() {
+        let (mut writer, _) = session_pair();
+        let archive = Archive::write(&mut writer, &["one"]).unwrap();
+        let other = SharedSecret::from_bytes(&[7; 32]);
+        let mut reader = Session::new(&other, b"archive test", Role::Responder);
+        assert!(matches!(archive.read(&mut reader), Err(DhError::DecryptionFailed)));
+    }
+}
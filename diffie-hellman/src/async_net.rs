@@ -0,0 +1,242 @@
+// The networked exchange for tokio. `handshake_async` runs the same handshake as `handshake`, with the same wire
+// format, so async and blocking peers can talk to each other, and wraps the stream in a `SecureStream` that encrypts
+// everything written to it and decrypts everything read from it. A `SecureStream` can be dropped into code written
+// against `AsyncRead` and `AsyncWrite`, e.g. with `tokio::io::copy` or a codec.
+//
+// On the wire every write becomes one `SecureChannel` message in a `send_frame` frame, so the peer of a `SecureStream`
+// can just as well be a blocking `SecureChannel` with the opposite role. Writes larger than `SECURE_STREAM_FRAME_LEN`
+// are cut short, as `AsyncWrite` allows, and the rest goes in the next frame.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use num_bigint::BigUint;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tracing::Instrument;
+
+use crate::channel::SecureChannel;
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::keys::{generate_keypair, PublicKey};
+use crate::net::{in_phase, trace_public_key};
+use crate::nonce::NONCE_LEN;
+use crate::params::DhParameters;
+use crate::session::Role;
+
+// The most plaintext one frame carries.
+pub const SECURE_STREAM_FRAME_LEN: usize = 16 * 1024;
+
+const LENGTH_LEN: usize = 4;
+const MAX_SEALED_LEN: usize = NONCE_LEN + SECURE_STREAM_FRAME_LEN + TAG_LEN;
+
+// Run the handshake with the peer on the other end of `stream` and return the stream wrapped for encrypted traffic.
+// The checks and errors are those of `handshake`. Both ends pass opposite roles, as for `SecureChannel::new`. No
+// timeout is applied; wrap the call in `tokio::time::timeout` to give up on a silent peer.
+pub async fn handshake_async<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    params: &DhParameters,
+    role: Role,
+) -> Result<SecureStream<S>, DhError> {
+    let span = tracing::debug_span!("handshake_async", modulus_bits = params.bit_length(), role = role.label());
+    exchange(stream, params, role).instrument(span).await
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    params: &DhParameters,
+    role: Role,
+) -> Result<SecureStream<S>, DhError> {
+    in_phase("sending parameters", write_all(&mut stream, &params.to_bytes()).await)?;
+    // Numbers longer than our own modulus can't match it, so they are refused as incompatible without being read.
+    let max_length = params.modulus.bits().div_ceil(8) as usize;
+    let mut numbers = [BigUint::default(), BigUint::default()];
+    for number in &mut numbers {
+        let bytes = in_phase("receiving parameters", read_prefixed(&mut stream, max_length).await).map_err(|error| {
+            match error {
+                DhError::FrameTooLarge { .. } => DhError::IncompatibleParameters,
+                other => other,
+            }
+        })?;
+        *number = BigUint::from_bytes_be(&bytes);
+    }
+    let [generator, modulus] = numbers;
+    if !(DhParameters { generator, modulus }).is_compatible_with(params) {
+        tracing::debug!("peer uses different parameters");
+        return Err(DhError::IncompatibleParameters);
+    }
+    tracing::debug!("parameters match");
+
+    let (private_key, public_key) = generate_keypair(params)?;
+    let key_bytes = public_key.to_wire_bytes();
+    let mut message = (key_bytes.len() as u32).to_be_bytes().to_vec();
+    message.extend_from_slice(&key_bytes);
+    in_phase("sending the public key", write_all(&mut stream, &message).await)?;
+    trace_public_key("sent public key", &public_key);
+    let peer_bytes = in_phase("receiving the public key", read_prefixed(&mut stream, max_length).await)?;
+    let peer_public = PublicKey::from_wire_bytes(&peer_bytes, params)?;
+    trace_public_key("received public key", &peer_public);
+    peer_public.validate_in_group(params)?;
+    if peer_public == public_key {
+        return Err(DhError::IdenticalPublicKeys);
+    }
+    tracing::debug!("peer public key is valid");
+    let secret = private_key.agree(&peer_public, params);
+    Ok(SecureStream::new(stream, SecureChannel::new(&secret, role)))
+}
+
+async fn write_all<S: AsyncWrite + Unpin>(stream: &mut S, bytes: &[u8]) -> Result<(), DhError> {
+    stream.write_all(bytes).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+// One length-prefixed message of at most `max_length` bytes, or `DhError::FrameTooLarge`.
+async fn read_prefixed<S: AsyncRead + Unpin>(stream: &mut S, max_length: usize) -> Result<Vec<u8>, DhError> {
+    let length = stream.read_u32().await? as usize;
+    if length > max_length {
+        return Err(DhError::FrameTooLarge { length, max_length });
+    }
+    let mut bytes = vec![0u8; length];
+    stream.read_exact(&mut bytes).await?;
+    Ok(bytes)
+}
+
+// An encrypted stream, returned by `handshake_async`. Reads return the decrypted contents of the peer's frames; a
+// frame that fails to decrypt is an `InvalidData` error, and a stream that ends partway through a frame an
+// `UnexpectedEof` error wrapping `DhError::Truncated`. Written data is sealed right away, but only goes out once it
+// is flushed or the next write needs the space, so call `flush` or `shutdown` when done.
+pub struct SecureStream<S> {
+    inner: S,
+    channel: SecureChannel,
+    // The frame being read: its length prefix and then its body, with how much of each has arrived.
+    length_bytes: [u8; LENGTH_LEN],
+    incoming: Vec<u8>,
+    filled: usize,
+    // Decrypted bytes not yet returned, and how far into them we are.
+    pending: Vec<u8>,
+    position: usize,
+    // A sealed frame waiting to go out, and how much of it has been written.
+    outgoing: Vec<u8>,
+    written: usize,
+}
+
+impl<S> SecureStream<S> {
+    fn new(inner: S, channel: SecureChannel) -> Self {
+        SecureStream {
+            inner,
+            channel,
+            length_bytes: [0; LENGTH_LEN],
+            incoming: Vec::new(),
+            filled: 0,
+            pending: Vec::new(),
+            position: 0,
+            outgoing: Vec::new(),
+            written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    // Hand back the underlying stream. Anything sealed but not yet flushed is lost.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncWrite + Unpin> SecureStream<S> {
+    // Write out what remains of the pending frame.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.outgoing.len() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.outgoing[self.written..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += written;
+        }
+        self.outgoing.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> SecureStream<S> {
+    // Read the next frame into `pending`. Gives `Ok(false)` if the peer hung up cleanly between frames.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        while self.filled < LENGTH_LEN {
+            let mut buf = ReadBuf::new(&mut self.length_bytes[self.filled..]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+            match buf.filled().len() {
+                0 if self.filled == 0 => return Poll::Ready(Ok(false)),
+                0 => return Poll::Ready(Err(truncated())),
+                read => self.filled += read,
+            }
+            if self.filled == LENGTH_LEN {
+                let length = u32::from_be_bytes(self.length_bytes) as usize;
+                if length > MAX_SEALED_LEN {
+                    let error = DhError::FrameTooLarge { length, max_length: MAX_SEALED_LEN };
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, error)));
+                }
+                self.incoming = vec![0u8; length];
+            }
+        }
+        while self.filled < LENGTH_LEN + self.incoming.len() {
+            let mut buf = ReadBuf::new(&mut self.incoming[self.filled - LENGTH_LEN..]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+            match buf.filled().len() {
+                0 => return Poll::Ready(Err(truncated())),
+                read => self.filled += read,
+            }
+        }
+        self.filled = 0;
+        let plain_bytes =
+            self.channel.open(&self.incoming).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        self.pending = plain_bytes;
+        self.position = 0;
+        Poll::Ready(Ok(true))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SecureStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        // An empty frame is legal, so keep going until there is something to return or the peer has hung up.
+        while this.position == this.pending.len() {
+            if !ready!(this.poll_frame(cx))? {
+                return Poll::Ready(Ok(()));
+            }
+        }
+        let len = buf.remaining().min(this.pending.len() - this.position);
+        buf.put_slice(&this.pending[this.position..this.position + len]);
+        this.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SecureStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        let len = buf.len().min(SECURE_STREAM_FRAME_LEN);
+        let sealed = this.channel.seal(&buf[..len]).map_err(io::Error::other)?;
+        this.outgoing.extend_from_slice(&(sealed.len() as u32).to_be_bytes());
+        this.outgoing.extend_from_slice(&sealed);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, DhError::Truncated)
+}
@@ -0,0 +1,100 @@
+// A teaching aid: list everything that is insecure about a configuration of the classic AES layer in cipher.rs, the
+// one the demo uses. Each warning names a weakness of naive Diffie-Hellman and what the crate offers instead.
+
+use std::fmt;
+
+use crate::cipher::CipherMode;
+use crate::params::{DhParameters, MIN_SECURE_BITS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditWarning {
+    // The modulus is shorter than `MIN_SECURE_BITS`, so discrete logarithms in it are feasible to compute.
+    SmallModulus { bits: u64 },
+    // The modulus isn't prime, so the group doesn't have the structure the exchange relies on.
+    CompositeModulus,
+    // The modulus is prime but the generator's order couldn't be confirmed as large.
+    UnverifiedGenerator,
+    // ECB encrypts equal blocks to equal ciphertext, leaking patterns in the message.
+    EcbMode,
+    // The AES key is the low 16 bytes of the raw secret rather than the output of a KDF.
+    TruncatedKey,
+    // Block-mode ciphertexts carry no MAC, so tampering goes undetected.
+    Unauthenticated,
+}
+
+impl fmt::Display for AuditWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditWarning::SmallModulus { bits } => write!(
+                f,
+                "the modulus is only {} bits; anything under {} bits can be broken, use a standard Group",
+                bits, MIN_SECURE_BITS
+            ),
+            AuditWarning::CompositeModulus => write!(f, "the modulus is not prime, so the exchange offers no security"),
+            AuditWarning::UnverifiedGenerator => {
+                write!(f, "the generator's order could not be verified; it may generate a small subgroup")
+            }
+            AuditWarning::EcbMode => write!(f, "ECB mode reveals which message blocks are equal; use encrypt_gcm"),
+            AuditWarning::TruncatedKey => {
+                write!(f, "the AES key is the secret truncated to 16 bytes instead of a KDF output; use derive_key")
+            }
+            AuditWarning::Unauthenticated => {
+                write!(f, "ciphertexts are not authenticated, so tampering goes unnoticed; use encrypt_gcm or Session")
+            }
+        }
+    }
+}
+
+// Every weakness of using `params` with the AES layer in `mode`. An empty list is never returned: the block-mode
+// layer always truncates the key and never authenticates, whatever the parameters.
+pub fn audit(params: &DhParameters, mode: CipherMode) -> Vec<AuditWarning> {
+    let mut warnings = Vec::new();
+    if params.bit_length() < MIN_SECURE_BITS as u64 {
+        warnings.push(AuditWarning::SmallModulus { bits: params.bit_length() });
+    }
+    let report = params.check();
+    if !report.modulus_is_prime {
+        warnings.push(AuditWarning::CompositeModulus);
+    } else if !report.generator_order_verified {
+        warnings.push(AuditWarning::UnverifiedGenerator);
+    }
+    if mode == CipherMode::Ecb {
+        warnings.push(AuditWarning::EcbMode);
+    }
+    warnings.push(AuditWarning::TruncatedKey);
+    warnings.push(AuditWarning::Unauthenticated);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    #[test]
+    fn the_demo_configuration_gets_every_warning_but_one() {
+        let demo = DhParameters::from_u64_allow_insecure(5, 57).unwrap();
+        assert_eq!(
+            audit(&demo, CipherMode::Ecb),
+            [
+                AuditWarning::SmallModulus { bits: 6 },
+                AuditWarning::CompositeModulus,
+                AuditWarning::EcbMode,
+                AuditWarning::TruncatedKey,
+                AuditWarning::Unauthenticated,
+            ]
+        );
+    }
+
+    #[test]
+    fn fixing_the_parameters_and_mode_leaves_the_block_layer_warnings() {
+        let not_safe = DhParameters::from_u64_allow_insecure(2, 29).unwrap();
+        let warnings = audit(&not_safe, CipherMode::Cbc);
+        assert_eq!(warnings[..2], [AuditWarning::SmallModulus { bits: 5 }, AuditWarning::UnverifiedGenerator]);
+        assert_eq!(
+            audit(&Group::Ffdhe2048.parameters(), CipherMode::Cbc),
+            [AuditWarning::TruncatedKey, AuditWarning::Unauthenticated]
+        );
+        assert!(AuditWarning::SmallModulus { bits: 6 }.to_string().contains("only 6 bits"));
+    }
+}
@@ -0,0 +1,77 @@
+// A simple two-way encrypted channel over an agreed secret: AES-128-GCM with a fresh random nonce for every message,
+// sent in front of the ciphertext. Messages are numbered in each direction and the number is authenticated as
+// associated data without being sent, so a message that is replayed, dropped or delivered out of order fails to
+// decrypt. Each direction has its own key, so a message can't be reflected back to its sender either. Use `Session`
+// instead when messages may legitimately arrive out of order, or for close records and resumption.
+//
+// Message layout: nonce (12 bytes) || ciphertext || GCM tag (16 bytes).
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::kdf::derive_key;
+use crate::keys::SharedSecret;
+use crate::nonce::NONCE_LEN;
+use crate::session::Role;
+
+// The cipher for messages sent by `role`.
+fn direction_cipher(secret: &SharedSecret, role: Role) -> Aes128Gcm {
+    let key = Zeroizing::new(derive_key(secret.as_biguint(), &format!("{} channel encryption key", role.label()), 16));
+    Aes128Gcm::new_from_slice(&key).expect("derived key is 16 bytes")
+}
+
+pub struct SecureChannel {
+    send_cipher: Aes128Gcm,
+    receive_cipher: Aes128Gcm,
+    // The sequence numbers of the next message to send and to receive.
+    send_sequence: u64,
+    receive_sequence: u64,
+}
+
+impl SecureChannel {
+    // Both ends pass the same secret and opposite roles.
+    pub fn new(secret: &SharedSecret, role: Role) -> Self {
+        SecureChannel {
+            send_cipher: direction_cipher(secret, role),
+            receive_cipher: direction_cipher(secret, role.peer()),
+            send_sequence: 0,
+            receive_sequence: 0,
+        }
+    }
+
+    // Encrypt the next outgoing message.
+    pub fn seal(&mut self, plain_bytes: &[u8]) -> Result<Vec<u8>, DhError> {
+        let sequence = self.send_sequence;
+        self.send_sequence = sequence.checked_add(1).ok_or(DhError::NonceExhausted)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .send_cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain_bytes, aad: &sequence.to_be_bytes() })
+            .map_err(|_| DhError::EncryptionFailed)?;
+
+        let mut message = nonce.to_vec();
+        message.extend_from_slice(&ciphertext);
+        Ok(message)
+    }
+
+    // Verify and decrypt the next incoming message. Anything other than the message the peer sent next, including
+    // tampering and replays, fails with `DhError::DecryptionFailed`, after which the channel still expects that
+    // message.
+    pub fn open(&mut self, message: &[u8]) -> Result<Vec<u8>, DhError> {
+        if message.len() < NONCE_LEN + TAG_LEN {
+            return Err(DhError::DecryptionFailed);
+        }
+        let (nonce, ciphertext) = message.split_at(NONCE_LEN);
+        let plain_bytes = self
+            .receive_cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &self.receive_sequence.to_be_bytes() })
+            .map_err(|_| DhError::DecryptionFailed)?;
+        self.receive_sequence += 1;
+        Ok(plain_bytes)
+    }
+}
@@ -0,0 +1,383 @@
+// The AES-128 layer used to encrypt messages with the agreed secret key.
+// Every cipher is built through `new_cipher`, which supplies each block mode with an IV of the size it expects.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use aes::Aes128;
+use block_modes::{BlockMode, Cbc, Ecb};
+use block_modes::block_padding::{Iso7816, NoPadding, Pkcs7};
+use num_bigint::BigUint;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::length_padding::{pad_to_bucket, strip_bucket_padding};
+
+type Aes128Ecb = Ecb<Aes128, Pkcs7>;
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+type Aes128EcbIso = Ecb<Aes128, Iso7816>;
+type Aes128CbcIso = Cbc<Aes128, Iso7816>;
+type Aes128EcbUnpadded = Ecb<Aes128, NoPadding>;
+type Aes128CbcUnpadded = Cbc<Aes128, NoPadding>;
+
+const BLOCK_LEN: usize = 16;
+
+// The block cipher modes messages can be encrypted with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherMode {
+    // Every block is encrypted on its own, so equal plaintext blocks give equal ciphertext blocks. Takes no IV.
+    Ecb,
+    // Each block is chained to the previous one, starting from a 16-byte IV.
+    Cbc,
+}
+
+impl CipherMode {
+    // The IV length in bytes this mode must be constructed with.
+    pub fn iv_len(self) -> usize {
+        match self {
+            CipherMode::Ecb => 0,
+            CipherMode::Cbc => 16,
+        }
+    }
+}
+
+// How plaintext is brought up to a whole number of blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    // PKCS#7 padding, added on encryption and checked and removed on decryption. Works for any length.
+    Pkcs7,
+    // ISO/IEC 7816-4 padding: a 0x80 byte, then zeros up to the end of the block. Works for any length, and is what
+    // smart cards and some hardware modules expect.
+    Iso7816,
+    // No padding: the data must already be a multiple of 16 bytes, e.g. because the application pads it itself.
+    None,
+}
+
+// An AES-128 cipher constructed for one of the supported modes and paddings.
+enum ModeCipher {
+    Ecb(Aes128Ecb),
+    Cbc(Aes128Cbc),
+    EcbIso(Aes128EcbIso),
+    CbcIso(Aes128CbcIso),
+    EcbUnpadded(Aes128EcbUnpadded),
+    CbcUnpadded(Aes128CbcUnpadded),
+}
+
+impl ModeCipher {
+    fn encrypt_vec(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ModeCipher::Ecb(cipher) => cipher.encrypt_vec(data),
+            ModeCipher::Cbc(cipher) => cipher.encrypt_vec(data),
+            ModeCipher::EcbIso(cipher) => cipher.encrypt_vec(data),
+            ModeCipher::CbcIso(cipher) => cipher.encrypt_vec(data),
+            ModeCipher::EcbUnpadded(cipher) => cipher.encrypt_vec(data),
+            ModeCipher::CbcUnpadded(cipher) => cipher.encrypt_vec(data),
+        }
+    }
+
+    fn decrypt_vec(self, data: &[u8]) -> Result<Vec<u8>, DhError> {
+        let decrypted = match self {
+            ModeCipher::Ecb(cipher) => cipher.decrypt_vec(data),
+            ModeCipher::Cbc(cipher) => cipher.decrypt_vec(data),
+            ModeCipher::EcbIso(cipher) => cipher.decrypt_vec(data),
+            ModeCipher::CbcIso(cipher) => cipher.decrypt_vec(data),
+            ModeCipher::EcbUnpadded(cipher) => cipher.decrypt_vec(data),
+            ModeCipher::CbcUnpadded(cipher) => cipher.decrypt_vec(data),
+        };
+        Ok(decrypted?)
+    }
+}
+
+// Build the cipher for `mode` and `padding`, checking the IV is exactly the size the mode needs rather than
+// letting a mismatched IV through. ECB must be given an empty IV, CBC a 16-byte one.
+fn new_cipher(mode: CipherMode, padding: Padding, key: &[u8; 16], iv: &[u8]) -> Result<ModeCipher, DhError> {
+    if iv.len() != mode.iv_len() {
+        return Err(DhError::InvalidIvLength { mode, expected: mode.iv_len(), actual: iv.len() });
+    }
+    let cipher = match (mode, padding) {
+        (CipherMode::Ecb, Padding::Pkcs7) => Aes128Ecb::new_from_slices(key, iv).map(ModeCipher::Ecb),
+        (CipherMode::Cbc, Padding::Pkcs7) => Aes128Cbc::new_from_slices(key, iv).map(ModeCipher::Cbc),
+        (CipherMode::Ecb, Padding::Iso7816) => Aes128EcbIso::new_from_slices(key, iv).map(ModeCipher::EcbIso),
+        (CipherMode::Cbc, Padding::Iso7816) => Aes128CbcIso::new_from_slices(key, iv).map(ModeCipher::CbcIso),
+        (CipherMode::Ecb, Padding::None) => Aes128EcbUnpadded::new_from_slices(key, iv).map(ModeCipher::EcbUnpadded),
+        (CipherMode::Cbc, Padding::None) => Aes128CbcUnpadded::new_from_slices(key, iv).map(ModeCipher::CbcUnpadded),
+    };
+    cipher.map_err(|_| DhError::InvalidIvLength { mode, expected: mode.iv_len(), actual: iv.len() })
+}
+
+// Convert our BigUint secret key into a 16-byte array suitable for AES-128. Both the key and the copy of the secret
+// it is cut from are wiped when dropped.
+pub(crate) fn generate_secret_key_spec(secret_key: &BigUint) -> Zeroizing<[u8; 16]> {
+    let key_bytes = Zeroizing::new(secret_key.to_bytes_le());
+    let mut valid_key_bytes = Zeroizing::new([0u8; 16]);
+    for (i, &byte) in key_bytes.iter().enumerate().take(16) {
+        valid_key_bytes[i] = byte;
+    }
+    valid_key_bytes
+}
+
+// The AES-128 key the ECB and CBC functions in this file use for this secret: its low 16 bytes, little-endian.
+pub fn aes_key(secret_key: &BigUint) -> [u8; 16] {
+    *generate_secret_key_spec(secret_key)
+}
+
+// Encrypt raw bytes with AES-128 in the chosen mode and PKCS#7 padding, using the provided secret key and IV.
+pub fn encrypt_with_mode(data: &[u8], secret_key: &BigUint, mode: CipherMode, iv: &[u8]) -> Result<Vec<u8>, DhError> {
+    encrypt_with_padding(data, secret_key, mode, Padding::Pkcs7, iv)
+}
+
+// Decrypt data produced by `encrypt_with_mode` using the same mode, secret key and IV.
+pub fn decrypt_with_mode(
+    encrypted_data: &[u8],
+    secret_key: &BigUint,
+    mode: CipherMode,
+    iv: &[u8],
+) -> Result<Vec<u8>, DhError> {
+    decrypt_with_padding(encrypted_data, secret_key, mode, Padding::Pkcs7, iv)
+}
+
+// Encrypt raw bytes in the chosen mode and padding. With `Padding::None`, input that isn't a whole number of blocks
+// is rejected with `DhError::UnalignedInput`; that is the choice for data that is already padded, or framed by a
+// stream or AEAD layer that needs no block padding.
+pub fn encrypt_with_padding(
+    data: &[u8],
+    secret_key: &BigUint,
+    mode: CipherMode,
+    padding: Padding,
+    iv: &[u8],
+) -> Result<Vec<u8>, DhError> {
+    check_alignment(data, padding)?;
+    let key = generate_secret_key_spec(secret_key);
+    let cipher = new_cipher(mode, padding, &key, iv)?;
+    Ok(cipher.encrypt_vec(data))
+}
+
+// Decrypt data produced by `encrypt_with_padding` using the same mode, padding, secret key and IV.
+pub fn decrypt_with_padding(
+    encrypted_data: &[u8],
+    secret_key: &BigUint,
+    mode: CipherMode,
+    padding: Padding,
+    iv: &[u8],
+) -> Result<Vec<u8>, DhError> {
+    check_alignment(encrypted_data, padding)?;
+    let key = generate_secret_key_spec(secret_key);
+    let cipher = new_cipher(mode, padding, &key, iv)?;
+    cipher.decrypt_vec(encrypted_data)
+}
+
+fn check_alignment(data: &[u8], padding: Padding) -> Result<(), DhError> {
+    if padding == Padding::None && !data.len().is_multiple_of(BLOCK_LEN) {
+        return Err(DhError::UnalignedInput { length: data.len(), block_len: BLOCK_LEN });
+    }
+    Ok(())
+}
+
+// Encrypt arbitrary bytes using AES-128 with the provided secret key. This is the demo's scheme: ECB with PKCS#7
+// padding, keyed with the secret truncated to 16 bytes. For real use, derive keys with `derive_session_keys` and
+// encrypt with `seal_with_keys`; for another mode or padding, use `encrypt_with_padding`.
+pub fn encrypt_data(data: &[u8], secret_key: &BigUint) -> Vec<u8> {
+    encrypt_with_padding(data, secret_key, CipherMode::Ecb, Padding::Pkcs7, &[]).expect("ECB takes an empty IV")
+}
+
+// Decrypt data produced by `encrypt_data`. Corrupted data or the wrong key usually fails with
+// `DhError::InvalidPadding`.
+pub fn decrypt_data(encrypted_data: &[u8], secret_key: &BigUint) -> Result<Vec<u8>, DhError> {
+    decrypt_with_padding(encrypted_data, secret_key, CipherMode::Ecb, Padding::Pkcs7, &[])
+}
+
+// `encrypt_data` for text.
+pub fn encrypt_str(plain_text: &str, secret_key: &BigUint) -> Vec<u8> {
+    encrypt_data(plain_text.as_bytes(), secret_key)
+}
+
+// Decrypt text encrypted with `encrypt_str`. As `decrypt_data`, and a result that isn't UTF-8 is
+// `DhError::InvalidUtf8`.
+pub fn decrypt_str(encrypted_data: &[u8], secret_key: &BigUint) -> Result<String, DhError> {
+    String::from_utf8(decrypt_data(encrypted_data, secret_key)?).map_err(|_| DhError::InvalidUtf8)
+}
+
+// Encrypt the given plain text after padding it to a multiple of `bucket_size`, so messages of different lengths
+// within a bucket produce ciphertexts of the same length.
+pub fn encrypt_padded(plain_text: &str, secret_key: &BigUint, bucket_size: usize) -> Result<Vec<u8>, DhError> {
+    let padded = pad_to_bucket(plain_text.as_bytes(), bucket_size)?;
+    Ok(encrypt_data(&padded, secret_key))
+}
+
+// Decrypt data produced by `encrypt_padded` and strip the length-hiding padding.
+pub fn decrypt_padded(encrypted_data: &[u8], secret_key: &BigUint) -> Result<String, DhError> {
+    let padded = decrypt_data(encrypted_data, secret_key)?;
+    let plain_bytes = strip_bucket_padding(&padded)?;
+    String::from_utf8(plain_bytes).map_err(|_| DhError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::CONTROL_CHARACTER_TEXT;
+
+    // AES-128 known-answer vectors, run on the raw block cipher with no key derivation or padding, so a mistake in
+    // how the cipher is wired up shows apart from the Diffie-Hellman layer. Each is (key, plaintext block, ciphertext
+    // block): the first from FIPS-197 appendix C.1, the rest the ECB-AES128 example of NIST SP 800-38A, section F.1.1.
+    const AES_KNOWN_ANSWERS: [([u8; 16], [u8; 16], [u8; 16]); 5] = [
+        (
+            hex!("000102030405060708090a0b0c0d0e0f"),
+            hex!("00112233445566778899aabbccddeeff"),
+            hex!("69c4e0d86a7b0430d8cdb78070b4c55a"),
+        ),
+        (
+            hex!("2b7e151628aed2a6abf7158809cf4f3c"),
+            hex!("6bc1bee22e409f96e93d7e117393172a"),
+            hex!("3ad77bb40d7a3660a89ecaf32466ef97"),
+        ),
+        (
+            hex!("2b7e151628aed2a6abf7158809cf4f3c"),
+            hex!("ae2d8a571e03ac9c9eb76fac45af8e51"),
+            hex!("f5d3d58503b9699de785895a96fdbaaf"),
+        ),
+        (
+            hex!("2b7e151628aed2a6abf7158809cf4f3c"),
+            hex!("30c81c46a35ce411e5fbc1191a0a52ef"),
+            hex!("43b1cd7f598ece23881b00e3ed030688"),
+        ),
+        (
+            hex!("2b7e151628aed2a6abf7158809cf4f3c"),
+            hex!("f69f2445df4f9b17ad2b417be66c3710"),
+            hex!("7b0c785e27e8ad3f8223207104725dd4"),
+        ),
+    ];
+
+    #[test]
+    fn raw_cipher_matches_known_answers() {
+        for (key, plain_block, cipher_block) in AES_KNOWN_ANSWERS {
+            let cipher = || new_cipher(CipherMode::Ecb, Padding::None, &key, &[]).unwrap();
+            assert_eq!(cipher().encrypt_vec(&plain_block), cipher_block);
+            assert_eq!(cipher().decrypt_vec(&cipher_block).unwrap(), plain_block);
+        }
+    }
+
+    fn secret() -> impl Strategy<Value = BigUint> {
+        prop::collection::vec(any::<u8>(), 1..64).prop_map(|bytes| BigUint::from_bytes_be(&bytes))
+    }
+
+    proptest! {
+        #[test]
+        fn decrypt_data_undoes_encrypt_data(data in prop::collection::vec(any::<u8>(), 0..100), key in secret()) {
+            prop_assert_eq!(decrypt_data(&encrypt_data(&data, &key), &key).unwrap(), data);
+        }
+
+        #[test]
+        fn decrypt_str_undoes_encrypt_str(text in any::<String>(), key in secret()) {
+            prop_assert_eq!(decrypt_str(&encrypt_str(&text, &key), &key).unwrap(), text);
+        }
+
+        #[test]
+        fn every_mode_and_padding_round_trips(
+            blocks in prop::collection::vec(any::<[u8; 16]>(), 0..6),
+            tail in prop::collection::vec(any::<u8>(), 0..16),
+            mode in prop::sample::select(vec![CipherMode::Ecb, CipherMode::Cbc]),
+            padding in prop::sample::select(vec![Padding::Pkcs7, Padding::Iso7816, Padding::None]),
+            key in secret(),
+            iv: [u8; 16],
+        ) {
+            let mut data = blocks.concat();
+            if padding != Padding::None {
+                data.extend_from_slice(&tail);
+            }
+            let iv = &iv[..mode.iv_len()];
+            let encrypted = encrypt_with_padding(&data, &key, mode, padding, iv).unwrap();
+            prop_assert_eq!(decrypt_with_padding(&encrypted, &key, mode, padding, iv).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn control_characters_round_trip() {
+        let key = BigUint::from(0x1234_5678u32);
+        assert_eq!(decrypt_str(&encrypt_str(CONTROL_CHARACTER_TEXT, &key), &key).unwrap(), CONTROL_CHARACTER_TEXT);
+        let padded = encrypt_padded(CONTROL_CHARACTER_TEXT, &key, 64).unwrap();
+        assert_eq!(decrypt_padded(&padded, &key).unwrap(), CONTROL_CHARACTER_TEXT);
+        for mode in [CipherMode::Ecb, CipherMode::Cbc] {
+            let iv = [7u8; 16];
+            let iv = &iv[..mode.iv_len()];
+            let encrypted = encrypt_with_mode(CONTROL_CHARACTER_TEXT.as_bytes(), &key, mode, iv).unwrap();
+            let decrypted = decrypt_with_mode(&encrypted, &key, mode, iv).unwrap();
+            assert_eq!(decrypted, CONTROL_CHARACTER_TEXT.as_bytes(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn modes_carry_bytes_that_are_not_text() {
+        let key = BigUint::from(0x1234_5678u32);
+        let data: Vec<u8> = (0..=255u8).rev().collect();
+        for mode in [CipherMode::Ecb, CipherMode::Cbc] {
+            let iv = &[3u8; 16][..mode.iv_len()];
+            let encrypted = encrypt_with_mode(&data, &key, mode, iv).unwrap();
+            assert_eq!(decrypt_with_mode(&encrypted, &key, mode, iv).unwrap(), data, "{mode:?}");
+            assert_eq!(encrypted, encrypt_with_padding(&data, &key, mode, Padding::Pkcs7, iv).unwrap(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn each_mode_takes_only_its_iv_length() {
+        let key = [0u8; 16];
+        for mode in [CipherMode::Ecb, CipherMode::Cbc] {
+            for padding in [Padding::Pkcs7, Padding::Iso7816, Padding::None] {
+                assert!(new_cipher(mode, padding, &key, &vec![0; mode.iv_len()]).is_ok(), "{mode:?} {padding:?}");
+                for wrong_len in [0, 8, 12, 16, 32].into_iter().filter(|&len| len != mode.iv_len()) {
+                    assert!(
+                        matches!(
+                            new_cipher(mode, padding, &key, &vec![0; wrong_len]),
+                            Err(DhError::InvalidIvLength { mode: m, expected, actual })
+                                if m == mode && expected == mode.iv_len() && actual == wrong_len
+                        ),
+                        "{mode:?} {padding:?} with a {wrong_len}-byte IV"
+                    );
+                }
+            }
+        }
+        let secret = BigUint::from(7u32);
+        assert!(encrypt_with_mode(b"text", &secret, CipherMode::Cbc, &[0; 8]).is_err());
+        assert!(decrypt_with_mode(&[0; 16], &secret, CipherMode::Ecb, &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn padded_ciphertexts_hide_length_within_a_bucket() {
+        let key = BigUint::from(0x1234_5678u32);
+        let messages = ["", "hi", "a somewhat longer message", &"x".repeat(60)];
+        let encrypted: Vec<Vec<u8>> = messages.iter().map(|text| encrypt_padded(text, &key, 64).unwrap()).collect();
+        for (text, ciphertext) in messages.iter().zip(&encrypted) {
+            assert_eq!(ciphertext.len(), encrypted[0].len(), "{text:?}");
+            assert_eq!(decrypt_padded(ciphertext, &key).unwrap(), *text);
+        }
+        assert!(encrypt_padded(&"x".repeat(61), &key, 64).unwrap().len() > encrypted[0].len());
+    }
+
+    #[test]
+    fn pkcs7_takes_any_length_and_none_only_whole_blocks() {
+        let key = BigUint::from(0x1234_5678u32);
+        for mode in [CipherMode::Ecb, CipherMode::Cbc] {
+            let iv = &[3; 16][..mode.iv_len()];
+            for len in 0..=48 {
+                let data = vec![0xa5; len];
+                let padded = encrypt_with_padding(&data, &key, mode, Padding::Pkcs7, iv).unwrap();
+                assert_eq!(padded.len(), (len / BLOCK_LEN + 1) * BLOCK_LEN);
+                assert_eq!(decrypt_with_padding(&padded, &key, mode, Padding::Pkcs7, iv).unwrap(), data);
+
+                let unpadded = encrypt_with_padding(&data, &key, mode, Padding::None, iv);
+                if len % BLOCK_LEN == 0 {
+                    let unpadded = unpadded.unwrap();
+                    assert_eq!(unpadded.len(), len);
+                    assert_eq!(decrypt_with_padding(&unpadded, &key, mode, Padding::None, iv).unwrap(), data);
+                } else {
+                    let error = unpadded.unwrap_err();
+                    assert!(matches!(error, DhError::UnalignedInput { length, block_len: 16 } if length == len));
+                    let decrypted = decrypt_with_padding(&data, &key, mode, Padding::None, iv);
+                    assert!(matches!(decrypted, Err(DhError::UnalignedInput { .. })));
+                }
+            }
+        }
+    }
+}
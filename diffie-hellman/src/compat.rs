@@ -0,0 +1,101 @@
+// Fixed-length encodings for working with OpenSSL and other libraries that follow RFC 2631. There, public values and
+// agreed secrets are big-endian and padded with leading zeros to the length of the modulus, so a 2048-bit group
+// always gives 256 bytes. The crate's own wire format and `to_bytes_be` are minimal instead, so about one value in
+// 256 comes out a byte shorter. The padded secret is what `openssl pkeyutl -derive -pkeyopt dh_pad:1` prints.
+// Without `dh_pad:1`, OpenSSL 3 prints the unpadded secret, which is `as_biguint().to_bytes_be()`. The padded public
+// value is OpenSSL's encoded public key (`OSSL_PKEY_PARAM_ENCODED_PUBLIC_KEY`). For keys in PEM files, see pkcs8.rs.
+
+use alloc::vec::Vec;
+
+use num_bigint::BigUint;
+use zeroize::Zeroizing;
+
+use crate::error::{DhError, ValidationError};
+use crate::keys::{PublicKey, SharedSecret};
+use crate::params::DhParameters;
+
+// `value` big-endian, padded with leading zeros to `len` bytes. A value that is already longer comes out as it is.
+// The buffer is allocated at its full size up front, so the value is never left behind in a reallocation.
+fn to_padded(value: &BigUint, len: usize) -> Vec<u8> {
+    let bytes = Zeroizing::new(value.to_bytes_be());
+    let mut padded = Vec::with_capacity(len.max(bytes.len()));
+    padded.resize(len.saturating_sub(bytes.len()), 0);
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+impl DhParameters {
+    // The length of the modulus in bytes, which every padded value has.
+    pub fn modulus_len(&self) -> usize {
+        self.modulus().bits().div_ceil(8) as usize
+    }
+}
+
+impl PublicKey {
+    // The public value padded to the modulus length, as OpenSSL encodes it.
+    pub fn to_padded_bytes(&self, params: &DhParameters) -> Vec<u8> {
+        to_padded(self.as_biguint(), params.modulus_len())
+    }
+
+    // Parse a padded public value from OpenSSL or another RFC 2631 implementation. Anything other than exactly the
+    // modulus length is `DhError::InvalidKeyEncoding`, and values outside [2, p-2] are `ValidationError::OutOfRange`.
+    pub fn from_padded_bytes(bytes: &[u8], params: &DhParameters) -> Result<PublicKey, DhError> {
+        if bytes.len() != params.modulus_len() {
+            return Err(DhError::InvalidKeyEncoding);
+        }
+        let key = PublicKey::from_biguint(BigUint::from_bytes_be(bytes));
+        if !key.is_nontrivial(params) {
+            return Err(ValidationError::OutOfRange.into());
+        }
+        Ok(key)
+    }
+}
+
+impl SharedSecret {
+    // The agreed secret padded to the modulus length, as RFC 2631 defines ZZ and as OpenSSL derives it with
+    // `dh_pad:1`. Feed this to another library's KDF when both ends must get the same key.
+    pub fn to_padded_bytes(&self, params: &DhParameters) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(to_padded(self.as_biguint(), params.modulus_len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    #[test]
+    fn short_values_are_padded_to_the_modulus_length() {
+        let params = Group::Ffdhe2048.parameters();
+        assert_eq!(params.modulus_len(), 256);
+        let padded = PublicKey::from_biguint(BigUint::from(0x0102u32)).to_padded_bytes(&params);
+        assert_eq!(padded.len(), 256);
+        assert!(padded[..254].iter().all(|&byte| byte == 0));
+        assert_eq!(padded[254..], [1, 2]);
+        assert_eq!(PublicKey::from_padded_bytes(&padded, &params).unwrap().as_biguint(), &BigUint::from(0x0102u32));
+
+        let secret = SharedSecret::from_bytes(&[7; 3]);
+        assert_eq!(secret.to_padded_bytes(&params)[253..], [7, 7, 7]);
+        assert_eq!(secret.to_padded_bytes(&params).len(), 256);
+    }
+
+    #[test]
+    fn padded_values_of_the_wrong_length_are_refused() {
+        let params = Group::Ffdhe2048.parameters();
+        let padded = PublicKey::from_biguint(BigUint::from(5u32)).to_padded_bytes(&params);
+        for bytes in [&padded[1..], &[padded.as_slice(), &[0]].concat(), &[]] {
+            let result = PublicKey::from_padded_bytes(bytes, &params);
+            assert!(matches!(result, Err(DhError::InvalidKeyEncoding)), "{} bytes", bytes.len());
+        }
+    }
+
+    #[test]
+    fn trivial_padded_values_are_refused() {
+        let params = Group::Ffdhe2048.parameters();
+        for value in [BigUint::from(0u32), BigUint::from(1u32), params.modulus() - 1u32] {
+            let padded = to_padded(&value, params.modulus_len());
+            let result = PublicKey::from_padded_bytes(&padded, &params);
+            assert!(matches!(result, Err(DhError::InvalidPublicKey(ValidationError::OutOfRange))), "{value}");
+        }
+    }
+}
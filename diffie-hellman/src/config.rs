@@ -0,0 +1,171 @@
+// Everything a server needs to set up its side of the exchange, bundled so a deployment can be reproduced from one
+// file: the group, the cipher, the KDF hash and, for static Diffie-Hellman, a long-term private key. With the `serde`
+// feature the bundle can be loaded from and saved to any serde format, e.g. JSON or TOML. Numbers are written as hex
+// strings.
+
+use crate::error::DhError;
+use crate::kdf::HkdfHash;
+use crate::keys::PrivateKey;
+use crate::params::DhParameters;
+use crate::party::DiffieHellman;
+
+// The authenticated cipher a configured party encrypts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Cipher {
+    // AES-128-GCM, see `encrypt_gcm`.
+    #[default]
+    Aes128Gcm,
+    // AES-128-CBC with HMAC-SHA256, encrypt-then-MAC, see `seal`.
+    Aes128CbcHmac,
+}
+
+pub struct ExchangeConfig {
+    pub params: DhParameters,
+    pub cipher: Cipher,
+    // The hash `DiffieHellman::derive_key` runs HKDF with.
+    pub kdf_hash: HkdfHash,
+    // A long-term private key every party from this config uses, or `None` for a fresh key per party.
+    pub static_private_key: Option<PrivateKey>,
+}
+
+impl ExchangeConfig {
+    // A config for `params` with the default cipher and KDF hash and ephemeral keys.
+    pub fn new(params: DhParameters) -> Self {
+        ExchangeConfig { params, cipher: Cipher::default(), kdf_hash: HkdfHash::default(), static_private_key: None }
+    }
+
+    // A party set up as configured, ready for a handshake. Called once per connection, this acts as the server's
+    // session factory. The parameters go through the same checks as `DiffieHellman::new`.
+    pub fn party(&self) -> Result<DiffieHellman, DhError> {
+        let static_private_key = self.static_private_key.as_ref().map(|key| PrivateKey::from_biguint(key.0.clone()));
+        DiffieHellman::configured(self.params.clone(), self.cipher, self.kdf_hash, static_private_key)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use num_bigint::BigUint;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Cipher, ExchangeConfig};
+    use crate::kdf::HkdfHash;
+    use crate::keys::PrivateKey;
+    use crate::params::DhParameters;
+
+    // The bundle as it is written out.
+    #[derive(Serialize, Deserialize)]
+    struct ConfigFile {
+        generator: String,
+        modulus: String,
+        #[serde(default)]
+        cipher: Cipher,
+        #[serde(default)]
+        kdf_hash: HkdfHash,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        static_private_key: Option<String>,
+    }
+
+    fn parse_hex<E: de::Error>(field: &str, hex: &str) -> Result<BigUint, E> {
+        BigUint::parse_bytes(hex.as_bytes(), 16).ok_or_else(|| E::custom(format!("{} is not a hex number", field)))
+    }
+
+    impl Serialize for ExchangeConfig {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            ConfigFile {
+                generator: self.params.generator.to_str_radix(16),
+                modulus: self.params.modulus.to_str_radix(16),
+                cipher: self.cipher,
+                kdf_hash: self.kdf_hash,
+                static_private_key: self.static_private_key.as_ref().map(|key| key.0.to_str_radix(16)),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    // Loading runs the parameters through `DhParameters::new`, so an insecure group is rejected at startup.
+    impl<'de> Deserialize<'de> for ExchangeConfig {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let file = ConfigFile::deserialize(deserializer)?;
+            let generator = parse_hex("generator", &file.generator)?;
+            let modulus = parse_hex("modulus", &file.modulus)?;
+            let params = DhParameters::new(generator, modulus).map_err(de::Error::custom)?;
+            let static_private_key = match file.static_private_key {
+                Some(hex) => Some(PrivateKey::from_biguint(parse_hex("static_private_key", &hex)?)),
+                None => None,
+            };
+            Ok(ExchangeConfig { params, cipher: file.cipher, kdf_hash: file.kdf_hash, static_private_key })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    fn static_config() -> ExchangeConfig {
+        ExchangeConfig {
+            params: Group::Ffdhe2048.parameters(),
+            cipher: Cipher::Aes128CbcHmac,
+            kdf_hash: HkdfHash::Sha512,
+            static_private_key: Some(PrivateKey::from_bytes_be(&[0x42; 28])),
+        }
+    }
+
+    #[test]
+    fn bundle_round_trips_through_json() {
+        let config = static_config();
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""cipher":"aes128-cbc-hmac""#), "{json}");
+        assert!(json.contains(r#""kdf_hash":"sha512""#), "{json}");
+
+        let loaded: ExchangeConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.params, config.params);
+        assert_eq!(loaded.cipher, config.cipher);
+        assert_eq!(loaded.kdf_hash, config.kdf_hash);
+        assert_eq!(loaded.static_private_key.unwrap().0, config.static_private_key.unwrap().0);
+    }
+
+    #[test]
+    fn loaded_bundle_gives_the_configured_party() {
+        let params = Group::Ffdhe2048.parameters();
+        let json = format!(
+            r#"{{"generator": "2", "modulus": "{}", "cipher": "aes128-cbc-hmac", "kdf_hash": "sha512",
+                "static_private_key": "{}"}}"#,
+            params.modulus.to_str_radix(16),
+            "42".repeat(28),
+        );
+        let config: ExchangeConfig = serde_json::from_str(&json).unwrap();
+        let mut server = config.party().unwrap();
+        assert_eq!(server.params(), &params);
+        assert_eq!(server.public_key(), &PrivateKey::from_bytes_be(&[0x42; 28]).public_key(&params));
+
+        // A peer with the same cipher and hash interoperates; one with the defaults can't read the messages.
+        let mut client = static_config();
+        client.static_private_key = None;
+        let mut client = client.party().unwrap();
+        let mut default_client = DiffieHellman::new(params).unwrap();
+        let server_public = server.public_key().clone();
+        server.agree(&client.public_key().clone()).unwrap();
+        client.agree(&server_public).unwrap();
+        default_client.agree(&server_public).unwrap();
+        assert_eq!(server.derive_key("test", 64).unwrap(), client.derive_key("test", 64).unwrap());
+        let message = server.encrypt("configured").unwrap();
+        assert_eq!(client.decrypt(&message).unwrap(), "configured");
+        assert!(default_client.decrypt(&message).is_err());
+    }
+
+    #[test]
+    fn bundle_fields_default_and_insecure_groups_are_refused() {
+        let modulus = Group::Ffdhe2048.parameters().modulus.to_str_radix(16);
+        let config: ExchangeConfig =
+            serde_json::from_str(&format!(r#"{{"generator": "2", "modulus": "{modulus}"}}"#)).unwrap();
+        assert_eq!(config.cipher, Cipher::Aes128Gcm);
+        assert_eq!(config.kdf_hash, HkdfHash::Sha256);
+        assert!(config.static_private_key.is_none());
+
+        assert!(serde_json::from_str::<ExchangeConfig>(r#"{"generator": "5", "modulus": "17"}"#).is_err());
+        assert!(serde_json::from_str::<ExchangeConfig>(r#"{"generator": "2", "modulus": "xyz"}"#).is_err());
+    }
+}
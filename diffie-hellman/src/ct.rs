@@ -0,0 +1,207 @@
+// Modular exponentiation with a secret exponent, for the private-key operations: computing the public key and agreeing
+// on the secret. `BigUint::modpow` skips work for zero bits and sizes its loops to the exponent, so its running time
+// leaks bits of the private key. With the `ct` feature, on by default, the exponentiation runs in Montgomery form on
+// crypto-bigint's fixed-width integers instead, so it takes the same steps for every exponent of the same width.
+// Every exponent is padded to the width its caller passes, the width of the range it was drawn from, so only that
+// width and the group size show in the timing. This costs speed: a 2048-bit exponentiation takes roughly fifteen times
+// as long as with `modpow`, which short exponents of `DhParameters::recommended_exponent_bits` make up for.
+//
+// Only the exponent is protected. The base and modulus are public values, and converting to and from `BigUint` is
+// not constant time in their value. Even moduli, which no real group has, fall back to `BigUint::modpow`.
+//
+// `ModulusContext` keeps the Montgomery constants of one modulus, so code that exponentiates many times under the
+// same modulus sets them up once. `FixedBase` goes further for a base that never changes, such as the generator: it
+// stores the base raised to every 4-bit digit at every digit position, so an exponentiation is one multiplication per
+// digit and no squarings. Each digit's entry is picked by reading every entry in its row and keeping the matching one
+// with a constant-time select, so which entry was used doesn't show in the memory access pattern either.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use num_bigint::BigUint;
+use zeroize::Zeroizing;
+
+// Bits of the exponent per `FixedBase` digit, and the number of table entries per digit.
+const WINDOW_BITS: u64 = 4;
+const WINDOW_ENTRIES: usize = 1 << WINDOW_BITS;
+
+// The exponent's bytes, least significant first, padded with zeros to `len`.
+fn exponent_bytes_le(exponent: &BigUint, len: usize) -> Zeroizing<Vec<u8>> {
+    let digits = Zeroizing::new(exponent.to_bytes_le());
+    let mut bytes = Zeroizing::new(vec![0u8; len.max(digits.len())]);
+    bytes[..digits.len()].copy_from_slice(&digits);
+    bytes
+}
+
+// Digit `index` of an exponent in `exponent_bytes_le` form. The position is public; only the value is secret.
+fn digit(bytes: &[u8], index: usize) -> u8 {
+    (bytes[index / 2] >> (WINDOW_BITS as usize * (index % 2))) & 0x0f
+}
+
+#[cfg(feature = "ct")]
+mod montgomery {
+    use alloc::vec::Vec;
+
+    use crypto_bigint::modular::{BoxedMontyForm, BoxedMontyParams};
+    use crypto_bigint::{BoxedUint, ConstantTimeSelect, Odd};
+    use num_bigint::BigUint;
+    use subtle::ConstantTimeEq;
+    use zeroize::{Zeroize, Zeroizing};
+
+    use super::{digit, exponent_bytes_le, WINDOW_BITS, WINDOW_ENTRIES};
+
+    fn to_boxed(value: &BigUint, bits: u32) -> BoxedUint {
+        let bytes = Zeroizing::new(value.to_bytes_be());
+        BoxedUint::from_be_slice(&bytes, bits).expect("value fits the chosen precision")
+    }
+
+    // The Montgomery constants for one modulus, or none for an even modulus.
+    pub(crate) struct ModulusContext {
+        modulus: BigUint,
+        params: Option<BoxedMontyParams>,
+    }
+
+    impl ModulusContext {
+        pub(crate) fn new(modulus: &BigUint) -> Self {
+            let odd_modulus = Odd::new(to_boxed(modulus, modulus.bits() as u32));
+            // The modulus is public, so its Montgomery constants can be set up with the faster variable-time routine.
+            let params = Option::<Odd<BoxedUint>>::from(odd_modulus).map(BoxedMontyParams::new_vartime);
+            ModulusContext { modulus: modulus.clone(), params }
+        }
+
+        pub(crate) fn modpow_secret(&self, base: &BigUint, exponent: &BigUint, exponent_width: u64) -> BigUint {
+            let Some(params) = &self.params else {
+                return base.modpow(exponent, &self.modulus);
+            };
+            // Precision for the exponent: `exponent_width`, or more for an oversized exponent, whose length then shows.
+            let exponent_bits = exponent_width.max(exponent.bits()).max(1) as u32;
+            let base = BoxedMontyForm::new(to_boxed(&(base % &self.modulus), params.bits_precision()), params.clone());
+            // The exponent is always secret and the result may be (an agreed secret), so the fixed-width copies are
+            // wiped.
+            let mut exponent = to_boxed(exponent, exponent_bits);
+            let mut result = base.pow(&exponent).retrieve();
+            let result_bytes = Zeroizing::new(result.to_be_bytes());
+            exponent.zeroize();
+            result.zeroize();
+            BigUint::from_bytes_be(&result_bytes)
+        }
+    }
+
+    // Every digit power of one base, in Montgomery form: `rows[i][j]` is base^(j * 16^i).
+    pub(crate) struct FixedBase {
+        params: Option<BoxedMontyParams>,
+        rows: Vec<Vec<BoxedUint>>,
+    }
+
+    impl FixedBase {
+        pub(crate) fn new(base: &BigUint, exponent_bits: u64, context: &ModulusContext) -> Self {
+            let Some(params) = &context.params else {
+                return FixedBase { params: None, rows: Vec::new() };
+            };
+            let digits = exponent_bits.div_ceil(WINDOW_BITS) as usize;
+            let one = BoxedMontyForm::one(params.clone());
+            let mut power =
+                BoxedMontyForm::new(to_boxed(&(base % &context.modulus), params.bits_precision()), params.clone());
+            let mut rows = Vec::with_capacity(digits);
+            for _ in 0..digits {
+                let mut row = Vec::with_capacity(WINDOW_ENTRIES);
+                let mut entry = one.clone();
+                for _ in 0..WINDOW_ENTRIES {
+                    row.push(entry.as_montgomery().clone());
+                    entry = entry.mul(&power);
+                }
+                // `entry` has gone one step past the row's last entry, to base^(16 * 16^i), the next row's step.
+                power = entry;
+                rows.push(row);
+            }
+            FixedBase { params: Some(params.clone()), rows }
+        }
+
+        // base^exponent, or none if the exponent has more digits than the table or the modulus is even.
+        pub(crate) fn pow(&self, exponent: &BigUint) -> Option<BigUint> {
+            let params = self.params.as_ref()?;
+            if exponent.bits() > self.rows.len() as u64 * WINDOW_BITS {
+                return None;
+            }
+            let bytes = exponent_bytes_le(exponent, self.rows.len().div_ceil(2));
+            let mut result = BoxedMontyForm::one(params.clone());
+            for (index, row) in self.rows.iter().enumerate() {
+                let mut digit = digit(&bytes, index);
+                let mut selected = row[0].clone();
+                for (value, entry) in row.iter().enumerate().skip(1) {
+                    selected.ct_assign(entry, (value as u8).ct_eq(&digit));
+                }
+                result = result.mul(&BoxedMontyForm::from_montgomery(selected, params.clone()));
+                digit.zeroize();
+            }
+            Some(BigUint::from_bytes_be(&result.retrieve().to_be_bytes()))
+        }
+    }
+}
+
+// Without the `ct` feature: the plain, variable-time arithmetic.
+#[cfg(not(feature = "ct"))]
+mod montgomery {
+    use alloc::vec::Vec;
+
+    use num_bigint::BigUint;
+
+    use super::{digit, exponent_bytes_le, WINDOW_BITS, WINDOW_ENTRIES};
+
+    pub(crate) struct ModulusContext {
+        modulus: BigUint,
+    }
+
+    impl ModulusContext {
+        pub(crate) fn new(modulus: &BigUint) -> Self {
+            ModulusContext { modulus: modulus.clone() }
+        }
+
+        pub(crate) fn modpow_secret(&self, base: &BigUint, exponent: &BigUint, _exponent_width: u64) -> BigUint {
+            base.modpow(exponent, &self.modulus)
+        }
+    }
+
+    pub(crate) struct FixedBase {
+        modulus: BigUint,
+        rows: Vec<Vec<BigUint>>,
+    }
+
+    impl FixedBase {
+        pub(crate) fn new(base: &BigUint, exponent_bits: u64, context: &ModulusContext) -> Self {
+            let modulus = &context.modulus;
+            let digits = exponent_bits.div_ceil(WINDOW_BITS) as usize;
+            let mut power = base % modulus;
+            let mut rows = Vec::with_capacity(digits);
+            for _ in 0..digits {
+                let mut row = Vec::with_capacity(WINDOW_ENTRIES);
+                let mut entry = BigUint::from(1u32) % modulus;
+                for _ in 0..WINDOW_ENTRIES {
+                    row.push(entry.clone());
+                    entry = entry * &power % modulus;
+                }
+                power = entry;
+                rows.push(row);
+            }
+            FixedBase { modulus: modulus.clone(), rows }
+        }
+
+        pub(crate) fn pow(&self, exponent: &BigUint) -> Option<BigUint> {
+            if exponent.bits() > self.rows.len() as u64 * WINDOW_BITS {
+                return None;
+            }
+            let bytes = exponent_bytes_le(exponent, self.rows.len().div_ceil(2));
+            let result = self.rows.iter().enumerate().fold(BigUint::from(1u32) % &self.modulus, |result, (index, row)| {
+                result * &row[digit(&bytes, index) as usize] % &self.modulus
+            });
+            Some(result)
+        }
+    }
+}
+
+pub(crate) use montgomery::{FixedBase, ModulusContext};
+
+// One exponentiation with a secret exponent, for code that doesn't keep a `ModulusContext`.
+pub(crate) fn modpow_secret(base: &BigUint, exponent: &BigUint, exponent_width: u64, modulus: &BigUint) -> BigUint {
+    ModulusContext::new(modulus).modpow_secret(base, exponent, exponent_width)
+}
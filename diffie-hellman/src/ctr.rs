@@ -0,0 +1,155 @@
+// AES-128 in CTR mode, with a choice of how each message's IV is produced:
+// - Random: a fresh 16-byte IV is generated per message and sent in front of the ciphertext.
+// - Derived: the IV is built from a per-session salt and a message counter, so only the 4-byte counter has to be sent.
+//
+// Derived IVs are laid out as salt (8 bytes) || message counter (4 bytes) || block counter (4 bytes, starting at 0).
+// CTR mode is only safe while no (key, IV) pair is ever used twice. With derived IVs that means a salt must never be
+// reused under the same key, and a session must stop at 2^32 messages; `encrypt` refuses to go past either limit
+// rather than wrapping. Messages are also capped at 2^32 blocks so the block counter can't run into the next message.
+// CTR provides no integrity on its own: pair it with a MAC or use the GCM session instead.
+
+use aes::cipher::{NewCipher, StreamCipher};
+use aes::Aes128Ctr;
+use num_bigint::BigUint;
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::cipher::generate_secret_key_spec;
+use crate::error::DhError;
+
+// Length of the salt used for derived IVs.
+pub const CTR_SALT_LEN: usize = 8;
+
+const IV_LEN: usize = 16;
+const COUNTER_LEN: usize = 4;
+const MAX_MESSAGE_BLOCKS: u64 = 1 << 32;
+
+// How `CtrCipher` produces the IV for each message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtrIvMode {
+    // A random 16-byte IV per message, prepended to the ciphertext.
+    Random,
+    // salt || counter, with only the 4-byte counter prepended. Both parties must use the same salt.
+    Derived { salt: [u8; CTR_SALT_LEN] },
+}
+
+pub struct CtrCipher {
+    key: Zeroizing<[u8; 16]>,
+    iv_mode: CtrIvMode,
+    next_counter: u32,
+    exhausted: bool,
+}
+
+impl CtrCipher {
+    pub fn new(secret_key: &BigUint, iv_mode: CtrIvMode) -> Self {
+        CtrCipher { key: generate_secret_key_spec(secret_key), iv_mode, next_counter: 0, exhausted: false }
+    }
+
+    // Encrypt one message, prefixed by its IV (random mode) or counter (derived mode).
+    pub fn encrypt(&mut self, plain_bytes: &[u8]) -> Result<Vec<u8>, DhError> {
+        if plain_bytes.len() as u64 > MAX_MESSAGE_BLOCKS * 16 {
+            return Err(DhError::MessageTooLong);
+        }
+        let (mut message, iv) = match self.iv_mode {
+            CtrIvMode::Random => {
+                let mut iv = [0u8; IV_LEN];
+                rand::thread_rng().fill_bytes(&mut iv);
+                (iv.to_vec(), iv)
+            }
+            CtrIvMode::Derived { salt } => {
+                if self.exhausted {
+                    return Err(DhError::NonceExhausted);
+                }
+                let counter = self.next_counter;
+                match counter.checked_add(1) {
+                    Some(next) => self.next_counter = next,
+                    None => self.exhausted = true,
+                }
+                (counter.to_be_bytes().to_vec(), derived_iv(&salt, counter))
+            }
+        };
+        let start = message.len();
+        message.extend_from_slice(plain_bytes);
+        self.apply_keystream(&iv, &mut message[start..]);
+        Ok(message)
+    }
+
+    // Decrypt a message produced by `encrypt` with the same key and IV mode.
+    pub fn decrypt(&self, message: &[u8]) -> Result<Vec<u8>, DhError> {
+        let (iv, ciphertext) = match self.iv_mode {
+            CtrIvMode::Random => {
+                if message.len() < IV_LEN {
+                    return Err(DhError::DecryptionFailed);
+                }
+                let (iv, ciphertext) = message.split_at(IV_LEN);
+                (iv.try_into().expect("IV is 16 bytes"), ciphertext)
+            }
+            CtrIvMode::Derived { salt } => {
+                if message.len() < COUNTER_LEN {
+                    return Err(DhError::DecryptionFailed);
+                }
+                let (counter, ciphertext) = message.split_at(COUNTER_LEN);
+                let counter = u32::from_be_bytes(counter.try_into().expect("counter is 4 bytes"));
+                (derived_iv(&salt, counter), ciphertext)
+            }
+        };
+        let mut plain_bytes = ciphertext.to_vec();
+        self.apply_keystream(&iv, &mut plain_bytes);
+        Ok(plain_bytes)
+    }
+
+    fn apply_keystream(&self, iv: &[u8; IV_LEN], data: &mut [u8]) {
+        let mut cipher = Aes128Ctr::new(&(*self.key).into(), iv.into());
+        cipher.apply_keystream(data);
+    }
+}
+
+// salt || message counter || zeroed block counter.
+fn derived_iv(salt: &[u8; CTR_SALT_LEN], counter: u32) -> [u8; IV_LEN] {
+    let mut iv = [0u8; IV_LEN];
+    iv[..CTR_SALT_LEN].copy_from_slice(salt);
+    iv[CTR_SALT_LEN..CTR_SALT_LEN + COUNTER_LEN].copy_from_slice(&counter.to_be_bytes());
+    iv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: [u8; CTR_SALT_LEN] = *b"ctr salt";
+
+    #[test]
+    fn derived_ivs_are_distinct_and_decrypt() {
+        let key = BigUint::from(0x1234_5678u32);
+        let mut sender = CtrCipher::new(&key, CtrIvMode::Derived { salt: SALT });
+        let receiver = CtrCipher::new(&key, CtrIvMode::Derived { salt: SALT });
+        let first = sender.encrypt(b"same text").unwrap();
+        let second = sender.encrypt(b"same text").unwrap();
+        assert_eq!(&first[..COUNTER_LEN], &0u32.to_be_bytes());
+        assert_eq!(&second[..COUNTER_LEN], &1u32.to_be_bytes());
+        assert_eq!(first.len(), COUNTER_LEN + b"same text".len());
+        assert_ne!(derived_iv(&SALT, 0), derived_iv(&SALT, 1));
+        assert_ne!(first[COUNTER_LEN..], second[COUNTER_LEN..]);
+        assert_eq!(receiver.decrypt(&second).unwrap(), b"same text");
+        assert_eq!(receiver.decrypt(&first).unwrap(), b"same text");
+    }
+
+    #[test]
+    fn derived_counter_stops_instead_of_wrapping() {
+        let mut cipher = CtrCipher::new(&BigUint::from(7u32), CtrIvMode::Derived { salt: SALT });
+        cipher.next_counter = u32::MAX;
+        let last = cipher.encrypt(b"last").unwrap();
+        assert_eq!(&last[..COUNTER_LEN], &u32::MAX.to_be_bytes());
+        assert!(matches!(cipher.encrypt(b"one more"), Err(DhError::NonceExhausted)));
+    }
+
+    #[test]
+    fn random_ivs_round_trip() {
+        let key = BigUint::from(0x1234_5678u32);
+        let mut cipher = CtrCipher::new(&key, CtrIvMode::Random);
+        let message = cipher.encrypt(b"random iv").unwrap();
+        assert_eq!(message.len(), IV_LEN + b"random iv".len());
+        assert_eq!(cipher.decrypt(&message).unwrap(), b"random iv");
+        assert!(matches!(cipher.decrypt(&message[..IV_LEN - 1]), Err(DhError::DecryptionFailed)));
+    }
+}
@@ -0,0 +1,298 @@
+// Securing datagram traffic, e.g. game state or telemetry over UDP, where packets are lost, duplicated and reordered
+// and there is no stream to frame. Every packet is one `HandshakeMessage`, so a receiver can tell handshake packets
+// from data by their type.
+//
+// The handshake is one round trip that survives loss. The initiator sends a `ClientHello` with its public key, and
+// sends the same bytes again until a reply arrives. The responder answers with a `ServerHello` carrying its public
+// key and a random salt. It keeps that reply and sends it again, unchanged, whenever the same hello turns up again,
+// because the first reply may have been the packet that got lost.
+//
+// After that, every data packet is an `EncryptedData` message that stands on its own: it carries its counter in the
+// clear as an explicit nonce, so it decrypts whatever arrived before it. Each direction has its own key and a
+// `ReplayWindow` over the counters. Duplicates, and packets that fall behind the window, are rejected.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::handshake_message::{
+    negotiate_version, HandshakeMessage, WireMessage, EXTENSION_SUPPORTED_VERSIONS, SUPPORTED_VERSIONS,
+};
+use crate::kdf::derive_key;
+use crate::keys::{generate_keypair, validate_public_key, PrivateKey, PublicKey, SharedSecret};
+use crate::nonce::{generate_session_salt, NonceSequence};
+use crate::params::DhParameters;
+use crate::replay::ReplayWindow;
+use crate::session::Role;
+
+// The key and nonce sequence for packets sent by `role`.
+fn direction(secret: &SharedSecret, salt: &[u8], role: Role) -> (Aes128Gcm, NonceSequence) {
+    let key = Zeroizing::new(derive_key(secret.as_biguint(), &format!("{} datagram encryption key", role.label()), 16));
+    let cipher = Aes128Gcm::new_from_slice(&key).expect("derived key is 16 bytes");
+    (cipher, NonceSequence::new(&[salt, role.label().as_bytes()].concat()))
+}
+
+// The public key in a hello, checked against the group.
+fn hello_public_key(public_key: &[u8], params: &DhParameters) -> Result<PublicKey, DhError> {
+    let public_key = PublicKey::from_wire_bytes(public_key, params)?;
+    validate_public_key(&public_key, params)?;
+    Ok(public_key)
+}
+
+// Encrypts and decrypts the data packets of one datagram association.
+pub struct DatagramChannel {
+    send_cipher: Aes128Gcm,
+    send_nonces: NonceSequence,
+    send_counter: u64,
+    receive_cipher: Aes128Gcm,
+    receive_nonces: NonceSequence,
+    replay_window: ReplayWindow,
+}
+
+impl DatagramChannel {
+    // A channel from a secret and salt agreed some other way. Both ends pass the same secret and salt, and opposite
+    // roles.
+    pub fn new(secret: &SharedSecret, salt: &[u8], role: Role) -> Self {
+        let (send_cipher, send_nonces) = direction(secret, salt, role);
+        let (receive_cipher, receive_nonces) = direction(secret, salt, role.peer());
+        DatagramChannel {
+            send_cipher,
+            send_nonces,
+            send_counter: 0,
+            receive_cipher,
+            receive_nonces,
+            replay_window: ReplayWindow::new(),
+        }
+    }
+
+    // Encrypt one packet.
+    pub fn seal(&mut self, plain_bytes: &[u8]) -> Result<Vec<u8>, DhError> {
+        let counter = self.send_counter;
+        let nonce = self.send_nonces.next_nonce()?;
+        let ciphertext = self
+            .send_cipher
+            .encrypt(Nonce::from_slice(&nonce), plain_bytes)
+            .map_err(|_| DhError::EncryptionFailed)?;
+        self.send_counter += 1;
+        Ok(HandshakeMessage::EncryptedData { counter, ciphertext }.encode())
+    }
+
+    // Verify and decrypt one packet, in any order. A packet that isn't `EncryptedData` is `DhError::MalformedMessage`,
+    // e.g. a retransmitted hello arriving late, which can simply be dropped. A duplicate is
+    // `DhError::ReplayedMessage`, one behind the replay window `DhError::StaleMessage`, and a forged or corrupted one
+    // `DhError::DecryptionFailed`.
+    pub fn open(&mut self, packet: &[u8]) -> Result<Vec<u8>, DhError> {
+        let HandshakeMessage::EncryptedData { counter, ciphertext } = HandshakeMessage::decode(packet)? else {
+            return Err(DhError::MalformedMessage);
+        };
+        self.replay_window.check(counter)?;
+        let plain_bytes = self
+            .receive_cipher
+            .decrypt(Nonce::from_slice(&self.receive_nonces.nonce_for(counter)), ciphertext.as_slice())
+            .map_err(|_| DhError::DecryptionFailed)?;
+        self.replay_window.accept(counter);
+        Ok(plain_bytes)
+    }
+}
+
+// The initiator's side of the datagram handshake.
+pub struct DatagramInitiator {
+    params: DhParameters,
+    private_key: PrivateKey,
+    hello: Vec<u8>,
+}
+
+impl DatagramInitiator {
+    pub fn new(params: &DhParameters) -> Result<Self, DhError> {
+        let (private_key, public_key) = generate_keypair(params)?;
+        let hello = HandshakeMessage::ClientHello { public_key: public_key.to_wire_bytes(), capabilities: 0 };
+        let hello = WireMessage::new(hello, HandshakeMessage::timestamp_now())
+            .with_extension(EXTENSION_SUPPORTED_VERSIONS, SUPPORTED_VERSIONS.to_vec())
+            .encode();
+        Ok(DatagramInitiator { params: params.clone(), private_key, hello })
+    }
+
+    // The hello packet. Send it, and send it again each time a reply fails to arrive in time.
+    pub fn hello(&self) -> &[u8] {
+        &self.hello
+    }
+
+    // Take the responder's reply and set up the channel. A packet that isn't a `ServerHello` is
+    // `DhError::MalformedMessage`, and a bad public key fails validation. Either way the initiator is left as it was,
+    // so it can go on waiting for the genuine reply.
+    pub fn finish(&self, reply: &[u8]) -> Result<DatagramChannel, DhError> {
+        let HandshakeMessage::ServerHello { public_key, salt } = HandshakeMessage::decode(reply)? else {
+            return Err(DhError::MalformedMessage);
+        };
+        let peer_public = hello_public_key(&public_key, &self.params)?;
+        let secret = self.private_key.agree(&peer_public, &self.params);
+        Ok(DatagramChannel::new(&secret, &salt, Role::Initiator))
+    }
+}
+
+// The responder's record of one handshake, kept to answer retransmitted hellos.
+pub struct DatagramResponder {
+    hello: Vec<u8>,
+    reply: Vec<u8>,
+}
+
+impl DatagramResponder {
+    // Answer a hello packet with a fresh keypair and salt, giving the record of the handshake and the channel. Send
+    // `reply`, and keep the record next to the channel for as long as the peer may still retransmit. A packet that
+    // isn't a `ClientHello` is `DhError::MalformedMessage`, and one from a peer that speaks none of our protocol
+    // versions `DhError::ProtocolVersionMismatch`.
+    pub fn respond(params: &DhParameters, hello: &[u8]) -> Result<(DatagramResponder, DatagramChannel), DhError> {
+        let hello_message = WireMessage::decode(hello)?;
+        if let Some(versions) = hello_message.extension(EXTENSION_SUPPORTED_VERSIONS) {
+            negotiate_version(versions)?;
+        }
+        let HandshakeMessage::ClientHello { public_key, .. } = hello_message.message else {
+            return Err(DhError::MalformedMessage);
+        };
+        let peer_public = hello_public_key(&public_key, params)?;
+        let (private_key, public_key) = generate_keypair(params)?;
+        let salt = generate_session_salt();
+        let secret = private_key.agree(&peer_public, params);
+        let reply =
+            HandshakeMessage::ServerHello { public_key: public_key.to_wire_bytes(), salt: salt.to_vec() }.encode();
+        let channel = DatagramChannel::new(&secret, &salt, Role::Responder);
+        Ok((DatagramResponder { hello: hello.to_vec(), reply }, channel))
+    }
+
+    // The reply to send, the first time and on every retransmission.
+    pub fn reply(&self) -> &[u8] {
+        &self.reply
+    }
+
+    // Whether `packet` is a retransmission of the hello this handshake answered, to be met with `reply` again rather
+    // than a new handshake.
+    pub fn is_retransmission(&self, packet: &[u8]) -> bool {
+        packet == self.hello.as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+    use crate::handshake_message::PROTOCOL_VERSION;
+    use crate::replay::WINDOW_SIZE;
+
+    // A 31-bit safe prime. Every hello's key is checked with `validate_public_key`, which tests the modulus for being a
+    // safe prime, and that takes seconds for the standard groups.
+    fn toy_group() -> DhParameters {
+        DhParameters::from_u64_allow_insecure(2, 2147483783).unwrap()
+    }
+
+    fn associated_pair() -> (DatagramChannel, DatagramChannel) {
+        let params = toy_group();
+        let initiator = DatagramInitiator::new(&params).unwrap();
+        let (responder, responder_channel) = DatagramResponder::respond(&params, initiator.hello()).unwrap();
+        (initiator.finish(responder.reply()).unwrap(), responder_channel)
+    }
+
+    #[test]
+    fn handshake_survives_a_lost_reply() {
+        let params = toy_group();
+        let initiator = DatagramInitiator::new(&params).unwrap();
+        let (responder, mut responder_channel) = DatagramResponder::respond(&params, initiator.hello()).unwrap();
+        // The reply is lost, so the initiator sends its hello again and gets the same reply back.
+        let retransmitted = initiator.hello().to_vec();
+        assert!(responder.is_retransmission(&retransmitted));
+        assert!(!responder.is_retransmission(DatagramInitiator::new(&params).unwrap().hello()));
+        let mut initiator_channel = initiator.finish(responder.reply()).unwrap();
+
+        let packet = initiator_channel.seal(b"state update").unwrap();
+        assert_eq!(responder_channel.open(&packet).unwrap(), b"state update");
+        let packet = responder_channel.seal(b"ack").unwrap();
+        assert_eq!(initiator_channel.open(&packet).unwrap(), b"ack");
+    }
+
+    #[test]
+    fn wrong_packets_during_the_handshake_are_refused() {
+        let params = toy_group();
+        let initiator = DatagramInitiator::new(&params).unwrap();
+        assert!(matches!(initiator.finish(initiator.hello()), Err(DhError::MalformedMessage)));
+        let (responder, _) = DatagramResponder::respond(&params, initiator.hello()).unwrap();
+        assert!(matches!(DatagramResponder::respond(&params, responder.reply()), Err(DhError::MalformedMessage)));
+        // The initiator was left waiting, and the genuine reply still works.
+        initiator.finish(responder.reply()).unwrap();
+    }
+
+    #[test]
+    fn hellos_carry_the_versions_their_sender_speaks() {
+        let params = toy_group();
+        let initiator = DatagramInitiator::new(&params).unwrap();
+        let hello = WireMessage::decode(initiator.hello()).unwrap();
+        assert_eq!(hello.extension(EXTENSION_SUPPORTED_VERSIONS), Some(SUPPORTED_VERSIONS));
+        assert!(hello.timestamp > 0);
+
+        // A peer that speaks only other versions is turned away before any key is agreed.
+        let foreign = WireMessage::new(hello.message.clone(), hello.timestamp)
+            .with_extension(EXTENSION_SUPPORTED_VERSIONS, vec![PROTOCOL_VERSION + 1]);
+        let result = DatagramResponder::respond(&params, &foreign.encode());
+        assert!(matches!(result, Err(DhError::ProtocolVersionMismatch { version }) if version == PROTOCOL_VERSION + 1));
+
+        // A hello without the list, or with extensions this end doesn't know, is still answered.
+        let bare = WireMessage::new(hello.message.clone(), 0);
+        DatagramResponder::respond(&params, &bare.encode()).unwrap();
+        let extended = hello.clone().with_extension(0xbeef, vec![1, 2, 3]);
+        let (responder, _) = DatagramResponder::respond(&params, &extended.encode()).unwrap();
+        initiator.finish(responder.reply()).unwrap();
+
+        let mut newer = initiator.hello().to_vec();
+        newer[2] = PROTOCOL_VERSION + 1;
+        let result = DatagramResponder::respond(&params, &newer);
+        assert!(matches!(result, Err(DhError::ProtocolVersionMismatch { version }) if version == PROTOCOL_VERSION + 1));
+    }
+
+    #[test]
+    fn packets_open_in_any_order_but_only_once() {
+        let (mut sender, mut receiver) = associated_pair();
+        let packets: Vec<_> = (0..5u8).map(|i| sender.seal(&[i]).unwrap()).collect();
+        for i in [4, 0, 2, 1, 3] {
+            assert_eq!(receiver.open(&packets[i]).unwrap(), [i as u8]);
+        }
+        assert!(matches!(receiver.open(&packets[2]), Err(DhError::ReplayedMessage { counter: 2 })));
+
+        let mut tampered = sender.seal(b"late").unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(receiver.open(&tampered), Err(DhError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn packets_behind_the_window_are_stale() {
+        let (mut sender, mut receiver) = associated_pair();
+        let first = sender.seal(b"first").unwrap();
+        for _ in 0..WINDOW_SIZE {
+            receiver.open(&sender.seal(b"later").unwrap()).unwrap();
+        }
+        assert!(matches!(receiver.open(&first), Err(DhError::StaleMessage { counter: 0 })));
+    }
+
+    #[test]
+    fn handshake_and_data_over_udp() {
+        let params = toy_group();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        let mut buffer = [0u8; 2048];
+
+        let initiator = DatagramInitiator::new(&params).unwrap();
+        client.send(initiator.hello()).unwrap();
+        let (len, client_addr) = server.recv_from(&mut buffer).unwrap();
+        let (responder, mut server_channel) = DatagramResponder::respond(&params, &buffer[..len]).unwrap();
+        server.send_to(responder.reply(), client_addr).unwrap();
+        let len = client.recv(&mut buffer).unwrap();
+        let mut client_channel = initiator.finish(&buffer[..len]).unwrap();
+
+        client.send(&client_channel.seal(b"ping").unwrap()).unwrap();
+        let (len, _) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(server_channel.open(&buffer[..len]).unwrap(), b"ping");
+        server.send_to(&server_channel.seal(b"pong").unwrap(), client_addr).unwrap();
+        let len = client.recv(&mut buffer).unwrap();
+        assert_eq!(client_channel.open(&buffer[..len]).unwrap(), b"pong");
+    }
+}
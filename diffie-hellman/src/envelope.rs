@@ -0,0 +1,251 @@
+// An AES-128-GCM message from `encrypt_gcm` taken apart into its nonce, ciphertext and tag, for formats that store
+// them as separate fields. `to_bytes` puts them back together in the layout `decrypt_gcm` expects, so an envelope
+// and a plain GCM message can be converted into each other freely.
+//
+// A plain envelope can be captured and replayed, and still decrypts. A `SequencedMessage` adds a direction flag and a
+// sequence number that count up from zero in each direction. Both go into the GCM associated data, so neither can be
+// changed without the tag failing. An `EnvelopeOpener` keeps a replay window over the sequence numbers. It rejects
+// any message it has already accepted, any message too far behind the newest one, and any message marked as sent in
+// its own direction, which would be one of our own messages reflected back.
+//
+// Sequenced layout: direction (1 byte: 0 initiator, 1 responder) || sequence (8 bytes, big-endian) || nonce ||
+// ciphertext || tag.
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use num_bigint::BigUint;
+use rand::RngCore;
+
+use crate::error::DhError;
+use crate::gcm::{decrypt_gcm, encrypt_gcm, gcm_cipher, TAG_LEN};
+use crate::nonce::NONCE_LEN;
+use crate::replay::ReplayWindow;
+use crate::session::Role;
+
+const SEQUENCED_HEADER_LEN: usize = 1 + 8;
+const SEQUENCED_LABEL: &[u8] = b"diffie-hellman sequenced envelope";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedMessage {
+    pub nonce: [u8; NONCE_LEN],
+    pub ciphertext: Vec<u8>,
+    pub tag: [u8; TAG_LEN],
+}
+
+impl EncryptedMessage {
+    // Encrypt and authenticate the given bytes with a key derived from the secret, as `encrypt_gcm` does.
+    pub fn seal(plain_bytes: &[u8], secret_key: &BigUint) -> Result<Self, DhError> {
+        Self::from_bytes(&encrypt_gcm(plain_bytes, secret_key)?)
+    }
+
+    // Verify and decrypt the message, as `decrypt_gcm` does.
+    pub fn open(&self, secret_key: &BigUint) -> Result<Vec<u8>, DhError> {
+        decrypt_gcm(&self.to_bytes(), secret_key)
+    }
+
+    // The message in the layout of gcm.rs: nonce || ciphertext || tag.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(NONCE_LEN + self.ciphertext.len() + TAG_LEN);
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes.extend_from_slice(&self.tag);
+        bytes
+    }
+
+    // Split a message in the layout of gcm.rs. Anything too short to hold a nonce and a tag is
+    // `DhError::MalformedMessage`; whether the rest is genuine is only found out by `open`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DhError> {
+        if bytes.len() < NONCE_LEN + TAG_LEN {
+            return Err(DhError::MalformedMessage);
+        }
+        let (nonce, rest) = bytes.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        Ok(EncryptedMessage {
+            nonce: nonce.try_into().expect("split off NONCE_LEN bytes"),
+            ciphertext: ciphertext.to_vec(),
+            tag: tag.try_into().expect("split off TAG_LEN bytes"),
+        })
+    }
+}
+
+// An envelope numbered for replay protection, from `EnvelopeSealer::seal`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequencedMessage {
+    // The role of the sender.
+    pub direction: Role,
+    pub sequence: u64,
+    pub message: EncryptedMessage,
+}
+
+fn direction_byte(role: Role) -> u8 {
+    match role {
+        Role::Initiator => 0,
+        Role::Responder => 1,
+    }
+}
+
+// The associated data the direction and sequence number are bound in with.
+fn sequenced_aad(direction: Role, sequence: u64) -> Vec<u8> {
+    let mut aad = SEQUENCED_LABEL.to_vec();
+    aad.push(direction_byte(direction));
+    aad.extend_from_slice(&sequence.to_be_bytes());
+    aad
+}
+
+impl SequencedMessage {
+    // The message in the sequenced layout above.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![direction_byte(self.direction)];
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.message.to_bytes());
+        bytes
+    }
+
+    // Split a message in the sequenced layout. A truncated message or an unknown direction byte is
+    // `DhError::MalformedMessage`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DhError> {
+        if bytes.len() < SEQUENCED_HEADER_LEN {
+            return Err(DhError::MalformedMessage);
+        }
+        let direction = match bytes[0] {
+            0 => Role::Initiator,
+            1 => Role::Responder,
+            _ => return Err(DhError::MalformedMessage),
+        };
+        let sequence = u64::from_be_bytes(bytes[1..SEQUENCED_HEADER_LEN].try_into().expect("8 bytes"));
+        let message = EncryptedMessage::from_bytes(&bytes[SEQUENCED_HEADER_LEN..])?;
+        Ok(SequencedMessage { direction, sequence, message })
+    }
+}
+
+// Numbers and seals the messages one end sends.
+pub struct EnvelopeSealer {
+    cipher: Aes128Gcm,
+    role: Role,
+    next_sequence: u64,
+}
+
+impl EnvelopeSealer {
+    // Seal messages sent by `role` under a key derived from the secret, as `encrypt_gcm` does.
+    pub fn new(secret_key: &BigUint, role: Role) -> Self {
+        EnvelopeSealer { cipher: gcm_cipher(secret_key), role, next_sequence: 0 }
+    }
+
+    // Encrypt the next outgoing message under the next sequence number.
+    pub fn seal(&mut self, plain_bytes: &[u8]) -> Result<SequencedMessage, DhError> {
+        let sequence = self.next_sequence;
+        self.next_sequence = sequence.checked_add(1).ok_or(DhError::NonceExhausted)?;
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let aad = sequenced_aad(self.role, sequence);
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain_bytes, aad: &aad })
+            .map_err(|_| DhError::EncryptionFailed)?;
+        let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+        let message = EncryptedMessage {
+            nonce,
+            ciphertext: ciphertext.to_vec(),
+            tag: tag.try_into().expect("split off TAG_LEN bytes"),
+        };
+        Ok(SequencedMessage { direction: self.role, sequence, message })
+    }
+}
+
+// Checks and opens the messages the peer sends, rejecting replays.
+pub struct EnvelopeOpener {
+    cipher: Aes128Gcm,
+    // The role of the peer, whose messages this opens.
+    peer: Role,
+    replay_window: ReplayWindow,
+}
+
+impl EnvelopeOpener {
+    // Open messages sent to `role` by the other end, which seals with the same secret and the opposite role.
+    pub fn new(secret_key: &BigUint, role: Role) -> Self {
+        EnvelopeOpener { cipher: gcm_cipher(secret_key), peer: role.peer(), replay_window: ReplayWindow::new() }
+    }
+
+    // Verify and decrypt a message from the peer. Messages may arrive out of order within the replay window. A message
+    // marked as sent in our own direction is `DhError::ReflectedMessage`, one already accepted
+    // `DhError::ReplayedMessage`, one too far behind the newest `DhError::StaleMessage`, and any tampering
+    // `DhError::DecryptionFailed`. Only authenticated messages move the window.
+    pub fn open(&mut self, sealed: &SequencedMessage) -> Result<Vec<u8>, DhError> {
+        if sealed.direction != self.peer {
+            return Err(DhError::ReflectedMessage);
+        }
+        self.replay_window.check(sealed.sequence)?;
+        let mut ciphertext = sealed.message.ciphertext.clone();
+        ciphertext.extend_from_slice(&sealed.message.tag);
+        let aad = sequenced_aad(sealed.direction, sealed.sequence);
+        let plain_bytes = self
+            .cipher
+            .decrypt(Nonce::from_slice(&sealed.message.nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| DhError::DecryptionFailed)?;
+        self.replay_window.accept(sealed.sequence);
+        Ok(plain_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::WINDOW_SIZE;
+
+    fn envelope_pair() -> (EnvelopeSealer, EnvelopeOpener) {
+        let key = BigUint::from(0x5eed_u32);
+        (EnvelopeSealer::new(&key, Role::Initiator), EnvelopeOpener::new(&key, Role::Responder))
+    }
+
+    #[test]
+    fn replays_are_rejected_and_reordering_is_not() {
+        let (mut sealer, mut opener) = envelope_pair();
+        let messages: Vec<_> = (0..4u8).map(|i| sealer.seal(&[i]).unwrap()).collect();
+        assert_eq!(messages.iter().map(|message| message.sequence).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        for i in [2, 0, 3, 1] {
+            let received = SequencedMessage::from_bytes(&messages[i].to_bytes()).unwrap();
+            assert_eq!(opener.open(&received).unwrap(), [i as u8]);
+        }
+        for message in &messages {
+            let result = opener.open(message);
+            assert!(matches!(result, Err(DhError::ReplayedMessage { counter }) if counter == message.sequence));
+        }
+    }
+
+    #[test]
+    fn changed_sequence_numbers_and_directions_fail() {
+        let (mut sealer, mut opener) = envelope_pair();
+        let message = sealer.seal(b"numbered").unwrap();
+
+        let renumbered = SequencedMessage { sequence: 7, ..message.clone() };
+        assert!(matches!(opener.open(&renumbered), Err(DhError::DecryptionFailed)));
+        // Our own messages coming back are refused before decryption.
+        let reflected = SequencedMessage { direction: Role::Responder, ..message.clone() };
+        let mut own_opener = EnvelopeOpener::new(&BigUint::from(0x5eed_u32), Role::Initiator);
+        assert!(matches!(own_opener.open(&message), Err(DhError::ReflectedMessage)));
+        assert!(matches!(opener.open(&reflected), Err(DhError::ReflectedMessage)));
+        // None of that moved the window.
+        assert_eq!(opener.open(&message).unwrap(), b"numbered");
+    }
+
+    #[test]
+    fn messages_behind_the_window_are_stale() {
+        let (mut sealer, mut opener) = envelope_pair();
+        let first = sealer.seal(b"first").unwrap();
+        for _ in 0..WINDOW_SIZE {
+            opener.open(&sealer.seal(b"later").unwrap()).unwrap();
+        }
+        assert!(matches!(opener.open(&first), Err(DhError::StaleMessage { counter: 0 })));
+    }
+
+    #[test]
+    fn sequenced_bytes_must_be_well_formed() {
+        let (mut sealer, _) = envelope_pair();
+        let bytes = sealer.seal(b"bytes").unwrap().to_bytes();
+        let truncated = &bytes[..SEQUENCED_HEADER_LEN - 1];
+        assert!(matches!(SequencedMessage::from_bytes(truncated), Err(DhError::MalformedMessage)));
+        let mut unknown_direction = bytes;
+        unknown_direction[0] = 2;
+        assert!(matches!(SequencedMessage::from_bytes(&unknown_direction), Err(DhError::MalformedMessage)));
+    }
+}
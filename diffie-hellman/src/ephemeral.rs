@@ -0,0 +1,144 @@
+// The two ways of holding a private key, as separate types so the choice is explicit. An `EphemeralSecret` is for
+// DHE: a fresh key for one exchange, consumed by `diffie_hellman`, so the compiler rejects any attempt to reuse it and
+// the exchange keeps forward secrecy. A `StaticKeyPair` is for static DH: a long-term key that can be stored and used
+// for any number of exchanges, such as a server's published key that clients combine with their ephemeral ones.
+// Leaking it later reveals every secret it was used for.
+//
+// Both validate the peer's key with `validate_public_key` and refuse a copy of their own key, as `handshake` does.
+
+use rand::{CryptoRng, RngCore};
+
+use crate::error::DhError;
+use crate::keys::{
+    derive_keypair_from_seed, generate_keypair_with_rng, validate_public_key, PrivateKey, PublicKey, SharedSecret,
+};
+use crate::params::DhParameters;
+
+// Deliberately neither `Clone` nor serializable: the only way to use it is to give it up.
+pub struct EphemeralSecret {
+    params: DhParameters,
+    private_key: PrivateKey,
+    public_key: PublicKey,
+}
+
+impl EphemeralSecret {
+    // A fresh keypair for `params`.
+    pub fn random(params: &DhParameters) -> Result<Self, DhError> {
+        Self::random_with_rng(params, &mut rand::thread_rng())
+    }
+
+    // A fresh keypair for `params`, drawn from `rng`.
+    pub fn random_with_rng<R: CryptoRng + RngCore>(params: &DhParameters, rng: &mut R) -> Result<Self, DhError> {
+        let (private_key, public_key) = generate_keypair_with_rng(params, rng)?;
+        Ok(EphemeralSecret { params: params.clone(), private_key, public_key })
+    }
+
+    // The public key to send to the peer.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    // Agree on the secret with the peer's key, using up the private key.
+    pub fn diffie_hellman(self, peer_public: &PublicKey) -> Result<SharedSecret, DhError> {
+        agree_checked(&self.private_key, &self.public_key, peer_public, &self.params)
+    }
+}
+
+pub struct StaticKeyPair {
+    params: DhParameters,
+    private_key: PrivateKey,
+    public_key: PublicKey,
+}
+
+// The long-term keypair under the name other libraries use, e.g. `KeyPair::from_seed` to restore an identity.
+pub type KeyPair = StaticKeyPair;
+
+impl StaticKeyPair {
+    // A new long-term keypair for `params`.
+    pub fn generate(params: &DhParameters) -> Result<Self, DhError> {
+        Self::generate_with_rng(params, &mut rand::thread_rng())
+    }
+
+    // A new long-term keypair for `params`, drawn from `rng`.
+    pub fn generate_with_rng<R: CryptoRng + RngCore>(params: &DhParameters, rng: &mut R) -> Result<Self, DhError> {
+        let (private_key, public_key) = generate_keypair_with_rng(params, rng)?;
+        Ok(StaticKeyPair { params: params.clone(), private_key, public_key })
+    }
+
+    // The keypair `derive_keypair_from_seed` derives from `seed`, for an identity that is backed up as its seed. The
+    // seed is as sensitive as the private key.
+    pub fn from_seed(seed: &[u8], params: &DhParameters) -> Result<Self, DhError> {
+        let (private_key, public_key) = derive_keypair_from_seed(params, seed)?;
+        Ok(StaticKeyPair { params: params.clone(), private_key, public_key })
+    }
+
+    // Load a stored private key, e.g. one read with `PrivateKey::from_bytes_be` or `PrivateKey::from_pkcs8_der`. A key
+    // whose public key would pin the shared secret is rejected with `DhError::InvalidKeyEncoding`.
+    pub fn from_private_key(private_key: PrivateKey, params: &DhParameters) -> Result<Self, DhError> {
+        let public_key = private_key.public_key(params);
+        if !public_key.is_nontrivial(params) {
+            return Err(DhError::InvalidKeyEncoding);
+        }
+        Ok(StaticKeyPair { params: params.clone(), private_key, public_key })
+    }
+
+    pub fn params(&self) -> &DhParameters {
+        &self.params
+    }
+
+    // The private key, for storing it with one of its encodings. Keep it somewhere only its owner can read.
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    // Agree on the secret with the peer's key. The keypair stays usable for further exchanges.
+    pub fn diffie_hellman(&self, peer_public: &PublicKey) -> Result<SharedSecret, DhError> {
+        agree_checked(&self.private_key, &self.public_key, peer_public, &self.params)
+    }
+}
+
+fn agree_checked(
+    private_key: &PrivateKey,
+    public_key: &PublicKey,
+    peer_public: &PublicKey,
+    params: &DhParameters,
+) -> Result<SharedSecret, DhError> {
+    validate_public_key(peer_public, params)?;
+    if peer_public == public_key {
+        return Err(DhError::IdenticalPublicKeys);
+    }
+    Ok(private_key.agree(peer_public, params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 31-bit safe prime, so that checking peer keys with `validate_public_key` is quick.
+    fn toy_group() -> DhParameters {
+        DhParameters::from_u64_allow_insecure(2, 2147483783).unwrap()
+    }
+
+    #[test]
+    fn an_identity_is_restored_from_its_seed() {
+        let params = toy_group();
+        let seed = [0x17; 32];
+        let identity = KeyPair::from_seed(&seed, &params).unwrap();
+        let restored = KeyPair::from_seed(&seed, &params).unwrap();
+        assert_eq!(restored.public_key(), identity.public_key());
+        assert_eq!(restored.private_key().to_bytes_be(), identity.private_key().to_bytes_be());
+
+        // The restored keypair agrees with a peer as the original did.
+        let peer = KeyPair::generate(&params).unwrap();
+        let secret = peer.diffie_hellman(identity.public_key()).unwrap();
+        assert!(restored.diffie_hellman(peer.public_key()).unwrap() == secret);
+        assert!(identity.diffie_hellman(peer.public_key()).unwrap() == secret);
+
+        assert_ne!(KeyPair::from_seed(&[0x18; 32], &params).unwrap().public_key(), identity.public_key());
+        assert!(matches!(KeyPair::from_seed(&seed[..8], &params), Err(DhError::SeedTooShort { len: 8, .. })));
+    }
+}
@@ -0,0 +1,320 @@
+// Errors returned by the fallible parts of the library.
+
+use core::fmt;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io;
+
+use block_modes::BlockModeError;
+
+use crate::cipher::CipherMode;
+
+#[derive(Debug)]
+pub enum DhError {
+    // The modulus is 2 or smaller, which can't form a usable group.
+    ModulusTooSmall,
+    // The generator is 0, 1, p-1 or not below the modulus, which makes every secret predictable.
+    InvalidGenerator,
+    // The modulus is below the minimum size accepted without an explicit opt-in.
+    InsecureModulus { bits: usize, min_bits: usize },
+    // The operation needs a safe-prime modulus, p = 2q + 1 with q prime, and this one isn't.
+    NotSafePrime,
+    // `DhParameters::generate` was asked for a modulus size outside the range it supports.
+    InvalidPrimeSize { bits: usize, min_bits: usize, max_bits: usize },
+    // The peer is using a different generator or modulus.
+    IncompatibleParameters,
+    // The peer's public key is the same as ours: both sides drew the same private key, pointing to a broken random
+    // number generator, or the peer reflected our key back.
+    IdenticalPublicKeys,
+    // The peer's signature over the handshake doesn't verify against its identity key: someone may be in the middle.
+    InvalidSignature,
+    // The peer's key confirmation tag doesn't match: the two sides disagree on the secret or the handshake.
+    KeyConfirmationFailed,
+    // The tag on a signed message doesn't verify: the message was altered, or signed under other keys.
+    InvalidTag,
+    // A public key received from the peer failed validation.
+    InvalidPublicKey(ValidationError),
+    // The IV handed to a cipher doesn't have the length its mode requires.
+    InvalidIvLength { mode: CipherMode, expected: usize, actual: usize },
+    // The plain text couldn't be encrypted.
+    EncryptionFailed,
+    // Unpadded block mode data has to be a whole number of blocks.
+    UnalignedInput { length: usize, block_len: usize },
+    // The ciphertext couldn't be decrypted, e.g. it was corrupted or the wrong key was used.
+    DecryptionFailed,
+    // ECB or CBC decryption didn't end in valid padding, which is usually what the wrong key or a corrupted ciphertext
+    // looks like in a mode without authentication.
+    InvalidPadding(BlockModeError),
+    // The decrypted bytes aren't valid UTF-8 text.
+    InvalidUtf8,
+    // Text that was expected to hold a decimal or hex number doesn't.
+    InvalidNumber,
+    // The salt given for password hashing is shorter than Argon2 allows.
+    SaltTooShort { length: usize, min_length: usize },
+    // A length-hiding bucket size of zero was requested.
+    InvalidBucketSize,
+    // The message is too long for its length to fit the 4-byte padding prefix.
+    MessageTooLong,
+    // The length prefix of padded data doesn't match the data that follows it.
+    MalformedPadding,
+    // No private key with a usable public key was found; the parameters are likely degenerate.
+    KeyGenerationFailed { attempts: u32 },
+    // A private exponent size was asked for below what the group's strength needs.
+    ExponentTooShort { bits: u64, min_bits: u64 },
+    // A seed passed to `derive_keypair_from_seed` is too short to give a key of full strength.
+    SeedTooShort { len: usize, min_len: usize },
+    // A private key lies outside [2, q-1] for the group it is used with.
+    InvalidPrivateKey,
+    // Every nonce for the session has been used; the session has to be rekeyed.
+    NonceExhausted,
+    // Reading from or writing to the peer failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    // The peer closed or reset the connection partway through the named phase of the handshake.
+    PeerDisconnected { phase: &'static str },
+    // The peer didn't send anything within the handshake timeout.
+    TimedOut { timeout: Duration },
+    // The peer announced a message longer than allowed.
+    FrameTooLarge { length: usize, max_length: usize },
+    // An encoded key isn't well-formed, or isn't a Diffie-Hellman key.
+    InvalidKeyEncoding,
+    // A protocol message is truncated, has trailing bytes, or is of an unknown type.
+    MalformedMessage,
+    // A protocol message was written for a version we don't speak.
+    UnsupportedVersion { version: u8 },
+    // The peer speaks a version of the wire protocol this end doesn't, named by its newest one.
+    ProtocolVersionMismatch { version: u8 },
+    // A message with this counter has already been accepted.
+    ReplayedMessage { counter: u64 },
+    // The message counter is too far behind the newest accepted message to be checked for replay.
+    StaleMessage { counter: u64 },
+    // A message is marked as sent by the receiving end itself, so it has been reflected back.
+    ReflectedMessage,
+    // No handshake or session has this ID, or it has expired.
+    UnknownSession,
+    // More data was handed to the record layer than fits in one record.
+    RecordTooLong { length: usize, max_length: usize },
+    // A ratchet message jumps further ahead of the next expected message than the skipped-key window allows.
+    TooManySkippedMessages { skipping: u64, max_skipping: u64 },
+    // The peer offered no cipher suite this end accepts, or the offer was empty.
+    NoCommonCipherSuite,
+    // The peer picked a cipher suite that was never offered to it.
+    CipherSuiteNotOffered { suite: u16 },
+    // A group exchange needs at least two parties.
+    InvalidGroupSize { size: usize },
+    // A party's position in a group exchange is outside the group.
+    InvalidGroupPosition { position: usize, size: usize },
+    // The agreed secret came out as 0, 1 or p-1, which an eavesdropper could guess.
+    TrivialSecret,
+    // Encryption was attempted before a secret was agreed.
+    NoSharedSecret,
+    // The session has been closed, by us or by the peer.
+    SessionClosed,
+    // The stream ended without the peer's close message, so messages may have been cut off.
+    Truncated,
+    // `self_test` found a mismatch at the named stage.
+    SelfTestFailed { stage: &'static str },
+    // Decrypting would produce more output than the caller allowed.
+    OutputTooLarge { length: usize, max_length: usize },
+    // The bytes handed to `PrivateKey::load` aren't a passphrase-protected key file.
+    InvalidKeyFile,
+    // A passphrase-protected key file didn't open: the passphrase is wrong, or the file has been altered.
+    WrongPassphrase,
+}
+
+impl fmt::Display for DhError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DhError::ModulusTooSmall => write!(f, "modulus must be greater than 2"),
+            DhError::InvalidGenerator => write!(f, "generator must lie in the range [2, p-2]"),
+            DhError::InsecureModulus { bits, min_bits } => {
+                write!(f, "{}-bit modulus is below the {}-bit minimum", bits, min_bits)
+            }
+            DhError::NotSafePrime => write!(f, "the modulus is not a safe prime"),
+            DhError::InvalidPrimeSize { bits, min_bits, max_bits } => {
+                write!(f, "cannot generate a {}-bit modulus, sizes run from {} to {} bits", bits, min_bits, max_bits)
+            }
+            DhError::IncompatibleParameters => write!(f, "the peer uses different parameters"),
+            DhError::IdenticalPublicKeys => {
+                write!(f, "the peer's public key equals ours, the random number generator may be broken")
+            }
+            DhError::InvalidSignature => write!(f, "the peer's handshake signature does not match its identity key"),
+            DhError::KeyConfirmationFailed => write!(f, "the peer did not confirm the same shared secret"),
+            DhError::InvalidTag => write!(f, "the message's authentication tag does not verify"),
+            DhError::InvalidPublicKey(reason) => write!(f, "invalid public key: {}", reason),
+            DhError::InvalidIvLength { mode, expected, actual } => {
+                write!(f, "{:?} mode needs a {}-byte IV but got {} bytes", mode, expected, actual)
+            }
+            DhError::EncryptionFailed => write!(f, "encryption failed"),
+            DhError::UnalignedInput { length, block_len } => {
+                write!(f, "{} bytes is not a multiple of the {}-byte block size", length, block_len)
+            }
+            DhError::DecryptionFailed => write!(f, "decryption failed"),
+            DhError::InvalidPadding(_) => write!(f, "decryption failed: the padding is invalid"),
+            DhError::InvalidUtf8 => write!(f, "decrypted data is not valid UTF-8"),
+            DhError::InvalidNumber => write!(f, "not a valid decimal or hex number"),
+            DhError::SaltTooShort { length, min_length } => {
+                write!(f, "{}-byte salt is shorter than the {}-byte minimum", length, min_length)
+            }
+            DhError::InvalidBucketSize => write!(f, "padding bucket size must be greater than zero"),
+            DhError::MessageTooLong => write!(f, "message is too long to pad"),
+            DhError::MalformedPadding => write!(f, "padded data has an invalid length prefix"),
+            DhError::KeyGenerationFailed { attempts } => {
+                write!(f, "no usable keypair found after {} attempts", attempts)
+            }
+            DhError::ExponentTooShort { bits, min_bits } => {
+                write!(f, "{}-bit private exponents are below the {}-bit minimum for the group", bits, min_bits)
+            }
+            DhError::SeedTooShort { len, min_len } => {
+                write!(f, "a {}-byte seed is shorter than the {}-byte minimum", len, min_len)
+            }
+            DhError::InvalidPrivateKey => write!(f, "private key is outside the range [2, q-1] for the group"),
+            DhError::NonceExhausted => write!(f, "all nonces for this session have been used"),
+            #[cfg(feature = "std")]
+            DhError::Io(error) => write!(f, "i/o error: {}", error),
+            DhError::PeerDisconnected { phase } => write!(f, "the peer disconnected while {}", phase),
+            DhError::TimedOut { timeout } => write!(f, "peer did not respond within {:?}", timeout),
+            DhError::FrameTooLarge { length, max_length } => {
+                write!(f, "peer sent a {}-byte message, more than the {} bytes allowed", length, max_length)
+            }
+            DhError::InvalidKeyEncoding => write!(f, "key is not a valid DER-encoded Diffie-Hellman key"),
+            DhError::MalformedMessage => write!(f, "protocol message is malformed"),
+            DhError::UnsupportedVersion { version } => write!(f, "unsupported protocol version {}", version),
+            DhError::ProtocolVersionMismatch { version } => {
+                write!(f, "the peer speaks protocol version {}, which this end does not support", version)
+            }
+            DhError::ReplayedMessage { counter } => write!(f, "message {} has already been received", counter),
+            DhError::StaleMessage { counter } => write!(f, "message {} is too old to accept", counter),
+            DhError::ReflectedMessage => write!(f, "message was sent by this end and reflected back"),
+            DhError::UnknownSession => write!(f, "no such session, or it has expired"),
+            DhError::RecordTooLong { length, max_length } => {
+                write!(f, "{} bytes don't fit in a record of at most {} bytes", length, max_length)
+            }
+            DhError::TooManySkippedMessages { skipping, max_skipping } => {
+                write!(f, "message skips {} messages, more than the {} allowed", skipping, max_skipping)
+            }
+            DhError::NoCommonCipherSuite => write!(f, "no cipher suite in common with the peer"),
+            DhError::CipherSuiteNotOffered { suite } => {
+                write!(f, "the peer chose cipher suite {:#06x}, which was not offered", suite)
+            }
+            DhError::InvalidGroupSize { size } => write!(f, "a group exchange needs at least 2 parties, not {}", size),
+            DhError::InvalidGroupPosition { position, size } => {
+                write!(f, "position {} is outside a group of {} parties", position, size)
+            }
+            DhError::TrivialSecret => write!(f, "the agreed secret is a trivial value"),
+            DhError::NoSharedSecret => write!(f, "no secret has been agreed yet"),
+            DhError::SessionClosed => write!(f, "session has been closed"),
+            DhError::Truncated => write!(f, "stream ended before the peer closed the session"),
+            DhError::SelfTestFailed { stage } => write!(f, "self-test failed at {}", stage),
+            DhError::OutputTooLarge { length, max_length } => {
+                write!(f, "decrypted message would be {} bytes, more than the {} bytes allowed", length, max_length)
+            }
+            DhError::InvalidKeyFile => write!(f, "not a passphrase-protected key file"),
+            DhError::WrongPassphrase => write!(f, "wrong passphrase, or the key file has been altered"),
+        }
+    }
+}
+
+// The underlying error, for the variants that wrap one, so error reporters can print the whole chain.
+impl core::error::Error for DhError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            DhError::InvalidPublicKey(reason) => Some(reason),
+            // block-modes only implements `Error` with its `std` feature.
+            #[cfg(feature = "std")]
+            DhError::InvalidPadding(error) => Some(error),
+            #[cfg(feature = "std")]
+            DhError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for DhError {
+    fn from(error: io::Error) -> Self {
+        DhError::Io(error)
+    }
+}
+
+impl From<BlockModeError> for DhError {
+    fn from(error: BlockModeError) -> Self {
+        DhError::InvalidPadding(error)
+    }
+}
+
+// Why a peer's public key was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    // The value is 0, 1, p-1 or not below the modulus.
+    OutOfRange,
+    // The value isn't in the prime-order subgroup, so it could confine the shared secret to a small set of values.
+    NotInSubgroup,
+    // The X25519 public key is a low-order point, which would make the shared secret all zeros.
+    LowOrderPoint,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::OutOfRange => write!(f, "value is outside the range [2, p-2]"),
+            ValidationError::NotInSubgroup => write!(f, "value is not in the prime-order subgroup"),
+            ValidationError::LowOrderPoint => write!(f, "point has low order"),
+        }
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+impl From<ValidationError> for DhError {
+    fn from(reason: ValidationError) -> Self {
+        DhError::InvalidPublicKey(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::error::Error;
+
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::cipher::{decrypt_with_padding, encrypt_with_padding, Padding};
+
+    // A block that decrypts to all zeros, which doesn't end in valid PKCS#7 padding.
+    #[test]
+    fn wrapped_cipher_error_has_a_message_and_a_source() {
+        let key = BigUint::from(0x5eed_u32);
+        let block = encrypt_with_padding(&[0; 16], &key, CipherMode::Ecb, Padding::None, &[]).unwrap();
+        let error = decrypt_with_padding(&block, &key, CipherMode::Ecb, Padding::Pkcs7, &[]).unwrap_err();
+        assert!(matches!(error, DhError::InvalidPadding(_)), "{error:?}");
+        assert_eq!(error.to_string(), "decryption failed: the padding is invalid");
+        #[cfg(feature = "std")]
+        assert_eq!(error.source().unwrap().to_string(), BlockModeError.to_string());
+    }
+
+    #[test]
+    fn validation_errors_chain_under_invalid_public_key() {
+        let error = DhError::from(ValidationError::NotInSubgroup);
+        assert_eq!(error.to_string(), "invalid public key: value is not in the prime-order subgroup");
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "value is not in the prime-order subgroup");
+        assert!(source.source().is_none());
+        assert!(DhError::DecryptionFailed.source().is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_errors_convert_with_question_mark_and_chain() {
+        fn read() -> Result<(), DhError> {
+            Err(io::Error::new(io::ErrorKind::UnexpectedEof, "stream ended"))?
+        }
+        fn report() -> Result<(), Box<dyn Error>> {
+            read()?;
+            Ok(())
+        }
+        let error = report().unwrap_err();
+        assert_eq!(error.to_string(), "i/o error: stream ended");
+        assert_eq!(error.source().unwrap().to_string(), "stream ended");
+    }
+}
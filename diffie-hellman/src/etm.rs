@@ -0,0 +1,169 @@
+// Encrypt-then-MAC over AES-128-CBC, for authenticated encryption without an AEAD mode. Separate encryption and MAC
+// keys are derived from the shared secret with HKDF, or taken from `derive_session_keys`, and the HMAC-SHA256 tag
+// covers the IV and the ciphertext.
+//
+// Message layout: IV (16 bytes) || ciphertext || HMAC-SHA256 tag (32 bytes).
+
+use aes::Aes128;
+use block_modes::block_padding::Pkcs7;
+use block_modes::{BlockMode, Cbc};
+use hmac::{Hmac, Mac};
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::error::DhError;
+use crate::kdf::derive_key;
+use crate::keys::DerivedKeys;
+
+type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+type HmacSha256 = Hmac<Sha256>;
+
+const IV_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+const ENCRYPTION_LABEL: &str = "aes-128-cbc etm encryption key";
+const MAC_LABEL: &str = "hmac-sha256 etm mac key";
+
+// The two keys one message is sealed with, wiped when dropped.
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct EtmKeys {
+    encryption: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+impl EtmKeys {
+    // Derive both keys from the secret under their own labels. Using one key for both AES and HMAC is a classic
+    // mistake, so debug builds assert the two never coincide, catching a misconfigured KDF (e.g. both labels made
+    // equal). The encryption key is compared with the start of the longer MAC key, since HKDF output for the same
+    // label only differs in length.
+    fn derive(secret_key: &BigUint) -> Self {
+        let keys = EtmKeys {
+            encryption: derive_key(secret_key, ENCRYPTION_LABEL, 16),
+            mac: derive_key(secret_key, MAC_LABEL, MAC_LEN),
+        };
+        debug_assert!(keys.are_separate(), "encryption and MAC keys must differ");
+        keys
+    }
+
+    fn from_derived(keys: &DerivedKeys) -> Self {
+        let keys = EtmKeys { encryption: keys.encryption_key.to_vec(), mac: keys.mac_key.to_vec() };
+        debug_assert!(keys.are_separate(), "encryption and MAC keys must differ");
+        keys
+    }
+
+    fn are_separate(&self) -> bool {
+        !self.mac.starts_with(&self.encryption)
+    }
+
+    fn cipher(&self, iv: &[u8]) -> Aes128Cbc {
+        Aes128Cbc::new_from_slices(&self.encryption, iv).expect("key and IV are 16 bytes")
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.mac).expect("HMAC takes keys of any length")
+    }
+}
+
+// Encrypt the plain text with AES-128-CBC under a fresh random IV, then append an HMAC-SHA256 tag over the IV and
+// ciphertext.
+pub fn seal(plain_text: &str, secret_key: &BigUint) -> Vec<u8> {
+    seal_with(plain_text, &EtmKeys::derive(secret_key))
+}
+
+// Verify and decrypt a message produced by `seal`. The tag is checked in constant time before the cipher is touched,
+// so tampered messages are turned away without decrypting anything. Any tampering, or the wrong secret, is reported as
+// `DhError::DecryptionFailed`.
+pub fn open(message: &[u8], secret_key: &BigUint) -> Result<String, DhError> {
+    open_with(message, &EtmKeys::derive(secret_key))
+}
+
+// Like `seal`, with keys from `derive_session_keys` or `agree_and_derive` instead of the bare secret.
+pub fn seal_with_keys(plain_text: &str, keys: &DerivedKeys) -> Vec<u8> {
+    seal_with(plain_text, &EtmKeys::from_derived(keys))
+}
+
+// Verify and decrypt a message produced by `seal_with_keys` with the same keys.
+pub fn open_with_keys(message: &[u8], keys: &DerivedKeys) -> Result<String, DhError> {
+    open_with(message, &EtmKeys::from_derived(keys))
+}
+
+fn seal_with(plain_text: &str, keys: &EtmKeys) -> Vec<u8> {
+    let mut iv = [0u8; IV_LEN];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let mut message = iv.to_vec();
+    message.extend_from_slice(&keys.cipher(&iv).encrypt_vec(plain_text.as_bytes()));
+    let tag = keys.mac().chain_update(&message).finalize().into_bytes();
+    message.extend_from_slice(&tag);
+    message
+}
+
+fn open_with(message: &[u8], keys: &EtmKeys) -> Result<String, DhError> {
+    if message.len() < IV_LEN + MAC_LEN {
+        return Err(DhError::DecryptionFailed);
+    }
+    let (authenticated, tag) = message.split_at(message.len() - MAC_LEN);
+    keys.mac().chain_update(authenticated).verify_slice(tag).map_err(|_| DhError::DecryptionFailed)?;
+
+    let (iv, ciphertext) = authenticated.split_at(IV_LEN);
+    let plain_bytes = keys.cipher(iv).decrypt_vec(ciphertext).map_err(|_| DhError::DecryptionFailed)?;
+    String::from_utf8(plain_bytes).map_err(|_| DhError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{derive_session_keys, DerivedKeys, SharedSecret};
+    use crate::CONTROL_CHARACTER_TEXT;
+
+    #[test]
+    fn control_characters_round_trip() {
+        let key = BigUint::from(0x1234_5678u32);
+        assert_eq!(open(&seal(CONTROL_CHARACTER_TEXT, &key), &key).unwrap(), CONTROL_CHARACTER_TEXT);
+        let keys = derive_session_keys(&SharedSecret::from_bytes(&[9; 32]), b"etm test");
+        let sealed = seal_with_keys(CONTROL_CHARACTER_TEXT, &keys);
+        assert_eq!(open_with_keys(&sealed, &keys).unwrap(), CONTROL_CHARACTER_TEXT);
+    }
+
+    #[test]
+    fn tampering_with_any_part_is_detected() {
+        let key = BigUint::from(0x1234_5678u32);
+        let sealed = seal("attack at dawn, bring snacks", &key);
+        assert_eq!(sealed.len(), IV_LEN + 32 + MAC_LEN);
+        assert_eq!(open(&sealed, &key).unwrap(), "attack at dawn, bring snacks");
+
+        let iv = 0..IV_LEN;
+        let ciphertext = IV_LEN..sealed.len() - MAC_LEN;
+        let tag = sealed.len() - MAC_LEN..sealed.len();
+        for index in iv.chain(ciphertext).chain(tag) {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 0x01;
+            assert!(matches!(open(&tampered, &key), Err(DhError::DecryptionFailed)), "{index}");
+        }
+        for len in [0, IV_LEN + MAC_LEN - 1, sealed.len() - 1] {
+            assert!(matches!(open(&sealed[..len], &key), Err(DhError::DecryptionFailed)), "{len}");
+        }
+        assert!(matches!(open(&sealed, &BigUint::from(0x1234_5679u32)), Err(DhError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn keys_from_one_label_are_caught() {
+        let secret = BigUint::from(0x1234_5678u32);
+        let same_label =
+            EtmKeys { encryption: derive_key(&secret, MAC_LABEL, 16), mac: derive_key(&secret, MAC_LABEL, MAC_LEN) };
+        assert!(!same_label.are_separate());
+        assert!(EtmKeys::derive(&secret).are_separate());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "encryption and MAC keys must differ")]
+    fn sealing_with_one_key_for_both_panics_in_debug_builds() {
+        let mut mac_key = [0x5a; 32];
+        mac_key[16..].fill(0xa5);
+        let keys = DerivedKeys { encryption_key: [0x5a; 16], mac_key };
+        seal_with_keys("never sent", &keys);
+    }
+}
@@ -0,0 +1,211 @@
+// A C ABI for callers outside Rust, e.g. a C service or Python through `ctypes` or `cffi`. It offers the same four
+// operations as the wasm bindings, with the same encodings: keys as `keygen` and `derive` write them on the command
+// line, the shared secret as its big-endian bytes, and messages in the `encrypt_gcm` layout. Groups are named as for
+// `ParametersRegistry::get`. The manifest builds only an rlib, so build the shared library with
+// `cargo rustc --lib --release --features ffi --crate-type cdylib`. The declarations are in
+// include/diffie_hellman.h, which `cbindgen --config cbindgen.toml --output include/diffie_hellman.h` regenerates.
+//
+// Every function returns a `DhStatus`, `DH_STATUS_OK` on success. On `DH_STATUS_ERROR`, `dh_last_error` gives the
+// `DhError` message for the calling thread. Output bytes are handed back in a `DhBuffer` the library allocates. The
+// caller passes each one to `dh_buffer_free`, which wipes it before freeing it, since it may hold a key or secret.
+
+// The safety requirements of each function are in its comment, as plain comments like everything else in the crate.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use std::slice;
+
+use zeroize::Zeroize;
+
+use crate::error::DhError;
+use crate::gcm::{decrypt_gcm, encrypt_gcm};
+use crate::keys::{generate_keypair, PrivateKey, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+use crate::registry::ParametersRegistry;
+
+// The outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhStatus {
+    Ok = 0,
+    // A required pointer is null, or the group name isn't UTF-8.
+    InvalidArgument = 1,
+    // No group has that name.
+    UnknownGroup = 2,
+    // The operation failed; `dh_last_error` says why.
+    Error = 3,
+}
+
+// Bytes allocated by the library. Free them with `dh_buffer_free`.
+#[repr(C)]
+pub struct DhBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// The message of the last `DH_STATUS_ERROR` on this thread, or null if there hasn't been one. It stays valid until the
+// next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn dh_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+// Wipe and free a buffer returned by the library, and leave it empty. Freeing an empty buffer does nothing.
+//
+// Safety: `buffer` must be null or point to a `DhBuffer` filled in by this library and not changed since.
+#[no_mangle]
+pub unsafe extern "C" fn dh_buffer_free(buffer: *mut DhBuffer) {
+    let Some(buffer) = buffer.as_mut() else { return };
+    if !buffer.data.is_null() {
+        let mut bytes = Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len));
+        bytes.zeroize();
+    }
+    buffer.data = ptr::null_mut();
+    buffer.len = 0;
+}
+
+// Generate a keypair for the named group, giving the private key's big-endian bytes and the public key in its wire
+// encoding. Keep the private key to yourself.
+//
+// Safety: `group` must be a NUL-terminated string; `private_key` and `public_key` must point to writable `DhBuffer`s.
+#[no_mangle]
+pub unsafe extern "C" fn dh_keygen(
+    group: *const c_char,
+    private_key: *mut DhBuffer,
+    public_key: *mut DhBuffer,
+) -> DhStatus {
+    let (Some(private_out), Some(public_out)) = (private_key.as_mut(), public_key.as_mut()) else {
+        return DhStatus::InvalidArgument;
+    };
+    let params = match lookup(group) {
+        Ok(params) => params,
+        Err(status) => return status,
+    };
+    match generate_keypair(&params) {
+        Ok((private_key, public_key)) => {
+            *private_out = into_buffer(private_key.to_bytes_be());
+            *public_out = into_buffer(public_key.to_wire_bytes());
+            DhStatus::Ok
+        }
+        Err(error) => fail(error),
+    }
+}
+
+// The secret shared with the owner of `peer_public_key`, after checking both keys against the group.
+//
+// Safety: `group` must be a NUL-terminated string, each pointer and length pair must describe readable memory, and
+// `secret` must point to a writable `DhBuffer`.
+#[no_mangle]
+pub unsafe extern "C" fn dh_derive(
+    group: *const c_char,
+    private_key: *const u8,
+    private_key_len: usize,
+    peer_public_key: *const u8,
+    peer_public_key_len: usize,
+    secret: *mut DhBuffer,
+) -> DhStatus {
+    let (Some(private_key), Some(peer_public_key), Some(secret_out)) = (
+        input(private_key, private_key_len),
+        input(peer_public_key, peer_public_key_len),
+        secret.as_mut(),
+    ) else {
+        return DhStatus::InvalidArgument;
+    };
+    let params = match lookup(group) {
+        Ok(params) => params,
+        Err(status) => return status,
+    };
+    let derive = || -> Result<Vec<u8>, DhError> {
+        let peer_public = PublicKey::from_wire_bytes(peer_public_key, &params)?;
+        peer_public.validate_in_group(&params)?;
+        let private_key = PrivateKey::from_bytes_be(private_key);
+        private_key.validate(&params)?;
+        Ok(private_key.agree(&peer_public, &params).dangerous_into_bytes().to_vec())
+    };
+    output(derive(), secret_out)
+}
+
+// Encrypt `plaintext` with AES-128-GCM under a key derived from `secret`, see `encrypt_gcm`.
+//
+// Safety: each pointer and length pair must describe readable memory, and `ciphertext` must point to a writable
+// `DhBuffer`.
+#[no_mangle]
+pub unsafe extern "C" fn dh_encrypt(
+    secret: *const u8,
+    secret_len: usize,
+    plaintext: *const u8,
+    plaintext_len: usize,
+    ciphertext: *mut DhBuffer,
+) -> DhStatus {
+    let (Some(secret), Some(plaintext), Some(ciphertext_out)) =
+        (input(secret, secret_len), input(plaintext, plaintext_len), ciphertext.as_mut())
+    else {
+        return DhStatus::InvalidArgument;
+    };
+    output(encrypt_gcm(plaintext, SharedSecret::from_bytes(secret).as_biguint()), ciphertext_out)
+}
+
+// Decrypt a message from `dh_encrypt`. A wrong secret or a tampered message is `DH_STATUS_ERROR`.
+//
+// Safety: each pointer and length pair must describe readable memory, and `plaintext` must point to a writable
+// `DhBuffer`.
+#[no_mangle]
+pub unsafe extern "C" fn dh_decrypt(
+    secret: *const u8,
+    secret_len: usize,
+    ciphertext: *const u8,
+    ciphertext_len: usize,
+    plaintext: *mut DhBuffer,
+) -> DhStatus {
+    let (Some(secret), Some(ciphertext), Some(plaintext_out)) =
+        (input(secret, secret_len), input(ciphertext, ciphertext_len), plaintext.as_mut())
+    else {
+        return DhStatus::InvalidArgument;
+    };
+    output(decrypt_gcm(ciphertext, SharedSecret::from_bytes(secret).as_biguint()), plaintext_out)
+}
+
+// The bytes behind a pointer and length from the caller. Null is only allowed for an empty input.
+unsafe fn input<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn lookup(group: *const c_char) -> Result<DhParameters, DhStatus> {
+    if group.is_null() {
+        return Err(DhStatus::InvalidArgument);
+    }
+    let name = CStr::from_ptr(group).to_str().map_err(|_| DhStatus::InvalidArgument)?;
+    ParametersRegistry::get(name).cloned().ok_or(DhStatus::UnknownGroup)
+}
+
+fn into_buffer(bytes: Vec<u8>) -> DhBuffer {
+    let bytes = bytes.into_boxed_slice();
+    let len = bytes.len();
+    DhBuffer { data: Box::into_raw(bytes).cast(), len }
+}
+
+fn output(result: Result<Vec<u8>, DhError>, out: &mut DhBuffer) -> DhStatus {
+    match result {
+        Ok(bytes) => {
+            *out = into_buffer(bytes);
+            DhStatus::Ok
+        }
+        Err(error) => fail(error),
+    }
+}
+
+fn fail(error: DhError) -> DhStatus {
+    let message = CString::new(error.to_string()).expect("error messages have no NUL bytes");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    DhStatus::Error
+}
@@ -0,0 +1,102 @@
+// Fingerprints and short authentication strings, for checking keys and exchanges out of band. A man in the middle
+// runs a separate exchange with each side, so the two sides end up with different secrets and see different keys.
+// Comparing a short value derived from them, e.g. by reading it out over the phone, reveals that.
+//
+// Both come in words from a fixed list of 256, one per byte, which are easier to read out and compare than hex.
+// `PublicKey::fingerprint` identifies a key, e.g. a long-term key published somewhere; `SharedSecret::sas` checks one
+// exchange, and both parties get the same words only if they agreed on the same secret.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use sha2::{Digest, Sha256};
+
+use crate::kdf::derive_key;
+use crate::keys::{PublicKey, SharedSecret};
+
+// How many words `Fingerprint::to_words` gives, covering the first 8 bytes of the hash. Forging a key with the same
+// words takes around 2^64 attempts.
+pub const FINGERPRINT_WORDS: usize = 8;
+
+// How many words `SharedSecret::sas` gives. A man in the middle who wants both sides to see the same words has to
+// find keys giving a 48-bit match while the exchange is running.
+pub const SAS_WORDS: usize = 6;
+
+const FINGERPRINT_LABEL: &[u8] = b"diffie-hellman public key fingerprint";
+const SAS_LABEL: &str = "diffie-hellman short authentication string";
+
+// One word per byte value, in order.
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alley", "amber", "angle", "ankle", "apple", "apron",
+    "arena", "armor", "arrow", "atlas", "attic", "autumn", "badge", "badger", "bagel", "baker", "bamboo", "banjo",
+    "barn", "basil", "basket", "beach", "beard", "beaver", "bench", "berry", "bicycle", "bishop", "blade", "blanket",
+    "bloom", "bonus", "boot", "bottle", "boxer", "brain", "branch", "bread", "brick", "bridge", "broom", "bubble",
+    "bucket", "buffalo", "bunny", "butter", "button", "cabin", "cactus", "camel", "candle", "canoe", "canyon", "carpet",
+    "carrot", "castle", "cattle", "cave", "cedar", "cello", "chalk", "cheese", "cherry", "chess", "chimney", "circle",
+    "clock", "cloud", "clover", "coast", "cobra", "cocoa", "comet", "compass", "copper", "coral", "cotton", "cougar",
+    "cradle", "crane", "crayon", "cricket", "crystal", "cube", "cup", "curtain", "cushion", "daisy", "dance", "delta",
+    "desert", "diamond", "dinner", "dolphin", "donkey", "dragon", "drum", "eagle", "earth", "easel", "echo", "eclipse",
+    "elbow", "ember", "engine", "falcon", "feather", "fern", "fiddle", "flame", "flute", "forest", "fossil", "fountain",
+    "fox", "frost", "galaxy", "garden", "garlic", "gecko", "giant", "ginger", "glacier", "globe", "goat", "gopher",
+    "grape", "gravel", "guitar", "hammer", "harbor", "hawk", "hazel", "helmet", "hero", "hippo", "hollow", "honey",
+    "hornet", "iceberg", "igloo", "island", "ivory", "jacket", "jaguar", "jelly", "jigsaw", "jungle", "kayak", "kernel",
+    "kettle", "kitten", "koala", "ladder", "lagoon", "lantern", "lemon", "lemur", "lily", "lizard", "lobster", "magnet",
+    "mango", "maple", "marble", "meadow", "melon", "mirror", "monkey", "moose", "mosaic", "motor", "muffin", "nectar",
+    "needle", "noodle", "oasis", "ocean", "olive", "onion", "orange", "orbit", "otter", "oyster", "paddle", "panda",
+    "parrot", "peach", "pebble", "pencil", "pepper", "piano", "pickle", "pilot", "planet", "plum", "pocket", "pony",
+    "potato", "pumpkin", "puzzle", "quartz", "rabbit", "radar", "raven", "ribbon", "river", "robin", "rocket", "saddle",
+    "salmon", "sandal", "scarf", "shadow", "shovel", "silver", "skate", "sparrow", "spider", "spoon", "squash",
+    "statue", "stove", "sugar", "summit", "sunset", "tiger", "timber", "tomato", "tractor", "trumpet", "tulip",
+    "tunnel", "turtle", "umbrella", "velvet", "violin", "volcano", "wagon", "walnut", "walrus", "whale", "willow",
+    "window", "winter", "wizard", "yacht", "yogurt", "zebra",
+];
+
+// A SHA-256 hash identifying a public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    // The whole hash as lowercase hex, for comparing by copy and paste.
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // The first `FINGERPRINT_WORDS` bytes as words, separated by spaces, for reading out.
+    pub fn to_words(&self) -> String {
+        to_words(&self.0[..FINGERPRINT_WORDS])
+    }
+}
+
+// The hex form.
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+fn to_words(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| WORDS[byte as usize]).collect::<Vec<_>>().join(" ")
+}
+
+impl PublicKey {
+    // The key's fingerprint: a domain-separated SHA-256 hash of its wire encoding, so the same key always has the same
+    // fingerprint whatever form it was stored in.
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint(Sha256::new().chain_update(FINGERPRINT_LABEL).chain_update(self.to_wire_bytes()).finalize().into())
+    }
+}
+
+impl SharedSecret {
+    // A short authentication string for the exchange that produced this secret: `SAS_WORDS` words derived from it
+    // with HKDF, which the two parties compare over a channel the attacker can't change, such as a phone call. Equal
+    // words mean both hold the same secret. The words reveal nothing useful about the secret.
+    pub fn sas(&self) -> String {
+        to_words(&derive_key(self.as_biguint(), SAS_LABEL, SAS_WORDS))
+    }
+}
@@ -0,0 +1,206 @@
+// One-shot authenticated encryption with AES-128-GCM. The AES key is derived from the shared secret with HKDF, and a
+// fresh random nonce is generated for every message and sent in front of the ciphertext.
+//
+// Message layout: nonce (12 bytes) || ciphertext || GCM tag (16 bytes).
+
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use hex_literal::hex;
+use num_bigint::BigUint;
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::kdf::derive_key;
+use crate::nonce::NONCE_LEN;
+
+pub(crate) const TAG_LEN: usize = 16;
+
+// The AES-128 key `encrypt_gcm` and `decrypt_gcm` use for this secret.
+pub fn derive_gcm_key(secret_key: &BigUint) -> [u8; 16] {
+    let key = Zeroizing::new(derive_key(secret_key, "aes-128-gcm key", 16));
+    key.as_slice().try_into().expect("derived key is 16 bytes")
+}
+
+// AES-128-GCM known-answer vectors from the GCM specification (McGrew and Viega), test cases 1 to 3, run on the raw
+// AEAD with no key derivation, so a broken GHASH or counter shows apart from the rest. Each is (key, nonce,
+// plaintext, ciphertext followed by the tag).
+type GcmKnownAnswer = ([u8; 16], [u8; NONCE_LEN], &'static [u8], &'static [u8]);
+
+const GCM_KNOWN_ANSWERS: [GcmKnownAnswer; 3] = [
+    ([0; 16], [0; NONCE_LEN], &[], &hex!("58e2fccefa7e3061367f1d57a4e7455a")),
+    (
+        [0; 16],
+        [0; NONCE_LEN],
+        &[0; 16],
+        &hex!("0388dace60b6a392f328c2b971b2fe78 ab6e47d42cec13bdf53a67b21257bddf"),
+    ),
+    (
+        hex!("feffe9928665731c6d6a8f9467308308"),
+        hex!("cafebabefacedbaddecaf888"),
+        &hex!(
+            "d9313225f88406e5a55909c5aff5269a 86a7a9531534f7da2e4c303d8a318a72"
+            "1c3c0c95956809532fcf0e2449a6b525 b16aedf5aa0de657ba637b391aafd255"
+        ),
+        &hex!(
+            "42831ec2217774244b7221b784d0d49c e3aa212f2c02a4e035c17e2329aca12e"
+            "21d514b25466931c7d8f6a5aac84aa05 1ba30b396a0aac973d58e091473f5985"
+            "4d5c2af327cd64a62cf35abd2ba6fab4"
+        ),
+    ),
+];
+
+// Whether AES-128-GCM encryption and decryption reproduce every known-answer vector. Run by `self_test`.
+pub fn gcm_known_answers_match() -> bool {
+    GCM_KNOWN_ANSWERS.iter().all(|(key, nonce, plain_bytes, sealed)| {
+        let cipher = Aes128Gcm::new_from_slice(key).expect("the key is 16 bytes");
+        let nonce = Nonce::from_slice(nonce);
+        let encrypted = cipher.encrypt(nonce, *plain_bytes);
+        let decrypted = cipher.decrypt(nonce, *sealed);
+        matches!((encrypted, decrypted), (Ok(e), Ok(d)) if e == *sealed && d == *plain_bytes)
+    })
+}
+
+pub(crate) fn gcm_cipher(secret_key: &BigUint) -> Aes128Gcm {
+    Aes128Gcm::new_from_slice(&Zeroizing::new(derive_gcm_key(secret_key))[..]).expect("derived key is 16 bytes")
+}
+
+// Encrypt and authenticate the given bytes with a key derived from the secret.
+#[cfg(feature = "std")]
+pub fn encrypt_gcm(plain_bytes: &[u8], secret_key: &BigUint) -> Result<Vec<u8>, DhError> {
+    encrypt_gcm_with_rng(plain_bytes, secret_key, &mut rand::thread_rng())
+}
+
+// Like `encrypt_gcm`, with the nonce drawn from `rng`, for builds without `std`.
+pub fn encrypt_gcm_with_rng<R: CryptoRng + RngCore>(
+    plain_bytes: &[u8],
+    secret_key: &BigUint,
+    rng: &mut R,
+) -> Result<Vec<u8>, DhError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+    let ciphertext = gcm_cipher(secret_key)
+        .encrypt(Nonce::from_slice(&nonce), plain_bytes)
+        .map_err(|_| DhError::EncryptionFailed)?;
+
+    let mut message = nonce.to_vec();
+    message.extend_from_slice(&ciphertext);
+    Ok(message)
+}
+
+// Like `encrypt_gcm`, but writes the message into `out`, replacing whatever it held. The buffer's capacity is reused,
+// so a server encrypting message after message into the same buffer stops allocating once it has grown to fit.
+#[cfg(feature = "std")]
+pub fn encrypt_gcm_into(plain_bytes: &[u8], secret_key: &BigUint, out: &mut Vec<u8>) -> Result<(), DhError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    out.clear();
+    out.reserve(NONCE_LEN + plain_bytes.len() + TAG_LEN);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(plain_bytes);
+    let tag = gcm_cipher(secret_key)
+        .encrypt_in_place_detached(Nonce::from_slice(&nonce), &[], &mut out[NONCE_LEN..])
+        .map_err(|_| DhError::EncryptionFailed)?;
+    out.extend_from_slice(&tag);
+    Ok(())
+}
+
+// Verify and decrypt a message produced by `encrypt_gcm`. Any tampering, or the wrong secret, is reported as
+// `DhError::DecryptionFailed`.
+pub fn decrypt_gcm(message: &[u8], secret_key: &BigUint) -> Result<Vec<u8>, DhError> {
+    decrypt_gcm_limited(message, secret_key, usize::MAX)
+}
+
+// Like `decrypt_gcm`, but refuses with `DhError::OutputTooLarge` if the plaintext would be longer than
+// `max_output_len` bytes. The plaintext length follows from the message length, so an oversized message is rejected
+// before anything is decrypted or allocated.
+pub fn decrypt_gcm_limited(message: &[u8], secret_key: &BigUint, max_output_len: usize) -> Result<Vec<u8>, DhError> {
+    if message.len() < NONCE_LEN + TAG_LEN {
+        return Err(DhError::DecryptionFailed);
+    }
+    let output_len = message.len() - NONCE_LEN - TAG_LEN;
+    if output_len > max_output_len {
+        return Err(DhError::OutputTooLarge { length: output_len, max_length: max_output_len });
+    }
+    let (nonce, ciphertext) = message.split_at(NONCE_LEN);
+    gcm_cipher(secret_key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| DhError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::CONTROL_CHARACTER_TEXT;
+
+    proptest! {
+        #[test]
+        fn decrypt_gcm_undoes_encrypt_gcm(
+            plain_bytes in prop::collection::vec(any::<u8>(), 0..200),
+            key in prop::collection::vec(any::<u8>(), 1..64),
+            seed: u64,
+        ) {
+            let key = BigUint::from_bytes_be(&key);
+            let message = encrypt_gcm_with_rng(&plain_bytes, &key, &mut StdRng::seed_from_u64(seed)).unwrap();
+            prop_assert_eq!(message.len(), NONCE_LEN + plain_bytes.len() + TAG_LEN);
+            prop_assert_eq!(decrypt_gcm(&message, &key).unwrap(), plain_bytes);
+        }
+    }
+
+    #[test]
+    fn control_characters_round_trip() {
+        let key = BigUint::from(0x1234_5678u32);
+        let mut rng = StdRng::seed_from_u64(1);
+        let message = encrypt_gcm_with_rng(CONTROL_CHARACTER_TEXT.as_bytes(), &key, &mut rng).unwrap();
+        assert_eq!(decrypt_gcm(&message, &key).unwrap(), CONTROL_CHARACTER_TEXT.as_bytes());
+    }
+
+    #[test]
+    fn oversized_payloads_exceed_the_limit() {
+        let key = BigUint::from(0x1234_5678u32);
+        let message = encrypt_gcm_with_rng(&[0x42; 1000], &key, &mut StdRng::seed_from_u64(132)).unwrap();
+        assert!(matches!(
+            decrypt_gcm_limited(&message, &key, 999),
+            Err(DhError::OutputTooLarge { length: 1000, max_length: 999 })
+        ));
+        assert_eq!(decrypt_gcm_limited(&message, &key, 1000).unwrap(), [0x42; 1000]);
+    }
+
+    // The limit is checked from the length alone, before authentication, so garbage of that size is refused too.
+    #[test]
+    fn limit_is_checked_before_decrypting() {
+        let garbage = vec![0u8; NONCE_LEN + TAG_LEN + 4096];
+        let key = BigUint::from(1u32);
+        assert!(matches!(decrypt_gcm_limited(&garbage, &key, 16), Err(DhError::OutputTooLarge { .. })));
+        assert!(matches!(decrypt_gcm_limited(&garbage, &key, 4096), Err(DhError::DecryptionFailed)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reused_buffer_holds_each_message_on_its_own() {
+        let key = BigUint::from(0x5eed_u32);
+        let mut out = Vec::new();
+        let mut messages = Vec::new();
+        for plain in [&b"a longer first message"[..], b"short", b"", b"short"] {
+            encrypt_gcm_into(plain, &key, &mut out).unwrap();
+            assert_eq!(out.len(), NONCE_LEN + plain.len() + TAG_LEN);
+            assert_eq!(decrypt_gcm(&out, &key).unwrap(), plain);
+            messages.push(out.clone());
+        }
+        // Equal plaintexts still get their own nonces.
+        assert_ne!(messages[1], messages[3]);
+
+        // Once the buffer fits the largest message, smaller ones don't reallocate it.
+        let (capacity, buffer) = (out.capacity(), out.as_ptr());
+        encrypt_gcm_into(b"tiny", &key, &mut out).unwrap();
+        assert_eq!((out.capacity(), out.as_ptr()), (capacity, buffer));
+    }
+}
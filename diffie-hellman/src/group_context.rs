@@ -0,0 +1,127 @@
+// Precomputation for servers that run many exchanges in one group. `PrivateKey::public_key` and `agree` set up the
+// modulus's Montgomery constants on every call, and the public key is a full exponentiation of the generator each
+// time. A `GroupContext` does that setup once: it keeps the Montgomery constants, and a fixed-base table of the
+// generator covering private keys of `DhParameters::recommended_exponent_bits`, the size `generate_keypair` draws.
+// With the table, computing a public key takes one multiplication per 4 bits of the key and no squarings, several
+// times faster than the plain exponentiation; `cargo bench --bench group_context` checks the gain.
+//
+// The table holds 16 entries per 4 bits of exponent, about 230 KB for a 2048-bit group, and building it costs
+// roughly as much as three public keys. It only pays off for a context that is kept and reused. Keys longer than the
+// table, e.g. from `generate_keypair_sized`, fall back to the plain exponentiation under the stored constants.
+// Timing is as for `PrivateKey::public_key`: with the `ct` feature it shows only the key's width class.
+
+use num_bigint::BigUint;
+use rand::{CryptoRng, RngCore};
+
+use crate::ct::{FixedBase, ModulusContext};
+use crate::error::DhError;
+use crate::keys::{sample_keypair, PrivateKey, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+
+pub struct GroupContext {
+    params: DhParameters,
+    modulus: ModulusContext,
+    generator: FixedBase,
+}
+
+impl GroupContext {
+    // Set up the constants and the generator table for `params`.
+    pub fn new(params: &DhParameters) -> Self {
+        let modulus = ModulusContext::new(&params.modulus);
+        let generator = FixedBase::new(&params.generator, params.recommended_exponent_bits(), &modulus);
+        GroupContext { params: params.clone(), modulus, generator }
+    }
+
+    pub fn params(&self) -> &DhParameters {
+        &self.params
+    }
+
+    // `generate_keypair` in this group, with the public key from the table.
+    #[cfg(feature = "std")]
+    pub fn generate_keypair(&self) -> Result<(PrivateKey, PublicKey), DhError> {
+        self.generate_keypair_with_rng(&mut rand::thread_rng())
+    }
+
+    // `generate_keypair_with_rng` in this group, with the public key from the table.
+    pub fn generate_keypair_with_rng<R: CryptoRng + RngCore>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(PrivateKey, PublicKey), DhError> {
+        sample_keypair(&self.params, self.params.recommended_exponent_bits(), rng, |key| self.public_key(key))
+    }
+
+    // The same value as `private_key.public_key(self.params())`.
+    pub fn public_key(&self, private_key: &PrivateKey) -> PublicKey {
+        let public_value = self.generator.pow(&private_key.0);
+        PublicKey(public_value.unwrap_or_else(|| self.modpow(&self.params.generator, private_key)))
+    }
+
+    // The same value as `private_key.agree(peer_public, self.params())`. The peer's key changes with every exchange,
+    // so only the Montgomery constants are reused.
+    pub fn agree(&self, private_key: &PrivateKey, peer_public: &PublicKey) -> SharedSecret {
+        SharedSecret(self.modpow(&peer_public.0, private_key))
+    }
+
+    // Check each of `peers` with `PublicKey::validate_in_group` and agree with it, spreading the work over rayon's
+    // thread pool. Meant for servers that terminate many handshakes against one key. Every peer gets its own result,
+    // in the order given, so one bad key fails only its own entry.
+    #[cfg(feature = "parallel")]
+    pub fn derive_many(&self, private_key: &PrivateKey, peers: &[PublicKey]) -> Vec<Result<SharedSecret, DhError>> {
+        use rayon::prelude::*;
+
+        peers
+            .par_iter()
+            .map(|peer_public| {
+                peer_public.validate_in_group(&self.params)?;
+                Ok(self.agree(private_key, peer_public))
+            })
+            .collect()
+    }
+
+    fn modpow(&self, base: &BigUint, private_key: &PrivateKey) -> BigUint {
+        self.modulus.modpow_secret(base, &private_key.0, private_key.exponent_width(&self.params))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::keys::generate_keypair_sized;
+
+    #[test]
+    fn the_context_matches_the_plain_operations() {
+        let params = DhParameters::modp_2048();
+        let context = GroupContext::new(&params);
+        let (private_key, public_key) = context.generate_keypair().unwrap();
+        assert_eq!(context.public_key(&private_key), private_key.public_key(&params));
+        assert_eq!(public_key, private_key.public_key(&params));
+
+        // A key longer than the table falls back to the plain exponentiation.
+        let (long_key, long_public) = generate_keypair_sized(&params, 2040).unwrap();
+        assert_eq!(context.public_key(&long_key), long_public);
+        assert!(context.agree(&private_key, &long_public) == long_key.agree(&public_key, &params));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn derive_many_fails_only_the_bad_entries() {
+        let params = DhParameters::modp_2048();
+        let context = GroupContext::new(&params);
+        let (private_key, _) = context.generate_keypair().unwrap();
+        let peers: Vec<_> = (0..8).map(|_| context.generate_keypair().unwrap()).collect();
+        let mut public_keys: Vec<_> = peers.iter().map(|(_, public_key)| public_key.clone()).collect();
+        public_keys[3] = PublicKey::from_biguint(&params.modulus - 1u32);
+        public_keys[6] = PublicKey::from_biguint(BigUint::from(1u32));
+
+        let secrets = context.derive_many(&private_key, &public_keys);
+        assert_eq!(secrets.len(), public_keys.len());
+        for (i, (secret, (peer_private, _))) in secrets.iter().zip(&peers).enumerate() {
+            if i == 3 || i == 6 {
+                assert!(matches!(secret, Err(DhError::InvalidPublicKey(_))), "{i}");
+            } else {
+                let expected = peer_private.agree(&context.public_key(&private_key), &params);
+                assert!(*secret.as_ref().unwrap() == expected, "{i}");
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// Diffie-Hellman among more than two parties, agreeing on one secret g^(x_1 * x_2 * ... * x_n) mod p. The parties sit
+// in a ring, numbered 0 to n-1, and each only ever sends to the next one (position + 1, wrapping around) and receives
+// from the previous one:
+//
+// 1. Every party sends its public key g^x to the next.
+// 2. For n-2 more rounds, every party raises the value it just received to its own private exponent and passes the
+//    result on, so each value picks up one more party's exponent on every hop.
+// 3. The value that arrives after that carries the exponents of everyone else, so raising it to its own exponent
+//    gives each party the shared secret.
+//
+// That is n - 1 rounds of messages and n exponentiations per party. Like two-party Diffie-Hellman this is only safe
+// against a passive eavesdropper: any party in the ring, or anyone who can rewrite its messages, can substitute
+// values, so the links need authenticating in practice. Each incoming value is checked with `validate_public_key`.
+
+use crate::error::DhError;
+use crate::keys::{generate_keypair, validate_public_key, PrivateKey, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+
+// One party's side of a group exchange.
+pub struct GroupExchange {
+    params: DhParameters,
+    private_key: PrivateKey,
+    public_key: PublicKey,
+    size: usize,
+    // How many values have arrived from the previous party so far.
+    received: usize,
+    secret: Option<SharedSecret>,
+}
+
+impl GroupExchange {
+    // Join a group of `size` parties at `position`, with a fresh private key. A group needs at least two parties,
+    // or `DhError::InvalidGroupSize`, and positions run from 0 to `size - 1`, or `DhError::InvalidGroupPosition`.
+    // The position only says where the party sits in the ring; every party computes the same secret.
+    pub fn new(params: &DhParameters, position: usize, size: usize) -> Result<Self, DhError> {
+        if size < 2 {
+            return Err(DhError::InvalidGroupSize { size });
+        }
+        if position >= size {
+            return Err(DhError::InvalidGroupPosition { position, size });
+        }
+        let (private_key, public_key) = generate_keypair(params)?;
+        Ok(GroupExchange { params: params.clone(), private_key, public_key, size, received: 0, secret: None })
+    }
+
+    // The value to send to the next party in the first round: our public key.
+    pub fn first_message(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    // Take the value the previous party sent this round. Until the last round this gives back the value to pass on
+    // to the next party; in the last round it computes the shared secret and gives back `None`. Values arriving after
+    // that are refused with `DhError::MalformedMessage`, and the exchange keeps its secret.
+    pub fn process(&mut self, incoming: &PublicKey) -> Result<Option<PublicKey>, DhError> {
+        if self.secret.is_some() {
+            return Err(DhError::MalformedMessage);
+        }
+        validate_public_key(incoming, &self.params)?;
+        let raised = self.private_key.agree(incoming, &self.params);
+        self.received += 1;
+        if self.received == self.size - 1 {
+            self.secret = Some(raised);
+            return Ok(None);
+        }
+        Ok(Some(PublicKey::from_biguint(raised.as_biguint().clone())))
+    }
+
+    // Whether every round has been processed.
+    pub fn is_complete(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    // The group's shared secret, once the last round has been processed.
+    pub fn shared_secret(&self) -> Option<&SharedSecret> {
+        self.secret.as_ref()
+    }
+}
+
+// Run a whole group exchange among `size` parties in this process, handing every message straight to its recipient,
+// and return each party's secret in ring order. All of them are equal. Useful for demos and for checking an
+// implementation of the other side; real parties run one `GroupExchange` each.
+pub fn run_group_exchange(params: &DhParameters, size: usize) -> Result<Vec<SharedSecret>, DhError> {
+    let mut parties =
+        (0..size).map(|position| GroupExchange::new(params, position, size)).collect::<Result<Vec<_>, _>>()?;
+    let mut in_flight: Vec<PublicKey> = parties.iter().map(|party| party.first_message().clone()).collect();
+    for _ in 1..size {
+        let mut next = Vec::with_capacity(size);
+        for (position, party) in parties.iter_mut().enumerate() {
+            let from_previous = &in_flight[(position + size - 1) % size];
+            if let Some(value) = party.process(from_previous)? {
+                next.push(value);
+            }
+        }
+        in_flight = next;
+    }
+    Ok(parties.iter_mut().map(|party| party.secret.take().expect("every round was processed")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::params::Group;
+
+    // A 31-bit safe prime, so every incoming value's subgroup check is quick.
+    fn toy_group() -> DhParameters {
+        DhParameters::from_u64_allow_insecure(2, 2147483783).unwrap()
+    }
+
+    #[test]
+    fn three_to_five_parties_agree() {
+        for size in 3..=5 {
+            let secrets = run_group_exchange(&toy_group(), size).unwrap();
+            assert_eq!(secrets.len(), size);
+            assert!(secrets.iter().all(|secret| *secret == secrets[0]), "{size} parties");
+        }
+    }
+
+    #[test]
+    fn the_secret_carries_every_exponent() {
+        let params = toy_group();
+        let size = 4;
+        let mut parties: Vec<_> =
+            (0..size).map(|position| GroupExchange::new(&params, position, size).unwrap()).collect();
+        let mut in_flight: Vec<_> = parties.iter().map(|party| party.first_message().clone()).collect();
+        for round in 1..size {
+            assert!(parties.iter().all(|party| !party.is_complete()));
+            let mut next = Vec::new();
+            for position in 0..size {
+                let outgoing = parties[position].process(&in_flight[(position + size - 1) % size]).unwrap();
+                assert_eq!(outgoing.is_none(), round == size - 1);
+                next.extend(outgoing);
+            }
+            in_flight = next;
+        }
+
+        let product = parties.iter().fold(BigUint::from(1u32), |product, party| product * &party.private_key.0);
+        let expected = params.generator.modpow(&product, &params.modulus);
+        for party in &mut parties {
+            assert_eq!(party.shared_secret().unwrap().as_biguint(), &expected);
+            let late = party.first_message().clone();
+            assert!(matches!(party.process(&late), Err(DhError::MalformedMessage)));
+            assert!(party.is_complete());
+        }
+    }
+
+    #[test]
+    fn bad_sizes_positions_and_values_are_refused() {
+        let params = Group::Ffdhe2048.parameters();
+        assert!(matches!(GroupExchange::new(&params, 0, 1), Err(DhError::InvalidGroupSize { size: 1 })));
+        let result = GroupExchange::new(&params, 3, 3);
+        assert!(matches!(result, Err(DhError::InvalidGroupPosition { position: 3, size: 3 })));
+
+        let mut party = GroupExchange::new(&toy_group(), 0, 3).unwrap();
+        let result = party.process(&PublicKey::from_biguint(BigUint::from(1u32)));
+        assert!(matches!(result, Err(DhError::InvalidPublicKey(_))));
+        assert!(!party.is_complete());
+    }
+}
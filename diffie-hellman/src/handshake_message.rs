@@ -0,0 +1,278 @@
+// Typed messages for a versioned wire protocol, so the handshake has an explicit structure instead of bytes written
+// ad hoc. Every message is
+//
+//     magic "DH" (2 bytes) || version (1 byte) || message type (1 byte) || timestamp (8 bytes) || fields
+//         || extensions
+//
+// where fixed-size fields are big-endian integers and variable-size fields are a u32 big-endian length followed by
+// that many bytes. The timestamp is the sender's clock in seconds since the Unix epoch, or 0 from a sender without
+// one. Public keys are carried as their wire bytes; check them with `PublicKey::from_wire_bytes` once the group is
+// known.
+//
+// Extensions are what keeps the format open: each is a u16 type, then its value as a variable-size field, and they run
+// to the end of the message. A later version adds a field as a new extension type, and a peer that doesn't know the
+// type keeps it in `WireMessage::extensions` without looking at it, so old peers go on parsing new messages.
+//
+// The magic bytes tell a protocol message from stray input. A message with another version is
+// `DhError::ProtocolVersionMismatch`, so a peer speaking a different version is turned away before anything is
+// decrypted. A hello can list the versions its sender speaks in `EXTENSION_SUPPORTED_VERSIONS`, and the other end
+// picks one with `negotiate_version`. A sender that speaks several versions writes its hello in the oldest of them,
+// so that every peer it might meet can read the list.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::error::DhError;
+
+// Written at the start of every message.
+pub const PROTOCOL_MAGIC: [u8; 2] = *b"DH";
+
+// The protocol version written into, and required of, every message.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+// The versions this end speaks, newest first.
+pub const SUPPORTED_VERSIONS: &[u8] = &[PROTOCOL_VERSION];
+
+// Extension type for the versions a hello's sender speaks, one byte each.
+pub const EXTENSION_SUPPORTED_VERSIONS: u16 = 1;
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const ENCRYPTED_DATA: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeMessage {
+    // Opens the handshake: the client's public key and a bitmask of the features it supports.
+    ClientHello { public_key: Vec<u8>, capabilities: u32 },
+    // The server's answer: its public key and the session salt both ends pass to `Session::new`.
+    ServerHello { public_key: Vec<u8>, salt: Vec<u8> },
+    // A message sealed with the agreed keys, carrying the counter its nonce was built from.
+    EncryptedData { counter: u64, ciphertext: Vec<u8> },
+}
+
+// One extension: its type and value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub kind: u16,
+    pub value: Vec<u8>,
+}
+
+// A message with everything the wire format carries around it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireMessage {
+    pub message: HandshakeMessage,
+    pub timestamp: u64,
+    pub extensions: Vec<Extension>,
+}
+
+impl HandshakeMessage {
+    // Encode with the current time and no extensions. Without `std` there is no clock, so the timestamp is 0; use
+    // `WireMessage` to set one.
+    pub fn encode(&self) -> Vec<u8> {
+        WireMessage::new(self.clone(), HandshakeMessage::timestamp_now()).encode()
+    }
+
+    // The timestamp `encode` writes: the current time in seconds since the Unix epoch, or 0 without `std`.
+    pub fn timestamp_now() -> u64 {
+        unix_time()
+    }
+
+    // Decode exactly one message, dropping its timestamp and extensions. See `WireMessage::decode` for the errors.
+    pub fn decode(bytes: &[u8]) -> Result<HandshakeMessage, DhError> {
+        Ok(WireMessage::decode(bytes)?.message)
+    }
+}
+
+impl WireMessage {
+    pub fn new(message: HandshakeMessage, timestamp: u64) -> Self {
+        WireMessage { message, timestamp, extensions: Vec::new() }
+    }
+
+    pub fn with_extension(mut self, kind: u16, value: Vec<u8>) -> Self {
+        self.extensions.push(Extension { kind, value });
+        self
+    }
+
+    // The value of the first extension of type `kind`, if there is one.
+    pub fn extension(&self, kind: u16) -> Option<&[u8]> {
+        self.extensions.iter().find(|extension| extension.kind == kind).map(|extension| extension.value.as_slice())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![PROTOCOL_MAGIC[0], PROTOCOL_MAGIC[1], PROTOCOL_VERSION];
+        let message_type = match self.message {
+            HandshakeMessage::ClientHello { .. } => CLIENT_HELLO,
+            HandshakeMessage::ServerHello { .. } => SERVER_HELLO,
+            HandshakeMessage::EncryptedData { .. } => ENCRYPTED_DATA,
+        };
+        out.push(message_type);
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        match &self.message {
+            HandshakeMessage::ClientHello { public_key, capabilities } => {
+                put_bytes(&mut out, public_key);
+                out.extend_from_slice(&capabilities.to_be_bytes());
+            }
+            HandshakeMessage::ServerHello { public_key, salt } => {
+                put_bytes(&mut out, public_key);
+                put_bytes(&mut out, salt);
+            }
+            HandshakeMessage::EncryptedData { counter, ciphertext } => {
+                out.extend_from_slice(&counter.to_be_bytes());
+                put_bytes(&mut out, ciphertext);
+            }
+        }
+        for extension in &self.extensions {
+            out.extend_from_slice(&extension.kind.to_be_bytes());
+            put_bytes(&mut out, &extension.value);
+        }
+        out
+    }
+
+    // Decode exactly one message. Input without the magic bytes, truncated input, an extension cut short and unknown
+    // message types are `DhError::MalformedMessage`; a different protocol version is
+    // `DhError::ProtocolVersionMismatch`. Extensions of unknown types are kept, not rejected.
+    pub fn decode(bytes: &[u8]) -> Result<WireMessage, DhError> {
+        let mut input = Input(bytes);
+        if input.take(PROTOCOL_MAGIC.len())? != PROTOCOL_MAGIC {
+            return Err(DhError::MalformedMessage);
+        }
+        let version = input.u8()?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(DhError::ProtocolVersionMismatch { version });
+        }
+        let message_type = input.u8()?;
+        let timestamp = input.u64()?;
+        let message = match message_type {
+            CLIENT_HELLO => HandshakeMessage::ClientHello { public_key: input.bytes()?, capabilities: input.u32()? },
+            SERVER_HELLO => HandshakeMessage::ServerHello { public_key: input.bytes()?, salt: input.bytes()? },
+            ENCRYPTED_DATA => HandshakeMessage::EncryptedData { counter: input.u64()?, ciphertext: input.bytes()? },
+            _ => return Err(DhError::MalformedMessage),
+        };
+        let mut extensions = Vec::new();
+        while !input.0.is_empty() {
+            extensions.push(Extension { kind: input.u16()?, value: input.bytes()? });
+        }
+        Ok(WireMessage { message, timestamp, extensions })
+    }
+}
+
+// Pick the version to speak with a peer that offers `peer_versions`, as carried in `EXTENSION_SUPPORTED_VERSIONS`:
+// the newest one both ends speak. None in common, or an empty offer, is `DhError::ProtocolVersionMismatch` naming the
+// peer's newest version.
+pub fn negotiate_version(peer_versions: &[u8]) -> Result<u8, DhError> {
+    SUPPORTED_VERSIONS.iter().copied().find(|version| peer_versions.contains(version)).ok_or_else(|| {
+        DhError::ProtocolVersionMismatch { version: peer_versions.iter().copied().max().unwrap_or(0) }
+    })
+}
+
+#[cfg(feature = "std")]
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(not(feature = "std"))]
+fn unix_time() -> u64 {
+    0
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+// The not yet decoded rest of a message.
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DhError> {
+        if self.0.len() < len {
+            return Err(DhError::MalformedMessage);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DhError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DhError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().expect("took 2 bytes")))
+    }
+
+    fn u32(&mut self) -> Result<u32, DhError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().expect("took 4 bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64, DhError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().expect("took 8 bytes")))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DhError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_variant() -> [HandshakeMessage; 3] {
+        [
+            HandshakeMessage::ClientHello { public_key: vec![0xc1; 256], capabilities: 0x8000_0001 },
+            HandshakeMessage::ServerHello { public_key: vec![0x5e; 256], salt: vec![7; 16] },
+            HandshakeMessage::EncryptedData { counter: u64::MAX, ciphertext: vec![] },
+        ]
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        for message in every_variant() {
+            assert_eq!(HandshakeMessage::decode(&message.encode()).unwrap(), message);
+            let wire = WireMessage::new(message.clone(), 1_700_000_000).with_extension(0xbeef, vec![1, 2, 3]);
+            let decoded = WireMessage::decode(&wire.encode()).unwrap();
+            assert_eq!(decoded, wire);
+            assert_eq!(decoded.extension(0xbeef), Some(&[1, 2, 3][..]));
+            assert_eq!(decoded.extension(EXTENSION_SUPPORTED_VERSIONS), None);
+        }
+    }
+
+    #[test]
+    fn truncated_input_is_malformed() {
+        for message in every_variant() {
+            let bare = WireMessage::new(message, 42);
+            let encoded = bare.clone().with_extension(9, vec![0xaa; 4]).encode();
+            // Cut right where the extensions start, what is left is the message without them.
+            let bare_len = bare.encode().len();
+            assert_eq!(WireMessage::decode(&encoded[..bare_len]).unwrap(), bare);
+            for len in (0..encoded.len()).filter(|&len| len != bare_len) {
+                assert!(matches!(WireMessage::decode(&encoded[..len]), Err(DhError::MalformedMessage)), "{len}");
+            }
+        }
+    }
+
+    #[test]
+    fn foreign_input_is_turned_away() {
+        let mut encoded = every_variant()[0].encode();
+        encoded[0] = b'X';
+        assert!(matches!(HandshakeMessage::decode(&encoded), Err(DhError::MalformedMessage)));
+
+        let mut encoded = every_variant()[0].encode();
+        encoded[2] = PROTOCOL_VERSION + 1;
+        let result = HandshakeMessage::decode(&encoded);
+        assert!(matches!(result, Err(DhError::ProtocolVersionMismatch { version }) if version == PROTOCOL_VERSION + 1));
+
+        let mut encoded = every_variant()[0].encode();
+        encoded[3] = 0x7f;
+        assert!(matches!(HandshakeMessage::decode(&encoded), Err(DhError::MalformedMessage)));
+    }
+
+    #[test]
+    fn versions_are_negotiated_from_the_peers_offer() {
+        assert_eq!(negotiate_version(&[1, PROTOCOL_VERSION, 9]).unwrap(), PROTOCOL_VERSION);
+        assert!(matches!(negotiate_version(&[1, 9]), Err(DhError::ProtocolVersionMismatch { version: 9 })));
+        assert!(matches!(negotiate_version(&[]), Err(DhError::ProtocolVersionMismatch { version: 0 })));
+    }
+}
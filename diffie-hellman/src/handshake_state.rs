@@ -0,0 +1,210 @@
+// The handshake as a typestate, so the compiler enforces its order instead of the caller. A
+// `Handshake<AwaitingPeer>` holds a fresh ephemeral key and can only give out its public key or take the peer's. Taking
+// the peer's key uses up the ephemeral key and moves to `Handshake<AwaitingConfirmation>`, which holds the secret but
+// offers no way to encrypt with it. Only a peer tag that verifies moves on to `Handshake<Confirmed>`, and only that
+// state starts a `Session`. Each step consumes the previous state, so a key can't be agreed with twice and nothing
+// can be encrypted before the peer has proven it holds the same secret.
+//
+// The states do no I/O; the caller carries the public keys and tags over whatever transport it has. Both ends send
+// their public key, then their `confirmation_tag`, and check the tag they receive with `confirm`. The transcript
+// covers the parameters and both public keys in ascending order, as in `confirmed_handshake`, and its hash is the
+// salt of the session, so the two ends get matching sessions without exchanging anything more.
+// `into_audited_session` starts the session with an audit log.
+
+use crate::confirmation::{confirmation_tag, verify_confirmation_tag, CONFIRMATION_TAG_LEN};
+use crate::ephemeral::EphemeralSecret;
+use crate::error::DhError;
+use crate::keys::{PublicKey, SharedSecret};
+use crate::params::DhParameters;
+use crate::session::{Role, Session};
+use crate::session_audit::SessionAudit;
+use crate::transcript::Transcript;
+
+// Before the peer's public key has arrived.
+pub struct AwaitingPeer {
+    ephemeral: EphemeralSecret,
+}
+
+// The secret is agreed, but the peer hasn't confirmed it yet.
+pub struct AwaitingConfirmation {
+    agreed: Agreed,
+}
+
+// Both ends have proven they hold the same secret over the same transcript.
+pub struct Confirmed {
+    agreed: Agreed,
+}
+
+// What the handshake has agreed on, kept from the peer's key onward.
+struct Agreed {
+    secret: SharedSecret,
+    transcript: Transcript,
+    public_key: PublicKey,
+    peer_public: PublicKey,
+}
+
+/// One end of a handshake, in state `S`. Deliberately not `Clone`, like `EphemeralSecret`. A handshake whose peer
+/// hasn't confirmed the secret can't start a session:
+///
+/// ```compile_fail
+/// use diffie_hellman::{DhParameters, Handshake, Role};
+///
+/// let params = DhParameters::modp_2048();
+/// let ours = Handshake::new(&params, Role::Initiator).unwrap();
+/// let theirs = Handshake::new(&params, Role::Responder).unwrap();
+/// let session = ours.receive_peer_key(theirs.public_key()).unwrap().into_session();
+/// ```
+///
+/// and the ephemeral key is gone once it has agreed with a peer:
+///
+/// ```compile_fail
+/// use diffie_hellman::{DhParameters, Handshake, Role};
+///
+/// let params = DhParameters::modp_2048();
+/// let ours = Handshake::new(&params, Role::Initiator).unwrap();
+/// let theirs = Handshake::new(&params, Role::Responder).unwrap();
+/// let first = ours.receive_peer_key(theirs.public_key());
+/// let second = ours.receive_peer_key(theirs.public_key());
+/// ```
+pub struct Handshake<S> {
+    params: DhParameters,
+    role: Role,
+    state: S,
+}
+
+impl<S> Handshake<S> {
+    pub fn params(&self) -> &DhParameters {
+        &self.params
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+}
+
+impl Handshake<AwaitingPeer> {
+    // Start a handshake with a fresh ephemeral key. The two ends take opposite roles, as for `Session::new`.
+    pub fn new(params: &DhParameters, role: Role) -> Result<Self, DhError> {
+        let ephemeral = EphemeralSecret::random(params)?;
+        Ok(Handshake { params: params.clone(), role, state: AwaitingPeer { ephemeral } })
+    }
+
+    // The public key to send to the peer.
+    pub fn public_key(&self) -> &PublicKey {
+        self.state.ephemeral.public_key()
+    }
+
+    // Agree on the secret with the peer's key, checked as by `EphemeralSecret::diffie_hellman`. The ephemeral key is
+    // used up either way.
+    pub fn receive_peer_key(self, peer_public: &PublicKey) -> Result<Handshake<AwaitingConfirmation>, DhError> {
+        let public_key = self.state.ephemeral.public_key().clone();
+        let secret = self.state.ephemeral.diffie_hellman(peer_public)?;
+        let mut transcript = Transcript::new(&self.params);
+        let mut keys = [public_key.as_biguint(), peer_public.as_biguint()];
+        keys.sort();
+        for key in keys {
+            transcript.append(&key.to_bytes_be());
+        }
+        let agreed = Agreed { secret, transcript, public_key, peer_public: peer_public.clone() };
+        Ok(Handshake { params: self.params, role: self.role, state: AwaitingConfirmation { agreed } })
+    }
+}
+
+impl Handshake<AwaitingConfirmation> {
+    // The tag to send to the peer, see `confirmation_tag`.
+    pub fn confirmation_tag(&self) -> [u8; CONFIRMATION_TAG_LEN] {
+        let agreed = &self.state.agreed;
+        confirmation_tag(&agreed.secret, &agreed.transcript, &agreed.public_key)
+    }
+
+    // Check the peer's tag. A tag that doesn't verify is `DhError::KeyConfirmationFailed`, and the handshake is over:
+    // start a new one rather than trying another tag.
+    pub fn confirm(self, peer_tag: &[u8]) -> Result<Handshake<Confirmed>, DhError> {
+        let agreed = self.state.agreed;
+        verify_confirmation_tag(&agreed.secret, &agreed.transcript, &agreed.peer_public, peer_tag)?;
+        Ok(Handshake { params: self.params, role: self.role, state: Confirmed { agreed } })
+    }
+}
+
+impl Handshake<Confirmed> {
+    pub fn transcript(&self) -> &Transcript {
+        &self.state.agreed.transcript
+    }
+
+    pub fn peer_public_key(&self) -> &PublicKey {
+        &self.state.agreed.peer_public
+    }
+
+    // The session for the confirmed secret, salted with the transcript hash.
+    pub fn into_session(self) -> Session {
+        let agreed = self.state.agreed;
+        Session::new(&agreed.secret, &agreed.transcript.hash(), self.role)
+    }
+
+    // `into_session` with an audit log of the group and both public keys, see session_audit.rs.
+    pub fn into_audited_session(self) -> Session {
+        let agreed = &self.state.agreed;
+        let audit = SessionAudit::new(&self.params, self.role, &agreed.public_key, &agreed.peer_public);
+        self.into_session().with_audit_log(audit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 31-bit safe prime, so that checking the peer's key with `validate_public_key` is quick.
+    fn toy_group() -> DhParameters {
+        DhParameters::from_u64_allow_insecure(2, 2147483783).unwrap()
+    }
+
+    // Both ends, each holding the other's public key.
+    fn agreed() -> (Handshake<AwaitingConfirmation>, Handshake<AwaitingConfirmation>) {
+        let params = toy_group();
+        let initiator = Handshake::new(&params, Role::Initiator).unwrap();
+        let responder = Handshake::new(&params, Role::Responder).unwrap();
+        let initiator_public = initiator.public_key().clone();
+        let initiator = initiator.receive_peer_key(responder.public_key()).unwrap();
+        (initiator, responder.receive_peer_key(&initiator_public).unwrap())
+    }
+
+    #[test]
+    fn a_confirmed_handshake_gives_matching_sessions() {
+        let (initiator, responder) = agreed();
+        let (initiator_tag, responder_tag) = (initiator.confirmation_tag(), responder.confirmation_tag());
+        assert_ne!(initiator_tag, responder_tag);
+        let initiator = initiator.confirm(&responder_tag).unwrap();
+        let responder = responder.confirm(&initiator_tag).unwrap();
+        assert_eq!(initiator.transcript().hash(), responder.transcript().hash());
+        assert_eq!(initiator.role(), Role::Initiator);
+
+        let mut initiator = initiator.into_session();
+        let mut responder = responder.into_audited_session();
+        assert_eq!(responder.decrypt_next(&initiator.encrypt_next("hello").unwrap()).unwrap(), "hello");
+        assert_eq!(initiator.decrypt_next(&responder.encrypt_next("hi").unwrap()).unwrap(), "hi");
+    }
+
+    #[test]
+    fn a_wrong_tag_ends_the_handshake() {
+        let (initiator, responder) = agreed();
+        // Our own tag reflected back doesn't confirm the peer.
+        let reflected = initiator.confirmation_tag();
+        assert!(matches!(initiator.confirm(&reflected), Err(DhError::KeyConfirmationFailed)));
+        let mut altered = responder.confirmation_tag();
+        altered[0] ^= 1;
+        let (initiator, _) = agreed();
+        assert!(matches!(initiator.confirm(&altered), Err(DhError::KeyConfirmationFailed)));
+        assert!(matches!(responder.confirm(&[]), Err(DhError::KeyConfirmationFailed)));
+    }
+
+    #[test]
+    fn bad_peer_keys_are_refused() {
+        let params = toy_group();
+        let handshake = Handshake::new(&params, Role::Initiator).unwrap();
+        let reflected = handshake.public_key().clone();
+        assert!(matches!(handshake.receive_peer_key(&reflected), Err(DhError::IdenticalPublicKeys)));
+        let handshake = Handshake::new(&params, Role::Initiator).unwrap();
+        let trivial = PublicKey::from_biguint(1u32.into());
+        assert!(matches!(handshake.receive_peer_key(&trivial), Err(DhError::InvalidPublicKey(_))));
+    }
+}
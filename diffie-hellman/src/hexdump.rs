@@ -0,0 +1,97 @@
+// Annotated hex dumps of wire traffic for debugging interop with other implementations, in the style of
+// `hexdump -C`: offset, sixteen bytes in hex, and their printable ASCII.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+
+// Format `bytes` as a labelled hex dump, for example:
+//
+// sent (5 bytes)
+// 00000000  00 00 00 01 41                                    |....A|
+pub fn hexdump(label: &str, bytes: &[u8]) -> String {
+    let mut out = format!("{} ({} bytes)\n", label, bytes.len());
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x}  ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, "{:02x} ", byte);
+                }
+                None => out.push_str("   "),
+            }
+            if i == 7 {
+                out.push(' ');
+            }
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        let _ = writeln!(out, " |{}|", ascii);
+    }
+    out
+}
+
+// Wraps a stream and writes a hex dump of every chunk read from or written to it to standard error.
+pub struct HexdumpStream<S> {
+    inner: S,
+}
+
+impl<S> HexdumpStream<S> {
+    pub fn new(inner: S) -> Self {
+        HexdumpStream { inner }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for HexdumpStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        eprint!("{}", hexdump("received", &buf[..len]));
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for HexdumpStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        eprint!("{}", hexdump("sent", &buf[..len]));
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_offsets_hex_and_ascii() {
+        let dump = hexdump("sent", b"Hello, hexdump!\x00\x01\x7f");
+        let expected = "sent (18 bytes)\n\
+            00000000  48 65 6c 6c 6f 2c 20 68  65 78 64 75 6d 70 21 00  |Hello, hexdump!.|\n\
+            00000010  01 7f                                             |..|\n";
+        assert_eq!(dump, expected);
+    }
+
+    #[test]
+    fn empty_input_is_just_the_label() {
+        assert_eq!(hexdump("received", &[]), "received (0 bytes)\n");
+    }
+
+    #[test]
+    fn stream_passes_bytes_through() {
+        let mut stream = HexdumpStream::new(Vec::new());
+        stream.write_all(b"\x00\x00\x00\x01A").unwrap();
+        assert_eq!(stream.into_inner(), b"\x00\x00\x00\x01A");
+        let mut received = Vec::new();
+        HexdumpStream::new(&b"reply"[..]).read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"reply");
+    }
+}
@@ -0,0 +1,122 @@
+// Authenticated key exchange, so a man in the middle can't sit between two parties by running a separate exchange
+// with each. Every party has a long-term Ed25519 identity key and knows its peer's public identity key in advance,
+// e.g. from a configuration file or by checking it out of band. After the ephemeral exchange each side signs the
+// handshake transcript, which covers the parameters and both ephemeral public keys, and checks the peer's signature.
+// An attacker who substituted either ephemeral key can't produce a matching signature without the identity key.
+//
+// This is the signed Diffie-Hellman core of Station-to-Station. The signed data also names the signer's and the
+// verifier's identity keys, so a signature can't be reflected back or presented to someone else. Unlike full STS,
+// the signatures travel in the clear, so an eavesdropper learns who is talking, but not what they say.
+//
+// Wire format: the plain handshake of net.rs, then each side sends its 64-byte signature in one `send_frame`.
+
+use std::io::{Read, Write};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, SIGNATURE_LENGTH};
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::error::DhError;
+use crate::keys::SharedSecret;
+use crate::net::{handshake_with_transcript, receive_frame, send_frame};
+use crate::params::DhParameters;
+use crate::transcript::Transcript;
+
+// Domain separation for the signed data, so these signatures can't be confused with any other use of the key.
+const SIGNATURE_LABEL: &[u8] = b"diffie-hellman signed handshake";
+
+// A party's long-term signing key. Keep it as secret as any private key; the public half is its `PeerIdentity`.
+pub struct IdentityKey(SigningKey);
+
+// A peer's public identity key, as pinned in advance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerIdentity(VerifyingKey);
+
+impl IdentityKey {
+    // A new random identity key.
+    pub fn generate() -> Self {
+        Self::generate_with_rng(&mut rand::rngs::OsRng)
+    }
+
+    // A new identity key drawn from `rng`.
+    pub fn generate_with_rng<R: CryptoRng + RngCore>(rng: &mut R) -> Self {
+        IdentityKey(SigningKey::generate(rng))
+    }
+
+    // Load a stored identity key from its 32-byte seed, as written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        IdentityKey(SigningKey::from_bytes(bytes))
+    }
+
+    // The 32-byte seed, for storing the key somewhere only its owner can read.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    // The public half, to hand to peers.
+    pub fn identity(&self) -> PeerIdentity {
+        PeerIdentity(self.0.verifying_key())
+    }
+
+    // Sign the handshake recorded in `transcript` for `peer`.
+    pub fn sign_handshake(&self, transcript: &Transcript, peer: &PeerIdentity) -> [u8; SIGNATURE_LENGTH] {
+        self.0.sign(&signed_data(transcript, &self.identity(), peer)).to_bytes()
+    }
+}
+
+impl PeerIdentity {
+    // Load a peer's 32-byte public identity key. Bytes that aren't a valid Ed25519 point are rejected with
+    // `DhError::InvalidKeyEncoding`.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, DhError> {
+        VerifyingKey::from_bytes(bytes).map(PeerIdentity).map_err(|_| DhError::InvalidKeyEncoding)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    // Check that this peer signed the handshake in `transcript` for us, identified by `ours`. Anything else, including
+    // a signature of the wrong length, is `DhError::InvalidSignature`. Uses strict verification, which also refuses
+    // weak keys and malleable signatures.
+    pub fn verify_handshake(
+        &self,
+        transcript: &Transcript,
+        ours: &PeerIdentity,
+        signature: &[u8],
+    ) -> Result<(), DhError> {
+        let signature = Signature::from_slice(signature).map_err(|_| DhError::InvalidSignature)?;
+        self.0
+            .verify_strict(&signed_data(transcript, self, ours), &signature)
+            .map_err(|_| DhError::InvalidSignature)
+    }
+}
+
+// label || transcript hash || signer's identity || verifier's identity, hashed so the signature covers a fixed-size
+// message.
+fn signed_data(transcript: &Transcript, signer: &PeerIdentity, verifier: &PeerIdentity) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(SIGNATURE_LABEL)
+        .chain_update(transcript.hash())
+        .chain_update(signer.to_bytes())
+        .chain_update(verifier.to_bytes())
+        .finalize()
+        .into()
+}
+
+// Run `handshake_with_transcript` with the peer on the other end of `stream`, then prove our identity and check the
+// peer's. A peer that doesn't hold the private half of `peer` fails with `DhError::InvalidSignature`, and one that
+// hangs up before sending its signature with `DhError::PeerDisconnected`. The secret must not be used unless this
+// returns `Ok`.
+pub fn authenticated_handshake<S: Read + Write>(
+    stream: &mut S,
+    params: &DhParameters,
+    identity: &IdentityKey,
+    peer: &PeerIdentity,
+) -> Result<(SharedSecret, Transcript), DhError> {
+    let (secret, transcript) = handshake_with_transcript(stream, params)?;
+    send_frame(stream, &identity.sign_handshake(&transcript, peer))?;
+    let signature = receive_frame(stream, SIGNATURE_LENGTH)?
+        .ok_or(DhError::PeerDisconnected { phase: "receiving the signature" })?;
+    peer.verify_handshake(&transcript, &identity.identity(), &signature)?;
+    Ok((secret, transcript))
+}
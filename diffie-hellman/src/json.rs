@@ -0,0 +1,68 @@
+// Encrypting structured values: serialize to JSON, then run the bytes through the same AES layer as plain text messages.
+
+use num_bigint::BigUint;
+use serde::de::{self, DeserializeOwned};
+use serde::Serialize;
+
+use crate::{decrypt_data, encrypt_data};
+
+// Serialize the value to JSON and encrypt it with the provided secret key.
+pub fn encrypt_json<T: Serialize>(value: &T, secret_key: &BigUint) -> serde_json::Result<Vec<u8>> {
+    let json = serde_json::to_vec(value)?;
+    Ok(encrypt_data(&json, secret_key))
+}
+
+// Decrypt the given encrypted data and parse the recovered JSON back into a value. Data that doesn't decrypt is
+// reported as a JSON error carrying the `DhError` message.
+pub fn decrypt_json<T: DeserializeOwned>(encrypted_data: &[u8], secret_key: &BigUint) -> serde_json::Result<T> {
+    let json = decrypt_data(encrypted_data, secret_key).map_err(de::Error::custom)?;
+    serde_json::from_slice(&json)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::CONTROL_CHARACTER_TEXT;
+
+    #[test]
+    fn control_characters_round_trip() {
+        let key = BigUint::from(0x1234_5678u32);
+        let value = serde_json::json!({ "text": CONTROL_CHARACTER_TEXT, "list": [CONTROL_CHARACTER_TEXT] });
+        let decrypted: serde_json::Value = decrypt_json(&encrypt_json(&value, &key).unwrap(), &key).unwrap();
+        assert_eq!(decrypted, value);
+        let text: String = decrypt_json(&encrypt_json(&CONTROL_CHARACTER_TEXT, &key).unwrap(), &key).unwrap();
+        assert_eq!(text, CONTROL_CHARACTER_TEXT);
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        customer: String,
+        items: Vec<String>,
+        total: f64,
+        gift_note: Option<String>,
+    }
+
+    #[test]
+    fn struct_round_trips() {
+        let key = BigUint::from(0x1234_5678u32);
+        let order = Order {
+            id: 42,
+            customer: "Alice".to_string(),
+            items: vec!["book".to_string(), "lamp".to_string()],
+            total: 31.5,
+            gift_note: None,
+        };
+        let encrypted = encrypt_json(&order, &key).unwrap();
+        assert!(!encrypted.windows(5).any(|window| window == b"Alice"));
+        assert_eq!(decrypt_json::<Order>(&encrypted, &key).unwrap(), order);
+    }
+
+    #[test]
+    fn wrong_key_is_an_error() {
+        let encrypted = encrypt_json(&vec![1, 2, 3], &BigUint::from(1u32)).unwrap();
+        assert!(decrypt_json::<Vec<u32>>(&encrypted, &BigUint::from(2u32)).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+// Key derivation from the shared secret. Rather than using the raw Diffie-Hellman value as key material,
+// the secret is run through HKDF-SHA256 so each purpose gets its own independent key.
+
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use hex_literal::hex;
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use sha2::{Sha256, Sha384, Sha512};
+use zeroize::Zeroizing;
+
+#[cfg(feature = "argon2")]
+use crate::error::DhError;
+
+// The hash function HKDF is instantiated with. Both parties must use the same one, so this is part of the protocol
+// configuration shared with the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum HkdfHash {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HkdfHash {
+    // The digest size in bytes. HKDF can derive at most 255 times this much from one label.
+    pub fn output_len(self) -> usize {
+        match self {
+            HkdfHash::Sha256 => 32,
+            HkdfHash::Sha384 => 48,
+            HkdfHash::Sha512 => 64,
+        }
+    }
+}
+
+// Derive a single key of `len` bytes from the shared secret for the purpose named by `label`.
+// Panics if `len` is larger than HKDF-SHA256 can produce (255 * 32 bytes).
+pub fn derive_key(secret: &BigUint, label: &str, len: usize) -> Vec<u8> {
+    derive_key_with_hash(secret, label, HkdfHash::Sha256, len)
+}
+
+// Like `derive_key`, but with HKDF over the chosen hash. The same secret and label give unrelated keys under
+// different hashes. Panics if `len` is more than 255 times `hash.output_len()`.
+pub fn derive_key_with_hash(secret: &BigUint, label: &str, hash: HkdfHash, len: usize) -> Vec<u8> {
+    derive_key_with_hash_and_salt(secret, label, hash, None, len)
+}
+
+// `derive_key_with_hash` with an optional HKDF salt, e.g. a transcript hash as in `derive_key_with_context`.
+pub(crate) fn derive_key_with_hash_and_salt(
+    secret: &BigUint,
+    label: &str,
+    hash: HkdfHash,
+    salt: Option<&[u8]>,
+    len: usize,
+) -> Vec<u8> {
+    let ikm = Zeroizing::new(secret.to_bytes_be());
+    let mut key = vec![0u8; len];
+    let result = match hash {
+        HkdfHash::Sha256 => Hkdf::<Sha256>::new(salt, &ikm).expand(label.as_bytes(), &mut key),
+        HkdfHash::Sha384 => Hkdf::<Sha384>::new(salt, &ikm).expand(label.as_bytes(), &mut key),
+        HkdfHash::Sha512 => Hkdf::<Sha512>::new(salt, &ikm).expand(label.as_bytes(), &mut key),
+    };
+    result.expect("HKDF can derive at most 255 hash lengths per label");
+    key
+}
+
+// Like `derive_key`, but with `context` (e.g. a handshake transcript hash) used as the HKDF salt, so the key also
+// depends on it: the same secret and label with a different context give an unrelated key.
+// Panics if `len` is larger than HKDF-SHA256 can produce (255 * 32 bytes).
+pub fn derive_key_with_context(secret: &BigUint, label: &str, context: &[u8], len: usize) -> Vec<u8> {
+    let mut key = vec![0u8; len];
+    Hkdf::<Sha256>::new(Some(context), &Zeroizing::new(secret.to_bytes_be()))
+        .expand(label.as_bytes(), &mut key)
+        .expect("HKDF-SHA256 can derive at most 8160 bytes per label");
+    key
+}
+
+// Like `derive_key_with_context`, but also bound to a password shared out of band, for a password-augmented exchange.
+// The password is stretched with Argon2id (19 MiB, two passes) under `salt`, and the result is used as the HKDF
+// salt over the Diffie-Hellman secret. Matching passwords give matching keys. If the Diffie-Hellman secret turns out
+// to be weak, an attacker still has to pay for an Argon2 run per password guess. This is not a PAKE: an active
+// attacker who can break the exchange can still test guesses offline. The salt must be at least 8 bytes, and should
+// be random and agreed per exchange; shorter ones are rejected with `DhError::SaltTooShort`.
+// Panics if `len` is larger than HKDF-SHA256 can produce (255 * 32 bytes).
+#[cfg(feature = "argon2")]
+pub fn derive_key_with_password(
+    dh_secret: &BigUint,
+    password: &[u8],
+    salt: &[u8],
+    info: &str,
+    len: usize,
+) -> Result<Vec<u8>, DhError> {
+    if salt.len() < argon2::MIN_SALT_LEN {
+        return Err(DhError::SaltTooShort { length: salt.len(), min_length: argon2::MIN_SALT_LEN });
+    }
+    let mut password_key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(password, salt, &mut password_key)
+        .expect("Argon2 accepts any password with a long enough salt and a 32-byte output");
+    Ok(derive_key_with_context(dh_secret, info, &password_key, len))
+}
+
+// Known-answer vector pinning the KDF output, so a dependency upgrade or refactor can't change derived keys silently:
+// HKDF-SHA256 with no salt over the secret 0x0102...20, info "session encryption key", and 16 bytes of output. The
+// expected value was computed independently with Python's hmac and hashlib. The other hashes are pinned by the tests.
+const KNOWN_ANSWER_SECRET: [u8; 32] = hex!("0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20");
+const KNOWN_ANSWER_LABEL: &str = "session encryption key";
+const KNOWN_ANSWER: [u8; 16] = hex!("c9eae1e5f2032281a6537847473c7f5a");
+
+// Whether `derive_key` reproduces the known-answer vector. Run by `self_test`.
+pub fn kdf_known_answers_match() -> bool {
+    derive_key(&BigUint::from_bytes_be(&KNOWN_ANSWER_SECRET), KNOWN_ANSWER_LABEL, 16) == KNOWN_ANSWER
+}
+
+// Derive one key of `len` bytes per label from a single shared secret, using each label as the HKDF info string.
+// The same secret and label always produce the same key, and different labels produce unrelated keys.
+// Panics if `len` is larger than HKDF-SHA256 can produce (255 * 32 bytes).
+#[cfg(feature = "std")]
+pub fn derive_labeled_keys(secret: &BigUint, labels: &[&str], len: usize) -> HashMap<String, Vec<u8>> {
+    labels.iter().map(|label| (label.to_string(), derive_key(secret, label, len))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same secret, label and length as the power-on vector, once per hash, computed with Python's hmac and hashlib.
+    const HASH_KNOWN_ANSWERS: [(HkdfHash, [u8; 16]); 3] = [
+        (HkdfHash::Sha256, hex!("c9eae1e5f2032281a6537847473c7f5a")),
+        (HkdfHash::Sha384, hex!("6e6ff0d44cae311c832de28ac18a9ff1")),
+        (HkdfHash::Sha512, hex!("51fa5b54f26aa622264345e1914dc6af")),
+    ];
+
+    #[test]
+    fn power_on_vector_matches() {
+        assert!(kdf_known_answers_match());
+    }
+
+    #[test]
+    fn every_hash_matches_its_vector() {
+        let secret = BigUint::from_bytes_be(&KNOWN_ANSWER_SECRET);
+        for (hash, expected) in HASH_KNOWN_ANSWERS {
+            assert_eq!(derive_key_with_hash(&secret, KNOWN_ANSWER_LABEL, hash, 16), expected, "{hash:?}");
+        }
+    }
+
+    #[test]
+    fn derive_key_is_hkdf_sha256() {
+        let secret = BigUint::from_bytes_be(&KNOWN_ANSWER_SECRET);
+        assert_eq!(
+            derive_key(&secret, KNOWN_ANSWER_LABEL, 32),
+            derive_key_with_hash(&secret, KNOWN_ANSWER_LABEL, HkdfHash::Sha256, 32)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn labeled_keys_are_distinct_and_deterministic() {
+        let secret = BigUint::from_bytes_be(&KNOWN_ANSWER_SECRET);
+        let labels = ["encryption", "mac", "iv"];
+        let keys = derive_labeled_keys(&secret, &labels, 32);
+        assert_eq!(keys.len(), labels.len());
+        for (i, first) in labels.iter().enumerate() {
+            assert_eq!(keys[*first].len(), 32);
+            assert_eq!(keys[*first], derive_key(&secret, first, 32));
+            for second in &labels[i + 1..] {
+                assert_ne!(keys[*first], keys[*second], "{first} and {second}");
+            }
+        }
+        assert_eq!(derive_labeled_keys(&secret, &labels, 32), keys);
+    }
+
+    #[test]
+    fn each_hash_gives_its_own_deterministic_key() {
+        let secret = BigUint::from(0x00c0_ffeeu32);
+        let hashes = [HkdfHash::Sha256, HkdfHash::Sha384, HkdfHash::Sha512];
+        let keys: Vec<_> = hashes.iter().map(|&hash| derive_key_with_hash(&secret, "session", hash, 32)).collect();
+        for (i, &hash) in hashes.iter().enumerate() {
+            assert_eq!(derive_key_with_hash(&secret, "session", hash, 32), keys[i], "{hash:?}");
+            for j in i + 1..hashes.len() {
+                assert_ne!(keys[i], keys[j], "{hash:?} and {:?}", hashes[j]);
+            }
+            // The longest key HKDF allows over this hash.
+            let max_len = 255 * hash.output_len();
+            assert_eq!(derive_key_with_hash(&secret, "session", hash, max_len).len(), max_len);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 255 hash lengths")]
+    fn keys_longer_than_hkdf_allows_panic() {
+        derive_key_with_hash(&BigUint::from(1u32), "session", HkdfHash::Sha384, 255 * 48 + 1);
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn password_keys_match_only_for_matching_passwords() {
+        let secret = BigUint::from_bytes_be(&KNOWN_ANSWER_SECRET);
+        let salt = b"exchange salt 01";
+        let alice = derive_key_with_password(&secret, b"correct horse", salt, "password key", 32).unwrap();
+        let bob = derive_key_with_password(&secret, b"correct horse", salt, "password key", 32).unwrap();
+        let mallory = derive_key_with_password(&secret, b"correct horsf", salt, "password key", 32).unwrap();
+        assert_eq!(alice, bob);
+        assert_ne!(alice, mallory);
+        // The password is mixed in: it isn't plain HKDF over the secret.
+        assert_ne!(alice, derive_key(&secret, "password key", 32));
+
+        let result = derive_key_with_password(&secret, b"correct horse", b"short", "password key", 32);
+        assert!(matches!(result, Err(DhError::SaltTooShort { length: 5, min_length: 8 })));
+    }
+}
@@ -0,0 +1,176 @@
+// A common interface over the key agreement algorithms, so code can be written once and switched from classic
+// finite-field Diffie-Hellman to X25519 (or back) by changing the value it is given. When the choice is only known at
+// run time, e.g. from a config file, `AnyKeyExchange` holds either one and passes public keys around as bytes.
+
+use rand::{CryptoRng, RngCore};
+
+use crate::error::DhError;
+use crate::keys::{generate_keypair_with_rng, PrivateKey, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+#[cfg(feature = "x25519")]
+use crate::x25519::X25519Dh;
+
+pub trait KeyExchange {
+    // The secret half of a keypair, kept by its owner.
+    type PrivateKey;
+    // The public half of a keypair, sent to the peer.
+    type PublicKey;
+
+    // Generate a fresh keypair.
+    fn keypair(&self) -> Result<(Self::PrivateKey, Self::PublicKey), DhError> {
+        self.keypair_with_rng(&mut rand::thread_rng())
+    }
+
+    // Generate a fresh keypair with randomness from `rng`.
+    fn keypair_with_rng<R: CryptoRng + RngCore>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(Self::PrivateKey, Self::PublicKey), DhError>;
+
+    // Combine our private key with the peer's public key. Fails if the peer's key is unacceptable.
+    fn agree(&self, private_key: &Self::PrivateKey, peer_public: &Self::PublicKey) -> Result<SharedSecret, DhError>;
+}
+
+// Finite-field Diffie-Hellman over a MODP group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfDh {
+    params: DhParameters,
+}
+
+impl FfDh {
+    pub fn new(params: DhParameters) -> Self {
+        FfDh { params }
+    }
+
+    pub fn params(&self) -> &DhParameters {
+        &self.params
+    }
+}
+
+impl KeyExchange for FfDh {
+    type PrivateKey = PrivateKey;
+    type PublicKey = PublicKey;
+
+    fn keypair_with_rng<R: CryptoRng + RngCore>(&self, rng: &mut R) -> Result<(PrivateKey, PublicKey), DhError> {
+        generate_keypair_with_rng(&self.params, rng)
+    }
+
+    // The peer's key is checked with `PublicKey::validate_in_group` first.
+    fn agree(&self, private_key: &PrivateKey, peer_public: &PublicKey) -> Result<SharedSecret, DhError> {
+        peer_public.validate_in_group(&self.params)?;
+        Ok(private_key.agree(peer_public, &self.params))
+    }
+}
+
+// Either algorithm, picked at run time. Public keys are their wire encoding: big-endian for finite-field keys, the
+// 32-byte RFC 7748 encoding for X25519.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnyKeyExchange {
+    FfDh(FfDh),
+    #[cfg(feature = "x25519")]
+    X25519(X25519Dh),
+}
+
+// A private key from `AnyKeyExchange::keypair`, for the algorithm that made it.
+pub enum AnyPrivateKey {
+    FfDh(PrivateKey),
+    #[cfg(feature = "x25519")]
+    X25519(x25519_dalek::StaticSecret),
+}
+
+impl KeyExchange for AnyKeyExchange {
+    type PrivateKey = AnyPrivateKey;
+    type PublicKey = Vec<u8>;
+
+    fn keypair_with_rng<R: CryptoRng + RngCore>(&self, rng: &mut R) -> Result<(AnyPrivateKey, Vec<u8>), DhError> {
+        match self {
+            AnyKeyExchange::FfDh(exchange) => {
+                let (private_key, public_key) = exchange.keypair_with_rng(rng)?;
+                Ok((AnyPrivateKey::FfDh(private_key), public_key.to_wire_bytes()))
+            }
+            #[cfg(feature = "x25519")]
+            AnyKeyExchange::X25519(exchange) => {
+                let (private_key, public_key) = exchange.keypair_with_rng(rng)?;
+                Ok((AnyPrivateKey::X25519(private_key), public_key.as_bytes().to_vec()))
+            }
+        }
+    }
+
+    // A peer key that doesn't decode for this algorithm fails with `DhError::InvalidKeyEncoding`, and a private key
+    // made by the other algorithm with `DhError::IncompatibleParameters`.
+    fn agree(&self, private_key: &AnyPrivateKey, peer_public: &Vec<u8>) -> Result<SharedSecret, DhError> {
+        match (self, private_key) {
+            (AnyKeyExchange::FfDh(exchange), AnyPrivateKey::FfDh(private_key)) => {
+                let peer_public = PublicKey::from_wire_bytes(peer_public, exchange.params())?;
+                exchange.agree(private_key, &peer_public)
+            }
+            #[cfg(feature = "x25519")]
+            (AnyKeyExchange::X25519(exchange), AnyPrivateKey::X25519(private_key)) => {
+                let bytes: [u8; 32] = peer_public.as_slice().try_into().map_err(|_| DhError::InvalidKeyEncoding)?;
+                exchange.agree(private_key, &x25519_dalek::PublicKey::from(bytes))
+            }
+            #[cfg(feature = "x25519")]
+            _ => Err(DhError::IncompatibleParameters),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    // A full exchange through the trait alone: both ends agree, and a third party's key gives another secret.
+    fn exercise<K: KeyExchange>(exchange: &K) {
+        let (alice_private, alice_public) = exchange.keypair().unwrap();
+        let (bob_private, bob_public) = exchange.keypair().unwrap();
+        let alice_secret = exchange.agree(&alice_private, &bob_public).unwrap();
+        assert!(alice_secret == exchange.agree(&bob_private, &alice_public).unwrap());
+        let (_, eve_public) = exchange.keypair().unwrap();
+        assert!(alice_secret != exchange.agree(&alice_private, &eve_public).unwrap());
+    }
+
+    #[test]
+    fn every_algorithm_agrees() {
+        let params = Group::Ffdhe2048.parameters();
+        exercise(&FfDh::new(params.clone()));
+        exercise(&AnyKeyExchange::FfDh(FfDh::new(params)));
+        #[cfg(feature = "x25519")]
+        {
+            exercise(&X25519Dh);
+            exercise(&AnyKeyExchange::X25519(X25519Dh));
+        }
+    }
+
+    #[test]
+    fn bad_public_key_encodings_are_refused() {
+        let params = Group::Ffdhe2048.parameters();
+        let exchange = AnyKeyExchange::FfDh(FfDh::new(params.clone()));
+        let (private_key, public_key) = exchange.keypair().unwrap();
+        let padded = [&[0][..], &public_key].concat();
+        assert!(matches!(exchange.agree(&private_key, &padded), Err(DhError::InvalidKeyEncoding)));
+        let p_minus_one = (params.modulus() - 1u32).to_bytes_be();
+        assert!(matches!(exchange.agree(&private_key, &p_minus_one), Err(DhError::InvalidPublicKey(_))));
+        assert!(matches!(exchange.agree(&private_key, &vec![1]), Err(DhError::InvalidPublicKey(_))));
+
+        #[cfg(feature = "x25519")]
+        {
+            let exchange = AnyKeyExchange::X25519(X25519Dh);
+            let (private_key, public_key) = exchange.keypair().unwrap();
+            let short = public_key[1..].to_vec();
+            assert!(matches!(exchange.agree(&private_key, &short), Err(DhError::InvalidKeyEncoding)));
+            assert!(matches!(exchange.agree(&private_key, &vec![0; 32]), Err(DhError::InvalidPublicKey(_))));
+        }
+    }
+
+    #[cfg(feature = "x25519")]
+    #[test]
+    fn private_keys_from_the_other_algorithm_are_refused() {
+        let ffdh = AnyKeyExchange::FfDh(FfDh::new(Group::Ffdhe2048.parameters()));
+        let x25519 = AnyKeyExchange::X25519(X25519Dh);
+        let (ffdh_private, ffdh_public) = ffdh.keypair().unwrap();
+        let (x25519_private, x25519_public) = x25519.keypair().unwrap();
+        assert!(matches!(ffdh.agree(&x25519_private, &ffdh_public), Err(DhError::IncompatibleParameters)));
+        assert!(matches!(x25519.agree(&ffdh_private, &x25519_public), Err(DhError::IncompatibleParameters)));
+    }
+}
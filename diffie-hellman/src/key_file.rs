@@ -0,0 +1,143 @@
+// Private keys stored under a passphrase, so a long-term key can live in a file between runs without lying around in
+// the clear. The passphrase is stretched with Argon2id (19 MiB, two passes) under a random salt into an AES-128-GCM
+// key, which encrypts the key's big-endian bytes. The header is authenticated along with the key, so a file whose salt
+// has been changed fails to open just like one tried with the wrong passphrase. Every guess at the passphrase costs an
+// attacker who has the file one Argon2 run.
+//
+// File layout: magic "DHPK" || version (1 byte) || salt (16 bytes) || nonce (12 bytes) || ciphertext || GCM tag.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use num_bigint::BigUint;
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::keys::PrivateKey;
+use crate::nonce::NONCE_LEN;
+
+const MAGIC: &[u8; 4] = b"DHPK";
+const KEY_FILE_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN;
+
+fn key_file_cipher(passphrase: &[u8], salt: &[u8]) -> Aes128Gcm {
+    let mut key = Zeroizing::new([0u8; 16]);
+    argon2::Argon2::default()
+        .hash_password_into(passphrase, salt, &mut key[..])
+        .expect("Argon2 accepts any passphrase with a 16-byte salt and a 16-byte output");
+    Aes128Gcm::new_from_slice(&key[..]).expect("the derived key is 16 bytes")
+}
+
+impl PrivateKey {
+    // The key encrypted under `passphrase`, in the key file layout above.
+    pub fn to_encrypted_bytes(&self, passphrase: &[u8]) -> Result<Vec<u8>, DhError> {
+        let mut header = MAGIC.to_vec();
+        header.push(KEY_FILE_VERSION);
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        header.extend_from_slice(&salt);
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let key_bytes = Zeroizing::new(self.0.to_bytes_be());
+        let ciphertext = key_file_cipher(passphrase, &salt)
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &key_bytes, aad: &header })
+            .map_err(|_| DhError::EncryptionFailed)?;
+        let mut file = header;
+        file.extend_from_slice(&nonce);
+        file.extend_from_slice(&ciphertext);
+        Ok(file)
+    }
+
+    // Open a key written by `to_encrypted_bytes`. Bytes that aren't a key file are `DhError::InvalidKeyFile`, a file
+    // from a newer version `DhError::UnsupportedVersion`, and a wrong passphrase or any tampering
+    // `DhError::WrongPassphrase`; the two can't be told apart.
+    pub fn from_encrypted_bytes(bytes: &[u8], passphrase: &[u8]) -> Result<PrivateKey, DhError> {
+        if bytes.len() < HEADER_LEN + NONCE_LEN + TAG_LEN || !bytes.starts_with(MAGIC) {
+            return Err(DhError::InvalidKeyFile);
+        }
+        let version = bytes[MAGIC.len()];
+        if version != KEY_FILE_VERSION {
+            return Err(DhError::UnsupportedVersion { version });
+        }
+        let (header, rest) = bytes.split_at(HEADER_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let key_bytes = Zeroizing::new(
+            key_file_cipher(passphrase, &header[MAGIC.len() + 1..])
+                .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+                .map_err(|_| DhError::WrongPassphrase)?,
+        );
+        Ok(PrivateKey(BigUint::from_bytes_be(&key_bytes)))
+    }
+
+    // Write the key encrypted under `passphrase` to a new file at `path`, readable only by its owner on Unix. An
+    // existing file is never overwritten: that fails with `DhError::Io`, so a stored key can't be lost by accident.
+    pub fn save(&self, path: &Path, passphrase: &[u8]) -> Result<(), DhError> {
+        let contents = self.to_encrypted_bytes(passphrase)?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(&contents)?;
+        Ok(())
+    }
+
+    // Read a key written by `save`, with the errors of `from_encrypted_bytes` and `DhError::Io` if the file can't be
+    // read.
+    pub fn load(path: &Path, passphrase: &[u8]) -> Result<PrivateKey, DhError> {
+        Self::from_encrypted_bytes(&fs::read(path)?, passphrase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_key_loads_with_its_passphrase_only() {
+        let path = std::env::temp_dir().join(format!("dh-key-file-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let key = PrivateKey::from_bytes_be(&[0x42; 28]);
+        key.save(&path, b"passphrase").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(matches!(key.save(&path, b"passphrase"), Err(DhError::Io(_))));
+
+        let loaded = PrivateKey::load(&path, b"passphrase").unwrap();
+        assert_eq!(loaded.0, key.0);
+        assert!(matches!(PrivateKey::load(&path, b"passphrasf"), Err(DhError::WrongPassphrase)));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(PrivateKey::load(&path, b"passphrase"), Err(DhError::Io(_))));
+    }
+
+    #[test]
+    fn altered_or_foreign_key_files_are_refused() {
+        let file = PrivateKey::from_bytes_be(&[0x42; 28]).to_encrypted_bytes(b"passphrase").unwrap();
+        assert_eq!(file.len(), HEADER_LEN + NONCE_LEN + 28 + TAG_LEN);
+
+        let mut salt_changed = file.clone();
+        salt_changed[MAGIC.len() + 1] ^= 1;
+        let result = PrivateKey::from_encrypted_bytes(&salt_changed, b"passphrase");
+        assert!(matches!(result, Err(DhError::WrongPassphrase)));
+
+        let mut newer = file.clone();
+        newer[MAGIC.len()] = 2;
+        let result = PrivateKey::from_encrypted_bytes(&newer, b"passphrase");
+        assert!(matches!(result, Err(DhError::UnsupportedVersion { version: 2 })));
+
+        let mut foreign = file.clone();
+        foreign[..4].copy_from_slice(b"DHAR");
+        assert!(matches!(PrivateKey::from_encrypted_bytes(&foreign, b"passphrase"), Err(DhError::InvalidKeyFile)));
+        let truncated = &file[..HEADER_LEN + NONCE_LEN + TAG_LEN - 1];
+        assert!(matches!(PrivateKey::from_encrypted_bytes(truncated, b"passphrase"), Err(DhError::InvalidKeyFile)));
+    }
+}
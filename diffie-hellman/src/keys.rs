@@ -0,0 +1,1049 @@
+// The key types each party holds during an exchange. The private exponent and the agreed secret are kept in their own
+// types, separate from the public key that gets sent to the peer, so they aren't passed around as bare numbers.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use hex_literal::hex;
+use hkdf::Hkdf;
+use num_bigint::{BigUint, RandBigInt};
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::ct::modpow_secret;
+use crate::error::{DhError, ValidationError};
+use crate::kdf::{derive_key, derive_key_with_context};
+use crate::params::{DhParameters, Group};
+use crate::{scrub_biguint, secret_fingerprint, secrets_match};
+
+/// A party's private exponent x. It never leaves the party that generated it.
+/// Deliberately not `Clone`, like `SharedSecret`, so secret values aren't duplicated by accident:
+///
+/// ```compile_fail
+/// use diffie_hellman::PrivateKey;
+///
+/// let private_key = PrivateKey::from_bytes_be(&[5]);
+/// let copy = private_key.clone();
+/// ```
+pub struct PrivateKey(pub(crate) BigUint);
+
+// A party's public value g^x mod p, sent to the peer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PublicKey(pub(crate) BigUint);
+
+// The secret both parties arrive at, g^(xy) mod p.
+pub struct SharedSecret(pub(crate) BigUint);
+
+// The digits of `PublicKey::to_compact_string`, in order of value.
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// How many private keys `generate_keypair` samples before giving up on the parameters.
+pub const MAX_KEYGEN_ATTEMPTS: u32 = 64;
+
+// The shortest seed `derive_keypair_from_seed` accepts, 128 bits.
+pub const MIN_SEED_LEN: usize = 16;
+
+const SEED_SALT: &[u8] = b"diffie-hellman keypair from seed";
+
+// Generate a fresh private key and the public key that goes with it for the given parameters. The private key is
+// uniform over [2, 2^n), where n is `DhParameters::recommended_exponent_bits`: twice the group's security level, and
+// far quicker to use than an exponent the size of the modulus. Use `generate_keypair_sized` for a different size. A
+// private key whose public key comes out as 0, 1 or p-1 is thrown away and resampled, since such a public key makes
+// the shared secret trivial. If no usable key turns up within MAX_KEYGEN_ATTEMPTS tries the parameters are
+// degenerate.
+#[cfg(feature = "std")]
+pub fn generate_keypair(params: &DhParameters) -> Result<(PrivateKey, PublicKey), DhError> {
+    generate_keypair_with_rng(params, &mut rand::thread_rng())
+}
+
+// Like `generate_keypair`, but the private key is drawn from `rng`. Without `std` this is the only way to generate
+// keys: there is no thread-local generator, so the caller passes the device's hardware generator or a CSPRNG seeded
+// from it. Tests can pass `test_utils::DeterministicRng` to get the same keys on every run.
+pub fn generate_keypair_with_rng<R: CryptoRng + RngCore>(
+    params: &DhParameters,
+    rng: &mut R,
+) -> Result<(PrivateKey, PublicKey), DhError> {
+    sample_keypair(params, params.recommended_exponent_bits(), rng, |key| key.public_key(params))
+}
+
+// Like `generate_keypair`, but with private exponents of up to `bits` bits, e.g. longer ones to match a policy, or
+// `params.bit_length()` for the whole range [2, q-1]. Sizes beyond the range are capped to it. Sizes below
+// `DhParameters::recommended_exponent_bits` are `DhError::ExponentTooShort`.
+#[cfg(feature = "std")]
+pub fn generate_keypair_sized(params: &DhParameters, bits: u64) -> Result<(PrivateKey, PublicKey), DhError> {
+    generate_keypair_sized_with_rng(params, bits, &mut rand::thread_rng())
+}
+
+// `generate_keypair_sized` with the private key drawn from `rng`.
+pub fn generate_keypair_sized_with_rng<R: CryptoRng + RngCore>(
+    params: &DhParameters,
+    bits: u64,
+    rng: &mut R,
+) -> Result<(PrivateKey, PublicKey), DhError> {
+    let min_bits = params.recommended_exponent_bits();
+    if bits < min_bits {
+        return Err(DhError::ExponentTooShort { bits, min_bits });
+    }
+    sample_keypair(params, bits, rng, |key| key.public_key(params))
+}
+
+// Derive a keypair from `seed` instead of drawing it at random, so a long-term key can be backed up as its seed and
+// restored from it later. The same seed and parameters always give the same keypair. Each attempt expands the seed
+// with HKDF-SHA256, under the parameters and the attempt number, into 64 bits more than an exponent of the
+// `generate_keypair` size and reduces that into range, so the key is as good as the seed. The seed must be at least
+// MIN_SEED_LEN bytes of real randomness, e.g. from `generate_random_key`; shorter ones are `DhError::SeedTooShort`.
+// A password is not a seed: run it through a slow KDF such as Argon2 first.
+pub fn derive_keypair_from_seed(params: &DhParameters, seed: &[u8]) -> Result<(PrivateKey, PublicKey), DhError> {
+    if seed.len() < MIN_SEED_LEN {
+        return Err(DhError::SeedTooShort { len: seed.len(), min_len: MIN_SEED_LEN });
+    }
+    let bound = params.private_key_bound();
+    let bits = params.recommended_exponent_bits();
+    let limit = if bits < bound.bits() { BigUint::from(1u32) << bits } else { bound };
+    let low = BigUint::from(2u32).min(&limit - 1u32);
+    let span = &limit - &low;
+    let hkdf = Hkdf::<Sha256>::new(Some(SEED_SALT), seed);
+    let mut info = Vec::new();
+    for number in [&params.generator, &params.modulus] {
+        let bytes = number.to_bytes_be();
+        info.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        info.extend_from_slice(&bytes);
+    }
+    let mut output = Zeroizing::new(vec![0u8; (limit.bits() as usize + 64).div_ceil(8)]);
+    for attempt in 0..MAX_KEYGEN_ATTEMPTS {
+        let mut attempt_info = info.clone();
+        attempt_info.extend_from_slice(&attempt.to_be_bytes());
+        hkdf.expand(&attempt_info, &mut output).expect("the output is well within HKDF's limit");
+        let mut wide = BigUint::from_bytes_be(&output);
+        let private_key = PrivateKey(&low + &wide % &span);
+        scrub_biguint(&mut wide);
+        let public_key = private_key.public_key(params);
+        if public_key.is_nontrivial(params) {
+            return Ok((private_key, public_key));
+        }
+    }
+    Err(DhError::KeyGenerationFailed { attempts: MAX_KEYGEN_ATTEMPTS })
+}
+
+// Draw private keys of up to `bits` bits until one has a usable public key, computed with `public_key`.
+pub(crate) fn sample_keypair<R: CryptoRng + RngCore>(
+    params: &DhParameters,
+    bits: u64,
+    rng: &mut R,
+    public_key: impl Fn(&PrivateKey) -> PublicKey,
+) -> Result<(PrivateKey, PublicKey), DhError> {
+    let bound = params.private_key_bound();
+    let limit = if bits < bound.bits() { BigUint::from(1u32) << bits } else { bound };
+    // Toy moduli with q = 2 have nothing in [2, q-1], so they fall back to 1.
+    let low = BigUint::from(2u32).min(&limit - 1u32);
+    for _ in 0..MAX_KEYGEN_ATTEMPTS {
+        let private_key = PrivateKey(rng.gen_biguint_range(&low, &limit));
+        let public_key = public_key(&private_key);
+        if public_key.is_nontrivial(params) {
+            return Ok((private_key, public_key));
+        }
+    }
+    Err(DhError::KeyGenerationFailed { attempts: MAX_KEYGEN_ATTEMPTS })
+}
+
+// Run the exchange with both private keys supplied by the caller instead of drawn at random, returning Alice's and
+// Bob's view of the secret. Meant for reproducing textbook examples and debugging, e.g. with g = 5, p = 23 and the
+// private keys 6 and 15 both sides arrive at 2. Nothing is validated, since textbook groups are far too small to pass.
+pub fn run_exchange_with_keys(
+    alice_private: &PrivateKey,
+    bob_private: &PrivateKey,
+    params: &DhParameters,
+) -> (SharedSecret, SharedSecret) {
+    let alice_public = alice_private.public_key(params);
+    let bob_public = bob_private.public_key(params);
+    (alice_private.agree(&bob_public, params), bob_private.agree(&alice_public, params))
+}
+
+// The keys `agree_and_derive` and `derive_session_keys` hand back, ready to give to a cipher and a MAC, e.g. with
+// `seal_with_keys`, or to authenticate messages without encrypting them with `sign`. Wiped when dropped.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct DerivedKeys {
+    pub encryption_key: [u8; 16],
+    pub mac_key: [u8; 32],
+}
+
+// The whole agreement in one call: validate the peer's key, compute the shared secret, make sure it isn't one of the
+// trivial values 0, 1 or p-1, and derive an AES-128 key and an HMAC-SHA256 key from it with HKDF. `info` is bound into
+// both keys, e.g. a protocol name or transcript hash, and both parties must pass the same bytes.
+pub fn agree_and_derive(
+    my_private: &PrivateKey,
+    their_public: &PublicKey,
+    params: &DhParameters,
+    info: &[u8],
+) -> Result<DerivedKeys, DhError> {
+    their_public.validate_in_group(params)?;
+    let secret = my_private.agree(their_public, params);
+    if secret.0 <= BigUint::from(1u32) || secret.0 >= &params.modulus - 1u32 {
+        return Err(DhError::TrivialSecret);
+    }
+    Ok(derive_session_keys(&secret, info))
+}
+
+// Derive an AES-128 encryption key and an HMAC-SHA256 key from an agreed secret with HKDF-SHA256, each under its
+// own label, so neither is the raw secret and knowing one says nothing about the other. `info` is bound into both
+// keys as in `agree_and_derive`, which does the same after checking the peer's key.
+pub fn derive_session_keys(secret: &SharedSecret, info: &[u8]) -> DerivedKeys {
+    let encryption_key = derive_key_with_context(&secret.0, "agreed encryption key", info, 16);
+    let mac_key = derive_key_with_context(&secret.0, "agreed mac key", info, 32);
+    DerivedKeys {
+        encryption_key: encryption_key.try_into().expect("derived 16 bytes"),
+        mac_key: mac_key.try_into().expect("derived 32 bytes"),
+    }
+}
+
+// Check a peer's public key before computing a shared secret with it, for any parameters: 0, 1, p-1 and values not
+// below p are always rejected, and for a safe-prime modulus the key must also be in the prime-order subgroup. Testing
+// whether the modulus is a safe prime takes a pair of primality tests, so code that uses a known safe-prime group,
+// such as the standard ones, should call `PublicKey::validate_in_group` directly.
+pub fn validate_public_key(key: &PublicKey, params: &DhParameters) -> Result<(), DhError> {
+    if !key.is_nontrivial(params) {
+        return Err(ValidationError::OutOfRange.into());
+    }
+    if params.is_safe_prime() {
+        key.validate_in_group(params)?;
+    }
+    Ok(())
+}
+
+// Run `PublicKey::validate_in_group` on each key, e.g. to admit or turn away a batch of incoming connections at once.
+// The results are in the same order as the keys. The subgroup order is worked out once for the whole batch.
+pub fn validate_public_keys(keys: &[PublicKey], params: &DhParameters) -> Vec<Result<(), ValidationError>> {
+    let order = (&params.modulus - 1u32) >> 1;
+    keys.iter().map(|key| key.validate_with_order(params, &order)).collect()
+}
+
+// Known-answer vector pinning the exponentiation, so a change of bignum backend, such as the `ct` feature, can't
+// change public keys or secrets silently. Two fixed private exponents, the SHA-256 of "dh known answer alice" and of
+// "dh known answer bob", over FFDHE-2048, with the SHA-256 of each public key and of the shared secret in their
+// big-endian bytes. The expected values were computed independently with Python's pow and hashlib.
+const KNOWN_ANSWER_PRIVATE_KEYS: [[u8; 32]; 2] = [
+    hex!("b6f75cb5df023ad3c5040b6fcff513f739619a17c100e84ea86230257bfd713a"),
+    hex!("975daf1f29f9e0e80148dd57255d0d33ea20b44f5828082ffedf8dbb364a1786"),
+];
+const KNOWN_ANSWER_PUBLIC_KEY_DIGESTS: [[u8; 32]; 2] = [
+    hex!("c5add9fd79ae3cc54565b5c047e118aa4716c939c12615c8053591ea77d241b7"),
+    hex!("f718a11d319f4bf023755e580577f4c415b2f886fc2b3956f81bdcab6b77ce1b"),
+];
+const KNOWN_ANSWER_SECRET_DIGEST: [u8; 32] = hex!("1c9cb565e85f9083b4eaaed4339e32f69d6d09dc905238224af446981a6457bb");
+
+// Whether computing both public keys and the secret, from each side, reproduces the known-answer vector. Run by
+// `self_test`.
+pub fn dh_known_answers_match() -> bool {
+    let params = Group::Ffdhe2048.parameters();
+    let [alice, bob] = KNOWN_ANSWER_PRIVATE_KEYS.map(|bytes| PrivateKey::from_bytes_be(&bytes));
+    let public_keys = [alice.public_key(&params), bob.public_key(&params)];
+    let digest = |value: &BigUint| -> [u8; 32] { Sha256::digest(value.to_bytes_be()).into() };
+    let public_keys_match =
+        public_keys.iter().zip(&KNOWN_ANSWER_PUBLIC_KEY_DIGESTS).all(|(key, expected)| digest(&key.0) == *expected);
+    let secrets = [alice.agree(&public_keys[1], &params), bob.agree(&public_keys[0], &params)];
+    public_keys_match && secrets.iter().all(|secret| digest(&secret.0) == KNOWN_ANSWER_SECRET_DIGEST)
+}
+
+// Generate `count` keypairs, spreading the exponentiations over rayon's thread pool. Meant for servers setting up many
+// sessions at once; fails if any single keypair can't be generated.
+#[cfg(feature = "parallel")]
+pub fn generate_keypairs_parallel(
+    count: usize,
+    params: &DhParameters,
+) -> Result<Vec<(PrivateKey, PublicKey)>, DhError> {
+    generate_keypairs_parallel_with_rng(count, params, &mut rand::thread_rng())
+}
+
+// Like `generate_keypairs_parallel`, with the keys coming from `rng`. The threads can't share one generator, so each
+// keypair gets its own `StdRng` seeded from `rng`. The seeds are drawn up front and in order, so a deterministic
+// `rng` gives the same keypairs however rayon schedules the work.
+#[cfg(feature = "parallel")]
+pub fn generate_keypairs_parallel_with_rng<R: CryptoRng + RngCore>(
+    count: usize,
+    params: &DhParameters,
+    rng: &mut R,
+) -> Result<Vec<(PrivateKey, PublicKey)>, DhError> {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rayon::prelude::*;
+
+    let seeds: Vec<Zeroizing<[u8; 32]>> = (0..count)
+        .map(|_| {
+            let mut seed = Zeroizing::new([0u8; 32]);
+            rng.fill_bytes(&mut seed[..]);
+            seed
+        })
+        .collect();
+    seeds.into_par_iter().map(|seed| generate_keypair_with_rng(params, &mut StdRng::from_seed(*seed))).collect()
+}
+
+impl PrivateKey {
+    // Wrap an existing private exponent.
+    pub fn from_biguint(value: BigUint) -> Self {
+        PrivateKey(value)
+    }
+
+    // The exponent as big-endian bytes, for storing the key, e.g. in a file only its owner can read. The bytes are as
+    // secret as the key itself.
+    pub fn to_bytes_be(&self) -> Vec<u8> {
+        self.0.to_bytes_be()
+    }
+
+    // Read a key stored with `to_bytes_be`.
+    pub fn from_bytes_be(bytes: &[u8]) -> Self {
+        PrivateKey(BigUint::from_bytes_be(bytes))
+    }
+
+    // Check a key that came from outside, e.g. a file or a config, before using it with `params`: it must lie in
+    // [2, q-1], where q = (p - 1) / 2. Keys of 0 and 1 make the public key give the shared secret away, and larger
+    // keys suggest one meant for a different group. Anything outside is `DhError::InvalidPrivateKey`.
+    pub fn validate(&self, params: &DhParameters) -> Result<(), DhError> {
+        if self.0 < BigUint::from(2u32) || self.0 >= params.private_key_bound() {
+            return Err(DhError::InvalidPrivateKey);
+        }
+        Ok(())
+    }
+
+    // The width the exponentiations pad the key to: the short-exponent size if it fits, as keys from
+    // `generate_keypair` do, or else the modulus size. Which of the two shows in the timing, and nothing finer.
+    pub(crate) fn exponent_width(&self, params: &DhParameters) -> u64 {
+        let short = params.recommended_exponent_bits();
+        if self.0.bits() <= short {
+            short
+        } else {
+            params.bit_length()
+        }
+    }
+
+    // Compute the public key g^x mod p for this private key.
+    pub fn public_key(&self, params: &DhParameters) -> PublicKey {
+        PublicKey(modpow_secret(&params.generator, &self.0, self.exponent_width(params), &params.modulus))
+    }
+
+    // Combine the peer's public key with our private key, i.e. peer_public ^ x mod p.
+    pub fn agree(&self, peer_public: &PublicKey, params: &DhParameters) -> SharedSecret {
+        SharedSecret(modpow_secret(&peer_public.0, &self.0, self.exponent_width(params), &params.modulus))
+    }
+}
+
+impl PublicKey {
+    // Wrap a public value received from a peer.
+    pub fn from_biguint(value: BigUint) -> Self {
+        PublicKey(value)
+    }
+
+    // Whether the value lies strictly between 1 and p-1, i.e. isn't one of the values that pin the shared secret.
+    pub(crate) fn is_nontrivial(&self, params: &DhParameters) -> bool {
+        let one = BigUint::from(1u32);
+        self.0 > one && self.0 < &params.modulus - &one
+    }
+
+    // Check a received public key against a safe-prime group p = 2q + 1: it must lie in [2, p-2] and satisfy
+    // y^q mod p == 1, i.e. belong to the subgroup of prime order q. Keys outside that subgroup would let a peer
+    // force the shared secret into a small set of values.
+    pub fn validate_in_group(&self, params: &DhParameters) -> Result<(), ValidationError> {
+        self.validate_with_order(params, &((&params.modulus - 1u32) >> 1))
+    }
+
+    fn validate_with_order(&self, params: &DhParameters, order: &BigUint) -> Result<(), ValidationError> {
+        if !self.is_nontrivial(params) {
+            return Err(ValidationError::OutOfRange);
+        }
+        if self.0.modpow(order, &params.modulus) != BigUint::from(1u32) {
+            return Err(ValidationError::NotInSubgroup);
+        }
+        Ok(())
+    }
+
+    // The public value, e.g. to send over the wire.
+    pub fn as_biguint(&self) -> &BigUint {
+        &self.0
+    }
+
+    // The key's canonical wire encoding: minimal big-endian bytes, without leading zeros.
+    pub fn to_wire_bytes(&self) -> Vec<u8> {
+        self.0.to_bytes_be()
+    }
+
+    // Parse a key in its canonical wire encoding, see `to_wire_bytes`. Each key has exactly one accepted encoding, so
+    // a peer can't vary its bytes without varying the key: leading zero bytes are rejected with
+    // `DhError::InvalidKeyEncoding`, and values outside [2, p-2] with `ValidationError::OutOfRange`.
+    pub fn from_wire_bytes(bytes: &[u8], params: &DhParameters) -> Result<PublicKey, DhError> {
+        if bytes.first() == Some(&0) {
+            return Err(DhError::InvalidKeyEncoding);
+        }
+        let key = PublicKey(BigUint::from_bytes_be(bytes));
+        if !key.is_nontrivial(params) {
+            return Err(ValidationError::OutOfRange.into());
+        }
+        Ok(key)
+    }
+
+    // The big-endian bytes of the key as lowercase hex, two digits per byte.
+    pub fn to_hex(&self) -> String {
+        self.0.to_bytes_be().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // Parse a key written by `to_hex` (or `Display`). An optional 0x prefix and either case are accepted.
+    pub fn from_hex(hex: &str) -> Result<PublicKey, DhError> {
+        let hex = hex.trim();
+        let digits = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X")).unwrap_or(hex);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(DhError::InvalidNumber);
+        }
+        BigUint::parse_bytes(digits.as_bytes(), 16).map(PublicKey).ok_or(DhError::InvalidNumber)
+    }
+
+    // The key in base58 with the Bitcoin alphabet, which leaves out the look-alike characters 0, O, I and l. About a
+    // third shorter than hex and easy to read aloud or put in a QR code for sharing or checking a key out of band.
+    pub fn to_compact_string(&self) -> String {
+        let base = BigUint::from(58u32);
+        let mut value = self.0.clone();
+        let mut digits = Vec::new();
+        loop {
+            let digit = (&value % &base).to_u32_digits().first().copied().unwrap_or(0);
+            digits.push(BASE58_ALPHABET[digit as usize]);
+            value /= &base;
+            if value.bits() == 0 {
+                break;
+            }
+        }
+        digits.reverse();
+        String::from_utf8(digits).expect("base58 digits are ASCII")
+    }
+
+    // Parse a key written by `to_compact_string`. Characters outside the alphabet, an empty string and leading zero
+    // digits ('1') are rejected with `DhError::InvalidKeyEncoding`, so each key has one compact form.
+    pub fn from_compact_string(compact: &str) -> Result<PublicKey, DhError> {
+        let compact = compact.trim();
+        if compact.is_empty() || (compact.len() > 1 && compact.starts_with('1')) {
+            return Err(DhError::InvalidKeyEncoding);
+        }
+        let mut value = BigUint::from(0u32);
+        for byte in compact.bytes() {
+            let digit = BASE58_ALPHABET.iter().position(|&c| c == byte).ok_or(DhError::InvalidKeyEncoding)?;
+            value = value * 58u32 + digit as u32;
+        }
+        Ok(PublicKey(value))
+    }
+
+    // The key as an SSH mpint (RFC 4251 section 5): a 4-byte big-endian length, then the value in two's complement.
+    // Since keys are positive, a 0x00 byte is put in front whenever the top bit of the first byte is set.
+    pub fn to_ssh_mpint(&self) -> Vec<u8> {
+        let mut value = if self.0.bits() == 0 { Vec::new() } else { self.0.to_bytes_be() };
+        if value.first().is_some_and(|byte| byte & 0x80 != 0) {
+            value.insert(0, 0);
+        }
+        let mut encoded = (value.len() as u32).to_be_bytes().to_vec();
+        encoded.extend_from_slice(&value);
+        encoded
+    }
+
+    // Parse a key written as an SSH mpint. The input must be exactly one mpint, minimally encoded as the RFC
+    // requires, and non-negative; anything else is `DhError::InvalidKeyEncoding`.
+    pub fn from_ssh_mpint(bytes: &[u8]) -> Result<PublicKey, DhError> {
+        let (length_bytes, value) = bytes.split_first_chunk::<4>().ok_or(DhError::InvalidKeyEncoding)?;
+        if u32::from_be_bytes(*length_bytes) as usize != value.len() {
+            return Err(DhError::InvalidKeyEncoding);
+        }
+        match value {
+            // The high bit is the sign bit.
+            [first, ..] if first & 0x80 != 0 => Err(DhError::InvalidKeyEncoding),
+            // A leading zero is only allowed to clear the sign bit of the next byte.
+            [0, rest @ ..] if rest.first().is_none_or(|byte| byte & 0x80 == 0) => Err(DhError::InvalidKeyEncoding),
+            _ => Ok(PublicKey(BigUint::from_bytes_be(value))),
+        }
+    }
+}
+
+// Shows the key as hex, see `to_hex`. The alternate form `{:#}` puts the key's length in bytes in front, e.g.
+// `[256 bytes] 5a3f...`, which makes truncated keys easy to spot in logs.
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "[{} bytes] ", self.0.to_bytes_be().len())?;
+        }
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl SharedSecret {
+    // Wrap secret bytes agreed through some other mechanism, e.g. a post-quantum KEM, so they can be combined.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        SharedSecret(BigUint::from_bytes_be(bytes))
+    }
+
+    // Merge two secrets into one for hybrid key exchange, e.g. a Diffie-Hellman secret with a KEM secret, so the
+    // result stays safe as long as either input is. The result is SHA-256 over a domain label followed by each secret
+    // with a 4-byte length prefix. It is order-sensitive: a.combine(&b) and b.combine(&a) differ, so both parties
+    // have to agree which secret comes first.
+    pub fn combine(&self, other: &SharedSecret) -> SharedSecret {
+        let mut hasher = Sha256::new();
+        hasher.update(b"diffie-hellman combined secret");
+        for secret in [self, other] {
+            let bytes = Zeroizing::new(secret.0.to_bytes_be());
+            hasher.update((bytes.len() as u32).to_be_bytes());
+            hasher.update(&bytes);
+        }
+        SharedSecret::from_bytes(&hasher.finalize())
+    }
+
+    // The raw secret value, for the ciphers and KDFs inside the crate. Outside it the secret is only reachable through
+    // `derive_key` and the types built on it, or through `dangerous_into_bytes`.
+    pub(crate) fn as_biguint(&self) -> &BigUint {
+        &self.0
+    }
+
+    // A key of `len` bytes for the purpose named by `label`, see `kdf::derive_key`. This is how the secret should leave
+    // the exchange: as keys for a particular use, never as itself.
+    pub fn derive_key(&self, label: &str, len: usize) -> Vec<u8> {
+        derive_key(&self.0, label, len)
+    }
+
+    // Like `derive_key`, with `context` bound into the key, see `kdf::derive_key_with_context`.
+    pub fn derive_key_with_context(&self, label: &str, context: &[u8], len: usize) -> Vec<u8> {
+        derive_key_with_context(&self.0, label, context, len)
+    }
+
+    // The raw secret as big-endian bytes, consuming it. Only for callers that really need the value itself, e.g. to
+    // hand it to another implementation or to the command line's `encrypt --key`; anything else should derive keys.
+    // The long name makes each use stand out in review. The bytes are wiped when dropped.
+    pub fn dangerous_into_bytes(self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.0.to_bytes_be())
+    }
+
+    // An estimate of how many bits of entropy the secret carries under `params`: every shared secret is a power of the
+    // generator, so at most log2 of the generator's order, however large the modulus. With the original demo
+    // parameters (g = 5, p = 57) that is 4 bits; with `Group::Modp2048` it is 2046.
+    //
+    // This is an upper bound set by the parameters alone. The secret itself isn't looked at, so every secret under
+    // the same parameters gets the same figure, and neither are the private key sizes. For a modulus over 20 bits that
+    // isn't a safe prime the order of g can't be found without factoring p - 1, and the estimate falls back to the
+    // modulus size, which overstates it whenever g generates a small subgroup. Check such parameters with
+    // `DhParameters::check` rather than trusting this figure.
+    pub fn effective_entropy_bits(&self, params: &DhParameters) -> u32 {
+        params.generator_order_bits()
+    }
+
+    // A short fingerprint of the secret that is safe to display, see `secret_fingerprint`.
+    pub fn fingerprint(&self) -> String {
+        secret_fingerprint(&self.0)
+    }
+}
+
+// Secret values are scrubbed when they go out of scope, see `scrub_biguint` for how far that goes.
+impl Zeroize for PrivateKey {
+    fn zeroize(&mut self) {
+        scrub_biguint(&mut self.0);
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for PrivateKey {}
+
+impl Zeroize for SharedSecret {
+    fn zeroize(&mut self) {
+        scrub_biguint(&mut self.0);
+    }
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SharedSecret {}
+
+// Debug output never shows secret values, so secrets can't end up in logs through `{:?}` or a derived `Debug` on a
+// type holding them.
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PrivateKey([REDACTED])")
+    }
+}
+
+impl fmt::Debug for SharedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedSecret([REDACTED])")
+    }
+}
+
+impl fmt::Debug for DerivedKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = format_args!("[REDACTED]");
+        f.debug_struct("DerivedKeys").field("encryption_key", &redacted).field("mac_key", &redacted).finish()
+    }
+}
+
+// Secrets are compared in constant time so that checking agreement doesn't leak the secret through timing.
+impl PartialEq for SharedSecret {
+    fn eq(&self, other: &Self) -> bool {
+        secrets_match(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedSecret {}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    // The power-on private keys over an RFC 3526 group and a larger RFC 7919 one, with the SHA-256 of the shared
+    // secret, computed with Python's pow and hashlib.
+    const GROUP_SECRET_DIGESTS: [(Group, [u8; 32]); 2] = [
+        (Group::Modp2048, hex!("1fe2b760d0bdb162fd1f09a5bc972bb40de3938e1a811824b2bf8f3708702c0f")),
+        (Group::Ffdhe3072, hex!("40a00f04b6be43ce2bfc3692ead507ff68fe84fb9b7561616a8e9c7266aa7871")),
+    ];
+
+    #[test]
+    fn power_on_vector_matches() {
+        assert!(dh_known_answers_match());
+    }
+
+    #[test]
+    fn standard_groups_match_known_answers() {
+        for (group, expected) in GROUP_SECRET_DIGESTS {
+            let params = group.parameters();
+            let [alice, bob] = KNOWN_ANSWER_PRIVATE_KEYS.map(|bytes| PrivateKey::from_bytes_be(&bytes));
+            let (alice_secret, bob_secret) = run_exchange_with_keys(&alice, &bob, &params);
+            assert_eq!(alice_secret, bob_secret, "{group:?}");
+            assert_eq!(Sha256::digest(alice_secret.0.to_bytes_be())[..], expected, "{group:?}");
+        }
+    }
+
+    #[test]
+    fn textbook_exchange() {
+        let params = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        let alice = PrivateKey::from_biguint(BigUint::from(6u32));
+        let bob = PrivateKey::from_biguint(BigUint::from(15u32));
+        assert_eq!(alice.public_key(&params).0, BigUint::from(8u32));
+        assert_eq!(bob.public_key(&params).0, BigUint::from(19u32));
+        let (alice_secret, bob_secret) = run_exchange_with_keys(&alice, &bob, &params);
+        assert_eq!(alice_secret.0, BigUint::from(2u32));
+        assert_eq!(bob_secret.0, BigUint::from(2u32));
+    }
+
+    proptest! {
+        // Each case is four exponentiations over a 2048- to 4096-bit modulus, so keep the count low.
+        #![proptest_config(ProptestConfig::with_cases(12))]
+
+        #[test]
+        fn both_parties_derive_the_same_secret(group in prop::sample::select(Group::ALL.to_vec()), seed: u64) {
+            let params = group.parameters();
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (alice_private, alice_public) = generate_keypair_with_rng(&params, &mut rng).unwrap();
+            let (bob_private, bob_public) = generate_keypair_with_rng(&params, &mut rng).unwrap();
+            prop_assert_eq!(alice_private.agree(&bob_public, &params), bob_private.agree(&alice_public, &params));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn small_groups_agree(
+            (generator, modulus) in prop::sample::select(vec![(5u64, 23u64), (2, 227), (5, 1019), (2, 2147483783)]),
+            seed: u64,
+        ) {
+            let params = DhParameters::from_u64_allow_insecure(generator, modulus).unwrap();
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (alice_private, alice_public) = generate_keypair_with_rng(&params, &mut rng).unwrap();
+            let (bob_private, bob_public) = generate_keypair_with_rng(&params, &mut rng).unwrap();
+            prop_assert_eq!(alice_private.agree(&bob_public, &params), bob_private.agree(&alice_public, &params));
+        }
+    }
+
+    // With g = 3 of order 3 mod 13, a quarter of the private keys in [2, 6) give the trivial public key 1.
+    #[test]
+    fn trivial_public_keys_are_resampled() {
+        let params = DhParameters::from_u64_allow_insecure(3, 13).unwrap();
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (private_key, public_key) = generate_keypair_with_rng(&params, &mut rng).unwrap();
+            assert!(public_key.is_nontrivial(&params), "seed {seed} gave {}", public_key.0);
+            assert_eq!(private_key.public_key(&params), public_key);
+        }
+        for seed in 0..50u8 {
+            let (_, public_key) = derive_keypair_from_seed(&params, &[seed; MIN_SEED_LEN]).unwrap();
+            assert!(public_key.is_nontrivial(&params));
+        }
+    }
+
+    // Modulo 8 the only private key is 2, and 3^2 = 1, so no key is usable.
+    #[test]
+    fn degenerate_group_fails_after_bounded_attempts() {
+        let params = DhParameters::from_u64_allow_insecure(3, 8).unwrap();
+        let mut rng = StdRng::seed_from_u64(109);
+        assert!(matches!(
+            generate_keypair_with_rng(&params, &mut rng),
+            Err(DhError::KeyGenerationFailed { attempts: MAX_KEYGEN_ATTEMPTS })
+        ));
+        assert!(matches!(
+            derive_keypair_from_seed(&params, &[1; MIN_SEED_LEN]),
+            Err(DhError::KeyGenerationFailed { attempts: MAX_KEYGEN_ATTEMPTS })
+        ));
+    }
+
+    #[test]
+    fn public_types_clone() {
+        let params = Group::Ffdhe2048.parameters();
+        let (_, public_key) = generate_keypair_with_rng(&params, &mut StdRng::seed_from_u64(113)).unwrap();
+        let copy = public_key.clone();
+        assert_eq!(copy, public_key);
+        assert!(params.clone().is_compatible_with(&params));
+        let group = Group::Modp2048;
+        assert_eq!(Clone::clone(&group), group);
+    }
+
+    #[test]
+    fn subgroup_members_validate() {
+        let params = Group::Ffdhe2048.parameters();
+        let (_, public_key) = generate_keypair_with_rng(&params, &mut StdRng::seed_from_u64(116)).unwrap();
+        assert_eq!(public_key.validate_in_group(&params), Ok(()));
+        assert_eq!(PublicKey::from_biguint(BigUint::from(4u32)).validate_in_group(&params), Ok(()));
+    }
+
+    // -1 is outside the subgroup of order q, so negating a member gives an element of order 2q. Modulo 23, 5 generates
+    // the whole group.
+    #[test]
+    fn elements_outside_the_subgroup_fail() {
+        let params = Group::Ffdhe2048.parameters();
+        let (_, public_key) = generate_keypair_with_rng(&params, &mut StdRng::seed_from_u64(116)).unwrap();
+        let negated = PublicKey::from_biguint(&params.modulus - &public_key.0);
+        assert_eq!(negated.validate_in_group(&params), Err(ValidationError::NotInSubgroup));
+
+        let toy = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        let generator = PublicKey::from_biguint(BigUint::from(5u32));
+        assert_eq!(generator.validate_in_group(&toy), Err(ValidationError::NotInSubgroup));
+        assert_eq!(PublicKey::from_biguint(BigUint::from(2u32)).validate_in_group(&toy), Ok(()));
+    }
+
+    #[test]
+    fn trivial_values_are_out_of_range() {
+        let params = Group::Ffdhe2048.parameters();
+        for value in [BigUint::from(0u32), BigUint::from(1u32), &params.modulus - 1u32, params.modulus.clone()] {
+            assert_eq!(PublicKey::from_biguint(value).validate_in_group(&params), Err(ValidationError::OutOfRange));
+        }
+    }
+
+    #[test]
+    fn combine_is_deterministic_and_order_sensitive() {
+        let classical = SharedSecret::from_bytes(&[1; 32]);
+        let post_quantum = SharedSecret::from_bytes(&[2; 32]);
+        let combined = classical.combine(&post_quantum);
+        assert_eq!(classical.combine(&post_quantum), combined);
+        assert_ne!(post_quantum.combine(&classical), combined);
+        assert_ne!(combined, classical);
+        assert_ne!(classical.combine(&SharedSecret::from_bytes(&[3; 32])), combined);
+        // The length prefixes keep the boundary between the two secrets.
+        let split_late = SharedSecret::from_bytes(&[1, 2]).combine(&SharedSecret::from_bytes(&[3]));
+        assert_ne!(split_late, SharedSecret::from_bytes(&[1]).combine(&SharedSecret::from_bytes(&[2, 3])));
+    }
+
+    #[test]
+    fn display_is_hex_with_an_optional_length() {
+        let key = PublicKey::from_biguint(BigUint::from(0x0102_abcdu32));
+        assert_eq!(key.to_string(), "0102abcd");
+        assert_eq!(format!("{key:#}"), "[4 bytes] 0102abcd");
+        assert_eq!(PublicKey::from_hex(&key.to_string()).unwrap(), key);
+        assert_eq!(PublicKey::from_hex("0x0102ABCD\n").unwrap(), key);
+        assert_eq!(PublicKey::from_biguint(BigUint::from(5u32)).to_string(), "05");
+    }
+
+    #[test]
+    fn from_hex_rejects_non_hex() {
+        for input in ["", "0x", "xyz", "12 34", "-1"] {
+            assert!(matches!(PublicKey::from_hex(input), Err(DhError::InvalidNumber)), "{input:?}");
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_keypairs_are_valid_and_reproducible() {
+        let params = Group::Modp2048.parameters();
+        let keypairs = generate_keypairs_parallel_with_rng(16, &params, &mut StdRng::seed_from_u64(137)).unwrap();
+        assert_eq!(keypairs.len(), 16);
+        for (private_key, public_key) in &keypairs {
+            private_key.validate(&params).unwrap();
+            assert_eq!(&private_key.public_key(&params), public_key);
+        }
+        let mut distinct: Vec<_> = keypairs.iter().map(|(_, public_key)| public_key.to_hex()).collect();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 16);
+
+        let again = generate_keypairs_parallel_with_rng(16, &params, &mut StdRng::seed_from_u64(137)).unwrap();
+        assert!(keypairs.iter().zip(&again).all(|(first, second)| first.1 == second.1));
+        assert!(generate_keypairs_parallel(0, &params).unwrap().is_empty());
+    }
+
+    // The examples of RFC 4251 section 5 that are non-negative.
+    #[test]
+    fn ssh_mpints_match_the_rfc_examples() {
+        let examples: [(u64, &[u8]); 3] = [
+            (0, &hex!("00000000")),
+            (0x09a3_78f9_b2e3_32a7, &hex!("0000000809a378f9b2e332a7")),
+            (0x80, &hex!("000000020080")),
+        ];
+        for (value, encoded) in examples {
+            let key = PublicKey::from_biguint(BigUint::from(value));
+            assert_eq!(key.to_ssh_mpint(), encoded, "{value:x}");
+            assert_eq!(PublicKey::from_ssh_mpint(encoded).unwrap(), key, "{value:x}");
+        }
+    }
+
+    #[test]
+    fn ssh_mpints_add_a_sign_byte_only_when_the_high_bit_is_set() {
+        let valid: [(u32, &[u8]); 3] =
+            [(0x7f, &hex!("000000017f")), (0xff, &hex!("0000000200ff")), (0x7fff, &hex!("000000027fff"))];
+        for (value, encoded) in valid {
+            let key = PublicKey::from_biguint(BigUint::from(value));
+            assert_eq!(key.to_ssh_mpint(), encoded, "{value:x}");
+            assert_eq!(PublicKey::from_ssh_mpint(encoded).unwrap(), key, "{value:x}");
+        }
+        let invalid: [&[u8]; 6] = [
+            // Negative: the sign bit is set.
+            &hex!("0000000180"),
+            // A leading zero the next byte doesn't need, and zero written as one byte.
+            &hex!("00000002007f"),
+            &hex!("0000000100"),
+            // The length doesn't match the value.
+            &hex!("000000027f"),
+            &hex!("000000017f7f"),
+            &hex!("000000"),
+        ];
+        for bytes in invalid {
+            assert!(matches!(PublicKey::from_ssh_mpint(bytes), Err(DhError::InvalidKeyEncoding)), "{bytes:02x?}");
+        }
+    }
+
+    #[test]
+    fn wire_bytes_accept_only_the_canonical_encoding() {
+        let params = DhParameters::from_u64_allow_insecure(2, 2_147_483_783).unwrap();
+        let canonical = PublicKey::from_biguint(BigUint::from(0x1234_5678u32));
+        assert_eq!(canonical.to_wire_bytes(), [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(PublicKey::from_wire_bytes(&canonical.to_wire_bytes(), &params).unwrap(), canonical);
+
+        for padded in [&[0x00, 0x12, 0x34, 0x56, 0x78][..], &[0x00, 0x00, 0x12, 0x34, 0x56, 0x78]] {
+            assert!(matches!(PublicKey::from_wire_bytes(padded, &params), Err(DhError::InvalidKeyEncoding)));
+        }
+        let out_of_range = [
+            params.modulus.clone(),
+            &params.modulus + 0x1234_5678u32,
+            &params.modulus - 1u32,
+            BigUint::from(1u32),
+        ];
+        for value in out_of_range {
+            let result = PublicKey::from_wire_bytes(&value.to_bytes_be(), &params);
+            assert!(matches!(result, Err(DhError::InvalidPublicKey(ValidationError::OutOfRange))), "{value}");
+        }
+        // Zero is the empty string of bytes; a single 0x00 is already a leading zero.
+        let zero = PublicKey::from_wire_bytes(&[], &params);
+        assert!(matches!(zero, Err(DhError::InvalidPublicKey(ValidationError::OutOfRange))));
+        assert!(matches!(PublicKey::from_wire_bytes(&[0], &params), Err(DhError::InvalidKeyEncoding)));
+        assert!(PublicKey::from_wire_bytes(&[2], &params).is_ok());
+        assert!(PublicKey::from_wire_bytes(&(&params.modulus - 2u32).to_bytes_be(), &params).is_ok());
+    }
+
+    // Stallings' worked example: q = 353, alpha = 3, X_A = 97 and X_B = 233 give Y_A = 40, Y_B = 248 and K = 160.
+    #[test]
+    fn supplied_keys_reproduce_the_stallings_example() {
+        let params = DhParameters::from_u64_allow_insecure(3, 353).unwrap();
+        let alice = PrivateKey::from_biguint(BigUint::from(97u32));
+        let bob = PrivateKey::from_biguint(BigUint::from(233u32));
+        assert_eq!(alice.public_key(&params).0, BigUint::from(40u32));
+        assert_eq!(bob.public_key(&params).0, BigUint::from(248u32));
+        let (alice_secret, bob_secret) = run_exchange_with_keys(&alice, &bob, &params);
+        assert_eq!(alice_secret.0, BigUint::from(160u32));
+        assert!(alice_secret == bob_secret);
+
+        // The same keys always give the same secret; swapping them over changes nothing.
+        let (again, _) = run_exchange_with_keys(&alice, &bob, &params);
+        let (swapped, _) = run_exchange_with_keys(&bob, &alice, &params);
+        assert!(again == alice_secret && swapped == alice_secret);
+    }
+
+    #[test]
+    fn compact_strings_round_trip_without_look_alikes() {
+        // "hello world" read as a big-endian number is the usual base58 test vector.
+        let hello = PublicKey::from_biguint(BigUint::from_bytes_be(b"hello world"));
+        assert_eq!(hello.to_compact_string(), "StV1DL6CwTryKyV");
+        for (value, compact) in [(0u32, "1"), (57, "z"), (58, "21")] {
+            assert_eq!(PublicKey::from_biguint(BigUint::from(value)).to_compact_string(), compact);
+            assert_eq!(PublicKey::from_compact_string(compact).unwrap().0, BigUint::from(value));
+        }
+
+        let params = Group::Ffdhe2048.parameters();
+        let key = PrivateKey::from_bytes_be(&[0x42; 28]).public_key(&params);
+        let compact = key.to_compact_string();
+        assert!(compact.len() < key.to_hex().len() * 3 / 4, "{compact}");
+        assert!(!compact.contains(['0', 'O', 'I', 'l']), "{compact}");
+        assert_eq!(PublicKey::from_compact_string(&format!(" {compact}\n")).unwrap(), key);
+    }
+
+    #[test]
+    fn compact_strings_reject_other_characters_and_leading_zeros() {
+        for compact in ["", "  ", "0", "StV1DL6CwTryKyO", "StV1DL6CwTryKyI", "StV1DL6CwTryKyl", "Stv+1", "12", "11"] {
+            assert!(matches!(PublicKey::from_compact_string(compact), Err(DhError::InvalidKeyEncoding)), "{compact:?}");
+        }
+    }
+
+    #[test]
+    fn toy_groups_report_next_to_no_entropy() {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        // 5 mod 57 has order 18, 5 mod 23 order 22 and 2 mod 23 order 11.
+        for ((generator, modulus), bits) in [((5, 57), 4), ((5, 23), 4), ((2, 23), 3)] {
+            let params = DhParameters::from_u64_allow_insecure(generator, modulus).unwrap();
+            assert_eq!(secret.effective_entropy_bits(&params), bits, "{generator} mod {modulus}");
+        }
+    }
+
+    #[test]
+    fn a_2048_bit_group_reports_nearly_its_full_size() {
+        let params = Group::Modp2048.parameters();
+        assert_eq!(SharedSecret::from_bytes(&[9; 32]).effective_entropy_bits(&params), 2046);
+        // Only the parameters count, not the secret.
+        assert_eq!(SharedSecret::from_bytes(&[1]).effective_entropy_bits(&params), 2046);
+    }
+
+    #[test]
+    fn large_unsafe_moduli_fall_back_to_their_size() {
+        // 2^127 - 1 is prime, but 2^126 - 1 isn't, so the order of 3 is unknown and the modulus size is all there is.
+        let mersenne = (BigUint::from(1u32) << 127) - 1u32;
+        let params = DhParameters::new_allow_insecure(BigUint::from(3u32), mersenne).unwrap();
+        assert!(!params.is_safe_prime());
+        assert_eq!(SharedSecret::from_bytes(&[9; 32]).effective_entropy_bits(&params), 126);
+    }
+
+    #[test]
+    fn agree_and_derive_gives_both_parties_the_same_keys() {
+        let params = Group::Ffdhe2048.parameters();
+        let alice = PrivateKey::from_bytes_be(&[0x42; 28]);
+        let bob = PrivateKey::from_bytes_be(&[0x17; 28]);
+        let (alice_public, bob_public) = (alice.public_key(&params), bob.public_key(&params));
+
+        let alice_keys = agree_and_derive(&alice, &bob_public, &params, b"agree test").unwrap();
+        let bob_keys = agree_and_derive(&bob, &alice_public, &params, b"agree test").unwrap();
+        assert_eq!(alice_keys.encryption_key, bob_keys.encryption_key);
+        assert_eq!(alice_keys.mac_key, bob_keys.mac_key);
+        assert_ne!(alice_keys.encryption_key[..], alice_keys.mac_key[..16]);
+
+        let other_info = agree_and_derive(&bob, &alice_public, &params, b"other protocol").unwrap();
+        assert_ne!(other_info.encryption_key, alice_keys.encryption_key);
+        assert_ne!(other_info.mac_key, alice_keys.mac_key);
+    }
+
+    #[test]
+    fn agree_and_derive_rejects_invalid_peer_keys() {
+        let params = Group::Ffdhe2048.parameters();
+        let alice = PrivateKey::from_bytes_be(&[0x42; 28]);
+        for bad in [BigUint::from(0u32), BigUint::from(1u32), &params.modulus - 1u32, params.modulus.clone()] {
+            let peer = PublicKey::from_biguint(bad);
+            assert!(matches!(agree_and_derive(&alice, &peer, &params, b""), Err(DhError::InvalidPublicKey(_))));
+        }
+    }
+
+    #[test]
+    fn batch_validation_reports_each_key() {
+        let params = Group::Ffdhe2048.parameters();
+        let valid = PrivateKey::from_bytes_be(&[0x42; 28]).public_key(&params);
+        // -y has the opposite Legendre symbol to y when p = 3 mod 4, so it falls outside the subgroup.
+        let negated = PublicKey::from_biguint(&params.modulus - valid.as_biguint());
+        let keys = [
+            valid.clone(),
+            PublicKey::from_biguint(BigUint::from(1u32)),
+            negated,
+            PublicKey::from_biguint(BigUint::from(2u32)),
+            PublicKey::from_biguint(&params.modulus - 1u32),
+            PublicKey::from_biguint(params.modulus.clone()),
+            PublicKey::from_biguint(BigUint::from(0u32)),
+        ];
+        assert_eq!(
+            validate_public_keys(&keys, &params),
+            [
+                Ok(()),
+                Err(ValidationError::OutOfRange),
+                Err(ValidationError::NotInSubgroup),
+                Ok(()),
+                Err(ValidationError::OutOfRange),
+                Err(ValidationError::OutOfRange),
+                Err(ValidationError::OutOfRange),
+            ]
+        );
+        for (key, result) in keys.iter().zip(validate_public_keys(&keys, &params)) {
+            assert_eq!(key.validate_in_group(&params), result);
+        }
+        assert!(validate_public_keys(&[], &params).is_empty());
+    }
+
+    fn validated(key: u32, params: &DhParameters) -> Result<(), ValidationError> {
+        match validate_public_key(&PublicKey::from_biguint(BigUint::from(key)), params) {
+            Ok(()) => Ok(()),
+            Err(DhError::InvalidPublicKey(reason)) => Err(reason),
+            Err(other) => panic!("unexpected error {other}"),
+        }
+    }
+
+    #[test]
+    fn degenerate_and_small_subgroup_keys_are_rejected() {
+        // 23 = 2 * 11 + 1, and the subgroup of order 11 is the quadratic residues.
+        let safe = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        for key in [0, 1, 22, 23, 100] {
+            assert_eq!(validated(key, &safe), Err(ValidationError::OutOfRange), "y = {key}");
+        }
+        for key in [5, 7, 10, 21] {
+            assert_eq!(validated(key, &safe), Err(ValidationError::NotInSubgroup), "y = {key}");
+        }
+        for key in [2, 3, 4, 12, 18] {
+            assert_eq!(validated(key, &safe), Ok(()), "y = {key}");
+        }
+
+        // 13 isn't a safe prime, so only the range is checked.
+        let unsafe_prime = DhParameters::from_u64_allow_insecure(2, 13).unwrap();
+        assert_eq!(validated(5, &unsafe_prime), Ok(()));
+        assert_eq!(validated(12, &unsafe_prime), Err(ValidationError::OutOfRange));
+    }
+
+    #[test]
+    fn seeds_give_the_same_keypair_every_time() {
+        let params = DhParameters::modp_2048();
+        let seed = [0x5a; MIN_SEED_LEN];
+        let (private_key, public_key) = derive_keypair_from_seed(&params, &seed).unwrap();
+        let (again, again_public) = derive_keypair_from_seed(&params, &seed).unwrap();
+        assert_eq!(private_key.to_bytes_be(), again.to_bytes_be());
+        assert_eq!(public_key, again_public);
+        assert_eq!(public_key, private_key.public_key(&params));
+        assert!(private_key.0.bits() <= params.recommended_exponent_bits());
+        assert!(private_key.0.bits() > params.recommended_exponent_bits() - 16);
+
+        let mut other_seed = seed;
+        other_seed[MIN_SEED_LEN - 1] ^= 1;
+        assert_ne!(derive_keypair_from_seed(&params, &other_seed).unwrap().1, public_key);
+        let other_group = DhParameters::ffdhe2048();
+        assert_ne!(derive_keypair_from_seed(&other_group, &seed).unwrap().0.to_bytes_be(), private_key.to_bytes_be());
+    }
+
+    #[test]
+    fn seeds_work_in_toy_groups_and_must_be_long_enough() {
+        let params = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        for byte in 0..32u8 {
+            let (private_key, public_key) = derive_keypair_from_seed(&params, &[byte; 20]).unwrap();
+            assert!(public_key.is_nontrivial(&params), "seed byte {byte}");
+            assert_eq!(public_key, private_key.public_key(&params));
+        }
+        let result = derive_keypair_from_seed(&params, &[1; MIN_SEED_LEN - 1]);
+        assert!(matches!(result, Err(DhError::SeedTooShort { len: 15, min_len: MIN_SEED_LEN })));
+    }
+}
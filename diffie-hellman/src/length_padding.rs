@@ -0,0 +1,66 @@
+// Length-hiding padding. Encryption hides what a message says but not how long it is, so before encrypting, messages
+// can be padded up to the next multiple of a bucket size. A 4-byte big-endian length prefix records the real length so
+// the padding can be stripped again after decryption. Every message in the same bucket ends up the same size.
+
+use alloc::vec::Vec;
+
+use crate::error::DhError;
+
+// Size of the length prefix written in front of the message.
+const LENGTH_PREFIX_LEN: usize = 4;
+
+// The bucket size used when callers have no particular preference.
+pub const DEFAULT_BUCKET_SIZE: usize = 256;
+
+// Prefix `data` with its length and zero-pad the result to the next multiple of `bucket_size`.
+pub fn pad_to_bucket(data: &[u8], bucket_size: usize) -> Result<Vec<u8>, DhError> {
+    if bucket_size == 0 {
+        return Err(DhError::InvalidBucketSize);
+    }
+    let length = u32::try_from(data.len()).map_err(|_| DhError::MessageTooLong)?;
+    let unpadded_len = LENGTH_PREFIX_LEN + data.len();
+    let padded_len = unpadded_len.div_ceil(bucket_size) * bucket_size;
+
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(&length.to_be_bytes());
+    padded.extend_from_slice(data);
+    padded.resize(padded_len, 0);
+    Ok(padded)
+}
+
+// Recover the original message from the output of `pad_to_bucket`.
+pub fn strip_bucket_padding(padded: &[u8]) -> Result<Vec<u8>, DhError> {
+    if padded.len() < LENGTH_PREFIX_LEN {
+        return Err(DhError::MalformedPadding);
+    }
+    let (prefix, rest) = padded.split_at(LENGTH_PREFIX_LEN);
+    let length = u32::from_be_bytes(prefix.try_into().expect("prefix is 4 bytes")) as usize;
+    if length > rest.len() {
+        return Err(DhError::MalformedPadding);
+    }
+    Ok(rest[..length].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_in_a_bucket_pad_to_the_same_length() {
+        for len in [0, 1, 100, DEFAULT_BUCKET_SIZE - LENGTH_PREFIX_LEN] {
+            let data = vec![b'x'; len];
+            let padded = pad_to_bucket(&data, DEFAULT_BUCKET_SIZE).unwrap();
+            assert_eq!(padded.len(), DEFAULT_BUCKET_SIZE, "{len} bytes");
+            assert_eq!(strip_bucket_padding(&padded).unwrap(), data);
+        }
+        let one_over = vec![b'x'; DEFAULT_BUCKET_SIZE - LENGTH_PREFIX_LEN + 1];
+        assert_eq!(pad_to_bucket(&one_over, DEFAULT_BUCKET_SIZE).unwrap().len(), 2 * DEFAULT_BUCKET_SIZE);
+    }
+
+    #[test]
+    fn bad_buckets_and_padding_are_errors() {
+        assert!(matches!(pad_to_bucket(b"data", 0), Err(DhError::InvalidBucketSize)));
+        assert!(matches!(strip_bucket_padding(&[0, 0, 1]), Err(DhError::MalformedPadding)));
+        assert!(matches!(strip_bucket_padding(&[0, 0, 0, 9, b'a']), Err(DhError::MalformedPadding)));
+    }
+}
@@ -0,0 +1,505 @@
+// Library half of the Diffie-Hellman demo: key generation and the AES layer used to encrypt messages with the agreed secret.
+// The binary in main.rs walks through the exchange between Alice and Bob on top of these helpers.
+//
+// Without the default `std` feature the crate is `no_std` and needs only `alloc`: what remains is the core a small
+// device needs to take part in an exchange, namely the parameters, key generation and agreement, key derivation, the
+// handshake messages and transcript, and the ciphers. Randomness then comes from the caller, through the `_with_rng`
+// functions. Networking, sessions, streams and everything else that needs an operating system require `std`. The unit
+// tests always link `std`, whichever features are on.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use num_bigint::{BigUint, RandBigInt};
+use rand::{CryptoRng, Rng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
+
+mod cipher;
+mod compat;
+mod confirmation;
+mod ct;
+mod error;
+mod fingerprint;
+mod gcm;
+mod group_context;
+mod handshake_message;
+mod kdf;
+mod keys;
+mod length_padding;
+mod nonce;
+mod params;
+mod primality;
+mod replay;
+mod signing;
+mod transcript;
+
+#[cfg(feature = "std")]
+mod agreement_cache;
+#[cfg(feature = "std")]
+mod archive;
+#[cfg(feature = "std")]
+mod audit;
+#[cfg(feature = "std")]
+mod channel;
+#[cfg(feature = "std")]
+mod config;
+#[cfg(feature = "std")]
+mod ctr;
+#[cfg(feature = "std")]
+mod datagram;
+#[cfg(feature = "std")]
+mod diagnostics;
+#[cfg(feature = "std")]
+mod envelope;
+#[cfg(feature = "std")]
+mod ephemeral;
+#[cfg(feature = "std")]
+mod etm;
+#[cfg(feature = "std")]
+mod group_exchange;
+#[cfg(feature = "std")]
+mod handshake_state;
+#[cfg(feature = "std")]
+mod hexdump;
+#[cfg(feature = "std")]
+mod key_exchange;
+#[cfg(feature = "std")]
+mod logging;
+#[cfg(feature = "std")]
+mod message;
+#[cfg(feature = "std")]
+mod multi;
+#[cfg(feature = "std")]
+mod net;
+#[cfg(feature = "std")]
+mod pake;
+#[cfg(feature = "std")]
+mod parse;
+#[cfg(feature = "std")]
+mod party;
+#[cfg(feature = "std")]
+mod ratchet;
+#[cfg(feature = "std")]
+mod record;
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "std")]
+mod sealed_box;
+#[cfg(feature = "std")]
+mod session;
+#[cfg(feature = "std")]
+mod session_audit;
+#[cfg(feature = "std")]
+mod session_manager;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+mod suite;
+#[cfg(feature = "std")]
+mod text;
+#[cfg(feature = "std")]
+mod textbook;
+#[cfg(feature = "std")]
+mod transport;
+
+#[cfg(feature = "argon2")]
+mod key_file;
+
+#[cfg(feature = "serde")]
+mod json;
+
+#[cfg(feature = "ed25519")]
+mod identity;
+
+#[cfg(feature = "pkcs8")]
+mod pkcs8;
+
+#[cfg(feature = "pq")]
+mod pq;
+
+#[cfg(feature = "serde")]
+mod serialization;
+
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+
+#[cfg(feature = "tokio")]
+mod async_net;
+
+#[cfg(feature = "x25519")]
+mod x25519;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "std")]
+pub use agreement_cache::AgreementCache;
+#[cfg(feature = "std")]
+pub use archive::Archive;
+#[cfg(feature = "std")]
+pub use audit::{audit, AuditWarning};
+#[cfg(feature = "std")]
+pub use channel::SecureChannel;
+pub use cipher::{
+    aes_key, decrypt_data, decrypt_padded, decrypt_str, decrypt_with_mode, decrypt_with_padding, encrypt_data,
+    encrypt_padded, encrypt_str, encrypt_with_mode, encrypt_with_padding, CipherMode, Padding,
+};
+#[cfg(feature = "std")]
+pub use config::{Cipher, ExchangeConfig};
+pub use confirmation::{confirmation_tag, verify_confirmation_tag, CONFIRMATION_TAG_LEN};
+#[cfg(feature = "std")]
+pub use ctr::{CtrCipher, CtrIvMode, CTR_SALT_LEN};
+#[cfg(feature = "std")]
+pub use datagram::{DatagramChannel, DatagramInitiator, DatagramResponder};
+#[cfg(feature = "std")]
+pub use diagnostics::{
+    aes_backend_info, measure_exchange, measure_throughput, per_second, self_test, ExchangeTimings, Throughput,
+};
+#[cfg(feature = "std")]
+pub use envelope::{EncryptedMessage, EnvelopeOpener, EnvelopeSealer, SequencedMessage};
+#[cfg(feature = "std")]
+pub use ephemeral::{EphemeralSecret, KeyPair, StaticKeyPair};
+pub use error::{DhError, ValidationError};
+#[cfg(feature = "std")]
+pub use etm::{open, open_with_keys, seal, seal_with_keys};
+pub use fingerprint::{Fingerprint, FINGERPRINT_WORDS, SAS_WORDS};
+pub use gcm::{decrypt_gcm, decrypt_gcm_limited, derive_gcm_key, encrypt_gcm_with_rng, gcm_known_answers_match};
+pub use group_context::GroupContext;
+#[cfg(feature = "std")]
+pub use group_exchange::{run_group_exchange, GroupExchange};
+pub use handshake_message::{
+    negotiate_version, Extension, HandshakeMessage, WireMessage, EXTENSION_SUPPORTED_VERSIONS, PROTOCOL_MAGIC,
+    PROTOCOL_VERSION, SUPPORTED_VERSIONS,
+};
+#[cfg(feature = "std")]
+pub use handshake_state::{AwaitingConfirmation, AwaitingPeer, Confirmed, Handshake};
+#[cfg(feature = "std")]
+pub use hexdump::{hexdump, HexdumpStream};
+pub use kdf::{derive_key, derive_key_with_context, derive_key_with_hash, kdf_known_answers_match, HkdfHash};
+#[cfg(feature = "std")]
+pub use key_exchange::{AnyKeyExchange, AnyPrivateKey, FfDh, KeyExchange};
+pub use keys::{
+    agree_and_derive, derive_keypair_from_seed, derive_session_keys, dh_known_answers_match,
+    generate_keypair_sized_with_rng, generate_keypair_with_rng, run_exchange_with_keys, validate_public_key,
+    validate_public_keys, DerivedKeys, PrivateKey, PublicKey, SharedSecret, MAX_KEYGEN_ATTEMPTS, MIN_SEED_LEN,
+};
+pub use length_padding::{pad_to_bucket, strip_bucket_padding, DEFAULT_BUCKET_SIZE};
+#[cfg(feature = "std")]
+pub use message::{decrypt, encrypt, Ciphertext, Plaintext};
+#[cfg(feature = "std")]
+pub use multi::{decrypt_multi, encrypt_multi};
+#[cfg(feature = "std")]
+pub use net::{
+    confirmed_handshake, connect, handshake, handshake_with_transcript, receive_frame, receive_public_key, send_frame,
+    send_public_key, tcp_handshake, ConnectOptions, HandshakeOptions, DEFAULT_HANDSHAKE_TIMEOUT,
+};
+pub use nonce::{generate_session_salt_with_rng, NonceSequence, NONCE_LEN, SALT_LEN};
+#[cfg(feature = "std")]
+pub use pake::PakeExchange;
+pub use params::{is_valid_generator, negotiate_group, DhParameters, DhParams, Group, ParameterReport, MIN_SECURE_BITS};
+#[cfg(feature = "std")]
+pub use parse::parse_biguint;
+#[cfg(feature = "std")]
+pub use party::{DhParty, DiffieHellman};
+pub use primality::is_probable_prime;
+#[cfg(feature = "std")]
+pub use ratchet::{RatchetState, MAX_SKIPPED_MESSAGES};
+#[cfg(feature = "std")]
+pub use record::{RecordLayer, DEFAULT_REKEY_BYTES, DEFAULT_REKEY_RECORDS, RECORD_MAX_LEN};
+#[cfg(feature = "std")]
+pub use registry::ParametersRegistry;
+pub use replay::{ReplayWindow, WINDOW_SIZE};
+#[cfg(feature = "std")]
+pub use sealed_box::{open_anonymous, seal_anonymous};
+#[cfg(feature = "std")]
+pub use session::{Record, Role, Session, SessionReceiver, SessionSender, SessionTicket, TICKET_LEN};
+#[cfg(feature = "std")]
+pub use session_audit::{AuditEvent, SessionAudit};
+#[cfg(feature = "std")]
+pub use session_manager::{SessionId, SessionManager, SESSION_ID_LEN};
+pub use signing::Tag;
+#[cfg(feature = "std")]
+pub use stream::{DecryptingReader, EncryptingWriter, StreamOpener, StreamSealer, STREAM_CHUNK_LEN};
+#[cfg(feature = "std")]
+pub use suite::{CipherSuite, SuiteChannel, SuiteOffer};
+#[cfg(feature = "std")]
+pub use text::{envelope_from_text, envelope_to_text};
+#[cfg(feature = "std")]
+pub use textbook::{
+    textbook_exchange, textbook_parameters, TextbookSteps, TEXTBOOK_ALICE_PRIVATE, TEXTBOOK_BOB_PRIVATE,
+    TEXTBOOK_GENERATOR, TEXTBOOK_MODULUS,
+};
+pub use transcript::Transcript;
+#[cfg(feature = "std")]
+pub use transport::DuplexTransport;
+
+#[cfg(feature = "std")]
+pub use confirmation::confirm;
+#[cfg(feature = "std")]
+pub use gcm::{encrypt_gcm, encrypt_gcm_into};
+#[cfg(feature = "std")]
+pub use kdf::derive_labeled_keys;
+#[cfg(feature = "std")]
+pub use keys::{generate_keypair, generate_keypair_sized};
+#[cfg(feature = "std")]
+pub use nonce::generate_session_salt;
+
+#[cfg(feature = "argon2")]
+pub use kdf::derive_key_with_password;
+
+#[cfg(feature = "ed25519")]
+pub use identity::{authenticated_handshake, IdentityKey, PeerIdentity};
+
+#[cfg(feature = "serde")]
+pub use json::{decrypt_json, encrypt_json};
+
+#[cfg(feature = "parallel")]
+pub use keys::{generate_keypairs_parallel, generate_keypairs_parallel_with_rng};
+
+#[cfg(feature = "pq")]
+pub use pq::{hybrid_respond, hybrid_respond_with_rng, HybridInitiator, HYBRID_OFFER_LEN, HYBRID_REPLY_LEN};
+
+#[cfg(feature = "tokio")]
+pub use async_net::{handshake_async, SecureStream, SECURE_STREAM_FRAME_LEN};
+
+#[cfg(feature = "x25519")]
+pub use x25519::X25519Dh;
+
+// Generates a random 128-bit key which will be the private keys for the parties involved. A 128-bit key means 10
+// rounds of AES
+#[cfg(feature = "std")]
+pub fn generate_random_key() -> BigUint {
+    generate_random_key_with_rng(&mut rand::thread_rng())
+}
+
+// `generate_random_key` with the bits drawn from `rng`.
+pub fn generate_random_key_with_rng<R: CryptoRng + RngCore>(rng: &mut R) -> BigUint {
+    BigUint::from(rng.gen::<u128>())
+}
+
+// Generates a uniformly random value below 2^bits, so the private key can be sized to the group instead of always
+// being 128 bits. The top bit isn't forced, so the result can be a few bits shorter than requested.
+#[cfg(feature = "std")]
+pub fn generate_random_key_bits(bits: usize) -> BigUint {
+    generate_random_key_bits_with_rng(bits, &mut rand::thread_rng())
+}
+
+// `generate_random_key_bits` with the bits drawn from `rng`.
+pub fn generate_random_key_bits_with_rng<R: CryptoRng + RngCore>(bits: usize, rng: &mut R) -> BigUint {
+    rng.gen_biguint(bits as u64)
+}
+
+// A random string of `len` printable ASCII characters (space through '~'), for exercising the ciphers with varied
+// inputs in demos and tests. Every character is one byte, so the string is exactly `len` bytes long.
+#[cfg(feature = "std")]
+pub fn random_plaintext(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len).map(|_| char::from(rng.gen_range(b' '..=b'~'))).collect()
+}
+
+// Text that C-string or line-oriented handling would mangle, for the round-trip tests of the text APIs: NULs (one of
+// them trailing), a byte that looks like PKCS#7 padding, line endings, an escape sequence, DEL and a C1 control. Valid
+// UTF-8, so it must come back exactly.
+#[cfg(test)]
+pub(crate) const CONTROL_CHARACTER_TEXT: &str = "\0lead\0ing\x01\t\r\n\x1b[0m\x7f\u{85}\x10trailing\0";
+
+// Compare two secret keys without short-circuiting on the first differing byte, so the time taken doesn't reveal how
+// much of a guess was right. Both values are padded to the same width first, otherwise the byte length of the secrets
+// would leak instead.
+pub fn secrets_match(a: &BigUint, b: &BigUint) -> bool {
+    let mut a_bytes = a.to_bytes_le();
+    let mut b_bytes = b.to_bytes_le();
+    let width = a_bytes.len().max(b_bytes.len());
+    a_bytes.resize(width, 0);
+    b_bytes.resize(width, 0);
+    let matches = a_bytes.ct_eq(&b_bytes).into();
+    a_bytes.zeroize();
+    b_bytes.zeroize();
+    matches
+}
+
+// Overwrite a secret number with zeros in place, as far as num-bigint allows, leaving it equal to 0. num-bigint has no
+// zeroize support, so this clears every bit through `set_bit`, lowest first, so each digit is zeroed in its existing
+// buffer before the top one is cleared and the buffer released. This is best effort only: copies left behind by
+// earlier arithmetic or reallocation, temporaries such as `to_bytes_be` output, and values in registers or swapped-out
+// memory are out of its reach.
+pub fn scrub_biguint(value: &mut BigUint) {
+    for bit in 0..value.bits() {
+        value.set_bit(bit, false);
+    }
+    core::hint::black_box(&*value);
+}
+
+// A short hex fingerprint of a secret key, so two parties can see they hold the same secret without printing it.
+// It is a truncated, domain-separated SHA-256 hash, which reveals nothing useful about the secret itself.
+pub fn secret_fingerprint(secret: &BigUint) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"diffie-hellman secret fingerprint")
+        .chain_update(Zeroizing::new(secret.to_bytes_be()))
+        .finalize();
+    digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Servers hand sessions and keys between threads, so these types must stay Send and Sync. Checked at compile time:
+// a field that breaks this (an Rc, a RefCell, ...) fails the build here rather than in downstream code.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<DhParameters>();
+    assert_send_sync::<Fingerprint>();
+    assert_send_sync::<GroupContext>();
+    assert_send_sync::<NonceSequence>();
+    assert_send_sync::<PrivateKey>();
+    assert_send_sync::<PublicKey>();
+    assert_send_sync::<ReplayWindow>();
+    assert_send_sync::<SharedSecret>();
+    assert_send_sync::<Tag>();
+};
+
+#[cfg(feature = "std")]
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AgreementCache>();
+    assert_send_sync::<AnyKeyExchange>();
+    assert_send_sync::<AnyPrivateKey>();
+    assert_send_sync::<CtrCipher>();
+    assert_send_sync::<DatagramChannel>();
+    assert_send_sync::<DatagramInitiator>();
+    assert_send_sync::<DiffieHellman>();
+    assert_send_sync::<EncryptedMessage>();
+    assert_send_sync::<EnvelopeOpener>();
+    assert_send_sync::<EnvelopeSealer>();
+    assert_send_sync::<EphemeralSecret>();
+    assert_send_sync::<FfDh>();
+    assert_send_sync::<GroupExchange>();
+    assert_send_sync::<Handshake<AwaitingPeer>>();
+    assert_send_sync::<Handshake<Confirmed>>();
+    assert_send_sync::<PakeExchange>();
+    assert_send_sync::<RatchetState>();
+    assert_send_sync::<SecureChannel>();
+    assert_send_sync::<Session>();
+    assert_send_sync::<SessionAudit>();
+    assert_send_sync::<SessionManager>();
+    assert_send_sync::<SessionReceiver>();
+    assert_send_sync::<SessionSender>();
+    assert_send_sync::<StaticKeyPair>();
+    assert_send_sync::<StreamOpener>();
+    assert_send_sync::<StreamSealer>();
+    assert_send_sync::<SuiteChannel>();
+    #[cfg(feature = "ed25519")]
+    assert_send_sync::<IdentityKey>();
+    #[cfg(feature = "ed25519")]
+    assert_send_sync::<PeerIdentity>();
+    #[cfg(feature = "pq")]
+    assert_send_sync::<HybridInitiator>();
+    #[cfg(feature = "x25519")]
+    assert_send_sync::<X25519Dh>();
+};
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    #[cfg(feature = "std")]
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    // Statistical, so noisy on a loaded machine: run with `cargo test --release -- --ignored`. Over 8 KiB values, a
+    // comparison that stops at the first differing byte takes about a quarter longer when that byte is the last than
+    // when it is the first. The median times for each position must stay within 10% of each other.
+    #[test]
+    #[ignore]
+    fn secrets_match_time_does_not_depend_on_first_difference() {
+        const LEN: usize = 8192;
+        const ROUNDS: usize = 2000;
+        let secret = BigUint::from_bytes_le(&[0xa5; LEN]);
+        let positions = [0, LEN / 4, LEN / 2, 3 * LEN / 4, LEN - 1];
+        let guesses: Vec<BigUint> = positions
+            .iter()
+            .map(|&position| {
+                let mut bytes = [0xa5; LEN];
+                bytes[position] ^= 1;
+                BigUint::from_bytes_le(&bytes)
+            })
+            .collect();
+        let mut samples = vec![Vec::with_capacity(ROUNDS); guesses.len()];
+        for _ in 0..ROUNDS {
+            // Interleave the positions, so drift in the machine's speed hits all of them alike.
+            for (guess, times) in guesses.iter().zip(&mut samples) {
+                let start = Instant::now();
+                assert!(!secrets_match(core::hint::black_box(&secret), core::hint::black_box(guess)));
+                times.push(start.elapsed());
+            }
+        }
+        let medians: Vec<Duration> = samples
+            .into_iter()
+            .map(|mut times| {
+                times.sort();
+                times[ROUNDS / 2]
+            })
+            .collect();
+        let fastest = *medians.iter().min().unwrap();
+        let slowest = *medians.iter().max().unwrap();
+        assert!(slowest.as_secs_f64() < fastest.as_secs_f64() * 1.1, "medians by position: {medians:?}");
+    }
+
+    #[test]
+    fn random_key_bits_stay_within_the_bound() {
+        let mut rng = StdRng::seed_from_u64(128);
+        for bits in [1, 8, 64, 100, 224, 2048] {
+            let samples: Vec<BigUint> = (0..200).map(|_| generate_random_key_bits_with_rng(bits, &mut rng)).collect();
+            assert!(samples.iter().all(|key| key.bits() <= bits as u64), "{bits} bits");
+            // Half of all values use the top bit, so 200 samples reaching it is all but certain.
+            assert!(samples.iter().any(|key| key.bits() == bits as u64), "{bits} bits never reached");
+        }
+        assert_eq!(generate_random_key_bits_with_rng(0, &mut rng), BigUint::from(0u32));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn random_plaintext_is_printable_ascii_of_the_requested_length() {
+        for len in [0, 1, 16, 1000] {
+            let text = random_plaintext(len);
+            assert_eq!(text.len(), len);
+            assert_eq!(text.chars().count(), len);
+            assert!(std::str::from_utf8(text.as_bytes()).is_ok());
+            assert!(text.bytes().all(|byte| (b' '..=b'~').contains(&byte)), "{text:?}");
+        }
+        assert_ne!(random_plaintext(32), random_plaintext(32));
+    }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        fn random_plaintexts_round_trip(len in 0usize..300, key: u128, seed: u64) {
+            let text = random_plaintext(len);
+            let key = BigUint::from(key);
+            prop_assert_eq!(decrypt_str(&encrypt_str(&text, &key), &key).unwrap(), text.clone());
+            let message = encrypt_gcm_with_rng(text.as_bytes(), &key, &mut StdRng::seed_from_u64(seed)).unwrap();
+            prop_assert_eq!(decrypt_gcm(&message, &key).unwrap(), text.into_bytes());
+        }
+    }
+
+    #[test]
+    fn scrubbed_numbers_are_zero() {
+        let mut value = BigUint::from_bytes_be(&[0xa5; 300]);
+        scrub_biguint(&mut value);
+        assert_eq!(value, BigUint::from(0u32));
+        assert_eq!(value.bits(), 0);
+
+        let mut zero = BigUint::from(0u32);
+        scrub_biguint(&mut zero);
+        assert_eq!(zero, BigUint::from(0u32));
+    }
+}
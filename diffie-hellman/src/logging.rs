@@ -0,0 +1,39 @@
+// Tracing for the handshakes, through the `tracing` crate. Events cost next to nothing until an application installs
+// a subscriber, which decides where they go and at what level; the CLI's `--verbose` does so with
+// `tracing-subscriber`. Each handshake runs in a span, with a debug-level event as each phase completes. Public keys
+// are recorded by fingerprint, and in full at trace level.
+//
+// Secret material must never reach a log. `tracing` can only record a value through `Display` (`%`), `Debug` (`?`)
+// or its own `Value` trait. None of the secret types implements `Display` or `Value`, which the assertions below
+// check at compile time, so adding either to one of them fails the build. Their `Debug` output, where they have one,
+// is `[REDACTED]`. Derived values such as the shared secret's fingerprint and SAS are left out of events too, even
+// though they are safe to show a user.
+
+use crate::ephemeral::{EphemeralSecret, StaticKeyPair};
+use crate::key_exchange::AnyPrivateKey;
+use crate::keys::{DerivedKeys, PrivateKey, SharedSecret};
+
+// Fails to build if one of the types implements `Display` or `tracing::Value`: the blanket impl and the bounded
+// one then both apply, and the call below can't pick between them.
+macro_rules! assert_not_loggable {
+    ($($secret:ty),* $(,)?) => {
+        $(
+            const _: fn() = || {
+                trait AmbiguousIfLoggable<A> {
+                    fn check() {}
+                }
+                impl<T: ?Sized> AmbiguousIfLoggable<()> for T {}
+                struct ViaDisplay;
+                impl<T: ?Sized + core::fmt::Display> AmbiguousIfLoggable<ViaDisplay> for T {}
+                struct ViaValue;
+                impl<T: ?Sized + tracing::Value> AmbiguousIfLoggable<ViaValue> for T {}
+                <$secret as AmbiguousIfLoggable<_>>::check();
+            };
+        )*
+    };
+}
+
+assert_not_loggable!(AnyPrivateKey, DerivedKeys, EphemeralSecret, PrivateKey, SharedSecret, StaticKeyPair);
+
+#[cfg(feature = "ed25519")]
+assert_not_loggable!(crate::identity::IdentityKey);
@@ -10,84 +10,1079 @@
 // 6. Bob receives the encrypted data and decrypts it with his own secret
 // 7. Bob encrypts some data using his secret key and sends it to Alice
 // 8. Alice receives the encrypted data and decrypts it with her own secret
+//
+// Running the binary without a subcommand plays through the same steps securely: a 3072-bit group and AES-128-GCM
+// through a `SecureChannel`. `--insecure-demo` plays them through exactly as listed, with the toy parameters below and
+// AES in ECB mode, to show the arithmetic. The `encrypt` and `decrypt` subcommands apply the AES layer to files or
+// standard input instead, using a secret key agreed on beforehand. `dh-server` and `dh-client` run the exchange
+// between two processes over TCP and then echo encrypted messages.
 
-use num_bigint::BigUint;
-use rand::Rng;
-use aes::Aes128;
-use block_modes::{BlockMode, Ecb};
-use block_modes::block_padding::Pkcs7;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 
-type Aes128Ecb = Ecb<Aes128, Pkcs7>;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use num_bigint::BigUint;
+use diffie_hellman::{
+    aes_backend_info, aes_key, audit, connect, decrypt_data, decrypt_str, encrypt_data, encrypt_str,
+    generate_random_key, generate_session_salt, parse_biguint, measure_throughput, receive_frame, secret_fingerprint,
+    secrets_match, self_test, send_frame, tcp_handshake, textbook_exchange, generate_keypair, CipherMode,
+    ConnectOptions, DecryptingReader, DhError, DhParameters, EncryptingWriter, EphemeralSecret, Group, HandshakeOptions,
+    ParametersRegistry, PrivateKey, PublicKey, Role, SecureChannel, SharedSecret, SALT_LEN, TEXTBOOK_ALICE_PRIVATE,
+    TEXTBOOK_BOB_PRIVATE, TEXTBOOK_GENERATOR, TEXTBOOK_MODULUS,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 // The values for the expression to be used i.e. BASE mod PRIMEMOD. It is advisable to use a large primenumber for primemod for more security
 const BASE: u32 = 5;
 const PRIMEMOD: u32 = 57;
 
-// Generates a random 128-bit key which will be the private keys for the parties involved. A 128-bit key means 10 rounds of AES
-fn generate_random_key() -> BigUint {
-    let mut rng = rand::thread_rng();
-    BigUint::from(rng.gen::<u128>())
+#[derive(Parser)]
+#[command(about = "Diffie-Hellman key exchange with AES-encrypted messages")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// How to print the demo exchange
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// INSECURE: run the original demo with g = 5, p = 57 and AES in ECB mode instead of the secure one
+    #[arg(long)]
+    insecure_demo: bool,
+    /// INSECURE: also print the private keys, the secret and the AES key each party derives in the insecure demo, to
+    /// check they match. Exposes key material
+    #[arg(long, requires = "insecure_demo")]
+    insecure_print_keys: bool,
+    /// Log what the exchange is doing to standard error: -v for each connection, -vv for every handshake phase,
+    /// -vvv with public keys in full. Secret values are never logged
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Run the `selftest` checks, then exit
+    #[arg(long)]
+    self_test: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a keypair for a group, writing the private key to a file and the public key to a file or standard
+    /// output
+    Keygen {
+        /// The group to generate the keypair for
+        #[arg(long, default_value = DEFAULT_GROUP, value_parser = parse_group)]
+        group: DhParameters,
+        /// File to write the private key to; it is created readable only by its owner
+        #[arg(long)]
+        private_key: PathBuf,
+        /// File to write the public key to; standard output if not given
+        #[arg(long)]
+        public_key: Option<PathBuf>,
+        /// How the key files are encoded
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        encoding: Encoding,
+        /// Store the private key encrypted under the passphrase in this file, instead of in `--encoding`
+        #[cfg(feature = "argon2")]
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+    },
+    /// Combine our private key with the peer's public key and print the shared secret, for `encrypt --key`
+    Derive {
+        /// The group both keys belong to
+        #[arg(long, default_value = DEFAULT_GROUP, value_parser = parse_group)]
+        group: DhParameters,
+        /// Our private key, as written by `keygen`
+        #[arg(long)]
+        private_key: PathBuf,
+        /// The peer's public key, as written by `keygen`
+        #[arg(long)]
+        peer_public_key: PathBuf,
+        /// How the key files are encoded
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        encoding: Encoding,
+        /// Open a private key that `keygen --passphrase-file` stored, with the passphrase in this file
+        #[cfg(feature = "argon2")]
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+    },
+    /// Generate fresh parameters: a random safe prime with generator 2. Slow; the file can then be passed to
+    /// `--group` in place of a group name
+    Genparams {
+        /// Size of the modulus in bits, from 1024 to 4096; `--group` only accepts files of 2048 bits or more
+        #[arg(long, default_value_t = 2048)]
+        bits: usize,
+        /// File to write the parameters to; standard output if not given
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// How the parameters file is encoded
+        #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+        encoding: Encoding,
+    },
+    /// Encrypt a file's contents with a shared secret key
+    Encrypt(FileArgs),
+    /// Decrypt a file written by `encrypt` with the same secret key
+    Decrypt(FileArgs),
+    /// List what is insecure about the settings the demo uses
+    Audit,
+    /// Measure handshakes per second and encryption speed for each standard group
+    Bench {
+        /// How long to spend on each measurement, in seconds
+        #[arg(long, default_value_t = 1.0)]
+        seconds: f64,
+    },
+    /// Check a generator and modulus are fit for use without running an exchange
+    CheckParams {
+        /// The generator, in decimal or 0x-prefixed hex
+        #[arg(long, value_parser = parse_biguint)]
+        base: BigUint,
+        /// The prime modulus, in decimal or 0x-prefixed hex
+        #[arg(long, value_parser = parse_biguint)]
+        modulus: BigUint,
+    },
+    /// Walk through the classic textbook example with p = 23 and g = 5, step by step
+    Textbook {
+        /// Alice's private key
+        #[arg(long, default_value_t = TEXTBOOK_ALICE_PRIVATE)]
+        alice: u64,
+        /// Bob's private key
+        #[arg(long, default_value_t = TEXTBOOK_BOB_PRIVATE)]
+        bob: u64,
+    },
+    /// Accept connections, run the exchange with each client and echo back every message it sends, encrypted
+    DhServer {
+        /// Address to listen on
+        #[arg(long, default_value = DEFAULT_SERVER_ADDR)]
+        listen: String,
+        /// The group to use; the client must use the same one
+        #[arg(long, default_value = DEFAULT_GROUP, value_parser = parse_group)]
+        group: DhParameters,
+    },
+    /// Connect to `dh-server`, run the exchange and send each line of standard input, printing the echoes
+    DhClient {
+        /// Address of the server
+        #[arg(long, default_value = DEFAULT_SERVER_ADDR)]
+        connect: String,
+        /// The group to use; the server must use the same one
+        #[arg(long, default_value = DEFAULT_GROUP, value_parser = parse_group)]
+        group: DhParameters,
+    },
+    /// Run the known-answer checks and a full exchange and encryption round trip, exiting non-zero if any fails, e.g.
+    /// as a startup or deployment health check. The same as `--self-test`
+    Selftest,
+}
+
+#[derive(clap::Args)]
+struct FileArgs {
+    /// The shared secret key, in decimal as printed by the demo or 0x-prefixed hex
+    #[arg(long, value_parser = parse_biguint)]
+    key: BigUint,
+    /// File to read from; standard input if not given
+    #[arg(long = "in")]
+    input: Option<PathBuf>,
+    /// File to write the result to; standard output if not given
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// How the ciphertext is encoded: what `encrypt` writes and `decrypt` reads
+    #[arg(long, value_enum, default_value_t = Encoding::Raw)]
+    encoding: Encoding,
+    /// Encrypt in authenticated chunks instead of as one message, so files of any size take constant memory. Both
+    /// `encrypt` and `decrypt` need it, and it only works with the raw encoding
+    #[arg(long)]
+    stream: bool,
+}
+
+// How binary data such as keys and ciphertexts is written to files and standard output.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Encoding {
+    /// The bytes as they are
+    Raw,
+    /// Lowercase hex digits
+    Hex,
+    /// Standard base64 with padding
+    Base64,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    init_logging(cli.verbose);
+    let result = match cli.command {
+        _ if cli.self_test => selftest(),
+        None if !cli.insecure_demo => secure_demo(cli.format),
+        None => insecure_demo(cli.format, cli.insecure_print_keys),
+        Some(Command::Keygen {
+            group,
+            private_key,
+            public_key,
+            encoding,
+            #[cfg(feature = "argon2")]
+            passphrase_file,
+        }) => {
+            #[cfg(not(feature = "argon2"))]
+            let passphrase_file: Option<PathBuf> = None;
+            keygen(&group, &private_key, public_key.as_deref(), encoding, passphrase_file.as_deref())
+        }
+        Some(Command::Derive {
+            group,
+            private_key,
+            peer_public_key,
+            encoding,
+            #[cfg(feature = "argon2")]
+            passphrase_file,
+        }) => {
+            #[cfg(not(feature = "argon2"))]
+            let passphrase_file: Option<PathBuf> = None;
+            derive(&group, &private_key, &peer_public_key, encoding, passphrase_file.as_deref())
+        }
+        Some(Command::Genparams { bits, out, encoding }) => genparams(bits, out.as_deref(), encoding),
+        Some(Command::Encrypt(args)) => encrypt_file(&args),
+        Some(Command::Decrypt(args)) => decrypt_file(&args),
+        Some(Command::Audit) => audit_demo(),
+        Some(Command::Bench { seconds }) => bench(seconds),
+        Some(Command::CheckParams { base, modulus }) => check_params(base, modulus),
+        Some(Command::Textbook { alice, bob }) => textbook(alice, bob),
+        Some(Command::DhServer { listen, group }) => dh_server(&listen, &group),
+        Some(Command::DhClient { connect, group }) => dh_client(&connect, &group),
+        Some(Command::Selftest) => selftest(),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Send the library's tracing events to standard error, at a level set by how often `--verbose` was given. Without it
+// only warnings show.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt().with_max_level(level).with_target(false).with_writer(io::stderr).init();
+}
+
+// Encrypt the contents of `--in` (or standard input) and write the ciphertext to `--out` (or standard output), so
+// the binary can sit in a pipeline: `echo hi | diffie-hellman encrypt --key 42 > hi.enc`.
+fn encrypt_file(args: &FileArgs) -> Result<(), String> {
+    if args.stream {
+        return encrypt_stream(args);
+    }
+    let plain_bytes = read_input(args.input.as_deref())?;
+    write_output(args.out.as_deref(), &encode(&encrypt_data(&plain_bytes, &args.key), args.encoding))
+}
+
+// Decrypt the ciphertext in `--in` (or standard input) and write the recovered bytes to `--out` (or standard output).
+fn decrypt_file(args: &FileArgs) -> Result<(), String> {
+    if args.stream {
+        return decrypt_stream(args);
+    }
+    let encrypted_bytes = decode(&read_input(args.input.as_deref())?, args.encoding)
+        .map_err(|e| format!("could not read {}: {}", input_name(args.input.as_deref()), e))?;
+    if encrypted_bytes.is_empty() {
+        return Err(format!("{} is empty, there is nothing to decrypt", input_name(args.input.as_deref())));
+    }
+    let plain_bytes = decrypt_data(&encrypted_bytes, &args.key)
+        .map_err(|e| format!("could not decrypt {}: {}", input_name(args.input.as_deref()), e))?;
+    write_output(args.out.as_deref(), &plain_bytes)
+}
+
+// `encrypt --stream`: a fresh salt, then the input through an `EncryptingWriter`, so only one chunk is in memory at
+// a time.
+fn encrypt_stream(args: &FileArgs) -> Result<(), String> {
+    require_raw_encoding(args)?;
+    let secret = SharedSecret::from_bytes(&args.key.to_bytes_be());
+    let salt = generate_session_salt();
+    let mut input = open_input(args.input.as_deref())?;
+    stream_to_output(args.out.as_deref(), |output| {
+        output.write_all(&salt)?;
+        let mut writer = EncryptingWriter::new(output, &secret, &salt);
+        io::copy(&mut input, &mut writer)?;
+        writer.finish().map(|_| ())
+    })
+    .map_err(|e| format!("could not encrypt {}: {}", input_name(args.input.as_deref()), describe_io_error(&e)))
+}
+
+// `decrypt --stream`: read the salt, then the chunks through a `DecryptingReader`. Each chunk is authenticated before
+// it is written out, but a stream that turns out to be cut short or tampered with further on has already been partly
+// written; an output file is removed again in that case.
+fn decrypt_stream(args: &FileArgs) -> Result<(), String> {
+    require_raw_encoding(args)?;
+    let secret = SharedSecret::from_bytes(&args.key.to_bytes_be());
+    let mut input = open_input(args.input.as_deref())?;
+    stream_to_output(args.out.as_deref(), |output| {
+        let mut salt = [0u8; SALT_LEN];
+        input.read_exact(&mut salt).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), DhError::Truncated),
+            _ => e,
+        })?;
+        io::copy(&mut DecryptingReader::new(input, &secret, &salt), output).map(|_| ())
+    })
+    .map_err(|e| format!("could not decrypt {}: {}", input_name(args.input.as_deref()), describe_io_error(&e)))
+}
+
+fn require_raw_encoding(args: &FileArgs) -> Result<(), String> {
+    if args.encoding != Encoding::Raw {
+        return Err("--stream only works with --encoding raw".to_string());
+    }
+    Ok(())
+}
+
+// Print a warning for each weakness of the demo's parameters and cipher, as a lesson in what naive Diffie-Hellman
+// gets wrong.
+fn audit_demo() -> Result<(), String> {
+    let params = DhParameters::from_u64_allow_insecure(BASE.into(), PRIMEMOD.into()).map_err(|e| e.to_string())?;
+    for warning in audit(&params, CipherMode::Ecb) {
+        println!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+fn selftest() -> Result<(), String> {
+    self_test().map_err(|e| e.to_string())?;
+    println!("self-test passed");
+    Ok(())
+}
+
+// Print which AES implementation is in use, then handshake and encryption rates for every standard group, one line
+// per group.
+fn bench(seconds: f64) -> Result<(), String> {
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration: {}", seconds))?;
+    println!("aes backend: {}", aes_backend_info());
+    println!("{:<10} {:>14} {:>14}", "group", "handshakes/s", "encrypt MB/s");
+    for group in Group::ALL {
+        let throughput = measure_throughput(group, duration).map_err(|e| e.to_string())?;
+        println!(
+            "{:<10} {:>14.1} {:>14.1}",
+            group.name(),
+            throughput.handshakes_per_sec,
+            throughput.encrypt_mb_per_sec
+        );
+    }
+    Ok(())
+}
+
+const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:7878";
+const DEFAULT_GROUP: &str = "ffdhe2048";
+// The largest echo message either side accepts, in bytes of ciphertext.
+const MAX_ECHO_FRAME_LEN: usize = 64 * 1024;
+
+// Look up a `--group` name in the registry, or failing that read it as a parameters file written by `genparams`, in
+// any of its encodings. Parameters from a file must be at least `MIN_SECURE_BITS` long and use a safe prime.
+fn parse_group(name: &str) -> Result<DhParameters, String> {
+    if let Some(params) = ParametersRegistry::get(name) {
+        return Ok(params.clone());
+    }
+    if !Path::new(name).is_file() {
+        return Err(format!("unknown group {}; known groups: {}", name, ParametersRegistry::list().join(", ")));
+    }
+    let contents = read_file(Path::new(name))?;
+    // The first encoding that yields well-formed parameters decides; a file that is well-formed but refused, e.g.
+    // for being too short, reports why.
+    let mut parsed = [Encoding::Hex, Encoding::Base64, Encoding::Raw]
+        .into_iter()
+        .filter_map(|encoding| decode(&contents, encoding).ok())
+        .map(|bytes| DhParameters::from_bytes(&bytes))
+        .filter(|result| !matches!(result, Err(DhError::MalformedMessage)));
+    let params = match parsed.next() {
+        Some(result) => result.map_err(|e| format!("{}: {}", name, e))?,
+        None => return Err(format!("{} does not hold parameters written by genparams", name)),
+    };
+    if !params.is_safe_prime() {
+        return Err(format!("{}: the modulus is not a safe prime", name));
+    }
+    Ok(params)
+}
+
+// Generate parameters with a `bits`-bit safe prime and write them in the compact binary form of
+// `DhParameters::to_bytes`, encoded as asked.
+fn genparams(bits: usize, out: Option<&Path>, encoding: Encoding) -> Result<(), String> {
+    eprintln!("searching for a {}-bit safe prime, this may take a while", bits);
+    let params = DhParameters::generate(bits).map_err(|e| e.to_string())?;
+    let mut bytes = encode(&params.to_bytes(), encoding);
+    if encoding != Encoding::Raw {
+        bytes.push(b'\n');
+    }
+    write_output(out, &bytes)
+}
+
+// Write a fresh keypair for `params`. The private key file is created with owner-only permissions on Unix, and an
+// existing file is never overwritten, so an old key can't be lost by accident.
+fn keygen(
+    params: &DhParameters,
+    private_path: &Path,
+    public_path: Option<&Path>,
+    encoding: Encoding,
+    passphrase_file: Option<&Path>,
+) -> Result<(), String> {
+    let (private_key, public_key) = generate_keypair(params).map_err(|e| e.to_string())?;
+    match passphrase_file {
+        #[cfg(feature = "argon2")]
+        Some(passphrase_file) => private_key
+            .save(private_path, &read_passphrase(passphrase_file)?)
+            .map_err(|e| format!("could not write {}: {}", private_path.display(), e))?,
+        _ => {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options
+                .open(private_path)
+                .and_then(|mut file| file.write_all(&encode(&private_key.to_bytes_be(), encoding)))
+                .map_err(|e| format!("could not write {}: {}", private_path.display(), describe_io_error(&e)))?;
+        }
+    }
+    // On standard error, so the public key can still be piped: something to compare with whoever receives it.
+    let fingerprint = public_key.fingerprint();
+    eprintln!("public key fingerprint: {} ({})", fingerprint, fingerprint.to_words());
+    let mut public_bytes = encode(&public_key.to_wire_bytes(), encoding);
+    if public_path.is_none() && encoding != Encoding::Raw {
+        public_bytes.push(b'\n');
+    }
+    write_output(public_path, &public_bytes)
+}
+
+// Print the secret shared with the owner of `peer_public_path` in the form `--key` takes. The peer's key is validated
+// against the group first.
+fn derive(
+    params: &DhParameters,
+    private_path: &Path,
+    peer_public_path: &Path,
+    encoding: Encoding,
+    passphrase_file: Option<&Path>,
+) -> Result<(), String> {
+    let read_key = |path: &Path| {
+        decode(&read_file(path)?, encoding).map_err(|e| format!("could not read {}: {}", path.display(), e))
+    };
+    let private_key = match passphrase_file {
+        #[cfg(feature = "argon2")]
+        Some(passphrase_file) => PrivateKey::load(private_path, &read_passphrase(passphrase_file)?)
+            .map_err(|e| format!("could not open {}: {}", private_path.display(), e))?,
+        _ => PrivateKey::from_biguint(BigUint::from_bytes_be(&read_key(private_path)?)),
+    };
+    private_key.validate(params).map_err(|e| format!("{}: {}", private_path.display(), e))?;
+    let peer_public = PublicKey::from_wire_bytes(&read_key(peer_public_path)?, params)
+        .map_err(|e| format!("{}: {}", peer_public_path.display(), e))?;
+    peer_public.validate_in_group(params).map_err(|e| format!("{}: {}", peer_public_path.display(), e))?;
+    // Printing the secret is what `derive` is for: it is the key `encrypt --key` takes.
+    let secret = private_key.agree(&peer_public, params).dangerous_into_bytes();
+    println!("{:#x}", BigUint::from_bytes_be(&secret));
+    Ok(())
+}
+
+// The passphrase in the first line of `path`, without its line ending, so `echo secret > pass` works.
+#[cfg(feature = "argon2")]
+fn read_passphrase(path: &Path) -> Result<zeroize::Zeroizing<Vec<u8>>, String> {
+    let contents = zeroize::Zeroizing::new(read_file(path)?);
+    let line = contents.split(|&byte| byte == b'\n').next().unwrap_or_default();
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.is_empty() {
+        return Err(format!("{} holds no passphrase", path.display()));
+    }
+    Ok(zeroize::Zeroizing::new(line.to_vec()))
+}
+
+fn encode(bytes: &[u8], encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Raw => bytes.to_vec(),
+        Encoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>().into_bytes(),
+        Encoding::Base64 => BASE64_STANDARD.encode(bytes).into_bytes(),
+    }
+}
+
+// Undo `encode`. Whitespace around and inside hex or base64 text is ignored, so hand-edited files with line breaks
+// still read.
+fn decode(text: &[u8], encoding: Encoding) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text.iter().copied().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    match encoding {
+        Encoding::Raw => Ok(text.to_vec()),
+        Encoding::Hex => {
+            // Only ASCII, so the two-byte slices below always fall on character boundaries.
+            let digits = std::str::from_utf8(&digits)
+                .ok()
+                .filter(|digits| digits.is_ascii())
+                .ok_or_else(|| "not valid hex".to_string())?;
+            if digits.len() % 2 != 0 {
+                return Err("hex has an odd number of digits".to_string());
+            }
+            (0..digits.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| "not valid hex".to_string()))
+                .collect()
+        }
+        Encoding::Base64 => BASE64_STANDARD.decode(&digits).map_err(|_| "not valid base64".to_string()),
+    }
+}
+
+// Serve clients one after another. Each connection is a handshake followed by an echo session over a
+// `SecureChannel`: every frame the client sends is decrypted, printed and sent back re-encrypted, until it hangs up.
+// A client that fails is reported and the server moves on to the next one.
+fn dh_server(listen: &str, params: &DhParameters) -> Result<(), String> {
+    let listener = TcpListener::bind(listen).map_err(|e| format!("could not listen on {}: {}", listen, e))?;
+    println!("listening on {}", listen);
+    for stream in listener.incoming() {
+        let result = stream.map_err(DhError::from).and_then(|mut stream| {
+            let peer = stream.peer_addr()?;
+            println!("{}: connected", peer);
+            serve_echo(&mut stream, params).map(|messages| println!("{}: closed after {} messages", peer, messages))
+        });
+        if let Err(error) = result {
+            eprintln!("error: {}", error);
+        }
+    }
+    Ok(())
+}
+
+fn serve_echo(stream: &mut TcpStream, params: &DhParameters) -> Result<u64, DhError> {
+    let secret = tcp_handshake(stream, params, &HandshakeOptions::default())?;
+    println!("agreed on secret {}", secret.fingerprint());
+    println!("check with the client that it reads: {}", secret.sas());
+    stream.set_read_timeout(None)?;
+    let mut channel = SecureChannel::new(&secret, Role::Responder);
+    let mut messages = 0;
+    while let Some(frame) = receive_frame(stream, MAX_ECHO_FRAME_LEN)? {
+        let message = channel.open(&frame)?;
+        println!("received: {}", String::from_utf8_lossy(&message));
+        send_frame(stream, &channel.seal(&message)?)?;
+        messages += 1;
+    }
+    Ok(messages)
+}
+
+// Send each line of standard input to the server and print what comes back.
+fn dh_client(addr: &str, params: &DhParameters) -> Result<(), String> {
+    let run = || -> Result<(), DhError> {
+        let mut stream = connect(addr, &ConnectOptions::default())?;
+        let secret = tcp_handshake(&mut stream, params, &HandshakeOptions::default())?;
+        println!("agreed on secret {}", secret.fingerprint());
+        println!("check with the server that it reads: {}", secret.sas());
+        // The handshake timeout is for an unresponsive server. Waiting on stdin between lines must not trip it.
+        stream.set_read_timeout(None)?;
+        let mut channel = SecureChannel::new(&secret, Role::Initiator);
+        for line in io::stdin().lock().lines() {
+            send_frame(&mut stream, &channel.seal(line?.as_bytes())?)?;
+            let frame = receive_frame(&mut stream, MAX_ECHO_FRAME_LEN)?.ok_or(DhError::Truncated)?;
+            println!("echo: {}", String::from_utf8_lossy(&channel.open(&frame)?));
+        }
+        Ok(())
+    };
+    run().map_err(|e| e.to_string())
+}
+
+// Print each value of the textbook exchange next to the formula that produces it.
+fn textbook(alice: u64, bob: u64) -> Result<(), String> {
+    let (g, p) = (TEXTBOOK_GENERATOR, TEXTBOOK_MODULUS);
+    let steps = textbook_exchange(alice, bob);
+    println!("public parameters: p = {}, g = {}", p, g);
+    println!("Alice picks a = {}, Bob picks b = {}", steps.alice_private, steps.bob_private);
+    println!("Alice sends A = g^a mod p = {}^{} mod {} = {}", g, alice, p, steps.alice_public);
+    println!("Bob sends   B = g^b mod p = {}^{} mod {} = {}", g, bob, p, steps.bob_public);
+    println!("Alice computes s = B^a mod p = {}^{} mod {} = {}", steps.bob_public, alice, p, steps.alice_secret);
+    println!("Bob computes   s = A^b mod p = {}^{} mod {} = {}", steps.alice_public, bob, p, steps.bob_secret);
+    Ok(())
+}
+
+// Print the outcome of each parameter check, failing if any of them didn't pass.
+fn check_params(base: BigUint, modulus: BigUint) -> Result<(), String> {
+    let report = DhParameters::new_allow_insecure(base, modulus).map_err(|e| e.to_string())?.check();
+    let verdict = |passed: bool| if passed { "pass" } else { "FAIL" };
+    println!("modulus is prime:          {}", verdict(report.modulus_is_prime));
+    println!("modulus is a safe prime:   {}", verdict(report.modulus_is_safe_prime));
+    println!("generator in range:        {}", verdict(report.generator_in_range));
+    println!("generator order verified:  {}", verdict(report.generator_order_verified));
+    println!("estimated security:        {} bits", report.security_bits);
+    if report.passed() {
+        Ok(())
+    } else {
+        Err("parameters failed validation".to_string())
+    }
+}
+
+// Read the whole input file, or standard input up to EOF when no file was given. Empty input is returned as is.
+fn read_input(path: Option<&Path>) -> Result<Vec<u8>, String> {
+    match path {
+        Some(path) => read_file(path),
+        None => read_all(&mut io::stdin().lock()),
+    }
+}
+
+// `--in` opened for reading as it goes, or standard input.
+fn open_input(path: Option<&Path>) -> Result<Box<dyn Read>, String> {
+    match path {
+        Some(path) => fs::File::open(path)
+            .map(|file| Box::new(io::BufReader::new(file)) as Box<dyn Read>)
+            .map_err(|e| format!("could not read {}: {}", path.display(), describe_io_error(&e))),
+        None => Ok(Box::new(io::stdin().lock())),
+    }
+}
+
+// Run `write` against `--out` or standard output. An output file that `write` fails on is removed, so no partial
+// result is left behind.
+fn stream_to_output(path: Option<&Path>, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    match path {
+        Some(path) => {
+            let mut file = io::BufWriter::new(fs::File::create(path)?);
+            let result = write(&mut file).and_then(|()| file.flush());
+            if result.is_err() {
+                let _ = fs::remove_file(path);
+            }
+            result
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            write(&mut stdout).and_then(|()| stdout.flush())
+        }
+    }
+}
+
+fn read_all<R: Read>(reader: &mut R) -> Result<Vec<u8>, String> {
+    let mut contents = Vec::new();
+    reader
+        .read_to_end(&mut contents)
+        .map_err(|e| format!("could not read standard input: {}", describe_io_error(&e)))?;
+    Ok(contents)
 }
-// Convert our BigUint secret key into a 16-byte array suitable for AES-128.
-fn generate_secret_key_spec(secret_key: &BigUint) -> [u8; 16] {
-    let key_bytes = secret_key.to_bytes_le();
-    let mut valid_key_bytes = [0u8; 16];
-    for (i, &byte) in key_bytes.iter().enumerate().take(16) {
-        valid_key_bytes[i] = byte;
+
+fn write_output(path: Option<&Path>, contents: &[u8]) -> Result<(), String> {
+    match path {
+        Some(path) => write_file(path, contents),
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout
+                .write_all(contents)
+                .and_then(|()| stdout.flush())
+                .map_err(|e| format!("could not write standard output: {}", describe_io_error(&e)))
+        }
     }
-    valid_key_bytes
 }
 
-// Encrypt the given plain text using AES-128 with the provided secret key.
-fn encrypt_data(plain_text: &str, secret_key: &BigUint) -> Vec<u8> {
-    let key = generate_secret_key_spec(secret_key);
-    let cipher = Aes128Ecb::new_from_slices(&key, Default::default()).unwrap();
-    cipher.encrypt_vec(plain_text.as_bytes())
+fn input_name(path: Option<&Path>) -> String {
+    match path {
+        Some(path) => path.display().to_string(),
+        None => "standard input".to_string(),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), describe_io_error(&e)))
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("could not write {}: {}", path.display(), describe_io_error(&e)))
+}
+
+fn describe_io_error(error: &io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::NotFound => "no such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+const ALICE_MESSAGE: &str = "This is the Diffie-Hellman key exchange protocol!";
+const BOB_MESSAGE: &str = "This protocol is a symmetric encryption algorithm!";
+
+// What the secure demo shows: which group was used, both public keys, a fingerprint of the agreed secret, the short
+// authentication string each side would read out and the messages as each side decrypted them. Nothing secret is
+// kept, so it can be printed as JSON as is.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+struct SecureDemoRun {
+    group: &'static str,
+    // Both public keys in hex, as `PublicKey::to_hex` writes them.
+    alice_public_key: String,
+    bob_public_key: String,
+    secret_fingerprint: String,
+    alice_sas: String,
+    bob_sas: String,
+    decrypted_by_bob: String,
+    decrypted_by_alice: String,
+}
+
+// The demo steps with `DiffieHellman::secure_default` and a `SecureChannel` for each side.
+fn run_secure_demo() -> Result<SecureDemoRun, String> {
+    // Ephemeral keys, one per exchange: `diffie_hellman` uses each secret up, so neither can be reused.
+    let params = DhParameters::from(Group::Ffdhe3072);
+    let alice = EphemeralSecret::random(&params).map_err(|e| e.to_string())?;
+    let bob = EphemeralSecret::random(&params).map_err(|e| e.to_string())?;
+    let (alice_public, bob_public) = (alice.public_key().clone(), bob.public_key().clone());
+    tracing::debug!(alice = %alice_public.fingerprint(), bob = %bob_public.fingerprint(), "exchanged public keys");
+    let alice_secret = alice.diffie_hellman(&bob_public).map_err(|e| e.to_string())?;
+    let bob_secret = bob.diffie_hellman(&alice_public).map_err(|e| e.to_string())?;
+    let secret_fingerprint = alice_secret.fingerprint();
+    let mut alice_channel = SecureChannel::new(&alice_secret, Role::Initiator);
+    let mut bob_channel = SecureChannel::new(&bob_secret, Role::Responder);
+
+    let relay = |from: &mut SecureChannel, to: &mut SecureChannel, text: &str| -> Result<String, String> {
+        let message = from.seal(text.as_bytes()).map_err(|e| e.to_string())?;
+        let plain_bytes = to.open(&message).map_err(|e| e.to_string())?;
+        String::from_utf8(plain_bytes).map_err(|_| DhError::InvalidUtf8.to_string())
+    };
+    Ok(SecureDemoRun {
+        group: Group::Ffdhe3072.name(),
+        alice_public_key: alice_public.to_hex(),
+        bob_public_key: bob_public.to_hex(),
+        secret_fingerprint,
+        alice_sas: alice_secret.sas(),
+        bob_sas: bob_secret.sas(),
+        decrypted_by_bob: relay(&mut alice_channel, &mut bob_channel, ALICE_MESSAGE)?,
+        decrypted_by_alice: relay(&mut bob_channel, &mut alice_channel, BOB_MESSAGE)?,
+    })
+}
+
+fn secure_demo(format: OutputFormat) -> Result<(), String> {
+    let run = run_secure_demo()?;
+    match format {
+        OutputFormat::Text => {
+            print_secure_demo_text(&run);
+            Ok(())
+        }
+        #[cfg(feature = "serde")]
+        OutputFormat::Json => print_secure_demo_json(&run),
+    }
 }
 
-// Decrypt the given encrypted data using AES-128 with the provided secret key.
-fn decrypt_data(encrypted_data: &[u8], secret_key: &BigUint) -> String {
-    let key = generate_secret_key_spec(secret_key);
-    let cipher = Aes128Ecb::new_from_slices(&key, Default::default()).unwrap();
-    let decrypted_data = cipher.decrypt_vec(encrypted_data).unwrap();
-    String::from_utf8(decrypted_data).unwrap()
+fn print_secure_demo_text(run: &SecureDemoRun) {
+    println!("Alice and Bob exchanged public keys in the {} group", run.group);
+    println!("Alice's public key starts {}...", &run.alice_public_key[..32]);
+    println!("Bob's public key starts {}...", &run.bob_public_key[..32]);
+    println!("They agree on a secret with fingerprint {}", run.secret_fingerprint);
+    println!("Alice reads out \"{}\" and Bob sees \"{}\"", run.alice_sas, run.bob_sas);
+    println!("Bob decrypted Alice's message: {}", run.decrypted_by_bob);
+    println!("Alice decrypted Bob's message: {}", run.decrypted_by_alice);
 }
 
-fn main() {
+#[cfg(feature = "serde")]
+fn print_secure_demo_json(run: &SecureDemoRun) -> Result<(), String> {
+    let output = serde_json::to_string_pretty(run).map_err(|e| format!("could not format output: {}", e))?;
+    println!("{}", output);
+    Ok(())
+}
+
+// Everything the insecure demo computes, so it can be printed as text or as JSON.
+struct DemoRun {
+    alice_private_key: BigUint,
+    alice_shared_key: BigUint,
+    bob_private_key: BigUint,
+    bob_shared_key: BigUint,
+    alice_secret_key: BigUint,
+    bob_secret_key: BigUint,
+    decrypted_by_bob: String,
+    decrypted_by_alice: String,
+}
+
+fn insecure_demo(format: OutputFormat, print_keys: bool) -> Result<(), String> {
+    let run = run_demo().map_err(|e| e.to_string())?;
+    if print_keys {
+        eprintln!("warning: --insecure-print-keys prints secret key material; never use it with real keys");
+    }
+    match format {
+        OutputFormat::Text => {
+            print_demo_text(&run, print_keys);
+            Ok(())
+        }
+        #[cfg(feature = "serde")]
+        OutputFormat::Json => print_demo_json(&run, print_keys),
+    }
+}
+
+fn run_demo() -> Result<DemoRun, DhError> {
     // 1. Alice gets a random number and generates a shared key using the shared base amd modulus
     let alice_random_key = generate_random_key();
-    println!("Alice's private key is: {}", alice_random_key);
     let alice_shared_key = BigUint::from(BASE).modpow(&alice_random_key, &BigUint::from(PRIMEMOD));
-    println!("Alice's shared key that has been generated is: {}", alice_shared_key);
+    tracing::debug!(public_value = %alice_shared_key, "Alice sends her public value");
 
     // 2. The shared key generated is sent to Bob.
     // Bob processes Alice's shared key, aliceSharedKey, using his private key, bobRandomKey (i.e. aliceSharedKey ^ bobRandomKey mod PRIMEMOD) and creates a secret key
     let bob_random_key = generate_random_key();
-    println!("Bob private key is: {}", bob_random_key);
     let bob_shared_key = BigUint::from(BASE).modpow(&bob_random_key, &BigUint::from(PRIMEMOD));
-    println!("Bob's shared key that has been generated is: {}", bob_shared_key);
+    tracing::debug!(public_value = %bob_shared_key, "Bob sends his public value");
     let bob_secret_key = alice_shared_key.modpow(&bob_random_key, &BigUint::from(PRIMEMOD));
-    println!("Bob has generated the secret key as: {}", bob_secret_key);
 
     // 3. Bob then gets a random number and generates a shared key using the shared base amd modulus
     // 4. Bob sends his generated shared key generated to Alice.
     // Alice processes Bob's shared key, bobSharedKey, using her private key, aliceRandomKey (i.e. bobSharedKey ^ aliceRandomKey mod PRIMEMOD) and creates a secret key
     let alice_secret_key = bob_shared_key.modpow(&alice_random_key, &BigUint::from(PRIMEMOD));
-    println!("Alice has generated the secret key as: {}", alice_secret_key);
 
     // 5. Alice encrypts some data using her secret key and sends it to Bob
-    let plain_text = "This is the Diffie-Hellman key exchange protocol!";
-    let encrypted_data = encrypt_data(plain_text, &alice_secret_key);
-    
+    let encrypted_data = encrypt_str(ALICE_MESSAGE, &alice_secret_key);
+
     // 6. Bob receives the encrypted data and decrypts it with his own secret
-    let decrypted_data = decrypt_data(&encrypted_data, &bob_secret_key);
-    println!("Alice's decrypted data is: {}", decrypted_data);
+    let decrypted_data = decrypt_str(&encrypted_data, &bob_secret_key)?;
 
     // 7. Bob encrypts some data using his secret key and sends it to Alice
-    let plain_text2 = "This protocol is a symmetric encryption algorithm!";
-    let encrypted_data2 = encrypt_data(plain_text2, &bob_secret_key);
-    
+    let encrypted_data2 = encrypt_str(BOB_MESSAGE, &bob_secret_key);
+
     // 8. Alice receives the encrypted data and decrypts it with her own secret
-    let decrypted_data2 = decrypt_data(&encrypted_data2, &alice_secret_key);
-    println!("Alice's decrypted data is: {}", decrypted_data2);
+    let decrypted_data2 = decrypt_str(&encrypted_data2, &alice_secret_key)?;
+
+    Ok(DemoRun {
+        alice_private_key: alice_random_key,
+        alice_shared_key,
+        bob_private_key: bob_random_key,
+        bob_shared_key,
+        alice_secret_key,
+        bob_secret_key,
+        decrypted_by_bob: decrypted_data,
+        decrypted_by_alice: decrypted_data2,
+    })
+}
+
+// The private keys and the secret only with `--insecure-print-keys`; the public values are safe to print.
+fn print_demo_text(run: &DemoRun, print_keys: bool) {
+    if print_keys {
+        println!("Alice's private key is: {}", run.alice_private_key);
+    }
+    println!("Alice's shared key that has been generated is: {}", run.alice_shared_key);
+    if print_keys {
+        println!("Bob private key is: {}", run.bob_private_key);
+    }
+    println!("Bob's shared key that has been generated is: {}", run.bob_shared_key);
+    if print_keys {
+        println!("Bob has generated the secret key as: {}", run.bob_secret_key);
+        println!("Alice has generated the secret key as: {}", run.alice_secret_key);
+    }
+    println!("The secret key has fingerprint: {}", secret_fingerprint(&run.alice_secret_key));
+    println!("Alice and Bob agree on the secret key: {}", secrets_match(&run.alice_secret_key, &run.bob_secret_key));
+    if print_keys {
+        println!("Alice's AES-128-ECB key is: {}", hex_key(&run.alice_secret_key));
+        println!("Bob's AES-128-ECB key is: {}", hex_key(&run.bob_secret_key));
+    }
+    println!("Alice's decrypted data is: {}", run.decrypted_by_bob);
+    println!("Alice's decrypted data is: {}", run.decrypted_by_alice);
+}
+
+// The demo as a single JSON object. Only public values and a fingerprint of the secret are included.
+#[cfg(feature = "serde")]
+fn print_demo_json(run: &DemoRun, print_keys: bool) -> Result<(), String> {
+    #[derive(Serialize)]
+    struct DemoJson<'a> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        alice_aes_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bob_aes_key: Option<String>,
+        alice_public_key: String,
+        bob_public_key: String,
+        secret_fingerprint: String,
+        secrets_match: bool,
+        decrypted_by_bob: &'a str,
+        decrypted_by_alice: &'a str,
+    }
+
+    let json = DemoJson {
+        alice_aes_key: print_keys.then(|| hex_key(&run.alice_secret_key)),
+        bob_aes_key: print_keys.then(|| hex_key(&run.bob_secret_key)),
+        alice_public_key: format!("{:x}", run.alice_shared_key),
+        bob_public_key: format!("{:x}", run.bob_shared_key),
+        secret_fingerprint: secret_fingerprint(&run.alice_secret_key),
+        secrets_match: secrets_match(&run.alice_secret_key, &run.bob_secret_key),
+        decrypted_by_bob: &run.decrypted_by_bob,
+        decrypted_by_alice: &run.decrypted_by_alice,
+    };
+    let output = serde_json::to_string_pretty(&json).map_err(|e| format!("could not format output: {}", e))?;
+    println!("{}", output);
+    Ok(())
+}
+
+// The AES-128-ECB key the demo encrypts with for a secret, as hex, for `--insecure-print-keys`.
+fn hex_key(secret: &BigUint) -> String {
+    aes_key(secret).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn piped_input_encrypts_and_decrypts() {
+        let key = BigUint::from(42u32);
+        let plain_bytes = read_all(&mut Cursor::new(b"hi\n".to_vec())).unwrap();
+        assert_eq!(plain_bytes, b"hi\n");
+        for encoding in [Encoding::Raw, Encoding::Hex, Encoding::Base64] {
+            let encoded = encode(&encrypt_data(&plain_bytes, &key), encoding);
+            let encrypted_bytes = decode(&encoded, encoding).unwrap();
+            assert_eq!(decrypt_data(&encrypted_bytes, &key).unwrap(), plain_bytes);
+        }
+    }
+
+    #[test]
+    fn empty_input_reads_as_empty() {
+        let plain_bytes = read_all(&mut Cursor::new(Vec::new())).unwrap();
+        assert!(plain_bytes.is_empty());
+        let key = BigUint::from(42u32);
+        assert_eq!(decrypt_data(&encrypt_data(&plain_bytes, &key), &key).unwrap(), plain_bytes);
+    }
+
+    #[test]
+    fn read_errors_are_reported() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"))
+            }
+        }
+        assert_eq!(read_all(&mut Broken).unwrap_err(), "could not read standard input: permission denied");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn the_secure_demo_json_parses_with_every_field() {
+        let run = run_secure_demo().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&serde_json::to_string_pretty(&run).unwrap()).unwrap();
+        assert_eq!(json["group"], "ffdhe3072");
+        for field in ["alice_public_key", "bob_public_key"] {
+            let key = json[field].as_str().unwrap();
+            assert!(key.len() > 700 && key.bytes().all(|byte| byte.is_ascii_hexdigit()), "{field}");
+        }
+        assert_ne!(json["alice_public_key"], json["bob_public_key"]);
+        assert_eq!(json["secret_fingerprint"], run.secret_fingerprint.as_str());
+        assert_eq!(json["alice_sas"], json["bob_sas"]);
+        assert_eq!(json["decrypted_by_bob"], ALICE_MESSAGE);
+        assert_eq!(json["decrypted_by_alice"], BOB_MESSAGE);
+    }
+
+    #[test]
+    fn printed_keys_match_when_the_secrets_agree() {
+        let run = run_demo().unwrap();
+        assert!(secrets_match(&run.alice_secret_key, &run.bob_secret_key));
+        let alice_key = hex_key(&run.alice_secret_key);
+        assert_eq!(alice_key, hex_key(&run.bob_secret_key));
+        assert_eq!(alice_key.len(), 32);
+        assert!(alice_key.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(hex_key(&(&run.alice_secret_key + 1u32)), alice_key);
+    }
+
+    #[test]
+    fn the_printed_key_decrypts_the_demo_message() {
+        use block_modes::block_padding::Pkcs7;
+        use block_modes::{BlockMode, Ecb};
+
+        let run = run_demo().unwrap();
+        let printed = hex_key(&run.alice_secret_key);
+        let key: Vec<u8> =
+            (0..printed.len()).step_by(2).map(|i| u8::from_str_radix(&printed[i..i + 2], 16).unwrap()).collect();
+        let cipher = Ecb::<aes::Aes128, Pkcs7>::new_from_slices(&key, &[]).unwrap();
+        // ECB is deterministic, so this is the ciphertext the demo sent Bob.
+        let encrypted = encrypt_str(ALICE_MESSAGE, &run.alice_secret_key);
+        assert_eq!(cipher.decrypt_vec(&encrypted).unwrap(), ALICE_MESSAGE.as_bytes());
+    }
+
+    #[test]
+    fn echo_server_sends_every_frame_back() {
+        let params = Group::Ffdhe2048.parameters();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client_params = params.clone();
+        let client = thread::spawn(move || {
+            let mut stream = connect(addr, &ConnectOptions::default()).unwrap();
+            let secret = tcp_handshake(&mut stream, &client_params, &HandshakeOptions::default()).unwrap();
+            stream.set_read_timeout(None).unwrap();
+            let mut channel = SecureChannel::new(&secret, Role::Initiator);
+            for line in ["hello", "", "second line"] {
+                send_frame(&mut stream, &channel.seal(line.as_bytes()).unwrap()).unwrap();
+                let frame = receive_frame(&mut stream, MAX_ECHO_FRAME_LEN).unwrap().unwrap();
+                assert_eq!(channel.open(&frame).unwrap(), line.as_bytes());
+            }
+        });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(serve_echo(&mut stream, &params).unwrap(), 3);
+        assert_eq!(stream.read_timeout().unwrap(), None);
+        client.join().unwrap();
+    }
+
+    // A path in the temporary directory for this test run, removed again when dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            TempPath(std::env::temp_dir().join(format!("dh-test-{}-{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn stream_args(input: &TempPath, out: &TempPath) -> FileArgs {
+        FileArgs {
+            key: BigUint::from(0x5eed_u32),
+            input: Some(input.0.clone()),
+            out: Some(out.0.clone()),
+            encoding: Encoding::Raw,
+            stream: true,
+        }
+    }
+
+    #[test]
+    fn files_round_trip_through_in_and_out() {
+        let (plain, sealed) = (TempPath::new("file-plain"), TempPath::new("file-sealed"));
+        let opened = TempPath::new("file-opened");
+        let contents: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&plain.0, &contents).unwrap();
+
+        for encoding in [Encoding::Raw, Encoding::Hex, Encoding::Base64] {
+            encrypt_file(&FileArgs { encoding, stream: false, ..stream_args(&plain, &sealed) }).unwrap();
+            assert_ne!(fs::read(&sealed.0).unwrap(), contents);
+            decrypt_file(&FileArgs { encoding, stream: false, ..stream_args(&sealed, &opened) }).unwrap();
+            assert_eq!(fs::read(&opened.0).unwrap(), contents);
+        }
+
+        // The wrong key doesn't give the file back.
+        let args = FileArgs { key: BigUint::from(7u32), stream: false, ..stream_args(&sealed, &opened) };
+        assert!(decrypt_file(&args).unwrap_err().starts_with("could not decrypt"));
+    }
+
+    #[test]
+    fn a_missing_input_file_is_an_error() {
+        let (missing, out) = (TempPath::new("file-missing"), TempPath::new("file-missing-out"));
+        let args = FileArgs { stream: false, ..stream_args(&missing, &out) };
+        let error = encrypt_file(&args).unwrap_err();
+        assert!(error.contains(&missing.0.display().to_string()), "{error}");
+        assert!(decrypt_file(&args).is_err());
+        assert!(!out.0.exists());
+    }
+
+    #[test]
+    fn stream_mode_round_trips_a_file_of_several_chunks() {
+        let (plain, sealed, opened) = (TempPath::new("plain"), TempPath::new("sealed"), TempPath::new("opened"));
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&plain.0, &contents).unwrap();
+
+        encrypt_stream(&stream_args(&plain, &sealed)).unwrap();
+        assert!(fs::read(&sealed.0).unwrap().len() > contents.len());
+        decrypt_stream(&stream_args(&sealed, &opened)).unwrap();
+        assert_eq!(fs::read(&opened.0).unwrap(), contents);
+
+        // A flipped byte in the last chunk fails, and what was decrypted before it is removed.
+        let mut tampered = fs::read(&sealed.0).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        fs::write(&sealed.0, tampered).unwrap();
+        assert!(decrypt_stream(&stream_args(&sealed, &opened)).unwrap_err().starts_with("could not decrypt"));
+        assert!(!opened.0.exists());
+    }
+
+    #[test]
+    fn stream_mode_needs_the_raw_encoding() {
+        let (plain, sealed) = (TempPath::new("plain-hex"), TempPath::new("sealed-hex"));
+        let args = FileArgs { encoding: Encoding::Hex, ..stream_args(&plain, &sealed) };
+        assert_eq!(encrypt_stream(&args).unwrap_err(), "--stream only works with --encoding raw");
+        assert_eq!(decrypt_stream(&args).unwrap_err(), "--stream only works with --encoding raw");
+    }
 }
@@ -0,0 +1,95 @@
+// Distinct types for the two sides of encryption, so a ciphertext can't be handed to `encrypt` (or a plaintext to
+// `decrypt`) by mistake: both are just bytes, but the compiler now tells them apart. Encryption is AES-128-GCM, as in
+// gcm.rs.
+
+use num_bigint::BigUint;
+
+use crate::error::DhError;
+use crate::gcm::{decrypt_gcm, encrypt_gcm};
+
+// Bytes before encryption or after decryption.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Plaintext(Vec<u8>);
+
+// Bytes as produced by `encrypt`, safe to send or store in the open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ciphertext(Vec<u8>);
+
+impl Plaintext {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Plaintext(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl From<&str> for Plaintext {
+    fn from(text: &str) -> Self {
+        Plaintext(text.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for Plaintext {
+    fn from(text: String) -> Self {
+        Plaintext(text.into_bytes())
+    }
+}
+
+impl Ciphertext {
+    // Wrap bytes received from the peer for decryption.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Ciphertext(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Encrypt and authenticate a plaintext with a key derived from the secret. A ciphertext is not a plaintext, so it
+/// can't be encrypted a second time by mistake:
+///
+/// ```compile_fail
+/// use diffie_hellman::{encrypt, Plaintext};
+/// use num_bigint::BigUint;
+///
+/// let key = BigUint::from(42u32);
+/// let ciphertext = encrypt(&Plaintext::from("hello"), &key).unwrap();
+/// let twice = encrypt(&ciphertext, &key);
+/// ```
+pub fn encrypt(plaintext: &Plaintext, secret_key: &BigUint) -> Result<Ciphertext, DhError> {
+    encrypt_gcm(&plaintext.0, secret_key).map(Ciphertext)
+}
+
+// Verify and decrypt a ciphertext produced by `encrypt` with the same secret.
+pub fn decrypt(ciphertext: &Ciphertext, secret_key: &BigUint) -> Result<Plaintext, DhError> {
+    decrypt_gcm(&ciphertext.0, secret_key).map(Plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrypt_undoes_encrypt() {
+        let key = BigUint::from(42u32);
+        let plaintext = Plaintext::from("hello");
+        let ciphertext = encrypt(&plaintext, &key).unwrap();
+        assert_ne!(ciphertext.as_bytes(), plaintext.as_bytes());
+        assert!(decrypt(&ciphertext, &key).unwrap() == plaintext);
+
+        let received = Ciphertext::new(ciphertext.clone().into_bytes());
+        assert_eq!(decrypt(&received, &key).unwrap().into_bytes(), b"hello");
+        assert!(matches!(decrypt(&ciphertext, &BigUint::from(43u32)), Err(DhError::DecryptionFailed)));
+    }
+}
@@ -0,0 +1,50 @@
+// Sending one message to several recipients, e.g. in a group chat, where the sender has run a separate exchange
+// with each of them.
+//
+// Every recipient gets their own ciphertext: the message is encrypted with `encrypt_gcm` under that recipient's
+// shared secret. This is simpler than encrypting once under a random content key and wrapping that key per
+// recipient, at the cost of encrypting the whole message once per recipient. A recipient can only read the
+// ciphertext made for them.
+
+use crate::error::DhError;
+use crate::gcm::{decrypt_gcm, encrypt_gcm};
+use crate::keys::SharedSecret;
+
+// Encrypt `plain_text` once for each recipient. The ciphertexts come back in the same order as the secrets.
+pub fn encrypt_multi(plain_text: &str, recipient_secrets: &[SharedSecret]) -> Result<Vec<Vec<u8>>, DhError> {
+    recipient_secrets
+        .iter()
+        .map(|secret| encrypt_gcm(plain_text.as_bytes(), secret.as_biguint()))
+        .collect()
+}
+
+// Decrypt the ciphertext `encrypt_multi` produced for this recipient, using their shared secret with the sender.
+pub fn decrypt_multi(ciphertext: &[u8], secret: &SharedSecret) -> Result<String, DhError> {
+    let plain_bytes = decrypt_gcm(ciphertext, secret.as_biguint())?;
+    String::from_utf8(plain_bytes).map_err(|_| DhError::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::generate_keypair;
+    use crate::params::Group;
+
+    #[test]
+    fn each_recipient_decrypts_their_own_copy() {
+        let params = Group::Ffdhe2048.parameters();
+        let (sender_private, sender_public) = generate_keypair(&params).unwrap();
+        let recipients: Vec<_> = (0..3).map(|_| generate_keypair(&params).unwrap()).collect();
+        let sender_secrets: Vec<SharedSecret> =
+            recipients.iter().map(|(_, public_key)| sender_private.agree(public_key, &params)).collect();
+
+        let ciphertexts = encrypt_multi("meeting moved to 3pm", &sender_secrets).unwrap();
+        assert_eq!(ciphertexts.len(), 3);
+        for (i, (private_key, _)) in recipients.iter().enumerate() {
+            let secret = private_key.agree(&sender_public, &params);
+            assert_eq!(decrypt_multi(&ciphertexts[i], &secret).unwrap(), "meeting moved to 3pm");
+            let other = &ciphertexts[(i + 1) % 3];
+            assert!(matches!(decrypt_multi(other, &secret), Err(DhError::DecryptionFailed)));
+        }
+    }
+}
@@ -0,0 +1,429 @@
+// Running the exchange between two processes. The handshake works over anything that implements `Read` and `Write`;
+// `tcp_handshake` adds the socket-specific parts such as a read timeout, so a silent peer can't hang it forever, and
+// optionally dumps all handshake traffic for debugging. `connect` opens the client side, retrying transient failures.
+// Each handshake is traced in a span, with an event per phase, see logging.rs.
+//
+// Wire format: each side first sends its generator and modulus, then its public key, each as a 4-byte big-endian
+// length followed by the number's big-endian bytes, and reads the peer's in the same order. The parameters are
+// compared before any key is generated, so a misconfigured peer is turned away without spending an exponentiation.
+// Messages after the handshake use the same length prefix, see `send_frame` and `receive_frame`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use num_bigint::BigUint;
+
+use crate::confirmation::confirm;
+use crate::error::DhError;
+use crate::hexdump::HexdumpStream;
+use crate::keys::{generate_keypair, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+use crate::transcript::Transcript;
+
+// How long `tcp_handshake` waits for the peer by default.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Settings for `tcp_handshake`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeOptions {
+    // How long to wait on the peer before failing with `DhError::TimedOut`.
+    pub timeout: Duration,
+    // Print a hex dump of every byte sent and received to standard error.
+    pub hexdump: bool,
+}
+
+impl Default for HandshakeOptions {
+    fn default() -> Self {
+        HandshakeOptions { timeout: DEFAULT_HANDSHAKE_TIMEOUT, hexdump: false }
+    }
+}
+
+// Settings for `connect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectOptions {
+    // How many more times to try after the first attempt fails with a transient error.
+    pub retries: u32,
+    // How long to wait before the first retry. The wait doubles after every further failure.
+    pub backoff: Duration,
+}
+
+impl Default for ConnectOptions {
+    // A single attempt, as with `TcpStream::connect`.
+    fn default() -> Self {
+        ConnectOptions { retries: 0, backoff: Duration::from_millis(100) }
+    }
+}
+
+// Connect to a server, retrying with exponential backoff if the attempt fails in a way that may clear up by itself,
+// e.g. the server isn't listening yet. Other errors, and the last transient one, are returned as `DhError::Io`.
+pub fn connect<A: ToSocketAddrs>(addr: A, options: &ConnectOptions) -> Result<TcpStream, DhError> {
+    let mut backoff = options.backoff;
+    let mut retries_left = options.retries;
+    loop {
+        match TcpStream::connect(&addr) {
+            Ok(stream) => return Ok(stream),
+            Err(error) if retries_left > 0 && is_transient(&error) => {
+                thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+                retries_left -= 1;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+// Write a public key in the length-prefixed wire format.
+pub fn send_public_key<W: Write>(writer: &mut W, public_key: &PublicKey) -> Result<(), DhError> {
+    let bytes = public_key.to_wire_bytes();
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Read a public key in the length-prefixed wire format. A key can't be longer than the modulus, so larger length
+// prefixes are rejected before anything is allocated for them. The key itself must be canonically encoded, see
+// `PublicKey::from_wire_bytes`.
+pub fn receive_public_key<R: Read>(reader: &mut R, params: &DhParameters) -> Result<PublicKey, DhError> {
+    let mut length_bytes = [0u8; 4];
+    reader.read_exact(&mut length_bytes)?;
+    let length = u32::from_be_bytes(length_bytes) as usize;
+    let max_length = params.modulus.bits().div_ceil(8) as usize;
+    if length > max_length {
+        return Err(DhError::FrameTooLarge { length, max_length });
+    }
+    let mut bytes = vec![0u8; length];
+    reader.read_exact(&mut bytes)?;
+    PublicKey::from_wire_bytes(&bytes, params)
+}
+
+// Write one message after the handshake, e.g. a `SecureChannel` ciphertext, as a 4-byte big-endian length followed
+// by its bytes.
+pub fn send_frame<W: Write>(writer: &mut W, frame: &[u8]) -> Result<(), DhError> {
+    let length = u32::try_from(frame.len()).map_err(|_| DhError::MessageTooLong)?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Read one message written by `send_frame`. Frames longer than `max_length` are rejected with
+// `DhError::FrameTooLarge` before anything is allocated for them. A peer that hangs up between frames gives `Ok(None)`;
+// one that hangs up partway through a frame is `DhError::Truncated`.
+pub fn receive_frame<R: Read>(reader: &mut R, max_length: usize) -> Result<Option<Vec<u8>>, DhError> {
+    let mut length_bytes = [0u8; 4];
+    let mut filled = 0;
+    while filled < length_bytes.len() {
+        match reader.read(&mut length_bytes[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(DhError::Truncated),
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    let length = u32::from_be_bytes(length_bytes) as usize;
+    if length > max_length {
+        return Err(DhError::FrameTooLarge { length, max_length });
+    }
+    let mut frame = vec![0u8; length];
+    reader.read_exact(&mut frame).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => DhError::Truncated,
+        _ => error.into(),
+    })?;
+    Ok(Some(frame))
+}
+
+fn send_parameters<W: Write>(writer: &mut W, params: &DhParameters) -> Result<(), DhError> {
+    for number in [&params.generator, &params.modulus] {
+        let bytes = number.to_bytes_be();
+        writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+        writer.write_all(&bytes)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Read the peer's parameters. Numbers longer than our own modulus can't match it, so they are refused as incompatible
+// without being read.
+fn receive_parameters<R: Read>(reader: &mut R, params: &DhParameters) -> Result<DhParameters, DhError> {
+    let max_length = params.modulus.bits().div_ceil(8) as usize;
+    let mut numbers = [BigUint::default(), BigUint::default()];
+    for number in &mut numbers {
+        let mut length_bytes = [0u8; 4];
+        reader.read_exact(&mut length_bytes)?;
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        if length > max_length {
+            return Err(DhError::IncompatibleParameters);
+        }
+        let mut bytes = vec![0u8; length];
+        reader.read_exact(&mut bytes)?;
+        *number = BigUint::from_bytes_be(&bytes);
+    }
+    let [generator, modulus] = numbers;
+    Ok(DhParameters { generator, modulus })
+}
+
+// Exchange public keys with the peer on the other end of `stream` and derive the shared secret. The handshake is
+// aborted with `DhError::IncompatibleParameters` if the peer's parameters differ from ours, and the peer's key is
+// checked with `PublicKey::validate_in_group` before it is used. A peer key equal to ours fails with
+// `DhError::IdenticalPublicKeys`: in a real group that never happens by chance. A peer that hangs up partway, even in
+// the middle of a message, is reported as `DhError::PeerDisconnected` naming the phase.
+pub fn handshake<S: Read + Write>(stream: &mut S, params: &DhParameters) -> Result<SharedSecret, DhError> {
+    handshake_with_transcript(stream, params).map(|(secret, _)| secret)
+}
+
+// Like `handshake`, but also returns the transcript of the parameters and both public keys, for deriving keys bound to
+// the handshake with `Transcript::derive_key`. Neither side has a fixed role, so the two keys are recorded in
+// ascending order, which gives both ends the same transcript.
+pub fn handshake_with_transcript<S: Read + Write>(
+    stream: &mut S,
+    params: &DhParameters,
+) -> Result<(SharedSecret, Transcript), DhError> {
+    exchange(stream, params).map(|(secret, transcript, _, _)| (secret, transcript))
+}
+
+// Like `handshake_with_transcript`, followed by key confirmation, see `confirm`: both sides prove they agree on the
+// secret and the transcript before either returns, so a mismatch is `DhError::KeyConfirmationFailed` here rather than
+// a failed decryption later.
+pub fn confirmed_handshake<S: Read + Write>(
+    stream: &mut S,
+    params: &DhParameters,
+) -> Result<(SharedSecret, Transcript), DhError> {
+    let (secret, transcript, public_key, peer_public) = exchange(stream, params)?;
+    let _span = tracing::debug_span!("key_confirmation").entered();
+    confirm(stream, &secret, &transcript, &public_key, &peer_public)?;
+    tracing::debug!("key confirmed");
+    Ok((secret, transcript))
+}
+
+// The handshake proper, also giving back our public key and the peer's, in that order.
+fn exchange<S: Read + Write>(
+    stream: &mut S,
+    params: &DhParameters,
+) -> Result<(SharedSecret, Transcript, PublicKey, PublicKey), DhError> {
+    let _span = tracing::debug_span!("handshake", modulus_bits = params.bit_length()).entered();
+    in_phase("sending parameters", send_parameters(stream, params))?;
+    let peer_params = in_phase("receiving parameters", receive_parameters(stream, params))?;
+    if !peer_params.is_compatible_with(params) {
+        tracing::debug!(peer_modulus_bits = peer_params.bit_length(), "peer uses different parameters");
+        return Err(DhError::IncompatibleParameters);
+    }
+    tracing::debug!("parameters match");
+
+    let (private_key, public_key) = generate_keypair(params)?;
+    in_phase("sending the public key", send_public_key(stream, &public_key))?;
+    trace_public_key("sent public key", &public_key);
+    let peer_public = in_phase("receiving the public key", receive_public_key(stream, params))?;
+    trace_public_key("received public key", &peer_public);
+    peer_public.validate_in_group(params)?;
+    if peer_public == public_key {
+        return Err(DhError::IdenticalPublicKeys);
+    }
+    tracing::debug!("peer public key is valid");
+
+    let mut transcript = Transcript::new(params);
+    let mut keys = [public_key.as_biguint(), peer_public.as_biguint()];
+    keys.sort();
+    for key in keys {
+        transcript.append(&key.to_bytes_be());
+    }
+    Ok((private_key.agree(&peer_public, params), transcript, public_key, peer_public))
+}
+
+// Run `handshake` over a TCP connection, giving up with `DhError::TimedOut` if the peer goes quiet for longer than
+// the configured timeout while we wait on it.
+pub fn tcp_handshake(
+    stream: &mut TcpStream,
+    params: &DhParameters,
+    options: &HandshakeOptions,
+) -> Result<SharedSecret, DhError> {
+    let peer = stream.peer_addr().ok().map(|addr| addr.to_string());
+    let _span = tracing::info_span!("tcp_handshake", peer).entered();
+    stream.set_read_timeout(Some(options.timeout))?;
+    let result = if options.hexdump {
+        handshake(&mut HexdumpStream::new(&mut *stream), params)
+    } else {
+        handshake(stream, params)
+    };
+    let result = result.map_err(|error| match error {
+        DhError::Io(io_error) if is_timeout(&io_error) => DhError::TimedOut { timeout: options.timeout },
+        other => other,
+    });
+    match &result {
+        Ok(_) => tracing::info!("handshake complete"),
+        Err(error) => tracing::info!(%error, "handshake failed"),
+    }
+    result
+}
+
+// A debug event for a public key, by fingerprint, with the whole key at trace level.
+pub(crate) fn trace_public_key(message: &str, public_key: &PublicKey) {
+    if tracing::enabled!(tracing::Level::TRACE) {
+        tracing::trace!(fingerprint = %public_key.fingerprint(), value = %public_key, "{}", message);
+    } else {
+        tracing::debug!(fingerprint = %public_key.fingerprint(), "{}", message);
+    }
+}
+
+// Connection failures worth retrying: nobody listening yet, or the connection dropped or timed out on the way.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+    )
+}
+
+// Turn the I/O errors of a vanished peer into `DhError::PeerDisconnected` for `phase`.
+pub(crate) fn in_phase<T>(phase: &'static str, result: Result<T, DhError>) -> Result<T, DhError> {
+    result.map_err(|error| match error {
+        DhError::Io(io_error) if is_disconnect(&io_error) => DhError::PeerDisconnected { phase },
+        other => other,
+    })
+}
+
+// End of stream before a message was complete, or the connection torn down under us.
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+// Read timeouts surface as WouldBlock on Unix and TimedOut on Windows.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::params::Group;
+
+    #[test]
+    fn silent_server_times_out_the_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done, wait_for_done) = mpsc::channel::<()>();
+        // The server accepts and then holds the connection open without ever sending.
+        let server = thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            wait_for_done.recv().ok();
+        });
+
+        let mut stream = connect(addr, &ConnectOptions::default()).unwrap();
+        let options = HandshakeOptions { timeout: Duration::from_millis(200), hexdump: false };
+        let start = Instant::now();
+        let result = tcp_handshake(&mut stream, &Group::Ffdhe2048.parameters(), &options);
+        assert!(matches!(result, Err(DhError::TimedOut { timeout }) if timeout == options.timeout), "{result:?}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        done.send(()).unwrap();
+        server.join().unwrap();
+    }
+
+    // An address nothing listens on yet: bind a free port and let it go again.
+    fn free_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    #[test]
+    fn connect_retries_until_the_server_is_up() {
+        let addr = free_addr();
+        assert!(matches!(connect(addr, &ConnectOptions::default()), Err(DhError::Io(_))));
+
+        // The server only starts listening after the first attempt has been refused.
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(150));
+            let listener = TcpListener::bind(addr).unwrap();
+            listener.accept().unwrap();
+        });
+        let options = ConnectOptions { retries: 6, backoff: Duration::from_millis(50) };
+        let stream = connect(addr, &options).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connect_gives_up_after_its_retries() {
+        let addr = free_addr();
+        let options = ConnectOptions { retries: 2, backoff: Duration::from_millis(20) };
+        let start = Instant::now();
+        let result = connect(addr, &options);
+        assert!(matches!(result, Err(DhError::Io(error)) if error.kind() == io::ErrorKind::ConnectionRefused));
+        // Waits of 20 and then 40 milliseconds.
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn mismatched_parameters_abort_both_ends() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            tcp_handshake(&mut stream, &Group::Modp2048.parameters(), &HandshakeOptions::default())
+        });
+
+        let mut stream = connect(addr, &ConnectOptions::default()).unwrap();
+        let result = tcp_handshake(&mut stream, &Group::Ffdhe2048.parameters(), &HandshakeOptions::default());
+        assert!(matches!(result, Err(DhError::IncompatibleParameters)), "{result:?}");
+        let server_result = server.join().unwrap();
+        assert!(matches!(server_result, Err(DhError::IncompatibleParameters)), "{server_result:?}");
+    }
+
+    #[test]
+    fn peer_closing_after_half_a_public_key_is_a_disconnect() {
+        let params = Group::Ffdhe2048.parameters();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_params = params.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            receive_parameters(&mut stream, &server_params).unwrap();
+            send_parameters(&mut stream, &server_params).unwrap();
+            receive_public_key(&mut stream, &server_params).unwrap();
+            // Announce a full-size key, send half of it and hang up.
+            stream.write_all(&256u32.to_be_bytes()).unwrap();
+            stream.write_all(&[0x42; 128]).unwrap();
+        });
+
+        let mut stream = connect(addr, &ConnectOptions::default()).unwrap();
+        let result = tcp_handshake(&mut stream, &params, &HandshakeOptions::default());
+        server.join().unwrap();
+        let error = result.unwrap_err();
+        assert!(matches!(error, DhError::PeerDisconnected { phase: "receiving the public key" }), "{error:?}");
+        assert_eq!(error.to_string(), "the peer disconnected while receiving the public key");
+    }
+
+    #[test]
+    fn peer_echoing_our_key_is_flagged() {
+        let params = Group::Ffdhe2048.parameters();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_params = params.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            receive_parameters(&mut stream, &server_params).unwrap();
+            send_parameters(&mut stream, &server_params).unwrap();
+            let client_public = receive_public_key(&mut stream, &server_params).unwrap();
+            send_public_key(&mut stream, &client_public).unwrap();
+        });
+
+        let mut stream = connect(addr, &ConnectOptions::default()).unwrap();
+        let result = tcp_handshake(&mut stream, &params, &HandshakeOptions::default());
+        server.join().unwrap();
+        assert!(matches!(result, Err(DhError::IdenticalPublicKeys)), "{result:?}");
+    }
+}
@@ -0,0 +1,111 @@
+// Nonce derivation for counter-based message encryption. A key reused across restarts would repeat its nonces if the
+// counter simply started at 0 again each time, so the nonces are bound to a random salt the parties exchange per session:
+// the salt is expanded into a base IV and each message nonce is the base IV XOR the message counter, as in TLS 1.3.
+
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+
+use crate::error::DhError;
+
+// Length of the nonces handed to the cipher (the 96-bit size GCM expects).
+pub const NONCE_LEN: usize = 12;
+
+// Length of the random salt exchanged at the start of each session.
+pub const SALT_LEN: usize = 16;
+
+// Generate a fresh random salt for a new session.
+#[cfg(feature = "std")]
+pub fn generate_session_salt() -> [u8; SALT_LEN] {
+    generate_session_salt_with_rng(&mut rand::thread_rng())
+}
+
+// Like `generate_session_salt`, drawing the salt from `rng`, for builds without `std`.
+pub fn generate_session_salt_with_rng<R: CryptoRng + RngCore>(rng: &mut R) -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    salt
+}
+
+// Hands out one nonce per message for a session. Within a session the counter never repeats, and sessions with
+// different salts start from unrelated base IVs, so the same counter gives different nonces in each.
+pub struct NonceSequence {
+    base_iv: [u8; NONCE_LEN],
+    counter: u64,
+}
+
+impl NonceSequence {
+    // Start a sequence at counter 0 for the session identified by `salt`.
+    pub fn new(salt: &[u8]) -> Self {
+        let mut base_iv = [0u8; NONCE_LEN];
+        Hkdf::<Sha256>::new(None, salt)
+            .expand(b"diffie-hellman nonce", &mut base_iv)
+            .expect("12 bytes is a valid HKDF-SHA256 output length");
+        NonceSequence { base_iv, counter: 0 }
+    }
+
+    // Continue the sequence from `counter`, e.g. when restoring a saved session.
+    #[cfg(feature = "serde")]
+    pub(crate) fn resume_at(&mut self, counter: u64) {
+        self.counter = counter;
+    }
+
+    // The nonce for a given message counter: the base IV with the big-endian counter XORed into its last 8 bytes.
+    pub fn nonce_for(&self, counter: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = self.base_iv;
+        for (byte, counter_byte) in nonce[NONCE_LEN - 8..].iter_mut().zip(counter.to_be_bytes()) {
+            *byte ^= counter_byte;
+        }
+        nonce
+    }
+
+    // The nonce for the next message. Errors once every counter value has been used rather than wrapping around.
+    pub fn next_nonce(&mut self) -> Result<[u8; NONCE_LEN], DhError> {
+        if self.counter == u64::MAX {
+            return Err(DhError::NonceExhausted);
+        }
+        let nonce = self.nonce_for(self.counter);
+        self.counter += 1;
+        Ok(nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn different_salts_never_share_nonces() {
+        let mut rng = StdRng::seed_from_u64(106);
+        let sessions: Vec<NonceSequence> =
+            (0..4).map(|_| NonceSequence::new(&generate_session_salt_with_rng(&mut rng))).collect();
+        let mut seen = HashSet::new();
+        for session in &sessions {
+            for counter in 0..2000 {
+                assert!(seen.insert(session.nonce_for(counter)), "repeated nonce at counter {counter}");
+            }
+        }
+    }
+
+    #[test]
+    fn same_salt_gives_the_same_sequence() {
+        let mut first = NonceSequence::new(b"salt");
+        let second = NonceSequence::new(b"salt");
+        for counter in 0..10 {
+            assert_eq!(first.next_nonce().unwrap(), second.nonce_for(counter));
+        }
+    }
+
+    #[test]
+    fn sequence_stops_before_wrapping() {
+        let mut sequence = NonceSequence::new(b"salt");
+        sequence.counter = u64::MAX - 1;
+        assert_eq!(sequence.next_nonce().unwrap(), sequence.nonce_for(u64::MAX - 1));
+        assert!(matches!(sequence.next_nonce(), Err(DhError::NonceExhausted)));
+    }
+}
@@ -0,0 +1,132 @@
+// Password-authenticated key exchange: SPAKE2 (RFC 9382) over a safe-prime group instead of an elliptic curve. Both
+// sides know the same password, possibly a short one, and nothing else. Each blinds its public key with the password:
+// the initiator sends X = g^x * M^w and the responder Y = g^y * N^w, where w is derived from the password and M and N
+// are fixed group elements nobody knows the discrete logarithm of. Each side strips the password back out of the
+// other's value and raises it to its own private key, so both get g^(xy) when the passwords match.
+//
+// An eavesdropper sees only blinded values and can't test password guesses offline. An active attacker who doesn't
+// know the password can test one guess per exchange it takes part in, and nothing more. With a wrong password the two
+// sides just end up with different secrets, so they have to confirm the key before trusting it, e.g. with
+// `confirmation_tag` over the transcript `finish` returns. Use a random password per pairing where possible, and
+// limit failed attempts.
+//
+// Only safe-prime groups work, since the derivation of M, N and w relies on the prime-order subgroup of squares.
+
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::ct::modpow_secret;
+use crate::error::DhError;
+use crate::kdf::derive_key;
+use crate::keys::{generate_keypair, PrivateKey, PublicKey, SharedSecret};
+use crate::params::{DhParameters, Group, MIN_SECURE_BITS};
+use crate::session::Role;
+use crate::transcript::Transcript;
+
+const SECRET_LABEL: &[u8] = b"diffie-hellman spake2 secret";
+const PASSWORD_SALT: &[u8] = b"diffie-hellman spake2 password salt";
+
+// A group element from `label` with no known discrete logarithm: wide output from HKDF keyed on the modulus, reduced
+// mod p and squared into the prime-order subgroup.
+fn hash_to_group(params: &DhParameters, label: &str) -> BigUint {
+    let p = params.modulus();
+    let wide = BigUint::from_bytes_be(&derive_key(p, label, params.modulus_len() + 16));
+    (&wide % p).modpow(&BigUint::from(2u32), p)
+}
+
+// The blinding element for messages sent by `role`: M for the initiator, N for the responder.
+fn blinding_element(params: &DhParameters, role: Role) -> BigUint {
+    hash_to_group(params, &format!("diffie-hellman spake2 {} element", role.label()))
+}
+
+// w, the password as an exponent mod q, kept in a `PrivateKey` so it is scrubbed on drop.
+fn password_scalar(password: &[u8], q: &BigUint, params: &DhParameters) -> PrivateKey {
+    let mut wide = Zeroizing::new(vec![0u8; params.modulus_len() + 16]);
+    Hkdf::<Sha256>::new(Some(PASSWORD_SALT), password)
+        .expand(b"diffie-hellman spake2 password", &mut wide)
+        .expect("a few hundred bytes is a valid HKDF output length");
+    PrivateKey(BigUint::from_bytes_be(&wide) % q)
+}
+
+// One side of a password-authenticated exchange.
+pub struct PakeExchange {
+    params: DhParameters,
+    role: Role,
+    private_key: PrivateKey,
+    password_scalar: PrivateKey,
+    // The blinded public key to send to the peer.
+    message: PublicKey,
+}
+
+impl PakeExchange {
+    // An exchange with `password` in the 3072-bit FFDHE group, as `DiffieHellman::secure_default` uses. The two sides
+    // take opposite roles.
+    pub fn new(password: &[u8], role: Role) -> Result<Self, DhError> {
+        Self::with_params(password, &Group::Ffdhe3072.into(), role)
+    }
+
+    // An exchange in `params`, which must be a safe-prime group of at least `MIN_SECURE_BITS`. Smaller moduli are
+    // `DhError::InsecureModulus`, and others `DhError::NotSafePrime`.
+    pub fn with_params(password: &[u8], params: &DhParameters, role: Role) -> Result<Self, DhError> {
+        let bits = params.bit_length() as usize;
+        if bits < MIN_SECURE_BITS {
+            return Err(DhError::InsecureModulus { bits, min_bits: MIN_SECURE_BITS });
+        }
+        // The built-in groups are known to be safe primes, which spares two slow primality tests.
+        let builtin = Group::ALL.iter().any(|group| group.parameters().is_compatible_with(params));
+        if !builtin && !params.is_safe_prime() {
+            return Err(DhError::NotSafePrime);
+        }
+        let p = params.modulus();
+        let q = (p - 1u32) >> 1;
+        let password_scalar = password_scalar(password, &q, params);
+        let (private_key, public_key) = generate_keypair(params)?;
+        let blinding = modpow_secret(&blinding_element(params, role), &password_scalar.0, params.bit_length(), p);
+        let message = PublicKey::from_biguint(public_key.as_biguint() * blinding % p);
+        Ok(PakeExchange { params: params.clone(), role, private_key, password_scalar, message })
+    }
+
+    // The blinded public key to send to the peer.
+    pub fn message(&self) -> &PublicKey {
+        &self.message
+    }
+
+    // Take the peer's blinded public key and compute the secret, along with the transcript of both messages for key
+    // confirmation. A value outside the prime-order subgroup is `DhError::InvalidPublicKey`, and one that strips down
+    // to a trivial key `DhError::TrivialSecret`. A wrong password on either side gives no error here, only a
+    // secret different from the peer's.
+    pub fn finish(self, peer_message: &PublicKey) -> Result<(SharedSecret, Transcript), DhError> {
+        peer_message.validate_in_group(&self.params)?;
+        let p = self.params.modulus();
+        let q = (p - 1u32) >> 1;
+        // Dividing out N^w is multiplying by N^(q - w), as N has order q.
+        let unblinding_exponent = PrivateKey(&q - &self.password_scalar.0);
+        let unblinding_element = blinding_element(&self.params, self.role.peer());
+        let unblinding = modpow_secret(&unblinding_element, &unblinding_exponent.0, self.params.bit_length(), p);
+        let peer_public = PublicKey::from_biguint(peer_message.as_biguint() * unblinding % p);
+        if !peer_public.is_nontrivial(&self.params) {
+            return Err(DhError::TrivialSecret);
+        }
+        let raw_secret = self.private_key.agree(&peer_public, &self.params);
+
+        let (initiator, responder) = match self.role {
+            Role::Initiator => (&self.message, peer_message),
+            Role::Responder => (peer_message, &self.message),
+        };
+        let mut transcript = Transcript::new(&self.params);
+        transcript.append(&initiator.to_wire_bytes());
+        transcript.append(&responder.to_wire_bytes());
+
+        let mut hasher = Sha256::new();
+        hasher.update(SECRET_LABEL);
+        hasher.update(transcript.hash());
+        for value in [raw_secret.as_biguint(), &self.password_scalar.0] {
+            let bytes = Zeroizing::new(value.to_bytes_be());
+            hasher.update((bytes.len() as u32).to_be_bytes());
+            hasher.update(&bytes);
+        }
+        Ok((SharedSecret::from_bytes(&hasher.finalize()), transcript))
+    }
+}
@@ -0,0 +1,732 @@
+// Public parameters for finite-field Diffie-Hellman: the generator g and the prime modulus p that both parties agree on
+// before exchanging keys, plus the standardized groups callers can pick instead of choosing their own numbers: the MODP
+// groups of RFC 3526 and the FFDHE groups of RFC 7919. All of them are safe primes with generator 2.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use num_bigint::BigUint;
+// f64's own `cbrt`, `ln` and `powf` need `std`; the trait's versions fall back to libm without it.
+use num_traits::Float;
+
+use crate::error::DhError;
+use crate::primality::is_probable_prime;
+#[cfg(feature = "std")]
+use crate::primality::random_safe_prime;
+
+// RFC 3526 group 14: the 2048-bit MODP prime.
+const MODP_2048_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF";
+
+// RFC 3526 group 15: the 3072-bit MODP prime.
+const MODP_3072_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+
+// RFC 3526 group 16: the 4096-bit MODP prime.
+const MODP_4096_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7\
+    88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8\
+    DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2\
+    233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9\
+    93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C934063199FFFFFFFFFFFFFFFF";
+
+// RFC 7919 appendix A.1: the 2048-bit ffdhe2048 prime.
+const FFDHE_2048_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695\
+    A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A\
+    D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935\
+    984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A\
+    BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4\
+    AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61\
+    9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005\
+    C58EF1837D1683B2C6F34A26C1B2EFFA886B423861285C97FFFFFFFFFFFFFFFF";
+
+// RFC 7919 appendix A.2: the 3072-bit ffdhe3072 prime.
+const FFDHE_3072_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695\
+    A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A\
+    D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935\
+    984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A\
+    BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4\
+    AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61\
+    9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005\
+    C58EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035B\
+    BC34F4DEF99C023861B46FC9D6E6C9077AD91D2691F7F7EE598CB0FAC186D91C\
+    AEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E21E71F54BFF\
+    5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E\
+    0ABCD06BFA53DDEF3C1B20EE3FD59D7C25E41D2B66C62E37FFFFFFFFFFFFFFFF";
+
+// RFC 7919 appendix A.3: the 4096-bit ffdhe4096 prime.
+const FFDHE_4096_PRIME: &str = "\
+    FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695\
+    A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A\
+    D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935\
+    984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A\
+    BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4\
+    AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61\
+    9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005\
+    C58EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035B\
+    BC34F4DEF99C023861B46FC9D6E6C9077AD91D2691F7F7EE598CB0FAC186D91C\
+    AEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E21E71F54BFF\
+    5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E\
+    0ABCD06BFA53DDEF3C1B20EE3FD59D7C25E41D2B669E1EF16E6F52C3164DF4FB\
+    7930E9E4E58857B6AC7D5F42D69F6D187763CF1D5503400487F55BA57E31CC7A\
+    7135C886EFB4318AED6A1E012D9E6832A907600A918130C46DC778F971AD0038\
+    092999A333CB8B7A1A1DB93D7140003C2A4ECEA9F98D0ACC0A8291CDCEC97DCF\
+    8EC9B55A7F88A46B4DB5A851F44182E1C68A007E5E655F6AFFFFFFFFFFFFFFFF";
+
+// The smallest modulus `DhParameters::new` accepts, in bits. 2048-bit groups give about 112 bits of security, the
+// current NIST minimum.
+pub const MIN_SECURE_BITS: usize = 2048;
+
+// The range of modulus sizes `DhParameters::generate` accepts.
+#[cfg(feature = "std")]
+const GENERATE_MIN_BITS: usize = 1024;
+#[cfg(feature = "std")]
+const GENERATE_MAX_BITS: usize = 4096;
+
+// The generator g and prime modulus p of a Diffie-Hellman group. Public keys are g^x mod p.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhParameters {
+    pub(crate) generator: BigUint,
+    pub(crate) modulus: BigUint,
+}
+
+// The parameters type under a shorter name.
+pub type DhParams = DhParameters;
+
+impl DhParameters {
+    // The standard groups, for callers who'd rather not go through `Group`. The FFDHE groups are the ones TLS 1.3
+    // uses and the better choice for new protocols; the MODP groups are for peers that only know RFC 3526.
+    pub fn modp_2048() -> Self {
+        Group::Modp2048.parameters()
+    }
+
+    pub fn modp_3072() -> Self {
+        Group::Modp3072.parameters()
+    }
+
+    pub fn modp_4096() -> Self {
+        Group::Modp4096.parameters()
+    }
+
+    pub fn ffdhe2048() -> Self {
+        Group::Ffdhe2048.parameters()
+    }
+
+    pub fn ffdhe3072() -> Self {
+        Group::Ffdhe3072.parameters()
+    }
+
+    pub fn ffdhe4096() -> Self {
+        Group::Ffdhe4096.parameters()
+    }
+
+    // Build parameters from an explicit generator and modulus. Moduli shorter than `MIN_SECURE_BITS` are rejected
+    // with `DhError::InsecureModulus`; use `new_allow_insecure` for toy or legacy parameters.
+    pub fn new(generator: BigUint, modulus: BigUint) -> Result<Self, DhError> {
+        let bits = modulus.bits() as usize;
+        if bits < MIN_SECURE_BITS {
+            return Err(DhError::InsecureModulus { bits, min_bits: MIN_SECURE_BITS });
+        }
+        Self::new_allow_insecure(generator, modulus)
+    }
+
+    // Build parameters without the minimum size check, e.g. for the classroom example with p = 57. A modulus of 0, 1
+    // or 2 is still rejected: there is no usable range of public keys below it and the exponentiation results would
+    // be meaningless. So is a trivial generator, with `DhError::InvalidGenerator`: with g = 0 or 1 every public key and
+    // every secret is that same value, g = p - 1 only ever gives 1 or p - 1, and g >= p is some other element in
+    // disguise.
+    pub fn new_allow_insecure(generator: BigUint, modulus: BigUint) -> Result<Self, DhError> {
+        if modulus <= BigUint::from(2u32) {
+            return Err(DhError::ModulusTooSmall);
+        }
+        if generator <= BigUint::from(1u32) || generator >= &modulus - 1u32 {
+            return Err(DhError::InvalidGenerator);
+        }
+        Ok(DhParameters { generator, modulus })
+    }
+
+    // Fresh parameters: a random `bits`-bit safe prime p = 2q + 1 with generator 2, which generates the subgroup of
+    // prime order q. Sizes from 1024 to 4096 bits are supported, anything else is `DhError::InvalidPrimeSize`, but
+    // `DiffieHellman::new` and `DhParameters::new` still refuse moduli below `MIN_SECURE_BITS`. Finding a safe prime
+    // is slow: about a second for 1024 bits, a minute or more for 2048 and far longer for 4096, so generate once and
+    // store the result. The standard groups are the better choice unless there's a reason to avoid them, since peers
+    // can recognise them without checking primality.
+    #[cfg(feature = "std")]
+    pub fn generate(bits: usize) -> Result<Self, DhError> {
+        if !(GENERATE_MIN_BITS..=GENERATE_MAX_BITS).contains(&bits) {
+            return Err(DhError::InvalidPrimeSize { bits, min_bits: GENERATE_MIN_BITS, max_bits: GENERATE_MAX_BITS });
+        }
+        Self::new_allow_insecure(BigUint::from(2u32), random_safe_prime(bits))
+    }
+
+    // Build parameters from machine integers, for tests and classroom examples. Any modulus that fits in a u64 is far
+    // below `MIN_SECURE_BITS`, so this only exists in an insecure form; the `TryFrom` impls for integer pairs go
+    // through `new` and always refuse.
+    pub fn from_u64_allow_insecure(generator: u64, modulus: u64) -> Result<Self, DhError> {
+        Self::new_allow_insecure(BigUint::from(generator), BigUint::from(modulus))
+    }
+
+    // The parameters in a compact binary form: the generator, then the modulus, each as a 4-byte big-endian length
+    // followed by the number's big-endian bytes. This is the layout the handshake sends them in, see net.rs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for number in [&self.generator, &self.modulus] {
+            let number_bytes = number.to_bytes_be();
+            bytes.extend_from_slice(&(number_bytes.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&number_bytes);
+        }
+        bytes
+    }
+
+    // Read parameters written by `to_bytes`. Input that doesn't hold exactly two length-prefixed numbers is
+    // `DhError::MalformedMessage`; the numbers then go through `new`, so an insecure group is refused here too.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DhError> {
+        let mut rest = bytes;
+        let mut numbers = [BigUint::default(), BigUint::default()];
+        for number in &mut numbers {
+            let (length_bytes, tail) = rest.split_first_chunk::<4>().ok_or(DhError::MalformedMessage)?;
+            let length = u32::from_be_bytes(*length_bytes) as usize;
+            if tail.len() < length {
+                return Err(DhError::MalformedMessage);
+            }
+            let (number_bytes, tail) = tail.split_at(length);
+            *number = BigUint::from_bytes_be(number_bytes);
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(DhError::MalformedMessage);
+        }
+        let [generator, modulus] = numbers;
+        Self::new(generator, modulus)
+    }
+
+    pub fn generator(&self) -> &BigUint {
+        &self.generator
+    }
+
+    pub fn modulus(&self) -> &BigUint {
+        &self.modulus
+    }
+
+    // Whether two peers with these parameters can complete an exchange: they need exactly the same generator and
+    // modulus. Mismatched parameters don't make the exchange fail by themselves, the two sides just end up with
+    // different secrets, so this is checked up front.
+    pub fn is_compatible_with(&self, other: &DhParameters) -> bool {
+        self.generator == other.generator && self.modulus == other.modulus
+    }
+
+    // The size of the modulus in bits, e.g. 2048 for `Group::Modp2048`.
+    pub fn bit_length(&self) -> u64 {
+        self.modulus.bits()
+    }
+
+    // Run the primality and generator-order checks without performing an exchange.
+    pub fn check(&self) -> ParameterReport {
+        let modulus_is_prime = is_probable_prime(&self.modulus);
+        let modulus_is_safe_prime = modulus_is_prime && is_probable_prime(&subgroup_order(&self.modulus));
+        let generator_in_range = generator_in_range(&self.generator, &self.modulus);
+        let generator_order_verified = modulus_is_safe_prime && has_large_order(&self.generator, &self.modulus);
+        ParameterReport {
+            modulus_is_prime,
+            modulus_is_safe_prime,
+            generator_in_range,
+            generator_order_verified,
+            security_bits: self.security_bits(),
+        }
+    }
+
+    // Whether p is a safe prime, p = 2q + 1 with q also prime. A prime p alone isn't enough: if p - 1 has small
+    // factors, public keys can be pushed into small subgroups that leak bits of the private key.
+    pub fn is_safe_prime(&self) -> bool {
+        is_probable_prime(&self.modulus) && is_probable_prime(&subgroup_order(&self.modulus))
+    }
+
+    // Rough symmetric-equivalent strength of these parameters, going by the modulus size alone.
+    // Standard sizes follow NIST SP 800-57 (2048 bits ~ 112, 3072 ~ 128, 7680 ~ 192, 15360 ~ 256). Below 1024 bits the
+    // number field sieve cost estimate is used instead, which puts toy moduli at or near zero.
+    pub fn security_bits(&self) -> u32 {
+        match self.modulus.bits() {
+            bits if bits >= 15360 => 256,
+            bits if bits >= 7680 => 192,
+            bits if bits >= 3072 => 128,
+            bits if bits >= 2048 => 112,
+            bits if bits >= 1024 => 80,
+            bits => {
+                let ln_p = bits as f64 * core::f64::consts::LN_2;
+                let work = 1.923 * Float::cbrt(ln_p) * Float::powf(Float::ln(ln_p), 2.0 / 3.0) - 4.69;
+                (work / core::f64::consts::LN_2).max(0.0) as u32
+            }
+        }
+    }
+
+    // How many bits a private exponent needs to match the strength of these parameters: twice `security_bits`, as
+    // RFC 7919 section 5.2 recommends for short exponents, since the best attacks on an exponent take about the square
+    // root of its range. That is 224 bits for a 2048-bit modulus and 256 for 3072, against the 2047 and 3071 of the
+    // full range, so keys are generated and used several times faster. Short exponents rely on g generating a large
+    // prime-order subgroup, as in the standard groups and any safe-prime group. Capped at the size of the full
+    // exponent range, which is also used for moduli below `MIN_SECURE_BITS`, where there is no strength to match.
+    pub fn recommended_exponent_bits(&self) -> u64 {
+        let full_range = self.private_key_bound().bits();
+        if self.bit_length() < MIN_SECURE_BITS as u64 {
+            return full_range;
+        }
+        (2 * self.security_bits() as u64).min(full_range)
+    }
+
+    // Private exponents are drawn from 1 to one less than this: q = (p - 1) / 2, the order of the subgroup g
+    // generates in the standard groups and in `generate`'s. For a modulus that isn't a safe prime the order of g isn't
+    // known, and stopping at half of p - 1 costs at most one bit against the full range. Never below 2, so that even
+    // the smallest toy modulus leaves one exponent to pick.
+    pub(crate) fn private_key_bound(&self) -> BigUint {
+        subgroup_order(&self.modulus).max(BigUint::from(2u32))
+    }
+
+    // log2 of the number of distinct values g^k mod p can take, rounded down, i.e. how many bits a shared secret can
+    // carry at most. For toy moduli the powers are simply enumerated. For safe primes the order of g is 1, 2, q or 2q.
+    // Otherwise how p - 1 factors is unknown, and the modulus size is the only bound on offer.
+    pub(crate) fn generator_order_bits(&self) -> u32 {
+        let order = if self.modulus.bits() <= MAX_ENUMERATED_BITS {
+            let modulus = self.modulus.to_u64_digits().first().copied().unwrap_or(0);
+            let generator = self.generator.to_u64_digits().first().copied().unwrap_or(0) % modulus;
+            let mut seen = BTreeSet::new();
+            let mut power = generator;
+            while seen.insert(power) {
+                power = power * generator % modulus;
+            }
+            BigUint::from(seen.len())
+        } else if self.is_safe_prime() {
+            let q = subgroup_order(&self.modulus);
+            let power = self.generator.modpow(&q, &self.modulus);
+            if !generator_in_range(&self.generator, &self.modulus) {
+                BigUint::from(2u32)
+            } else if power == BigUint::from(1u32) {
+                q
+            } else {
+                q << 1
+            }
+        } else {
+            self.modulus.clone()
+        };
+        (order.bits().max(1) - 1) as u32
+    }
+}
+
+// Moduli up to this size have the powers of their generator counted one by one.
+const MAX_ENUMERATED_BITS: u64 = 20;
+
+// Whether `base` is a sound generator for the safe prime `modulus` = 2q + 1, i.e. it generates either the subgroup
+// of prime order q or the whole group of order 2q. Returns false when the modulus isn't a safe prime, since the order
+// of a generator can't be confirmed without knowing how p - 1 factors.
+pub fn is_valid_generator(base: &BigUint, modulus: &BigUint) -> bool {
+    is_probable_prime(modulus) && is_probable_prime(&subgroup_order(modulus)) && has_large_order(base, modulus)
+}
+
+// q = (p - 1) / 2, the order of the prime-order subgroup when p is a safe prime.
+fn subgroup_order(modulus: &BigUint) -> BigUint {
+    (modulus - 1u32) >> 1
+}
+
+fn generator_in_range(base: &BigUint, modulus: &BigUint) -> bool {
+    *modulus > BigUint::from(3u32) && *base > BigUint::from(1u32) && *base < modulus - 1u32
+}
+
+// For a safe prime the only orders are 1, 2, q and 2q. Bases other than 1 and p-1 have order q, in which case
+// base^q = 1, or 2q, in which case base^q = p - 1.
+fn has_large_order(base: &BigUint, modulus: &BigUint) -> bool {
+    if !generator_in_range(base, modulus) {
+        return false;
+    }
+    let power = base.modpow(&subgroup_order(modulus), modulus);
+    power == BigUint::from(1u32) || power == modulus - 1u32
+}
+
+// The outcome of checking a parameter set before it is deployed. `passed` says whether it is fit for use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterReport {
+    // The modulus passed the primality test.
+    pub modulus_is_prime: bool,
+    // The modulus is a safe prime, see `DhParameters::is_safe_prime`.
+    pub modulus_is_safe_prime: bool,
+    // The generator lies in [2, p-2], so it isn't 0, 1 or p-1.
+    pub generator_in_range: bool,
+    // The generator's order was confirmed to be q or 2q for p = 2q + 1, see `is_valid_generator`.
+    pub generator_order_verified: bool,
+    // The strength estimate from `DhParameters::security_bits`.
+    pub security_bits: u32,
+}
+
+impl ParameterReport {
+    // Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.modulus_is_prime && self.generator_in_range && self.generator_order_verified
+    }
+}
+
+// The standardized groups: the MODP groups from RFC 3526 and the FFDHE groups from RFC 7919. All of them use 2 as the
+// generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Group {
+    Modp2048,
+    Modp3072,
+    Modp4096,
+    Ffdhe2048,
+    Ffdhe3072,
+    Ffdhe4096,
+}
+
+impl Group {
+    // Every standard group, from smallest to largest modulus. Of two groups the same size the FFDHE one comes second.
+    pub const ALL: [Group; 6] =
+        [Group::Modp2048, Group::Ffdhe2048, Group::Modp3072, Group::Ffdhe3072, Group::Modp4096, Group::Ffdhe4096];
+
+    // The group's short name, as used by `ParametersRegistry`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Group::Modp2048 => "modp2048",
+            Group::Modp3072 => "modp3072",
+            Group::Modp4096 => "modp4096",
+            Group::Ffdhe2048 => "ffdhe2048",
+            Group::Ffdhe3072 => "ffdhe3072",
+            Group::Ffdhe4096 => "ffdhe4096",
+        }
+    }
+
+    // The group's ID in RFC 3526, or `None` for the FFDHE groups, which aren't in it.
+    pub fn rfc3526_id(self) -> Option<u32> {
+        match self {
+            Group::Modp2048 => Some(14),
+            Group::Modp3072 => Some(15),
+            Group::Modp4096 => Some(16),
+            Group::Ffdhe2048 | Group::Ffdhe3072 | Group::Ffdhe4096 => None,
+        }
+    }
+
+    // The generator and modulus for this group.
+    pub fn parameters(self) -> DhParameters {
+        let prime = match self {
+            Group::Modp2048 => MODP_2048_PRIME,
+            Group::Modp3072 => MODP_3072_PRIME,
+            Group::Modp4096 => MODP_4096_PRIME,
+            Group::Ffdhe2048 => FFDHE_2048_PRIME,
+            Group::Ffdhe3072 => FFDHE_3072_PRIME,
+            Group::Ffdhe4096 => FFDHE_4096_PRIME,
+        };
+        let modulus = BigUint::parse_bytes(prime.as_bytes(), 16).expect("group primes are valid hex");
+        DhParameters { generator: BigUint::from(2u32), modulus }
+    }
+}
+
+// The strongest group both peers support, or `None` if they have none in common. Each side lists the groups it
+// accepts, in any order; the choice doesn't depend on either side's preferences, so both ends arrive at the same group.
+// Between two groups the same size, FFDHE wins.
+pub fn negotiate_group(local: &[Group], remote: &[Group]) -> Option<Group> {
+    Group::ALL.into_iter().rev().find(|group| local.contains(group) && remote.contains(group))
+}
+
+// Lets a group be used wherever parameters are expected, e.g. `let params: DhParameters = Group::Modp2048.into();`.
+impl From<Group> for DhParameters {
+    fn from(group: Group) -> Self {
+        group.parameters()
+    }
+}
+
+// (generator, modulus) pairs. These always fail with `DhError::InsecureModulus`, which is the point: small integer
+// parameters can't slip into production code by accident. Use `DhParameters::from_u64_allow_insecure` deliberately.
+impl TryFrom<(u32, u32)> for DhParameters {
+    type Error = DhError;
+
+    fn try_from((generator, modulus): (u32, u32)) -> Result<Self, DhError> {
+        DhParameters::new(BigUint::from(generator), BigUint::from(modulus))
+    }
+}
+
+impl TryFrom<(u64, u64)> for DhParameters {
+    type Error = DhError;
+
+    fn try_from((generator, modulus): (u64, u64)) -> Result<Self, DhError> {
+        DhParameters::new(BigUint::from(generator), BigUint::from(modulus))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An odd modulus of exactly `bits` bits. Only its size matters to the estimates, so it needn't be prime.
+    fn modulus_of_size(bits: u64) -> DhParameters {
+        DhParameters::new_allow_insecure(BigUint::from(2u32), (BigUint::from(1u32) << (bits - 1)) + 1u32).unwrap()
+    }
+
+    #[test]
+    fn standard_groups_map_to_their_security_level() {
+        let expected = [
+            (Group::Modp2048, 112),
+            (Group::Modp3072, 128),
+            (Group::Modp4096, 128),
+            (Group::Ffdhe2048, 112),
+            (Group::Ffdhe3072, 128),
+            (Group::Ffdhe4096, 128),
+        ];
+        for (group, bits) in expected {
+            assert_eq!(group.parameters().security_bits(), bits, "{group:?}");
+        }
+    }
+
+    #[test]
+    fn security_bits_follow_sp_800_57() {
+        for (modulus_bits, security_bits) in [(1024, 80), (2047, 80), (7680, 192), (15360, 256)] {
+            assert_eq!(modulus_of_size(modulus_bits).security_bits(), security_bits, "{modulus_bits}-bit modulus");
+        }
+    }
+
+    #[test]
+    fn toy_moduli_have_next_to_no_security() {
+        assert_eq!(DhParameters::from_u64_allow_insecure(5, 23).unwrap().security_bits(), 0);
+        let small = modulus_of_size(512).security_bits();
+        assert!(small > 0 && small < 80, "{small}");
+    }
+
+    #[test]
+    fn groups_convert_into_parameters() {
+        for (group, bits) in Group::ALL.into_iter().zip([2048, 2048, 3072, 3072, 4096, 4096]) {
+            let params: DhParameters = group.into();
+            assert_eq!(params.bit_length(), bits, "{group:?}");
+            assert_eq!(params.generator, BigUint::from(2u32));
+            assert!(params.is_compatible_with(&group.parameters()));
+        }
+    }
+
+    #[test]
+    fn check_passes_standard_groups() {
+        let report = Group::Ffdhe2048.parameters().check();
+        assert_eq!(
+            report,
+            ParameterReport {
+                modulus_is_prime: true,
+                modulus_is_safe_prime: true,
+                generator_in_range: true,
+                generator_order_verified: true,
+                security_bits: 112,
+            }
+        );
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn check_reports_each_failure() {
+        let composite = DhParameters::from_u64_allow_insecure(2, 91).unwrap().check();
+        assert!(!composite.modulus_is_prime && !composite.generator_order_verified && !composite.passed());
+
+        // 29 is prime but 14 isn't, so the order of the generator can't be confirmed.
+        let not_safe = DhParameters::from_u64_allow_insecure(2, 29).unwrap().check();
+        assert!(not_safe.modulus_is_prime && !not_safe.modulus_is_safe_prime);
+        assert!(!not_safe.generator_order_verified && !not_safe.passed());
+
+        let bad_generator = DhParameters { generator: BigUint::from(22u32), modulus: BigUint::from(23u32) }.check();
+        assert!(bad_generator.modulus_is_safe_prime && !bad_generator.generator_in_range && !bad_generator.passed());
+
+        let toy = DhParameters::from_u64_allow_insecure(5, 23).unwrap().check();
+        assert!(toy.passed());
+        assert_eq!(toy.security_bits, 0);
+    }
+
+    #[test]
+    fn valid_generators_pass() {
+        let ffdhe = Group::Ffdhe2048.parameters();
+        assert!(is_valid_generator(&BigUint::from(2u32), &ffdhe.modulus));
+        // Modulo 23, 2 generates the subgroup of order 11 and 5 the whole group of order 22.
+        assert!(is_valid_generator(&BigUint::from(2u32), &BigUint::from(23u32)));
+        assert!(is_valid_generator(&BigUint::from(5u32), &BigUint::from(23u32)));
+    }
+
+    #[test]
+    fn trivial_generators_and_unsafe_moduli_fail() {
+        let modulus = BigUint::from(23u32);
+        for generator in [0u32, 1, 22, 23, 24] {
+            assert!(!is_valid_generator(&BigUint::from(generator), &modulus), "{generator}");
+        }
+        // The classroom example: 57 = 3 * 19 isn't prime at all.
+        assert!(!is_valid_generator(&BigUint::from(5u32), &BigUint::from(57u32)));
+        assert!(!DhParameters::from_u64_allow_insecure(5, 57).unwrap().check().generator_order_verified);
+    }
+
+    #[test]
+    fn moduli_of_two_or_less_are_rejected() {
+        for modulus in [0u64, 1, 2] {
+            let result = DhParameters::from_u64_allow_insecure(2, modulus);
+            assert!(matches!(result, Err(DhError::ModulusTooSmall)), "{modulus}");
+            assert!(matches!(
+                DhParameters::new_allow_insecure(BigUint::from(5u32), BigUint::from(modulus)),
+                Err(DhError::ModulusTooSmall)
+            ));
+        }
+        DhParameters::from_u64_allow_insecure(2, 5).unwrap();
+    }
+
+    #[test]
+    fn new_enforces_the_minimum_size_at_the_boundary() {
+        let just_short = (BigUint::from(1u32) << (MIN_SECURE_BITS - 1)) - 1u32;
+        assert_eq!(just_short.bits(), 2047);
+        assert!(matches!(
+            DhParameters::new(BigUint::from(2u32), just_short.clone()),
+            Err(DhError::InsecureModulus { bits: 2047, min_bits: MIN_SECURE_BITS })
+        ));
+        let just_enough = (BigUint::from(1u32) << (MIN_SECURE_BITS - 1)) + 1u32;
+        assert_eq!(just_enough.bits(), 2048);
+        assert_eq!(DhParameters::new(BigUint::from(2u32), just_enough).unwrap().bit_length(), 2048);
+        // The insecure path still takes the short one.
+        DhParameters::new_allow_insecure(BigUint::from(2u32), just_short).unwrap();
+    }
+
+    #[test]
+    fn is_safe_prime_needs_both_p_and_q_prime() {
+        for modulus in [23u64, 227, 1019, 2_147_483_783] {
+            assert!(DhParameters::from_u64_allow_insecure(2, modulus).unwrap().is_safe_prime(), "{modulus}");
+        }
+        assert!(Group::Ffdhe2048.parameters().is_safe_prime());
+        // 13, 29, 97 and 1013 are prime, but (p-1)/2 is 6, 14, 48 and 506. And 15 isn't prime itself, even though 7 is.
+        for modulus in [13u64, 29, 97, 1013, 15] {
+            assert!(!DhParameters::from_u64_allow_insecure(2, modulus).unwrap().is_safe_prime(), "{modulus}");
+        }
+    }
+
+    #[test]
+    fn accessors_return_the_group_values() {
+        let params = Group::Modp2048.parameters();
+        assert_eq!(params.generator(), &BigUint::from(2u32));
+        let modulus = params.modulus().to_str_radix(16);
+        assert!(modulus.starts_with("ffffffffffffffffc90fdaa22168c234"), "{modulus}");
+        assert!(modulus.ends_with("ffffffffffffffff"), "{modulus}");
+        assert_eq!(params.bit_length(), 2048);
+
+        for (group, bits) in Group::ALL.into_iter().zip([2048, 2048, 3072, 3072, 4096, 4096]) {
+            assert_eq!(group.parameters().bit_length(), bits, "{group:?}");
+        }
+
+        let toy = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        assert_eq!(toy.generator(), &BigUint::from(5u32));
+        assert_eq!(toy.modulus(), &BigUint::from(23u32));
+        assert_eq!(toy.bit_length(), 5);
+    }
+
+    #[test]
+    fn small_integer_moduli_need_the_insecure_constructor() {
+        let from_u32 = DhParameters::try_from((5u32, 23u32));
+        assert!(matches!(from_u32, Err(DhError::InsecureModulus { bits: 5, min_bits: MIN_SECURE_BITS })));
+        // Even the largest u64 is far below the minimum, so the integer conversions never succeed.
+        let from_u64 = DhParameters::try_from((2u64, 18_446_744_073_709_551_557u64));
+        assert!(matches!(from_u64, Err(DhError::InsecureModulus { bits: 64, .. })));
+
+        let insecure = DhParameters::from_u64_allow_insecure(5, 23).unwrap();
+        assert_eq!((insecure.generator(), insecure.modulus()), (&BigUint::from(5u32), &BigUint::from(23u32)));
+
+        let ffdhe = Group::Ffdhe2048.parameters();
+        let large = DhParameters::new(ffdhe.generator().clone(), ffdhe.modulus().clone()).unwrap();
+        assert_eq!(large, ffdhe);
+    }
+
+    #[test]
+    fn compatibility_needs_the_same_generator_and_modulus() {
+        let ffdhe = Group::Ffdhe2048.parameters();
+        let rebuilt = DhParameters::new_allow_insecure(ffdhe.generator.clone(), ffdhe.modulus.clone()).unwrap();
+        assert!(ffdhe.is_compatible_with(&rebuilt));
+        assert!(rebuilt.is_compatible_with(&ffdhe));
+
+        let other_generator = DhParameters::new_allow_insecure(BigUint::from(5u32), ffdhe.modulus.clone()).unwrap();
+        assert!(!ffdhe.is_compatible_with(&other_generator));
+        assert!(!ffdhe.is_compatible_with(&Group::Modp2048.parameters()));
+        assert!(!Group::Modp2048.parameters().is_compatible_with(&ffdhe));
+
+        let toy = DhParameters::new_allow_insecure(BigUint::from(5u32), BigUint::from(23u32)).unwrap();
+        let toy_other_modulus = DhParameters::new_allow_insecure(BigUint::from(5u32), BigUint::from(47u32)).unwrap();
+        assert!(toy.is_compatible_with(&toy.clone()));
+        assert!(!toy.is_compatible_with(&toy_other_modulus));
+    }
+
+    #[test]
+    fn trivial_generators_are_rejected() {
+        for generator in [0u64, 1, 22, 23, 24, 1000] {
+            let result = DhParameters::from_u64_allow_insecure(generator, 23);
+            assert!(matches!(result, Err(DhError::InvalidGenerator)), "g = {generator}");
+        }
+        assert!(DhParameters::from_u64_allow_insecure(2, 23).is_ok());
+        assert!(DhParameters::from_u64_allow_insecure(21, 23).is_ok());
+
+        let modulus = Group::Ffdhe2048.parameters().modulus;
+        for generator in [BigUint::from(0u32), BigUint::from(1u32), &modulus - 1u32, modulus.clone(), &modulus + 2u32] {
+            assert!(matches!(DhParameters::new(generator, modulus.clone()), Err(DhError::InvalidGenerator)));
+        }
+        assert!(matches!(DhParameters::from_u64_allow_insecure(1, 2), Err(DhError::ModulusTooSmall)));
+    }
+
+    #[test]
+    fn negotiation_picks_the_strongest_common_group() {
+        use Group::*;
+        let (local, remote) = ([Modp2048, Ffdhe3072, Modp4096], [Ffdhe3072, Modp2048, Ffdhe4096]);
+        assert_eq!(negotiate_group(&local, &remote), Some(Ffdhe3072));
+        assert_eq!(negotiate_group(&[Ffdhe2048, Modp2048], &[Modp2048, Ffdhe2048]), Some(Ffdhe2048));
+        assert_eq!(negotiate_group(&Group::ALL, &Group::ALL), Some(Ffdhe4096));
+        assert_eq!(negotiate_group(&[Modp3072], &[Modp3072]), Some(Modp3072));
+        // The order each side lists its groups in doesn't matter, and both ends agree.
+        let (local, remote) = ([Modp4096, Modp2048, Ffdhe2048], [Ffdhe2048, Modp4096]);
+        assert_eq!(negotiate_group(&local, &remote), Some(Modp4096));
+        assert_eq!(negotiate_group(&remote, &local), Some(Modp4096));
+    }
+
+    #[test]
+    fn negotiation_without_a_common_group_fails() {
+        use Group::*;
+        assert_eq!(negotiate_group(&[Modp2048, Modp3072], &[Ffdhe2048, Ffdhe3072]), None);
+        assert_eq!(negotiate_group(&[], &Group::ALL), None);
+        assert_eq!(negotiate_group(&Group::ALL, &[]), None);
+    }
+
+    #[test]
+    fn named_constructors_give_the_standard_groups() {
+        let constructed = [
+            (DhParams::modp_2048(), Group::Modp2048, 2048),
+            (DhParams::modp_3072(), Group::Modp3072, 3072),
+            (DhParams::modp_4096(), Group::Modp4096, 4096),
+            (DhParams::ffdhe2048(), Group::Ffdhe2048, 2048),
+            (DhParams::ffdhe3072(), Group::Ffdhe3072, 3072),
+            (DhParams::ffdhe4096(), Group::Ffdhe4096, 4096),
+        ];
+        for (params, group, bits) in constructed {
+            assert_eq!(params, group.parameters());
+            assert_eq!(params, DhParameters::from(group));
+            assert_eq!(params.bit_length(), bits);
+            assert_eq!(params.generator, BigUint::from(2u32));
+        }
+    }
+}
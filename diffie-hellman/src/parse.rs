@@ -0,0 +1,56 @@
+// Parsing big numbers from the textual forms parameters turn up in: plain decimal, 0x-prefixed hex, and hex split
+// over several lines or groups the way RFCs and PEM-style dumps print long primes.
+
+use num_bigint::BigUint;
+
+use crate::error::DhError;
+
+// Parse a non-negative integer. All whitespace is ignored first, so multi-line input is fine. Then:
+// - a `0x` or `0X` prefix means hex,
+// - a string of only decimal digits is decimal,
+// - any other string of hex digits (e.g. an RFC prime written without a prefix) is hex.
+// Anything else, including an empty string, is rejected.
+pub fn parse_biguint(s: &str) -> Result<BigUint, DhError> {
+    let digits: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let (digits, radix) = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None if digits.chars().all(|c| c.is_ascii_digit()) => (digits.as_str(), 10),
+        None => (digits.as_str(), 16),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(DhError::InvalidNumber);
+    }
+    BigUint::parse_bytes(digits.as_bytes(), radix).ok_or(DhError::InvalidNumber)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal() {
+        assert_eq!(parse_biguint("57").unwrap(), BigUint::from(57u32));
+        assert_eq!(parse_biguint(" 18446744073709551616\n").unwrap(), BigUint::from(1u32) << 64);
+    }
+
+    #[test]
+    fn prefixed_hex() {
+        assert_eq!(parse_biguint("0x39").unwrap(), BigUint::from(57u32));
+        assert_eq!(parse_biguint("0XfF").unwrap(), BigUint::from(255u32));
+    }
+
+    #[test]
+    fn multi_line_hex_without_prefix() {
+        let rfc_style = "FFFFFFFF FFFFFFFF\n    C90FDAA2 2168C234\r\n";
+        let expected = BigUint::parse_bytes(b"FFFFFFFFFFFFFFFFC90FDAA22168C234", 16).unwrap();
+        assert_eq!(parse_biguint(rfc_style).unwrap(), expected);
+        assert_eq!(parse_biguint("0x 12\n34").unwrap(), BigUint::from(0x1234u32));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        for input in ["", "   \n", "0x", "12g4", "0x12g4", "-5", "1.5", "0o17", "ff ff!"] {
+            assert!(matches!(parse_biguint(input), Err(DhError::InvalidNumber)), "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,347 @@
+// One side of the exchange as a single stateful value: the parameters, this party's keypair and, once the peer's key
+// has arrived, the agreed secret. `reset` starts over with a fresh keypair, so a server can keep one per pooled
+// connection instead of building a new one for every handshake. Parties built from an `ExchangeConfig` can keep a
+// static private key across resets and encrypt with the configured cipher.
+
+use num_bigint::BigUint;
+
+use crate::cipher::{decrypt_with_mode, encrypt_with_mode, CipherMode};
+use crate::config::Cipher;
+use crate::error::DhError;
+use crate::etm::{open, seal};
+use crate::gcm::{decrypt_gcm, encrypt_gcm};
+use crate::kdf::{derive_key_with_hash, HkdfHash};
+use crate::keys::{generate_keypair, generate_keypair_sized, PrivateKey, PublicKey, SharedSecret};
+use crate::params::{DhParameters, Group, MIN_SECURE_BITS};
+
+// The parameters of the original demo, g = 5 and p = 57. Used only by `DiffieHellman::insecure_demo`.
+const DEMO_BASE: u32 = 5;
+const DEMO_MODULUS: u32 = 57;
+
+// The name other projects tend to look for: one party of the exchange, with `new`, `public_key` and
+// `derive_shared_secret`.
+pub type DhParty = DiffieHellman;
+
+pub struct DiffieHellman {
+    // Whether this is the insecure demo party: no peer key validation and ECB encryption.
+    insecure_demo: bool,
+    cipher: Cipher,
+    kdf_hash: HkdfHash,
+    // Whether the private key is a configured static one, which `reset` keeps.
+    static_key: bool,
+    // The private exponent size set with `with_exponent_bits`, or `None` for the group's recommended size.
+    exponent_bits: Option<u64>,
+    params: DhParameters,
+    private_key: PrivateKey,
+    public_key: PublicKey,
+    secret: Option<SharedSecret>,
+}
+
+impl DiffieHellman {
+    // A party with a freshly generated keypair for `params`. Moduli shorter than `MIN_SECURE_BITS` are rejected with
+    // `DhError::InsecureModulus` even if the parameters were built with `DhParameters::new_allow_insecure`.
+    pub fn new(params: DhParameters) -> Result<Self, DhError> {
+        Self::configured(params, Cipher::default(), HkdfHash::default(), None)
+    }
+
+    // A party with settings that are safe without knowing anything about them: the 3072-bit FFDHE group (about 128
+    // bits of security, matching the AES-128 keys), AES-128-GCM and HKDF-SHA256. Start here unless the peer needs
+    // something else. This is FFDHE-3072 even with the `x25519` feature: a `DiffieHellman` is built on finite-field
+    // parameters and keys, which X25519 keys don't fit. For X25519, use `X25519Dh`, or `AnyKeyExchange` to choose
+    // the algorithm at run time.
+    pub fn secure_default() -> Result<Self, DhError> {
+        Self::configured(Group::Ffdhe3072.into(), Cipher::Aes128Gcm, HkdfHash::Sha256, None)
+    }
+
+    // A party as described by an `ExchangeConfig`. A static private key is checked with `static_keypair`.
+    pub(crate) fn configured(
+        params: DhParameters,
+        cipher: Cipher,
+        kdf_hash: HkdfHash,
+        static_private_key: Option<PrivateKey>,
+    ) -> Result<Self, DhError> {
+        let bits = params.bit_length() as usize;
+        if bits < MIN_SECURE_BITS {
+            return Err(DhError::InsecureModulus { bits, min_bits: MIN_SECURE_BITS });
+        }
+        let static_key = static_private_key.is_some();
+        let (private_key, public_key) = match static_private_key {
+            Some(private_key) => static_keypair(&params, private_key)?,
+            None => generate_keypair(&params)?,
+        };
+        Ok(DiffieHellman {
+            insecure_demo: false,
+            cipher,
+            kdf_hash,
+            static_key,
+            exponent_bits: None,
+            params,
+            private_key,
+            public_key,
+            secret: None,
+        })
+    }
+
+    // INSECURE, for demonstration only. A party with the crate's original demo settings: g = 5, the 6-bit composite
+    // modulus p = 57, no validation of the peer's key, and AES in ECB mode keyed with the truncated secret. Anyone can
+    // recover the secret by trying all 57 values. This exists to show the exchange working end to end on numbers
+    // small enough to follow by hand; `audit` lists everything that is wrong with it.
+    pub fn insecure_demo() -> Self {
+        let params = DhParameters::new_allow_insecure(BigUint::from(DEMO_BASE), BigUint::from(DEMO_MODULUS))
+            .expect("the demo parameters are well-formed");
+        let (private_key, public_key) = generate_keypair(&params).expect("the demo group has usable keys");
+        DiffieHellman {
+            insecure_demo: true,
+            cipher: Cipher::default(),
+            kdf_hash: HkdfHash::default(),
+            static_key: false,
+            exponent_bits: None,
+            params,
+            private_key,
+            public_key,
+            secret: None,
+        }
+    }
+
+    // Use private exponents of `bits` bits instead of the group's recommended size, starting with a fresh keypair
+    // now, and on every `reset`. Sizes below `DhParameters::recommended_exponent_bits` are
+    // `DhError::ExponentTooShort`. A static private key is kept as it is.
+    pub fn with_exponent_bits(mut self, bits: u64) -> Result<Self, DhError> {
+        if !self.static_key {
+            let (private_key, public_key) = generate_keypair_sized(&self.params, bits)?;
+            self.private_key = private_key;
+            self.public_key = public_key;
+        }
+        self.exponent_bits = Some(bits);
+        Ok(self)
+    }
+
+    pub fn params(&self) -> &DhParameters {
+        &self.params
+    }
+
+    // The public key to send to the peer.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    // Validate the peer's key and agree on the shared secret, replacing any earlier one. A peer key equal to our own is
+    // refused with `DhError::IdenticalPublicKeys`, see `handshake`. The demo party skips these checks: its composite
+    // modulus couldn't pass validation, and with so few keys collisions are expected.
+    pub fn agree(&mut self, peer_public: &PublicKey) -> Result<&SharedSecret, DhError> {
+        if !self.insecure_demo {
+            peer_public.validate_in_group(&self.params)?;
+            if *peer_public == self.public_key {
+                return Err(DhError::IdenticalPublicKeys);
+            }
+        }
+        Ok(self.secret.insert(self.private_key.agree(peer_public, &self.params)))
+    }
+
+    // Another name for `agree`.
+    pub fn derive_shared_secret(&mut self, peer_public: &PublicKey) -> Result<&SharedSecret, DhError> {
+        self.agree(peer_public)
+    }
+
+    // The secret agreed by the last successful `agree`, if there was one since the last reset.
+    pub fn shared_secret(&self) -> Option<&SharedSecret> {
+        self.secret.as_ref()
+    }
+
+    // Derive a key of `len` bytes for the purpose named by `label` from the agreed secret, with HKDF over the
+    // configured hash. Fails with `DhError::NoSharedSecret` before `agree`.
+    pub fn derive_key(&self, label: &str, len: usize) -> Result<Vec<u8>, DhError> {
+        let secret = self.secret.as_ref().ok_or(DhError::NoSharedSecret)?.as_biguint();
+        Ok(derive_key_with_hash(secret, label, self.kdf_hash, len))
+    }
+
+    // Encrypt a message with the agreed secret using the configured cipher (AES-128-GCM unless configured otherwise),
+    // or ECB for the demo party. Fails with `DhError::NoSharedSecret` before `agree`.
+    pub fn encrypt(&self, plain_text: &str) -> Result<Vec<u8>, DhError> {
+        let secret = self.secret.as_ref().ok_or(DhError::NoSharedSecret)?.as_biguint();
+        if self.insecure_demo {
+            return encrypt_with_mode(plain_text.as_bytes(), secret, CipherMode::Ecb, &[]);
+        }
+        match self.cipher {
+            Cipher::Aes128Gcm => encrypt_gcm(plain_text.as_bytes(), secret),
+            Cipher::Aes128CbcHmac => Ok(seal(plain_text, secret)),
+        }
+    }
+
+    // Decrypt a message from a peer using the same kind of party.
+    pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<String, DhError> {
+        let secret = self.secret.as_ref().ok_or(DhError::NoSharedSecret)?.as_biguint();
+        if self.insecure_demo {
+            let plain_bytes = decrypt_with_mode(encrypted_data, secret, CipherMode::Ecb, &[])?;
+            return String::from_utf8(plain_bytes).map_err(|_| DhError::InvalidUtf8);
+        }
+        match self.cipher {
+            Cipher::Aes128Gcm => {
+                let plain_bytes = decrypt_gcm(encrypted_data, secret)?;
+                String::from_utf8(plain_bytes).map_err(|_| DhError::InvalidUtf8)
+            }
+            Cipher::Aes128CbcHmac => open(encrypted_data, secret),
+        }
+    }
+
+    // Get ready for a new handshake with the same parameters: generate a new keypair and forget the agreed secret.
+    // A static private key is kept. On failure the party keeps its old keypair but still drops the secret.
+    pub fn reset(&mut self) -> Result<(), DhError> {
+        self.secret = None;
+        if self.static_key {
+            return Ok(());
+        }
+        let (private_key, public_key) = match self.exponent_bits {
+            Some(bits) => generate_keypair_sized(&self.params, bits)?,
+            None => generate_keypair(&self.params)?,
+        };
+        self.private_key = private_key;
+        self.public_key = public_key;
+        Ok(())
+    }
+}
+
+// The keypair for a configured static private key. A key outside [2, q-1], or one whose public key is 1 or p-1 and so
+// would pin the shared secret, is rejected with `DhError::InvalidPrivateKey`.
+fn static_keypair(params: &DhParameters, private_key: PrivateKey) -> Result<(PrivateKey, PublicKey), DhError> {
+    private_key.validate(params)?;
+    let public_key = private_key.public_key(params);
+    if !public_key.is_nontrivial(params) {
+        return Err(DhError::InvalidPrivateKey);
+    }
+    Ok((private_key, public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExchangeConfig;
+
+    fn agreed_pair() -> (DiffieHellman, DiffieHellman) {
+        let mut alice = DiffieHellman::new(Group::Ffdhe2048.into()).unwrap();
+        let mut bob = DiffieHellman::new(Group::Ffdhe2048.into()).unwrap();
+        let alice_public = alice.public_key().clone();
+        alice.agree(&bob.public_key().clone()).unwrap();
+        bob.agree(&alice_public).unwrap();
+        (alice, bob)
+    }
+
+    #[test]
+    fn reset_gives_a_fresh_key_for_a_new_agreement() {
+        let (mut alice, mut bob) = agreed_pair();
+        let old_public = alice.public_key().clone();
+        let old_key = alice.derive_key("test", 16).unwrap();
+
+        alice.reset().unwrap();
+        assert_ne!(alice.public_key(), &old_public);
+        assert!(alice.shared_secret().is_none());
+        assert!(matches!(alice.derive_key("test", 16), Err(DhError::NoSharedSecret)));
+
+        let alice_public = alice.public_key().clone();
+        alice.agree(&bob.public_key().clone()).unwrap();
+        bob.agree(&alice_public).unwrap();
+        assert!(alice.shared_secret() == bob.shared_secret());
+        assert_eq!(alice.derive_key("test", 16).unwrap(), bob.derive_key("test", 16).unwrap());
+        assert_ne!(alice.derive_key("test", 16).unwrap(), old_key);
+    }
+
+    #[test]
+    fn reset_keeps_a_static_key() {
+        let mut config = ExchangeConfig::new(Group::Ffdhe2048.into());
+        config.static_private_key = Some(PrivateKey::from_bytes_be(&[0x42; 28]));
+        let mut party = config.party().unwrap();
+        let public_key = party.public_key().clone();
+        party.agree(&DiffieHellman::new(Group::Ffdhe2048.into()).unwrap().public_key().clone()).unwrap();
+        party.reset().unwrap();
+        assert_eq!(party.public_key(), &public_key);
+        assert!(party.shared_secret().is_none());
+    }
+
+    // Over g = 3, p = 13 the private keys are 2 to 5, and 3^3 = 1 mod 13, so one key in four has a trivial public key.
+    #[test]
+    fn static_keys_with_trivial_public_keys_are_rejected() {
+        let params = DhParameters::new_allow_insecure(BigUint::from(3u32), BigUint::from(13u32)).unwrap();
+        let mut rejected = 0;
+        for key in 2u32..=5 {
+            match static_keypair(&params, PrivateKey::from_biguint(BigUint::from(key))) {
+                Ok((_, public_key)) => assert!(public_key.is_nontrivial(&params)),
+                Err(DhError::InvalidPrivateKey) => rejected += 1,
+                Err(other) => panic!("unexpected error {other}"),
+            }
+        }
+        assert_eq!(rejected, 1);
+        for key in [0u32, 1, 6] {
+            let result = static_keypair(&params, PrivateKey::from_biguint(BigUint::from(key)));
+            assert!(matches!(result, Err(DhError::InvalidPrivateKey)));
+        }
+    }
+
+    // Two parties given the same private key, as a broken random number generator would, see each other's key as their
+    // own.
+    #[test]
+    fn identical_keys_are_flagged() {
+        let mut config = ExchangeConfig::new(Group::Ffdhe2048.into());
+        config.static_private_key = Some(PrivateKey::from_bytes_be(&[0x42; 28]));
+        let mut alice = config.party().unwrap();
+        let mut bob = config.party().unwrap();
+        let bob_public = bob.public_key().clone();
+        let alice_public = alice.public_key().clone();
+        assert!(matches!(alice.agree(&bob_public), Err(DhError::IdenticalPublicKeys)));
+        assert!(matches!(bob.agree(&alice_public), Err(DhError::IdenticalPublicKeys)));
+        assert!(alice.shared_secret().is_none());
+
+        // The demo party's group is too small for the check to mean anything.
+        let mut demo = DiffieHellman::insecure_demo();
+        let demo_public = demo.public_key().clone();
+        assert!(demo.agree(&demo_public).is_ok());
+    }
+
+    #[test]
+    fn secure_default_is_ffdhe3072_whatever_the_features() {
+        let party = DiffieHellman::secure_default().unwrap();
+        assert_eq!(party.params(), &Group::Ffdhe3072.parameters());
+        assert_eq!(party.params().bit_length(), 3072);
+        assert_eq!((party.cipher, party.kdf_hash), (Cipher::Aes128Gcm, HkdfHash::Sha256));
+        assert!(!party.insecure_demo && !party.static_key);
+    }
+
+    #[test]
+    fn secure_default_round_trips_with_authenticated_encryption() {
+        let mut alice = DiffieHellman::secure_default().unwrap();
+        let mut bob = DiffieHellman::secure_default().unwrap();
+        assert_eq!(alice.params(), &Group::Ffdhe3072.parameters());
+        let alice_public = alice.public_key().clone();
+        alice.agree(&bob.public_key().clone()).unwrap();
+        bob.agree(&alice_public).unwrap();
+
+        let message = alice.encrypt("secure by default").unwrap();
+        assert_eq!(bob.decrypt(&message).unwrap(), "secure by default");
+        // It is AES-GCM: the message opens with `decrypt_gcm`, and any change to it is caught.
+        let secret = bob.shared_secret().unwrap().as_biguint();
+        assert_eq!(decrypt_gcm(&message, secret).unwrap(), b"secure by default");
+        let mut tampered = message.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(bob.decrypt(&tampered), Err(DhError::DecryptionFailed)));
+        tampered = message;
+        tampered[15] ^= 1;
+        assert!(matches!(bob.decrypt(&tampered), Err(DhError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn demo_parties_round_trip_a_message() {
+        let mut alice = DiffieHellman::insecure_demo();
+        let mut bob = DiffieHellman::insecure_demo();
+        assert_eq!(alice.params().modulus(), &BigUint::from(DEMO_MODULUS));
+        let alice_public = alice.public_key().clone();
+        alice.agree(&bob.public_key().clone()).unwrap();
+        bob.agree(&alice_public).unwrap();
+        assert!(alice.shared_secret() == bob.shared_secret());
+
+        let text = "sixteen bytes...sixteen bytes...";
+        let message = alice.encrypt(text).unwrap();
+        assert_eq!(bob.decrypt(&message).unwrap(), text);
+        assert_eq!(alice.decrypt(&bob.encrypt("and back").unwrap()).unwrap(), "and back");
+        // It is ECB: equal plaintext blocks give equal ciphertext blocks.
+        assert_eq!(message[..16], message[16..32]);
+    }
+}
@@ -0,0 +1,131 @@
+// Hybrid post-quantum key exchange: X25519 and ML-KEM-768 (FIPS 203) run side by side, and the session key depends
+// on both. Traffic recorded today stays safe if a quantum computer later breaks X25519, as long as ML-KEM holds, and
+// stays safe if ML-KEM turns out to be flawed, as long as X25519 holds. The pairing is the one in TLS's X25519MLKEM768,
+// but the concatenated secrets go through HKDF here instead of being used as they are.
+//
+// The initiator sends an offer: its X25519 public key and an ML-KEM encapsulation key. The responder runs X25519
+// against it, encapsulates a second secret to the ML-KEM key, and replies with its X25519 public key and the ML-KEM
+// ciphertext. Both sides then compute HKDF-SHA256 with the X25519 secret || ML-KEM secret as input keying material
+// and a hash of both messages as info, so the key is also bound to exactly what was sent.
+//
+// Offer layout: X25519 public key (32 bytes) || ML-KEM-768 encapsulation key (1184 bytes).
+// Reply layout: X25519 public key (32 bytes) || ML-KEM-768 ciphertext (1088 bytes).
+
+use hkdf::Hkdf;
+use ml_kem::kem::{Decapsulate, DecapsulationKey, Encapsulate, EncapsulationKey};
+use ml_kem::{Ciphertext, EncodedSizeUser, KemCore, MlKem768, MlKem768Params};
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::key_exchange::KeyExchange;
+use crate::keys::SharedSecret;
+use crate::x25519::X25519Dh;
+
+const X25519_KEY_LEN: usize = 32;
+const ENCAPSULATION_KEY_LEN: usize = 1184;
+const CIPHERTEXT_LEN: usize = 1088;
+pub const HYBRID_OFFER_LEN: usize = X25519_KEY_LEN + ENCAPSULATION_KEY_LEN;
+pub const HYBRID_REPLY_LEN: usize = X25519_KEY_LEN + CIPHERTEXT_LEN;
+
+const HYBRID_SALT: &[u8] = b"diffie-hellman x25519 ml-kem-768 hybrid";
+
+// The initiator's half of a hybrid exchange, between sending the offer and receiving the reply.
+pub struct HybridInitiator {
+    x25519_secret: StaticSecret,
+    decapsulation_key: DecapsulationKey<MlKem768Params>,
+    offer: Vec<u8>,
+}
+
+// X25519 public key bytes from a message, rejecting nothing: low-order points are caught by `X25519Dh::agree`.
+fn x25519_public(bytes: &[u8]) -> X25519PublicKey {
+    X25519PublicKey::from(<[u8; X25519_KEY_LEN]>::try_from(bytes).expect("split at X25519_KEY_LEN"))
+}
+
+// HKDF over both secrets, with the two messages as context.
+fn combine(x25519_secret: &SharedSecret, kem_secret: &[u8], offer: &[u8], reply: &[u8]) -> SharedSecret {
+    let x25519_bytes = Zeroizing::new(x25519_secret.as_biguint().to_bytes_be());
+    // `SharedSecret` drops leading zeros; put them back so the input is always 32 + 32 bytes. The capacity is set up
+    // front so the buffer never reallocates and leaves copies behind.
+    let mut ikm = Zeroizing::new(Vec::with_capacity(X25519_KEY_LEN + kem_secret.len()));
+    ikm.resize(X25519_KEY_LEN - x25519_bytes.len(), 0);
+    ikm.extend_from_slice(&x25519_bytes);
+    ikm.extend_from_slice(kem_secret);
+    let context = Sha256::new().chain_update(offer).chain_update(reply).finalize();
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(HYBRID_SALT), &ikm)
+        .expand(&context, &mut key[..])
+        .expect("32 bytes is a valid HKDF output length");
+    SharedSecret::from_bytes(&key[..])
+}
+
+impl HybridInitiator {
+    // Start an exchange with fresh X25519 and ML-KEM keys.
+    pub fn new() -> Self {
+        Self::with_rng(&mut rand::thread_rng())
+    }
+
+    // `new` with the keys drawn from `rng`.
+    pub fn with_rng<R: CryptoRng + RngCore>(rng: &mut R) -> Self {
+        let (x25519_secret, x25519_public) = X25519Dh.keypair_with_rng(rng).expect("X25519 key generation can't fail");
+        let (decapsulation_key, encapsulation_key) = MlKem768::generate(rng);
+        let mut offer = x25519_public.as_bytes().to_vec();
+        offer.extend_from_slice(&encapsulation_key.as_bytes());
+        HybridInitiator { x25519_secret, decapsulation_key, offer }
+    }
+
+    // The offer to send to the responder, `HYBRID_OFFER_LEN` bytes.
+    pub fn offer(&self) -> &[u8] {
+        &self.offer
+    }
+
+    // Take the responder's reply and compute the shared secret. A reply of the wrong length is
+    // `DhError::MalformedMessage`, and a low-order X25519 key `ValidationError::LowOrderPoint`. A corrupted
+    // ciphertext isn't detected here: ML-KEM then yields an unrelated secret, so the two sides' keys differ.
+    pub fn finish(self, reply: &[u8]) -> Result<SharedSecret, DhError> {
+        if reply.len() != HYBRID_REPLY_LEN {
+            return Err(DhError::MalformedMessage);
+        }
+        let (peer_x25519, ciphertext) = reply.split_at(X25519_KEY_LEN);
+        let x25519_secret = X25519Dh.agree(&self.x25519_secret, &x25519_public(peer_x25519))?;
+        let ciphertext = Ciphertext::<MlKem768>::try_from(ciphertext).expect("ciphertext is CIPHERTEXT_LEN bytes");
+        let kem_secret = self.decapsulation_key.decapsulate(&ciphertext).expect("ML-KEM decapsulation can't fail");
+        Ok(combine(&x25519_secret, &kem_secret, &self.offer, reply))
+    }
+}
+
+impl Default for HybridInitiator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The responder's side: answer an offer from `HybridInitiator::offer` and get the shared secret together with the
+// reply to send back. An offer of the wrong length is `DhError::MalformedMessage`, and a low-order X25519 key
+// `ValidationError::LowOrderPoint`.
+pub fn hybrid_respond(offer: &[u8]) -> Result<(SharedSecret, Vec<u8>), DhError> {
+    hybrid_respond_with_rng(offer, &mut rand::thread_rng())
+}
+
+// `hybrid_respond` with the keys drawn from `rng`.
+pub fn hybrid_respond_with_rng<R: CryptoRng + RngCore>(
+    offer: &[u8],
+    rng: &mut R,
+) -> Result<(SharedSecret, Vec<u8>), DhError> {
+    if offer.len() != HYBRID_OFFER_LEN {
+        return Err(DhError::MalformedMessage);
+    }
+    let (peer_x25519, encapsulation_key) = offer.split_at(X25519_KEY_LEN);
+    let (x25519_private, x25519_public_key) = X25519Dh.keypair_with_rng(rng)?;
+    let x25519_secret = X25519Dh.agree(&x25519_private, &x25519_public(peer_x25519))?;
+    let encapsulation_key = EncapsulationKey::<MlKem768Params>::from_bytes(
+        &encapsulation_key.try_into().expect("encapsulation key is ENCAPSULATION_KEY_LEN bytes"),
+    );
+    let (ciphertext, kem_secret) = encapsulation_key.encapsulate(rng).expect("ML-KEM encapsulation can't fail");
+
+    let mut reply = x25519_public_key.as_bytes().to_vec();
+    reply.extend_from_slice(&ciphertext);
+    Ok((combine(&x25519_secret, &kem_secret, offer, &reply), reply))
+}
@@ -0,0 +1,143 @@
+// Probabilistic primality testing for user-supplied moduli: trial division by small primes, then Miller-Rabin rounds.
+// Also the search for fresh safe primes behind `DhParameters::generate`.
+
+#[cfg(feature = "std")]
+use alloc::vec;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+use num_bigint::{BigUint, RandBigInt};
+#[cfg(feature = "std")]
+use num_traits::ToPrimitive;
+use num_traits::{One, Zero};
+
+// Miller-Rabin rounds per test. Each round lets a composite through with probability at most 1/4.
+const MILLER_RABIN_ROUNDS: usize = 32;
+
+const SMALL_PRIMES: [u32; 25] =
+    [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97];
+
+// Whether `n` is prime, with an error probability of at most 4^-32 for composites.
+pub fn is_probable_prime(n: &BigUint) -> bool {
+    if *n < BigUint::from(2u32) {
+        return false;
+    }
+    for &p in SMALL_PRIMES.iter() {
+        let p = BigUint::from(p);
+        if *n == p {
+            return true;
+        }
+        if (n % &p).is_zero() {
+            return false;
+        }
+    }
+
+    // Write n - 1 as d * 2^s with d odd.
+    let one = BigUint::one();
+    let n_minus_one = n - &one;
+    let s = n_minus_one.trailing_zeros().expect("n - 1 is non-zero");
+    let d = &n_minus_one >> s;
+
+    let mut rng = witness_rng(n);
+    let two = BigUint::from(2u32);
+    'witness: for _ in 0..MILLER_RABIN_ROUNDS {
+        let a = rng.gen_biguint_range(&two, &n_minus_one);
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Where the Miller-Rabin witnesses come from. With `std`, fresh randomness for every test.
+#[cfg(feature = "std")]
+fn witness_rng(_n: &BigUint) -> rand::rngs::ThreadRng {
+    rand::thread_rng()
+}
+
+// Without `std` there is no generator to draw from, so the witnesses are derived from `n` itself, through a CSPRNG
+// seeded with its hash. Testing the same number always gives the same answer then. Someone searching for a composite
+// that passes can't pick the witnesses, only try numbers, and each one still gets through with probability at most
+// 4^-32, so it takes around 2^64 attempts.
+#[cfg(not(feature = "std"))]
+fn witness_rng(n: &BigUint) -> rand::rngs::StdRng {
+    use rand::SeedableRng;
+    use sha2::{Digest, Sha256};
+
+    let seed =
+        Sha256::new().chain_update(b"diffie-hellman miller-rabin witnesses").chain_update(n.to_bytes_be()).finalize();
+    rand::rngs::StdRng::from_seed(seed.into())
+}
+
+// Sieve bound for the safe-prime search. Candidates with a factor below it are skipped without any exponentiation.
+#[cfg(feature = "std")]
+const SIEVE_LIMIT: u32 = 1 << 14;
+
+// A random safe prime p = 2q + 1 of exactly `bits` bits, with q ≡ 3 (mod 4) so that p ≡ 7 (mod 8). For such p, 2 is
+// a quadratic residue and so generates the subgroup of prime order q. `bits` must be at least 16.
+//
+// The search picks a random odd q of `bits - 1` bits and walks upwards in steps of 4, keeping q's residues modulo the
+// primes below `SIEVE_LIMIT` up to date so that candidates where q or 2q + 1 has a small factor cost one addition per
+// prime. Survivors get a cheap Fermat test to base 2 on both numbers before the full Miller-Rabin tests.
+#[cfg(feature = "std")]
+pub(crate) fn random_safe_prime(bits: usize) -> BigUint {
+    assert!(bits >= 16, "safe prime search needs at least 16 bits");
+    let sieve_primes = primes_below(SIEVE_LIMIT);
+    let mut rng = rand::thread_rng();
+    let one = BigUint::one();
+    let two = BigUint::from(2u32);
+    'restart: loop {
+        let mut q = rng.gen_biguint(bits as u64 - 1);
+        q.set_bit(bits as u64 - 2, true);
+        q |= BigUint::from(3u32);
+        let mut residues: Vec<u32> =
+            sieve_primes.iter().map(|&r| (&q % r).to_u32().expect("residue below a u32 prime")).collect();
+        loop {
+            if q.bits() as usize != bits - 1 {
+                continue 'restart;
+            }
+            let sieved =
+                sieve_primes.iter().zip(&residues).all(|(&r, &residue)| residue != 0 && (2 * residue + 1) % r != 0);
+            if sieved {
+                let p = &q * 2u32 + 1u32;
+                if two.modpow(&(&q - 1u32), &q) == one
+                    && two.modpow(&(&p - 1u32), &p) == one
+                    && is_probable_prime(&q)
+                    && is_probable_prime(&p)
+                {
+                    return p;
+                }
+            }
+            q += 4u32;
+            for (residue, &r) in residues.iter_mut().zip(&sieve_primes) {
+                *residue = (*residue + 4) % r;
+            }
+        }
+    }
+}
+
+// The odd primes below `limit`, by the sieve of Eratosthenes.
+#[cfg(feature = "std")]
+fn primes_below(limit: u32) -> Vec<u32> {
+    let limit = limit as usize;
+    let mut composite = vec![false; limit];
+    let mut primes = Vec::new();
+    for n in (3..limit).step_by(2) {
+        if composite[n] {
+            continue;
+        }
+        primes.push(n as u32);
+        for multiple in (n * n..limit).step_by(n) {
+            composite[multiple] = true;
+        }
+    }
+    primes
+}
@@ -0,0 +1,237 @@
+// A symmetric-key ratchet, as in the Signal protocol's chains, for forward secrecy from one message to the next. Each
+// direction starts from a chain key derived from the agreed secret. Every message moves the chain on one step: HKDF
+// keyed on the chain key gives that message's AES-128-GCM key and the next chain key, and the old chain key is wiped.
+// Someone who later steals the state can't work backwards to the keys of messages already sent or received; only the
+// agreed secret itself, which the caller should drop once the ratchet is set up, gives those away.
+//
+// Messages may arrive out of order. When one arrives ahead of the next expected message, the keys of the messages it
+// skipped over are kept, so those can still be decrypted when they turn up, as long as they are no more than
+// MAX_SKIPPED_MESSAGES behind the newest message. Each message key is deleted once used, so replays fail.
+//
+// Message layout: counter (8 bytes, big-endian) || ciphertext || GCM tag (16 bytes). Every key encrypts exactly one
+// message, so the nonce is fixed at zero.
+
+use std::collections::BTreeMap;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::kdf::derive_key;
+use crate::keys::SharedSecret;
+use crate::nonce::NONCE_LEN;
+use crate::session::Role;
+
+const COUNTER_LEN: usize = 8;
+const CHAIN_KEY_LEN: usize = 32;
+const MESSAGE_KEY_LEN: usize = 16;
+
+// How far behind the newest received message an out-of-order message may be, and how far ahead of the next expected
+// message one may jump. This also bounds how many skipped message keys are held at once.
+pub const MAX_SKIPPED_MESSAGES: u64 = 64;
+
+type ChainKey = Zeroizing<[u8; CHAIN_KEY_LEN]>;
+type MessageKey = Zeroizing<[u8; MESSAGE_KEY_LEN]>;
+
+// The chain key for messages sent by `role`.
+fn initial_chain_key(secret: &SharedSecret, role: Role) -> ChainKey {
+    let key = Zeroizing::new(derive_key(secret.as_biguint(), &format!("{} ratchet chain key", role.label()), 32));
+    Zeroizing::new(key[..].try_into().expect("derived key is 32 bytes"))
+}
+
+// One step of a chain: the key for the current message and the chain key for the next one.
+fn step(chain_key: &ChainKey) -> (MessageKey, ChainKey) {
+    let hkdf = Hkdf::<Sha256>::from_prk(&chain_key[..]).expect("chain key is a full SHA-256 output");
+    let mut message_key = Zeroizing::new([0u8; MESSAGE_KEY_LEN]);
+    let mut next_chain_key = Zeroizing::new([0u8; CHAIN_KEY_LEN]);
+    hkdf.expand(b"ratchet message key", &mut message_key[..]).expect("16 bytes is a valid HKDF output length");
+    hkdf.expand(b"ratchet chain key", &mut next_chain_key[..]).expect("32 bytes is a valid HKDF output length");
+    (message_key, next_chain_key)
+}
+
+fn cipher(message_key: &MessageKey) -> Aes128Gcm {
+    Aes128Gcm::new_from_slice(&message_key[..]).expect("message key is 16 bytes")
+}
+
+pub struct RatchetState {
+    send_chain: ChainKey,
+    // The counter of the next message to send.
+    send_counter: u64,
+    receive_chain: ChainKey,
+    // The counter of the next message expected from the peer; everything below it has been received or skipped.
+    receive_counter: u64,
+    // Keys for skipped messages that haven't arrived yet, by counter.
+    skipped: BTreeMap<u64, MessageKey>,
+}
+
+impl RatchetState {
+    // Both ends pass the same secret and opposite roles.
+    pub fn new(secret: &SharedSecret, role: Role) -> Self {
+        RatchetState {
+            send_chain: initial_chain_key(secret, role),
+            send_counter: 0,
+            receive_chain: initial_chain_key(secret, role.peer()),
+            receive_counter: 0,
+            skipped: BTreeMap::new(),
+        }
+    }
+
+    // Encrypt the next outgoing message under a key of its own, and move the sending chain on.
+    pub fn encrypt(&mut self, plain_bytes: &[u8]) -> Result<Vec<u8>, DhError> {
+        let counter = self.send_counter;
+        let next_counter = counter.checked_add(1).ok_or(DhError::NonceExhausted)?;
+        let (message_key, next_chain) = step(&self.send_chain);
+        let counter_bytes = counter.to_be_bytes();
+        let ciphertext = cipher(&message_key)
+            .encrypt(Nonce::from_slice(&[0u8; NONCE_LEN]), Payload { msg: plain_bytes, aad: &counter_bytes })
+            .map_err(|_| DhError::EncryptionFailed)?;
+        self.send_chain = next_chain;
+        self.send_counter = next_counter;
+
+        let mut message = counter_bytes.to_vec();
+        message.extend_from_slice(&ciphertext);
+        Ok(message)
+    }
+
+    // Verify and decrypt an incoming message, in any order within the window. A message that has already been
+    // decrypted is `DhError::ReplayedMessage`, one more than MAX_SKIPPED_MESSAGES behind the newest
+    // `DhError::StaleMessage`, and one that would skip more than that many `DhError::TooManySkippedMessages`.
+    // Tampering is `DhError::DecryptionFailed`. The state only changes once a message has been authenticated, so none
+    // of these stop the genuine message from being decrypted later.
+    pub fn decrypt(&mut self, message: &[u8]) -> Result<Vec<u8>, DhError> {
+        if message.len() < COUNTER_LEN + TAG_LEN {
+            return Err(DhError::DecryptionFailed);
+        }
+        let (counter_bytes, ciphertext) = message.split_at(COUNTER_LEN);
+        let counter = u64::from_be_bytes(counter_bytes.try_into().expect("split at COUNTER_LEN"));
+        let open = |message_key: &MessageKey| {
+            cipher(message_key)
+                .decrypt(Nonce::from_slice(&[0u8; NONCE_LEN]), Payload { msg: ciphertext, aad: counter_bytes })
+                .map_err(|_| DhError::DecryptionFailed)
+        };
+
+        if counter < self.receive_counter {
+            let Some(message_key) = self.skipped.get(&counter) else {
+                if self.receive_counter - counter > MAX_SKIPPED_MESSAGES {
+                    return Err(DhError::StaleMessage { counter });
+                }
+                return Err(DhError::ReplayedMessage { counter });
+            };
+            let plain_bytes = open(message_key)?;
+            self.skipped.remove(&counter);
+            return Ok(plain_bytes);
+        }
+
+        let skipping = counter - self.receive_counter;
+        if skipping > MAX_SKIPPED_MESSAGES {
+            return Err(DhError::TooManySkippedMessages { skipping, max_skipping: MAX_SKIPPED_MESSAGES });
+        }
+        let next_counter = counter.checked_add(1).ok_or(DhError::NonceExhausted)?;
+        // Walk the chain on a copy, so a forged counter can't advance it.
+        let mut chain = self.receive_chain.clone();
+        let mut skipped_keys = Vec::new();
+        for skipped_counter in self.receive_counter..counter {
+            let (message_key, next_chain) = step(&chain);
+            skipped_keys.push((skipped_counter, message_key));
+            chain = next_chain;
+        }
+        let (message_key, next_chain) = step(&chain);
+        let plain_bytes = open(&message_key)?;
+
+        self.receive_chain = next_chain;
+        self.receive_counter = next_counter;
+        self.skipped.extend(skipped_keys);
+        // Forget the keys of skipped messages that have fallen out of the window.
+        let oldest = next_counter.saturating_sub(MAX_SKIPPED_MESSAGES);
+        self.skipped = self.skipped.split_off(&oldest);
+        Ok(plain_bytes)
+    }
+
+    // How many skipped messages can still be decrypted if they arrive.
+    pub fn pending_skipped(&self) -> usize {
+        self.skipped.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratchet_pair() -> (RatchetState, RatchetState) {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        (RatchetState::new(&secret, Role::Initiator), RatchetState::new(&secret, Role::Responder))
+    }
+
+    #[test]
+    fn out_of_order_messages_decrypt_once() {
+        let (mut alice, mut bob) = ratchet_pair();
+        let messages: Vec<_> = (0..5u8).map(|i| alice.encrypt(&[i; 10]).unwrap()).collect();
+        assert_eq!(bob.decrypt(&messages[3]).unwrap(), [3; 10]);
+        assert_eq!(bob.pending_skipped(), 3);
+        for i in [0, 2, 4, 1] {
+            assert_eq!(bob.decrypt(&messages[i]).unwrap(), [i as u8; 10]);
+        }
+        assert_eq!(bob.pending_skipped(), 0);
+        for (counter, message) in messages.iter().enumerate() {
+            let result = bob.decrypt(message);
+            assert!(matches!(result, Err(DhError::ReplayedMessage { counter: c }) if c == counter as u64));
+        }
+
+        // The other direction has a chain of its own.
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        assert!(bob.decrypt(&reply).is_err());
+    }
+
+    #[test]
+    fn every_message_has_its_own_key() {
+        let (mut alice, _) = ratchet_pair();
+        let first = alice.encrypt(b"same text").unwrap();
+        let second = alice.encrypt(b"same text").unwrap();
+        assert_ne!(first[COUNTER_LEN..], second[COUNTER_LEN..]);
+
+        // Moving a ciphertext to another counter doesn't authenticate.
+        let (_, mut bob) = ratchet_pair();
+        let mut moved = second[..COUNTER_LEN].to_vec();
+        moved.extend_from_slice(&first[COUNTER_LEN..]);
+        assert!(matches!(bob.decrypt(&moved), Err(DhError::DecryptionFailed)));
+        // And the forgery left the state alone.
+        assert_eq!(bob.decrypt(&second).unwrap(), b"same text");
+        assert_eq!(bob.decrypt(&first).unwrap(), b"same text");
+    }
+
+    #[test]
+    fn the_skip_window_is_bounded() {
+        let (mut alice, mut bob) = ratchet_pair();
+        let messages: Vec<_> = (0..=MAX_SKIPPED_MESSAGES + 2).map(|_| alice.encrypt(b"x").unwrap()).collect();
+        let too_far = &messages[MAX_SKIPPED_MESSAGES as usize + 1];
+        assert!(matches!(
+            bob.decrypt(too_far),
+            Err(DhError::TooManySkippedMessages { skipping, max_skipping: MAX_SKIPPED_MESSAGES })
+                if skipping == MAX_SKIPPED_MESSAGES + 1
+        ));
+        assert_eq!(bob.pending_skipped(), 0);
+
+        bob.decrypt(&messages[MAX_SKIPPED_MESSAGES as usize]).unwrap();
+        bob.decrypt(&messages[MAX_SKIPPED_MESSAGES as usize + 2]).unwrap();
+        // Message 0 is now more than the window behind the newest one, and its key is gone.
+        assert!(matches!(bob.decrypt(&messages[0]), Err(DhError::StaleMessage { counter: 0 })));
+        assert!(bob.pending_skipped() <= MAX_SKIPPED_MESSAGES as usize);
+        assert_eq!(bob.decrypt(&messages[3]).unwrap(), b"x");
+    }
+
+    #[test]
+    fn short_or_tampered_messages_fail() {
+        let (mut alice, mut bob) = ratchet_pair();
+        let message = alice.encrypt(b"intact").unwrap();
+        assert!(matches!(bob.decrypt(&message[..COUNTER_LEN + TAG_LEN - 1]), Err(DhError::DecryptionFailed)));
+        let mut tampered = message.clone();
+        tampered[COUNTER_LEN] ^= 1;
+        assert!(matches!(bob.decrypt(&tampered), Err(DhError::DecryptionFailed)));
+        assert_eq!(bob.decrypt(&message).unwrap(), b"intact");
+    }
+}
@@ -0,0 +1,297 @@
+// A record layer in the style of TLS 1.3 over any `Read + Write` stream, for long-lived connections. Each direction
+// has a traffic secret derived from the agreed secret, and each record is AES-128-GCM under a key and IV expanded
+// from it. The nonce is the IV XORed with the record's sequence number, so nothing but the ciphertext goes on the
+// wire, and records that are reordered, dropped or replayed fail to decrypt.
+//
+// Records can be padded to a multiple of a block size with `with_padding`, which hides message lengths to within a
+// block. The padding is inside the encryption, in the layout of `pad_to_bucket`. Without it the block size is 1.
+//
+// Keys are changed automatically: after a set number of records or plaintext bytes in one direction, the next traffic
+// secret is HKDF-Expand of the current one, the sequence number starts again at zero, and the old secret is wiped.
+// Both ends count the same records, so they switch at the same point without sending anything. Both must be
+// configured with the same limits. A key that leaks exposes only the records of its own epoch and the ones after it.
+//
+// Wire format: each record is the 4-byte big-endian length of `send_frame`, then ciphertext || GCM tag. The
+// plaintext is the padded record.
+
+use std::io::{Read, Write};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::kdf::derive_key;
+use crate::keys::SharedSecret;
+use crate::length_padding::{pad_to_bucket, strip_bucket_padding};
+use crate::net::{receive_frame, send_frame};
+use crate::nonce::NONCE_LEN;
+use crate::session::Role;
+
+// The most data one record can carry, as in TLS.
+pub const RECORD_MAX_LEN: usize = 16 * 1024;
+
+// The default rekeying limits: well inside what AES-GCM tolerates under one key.
+pub const DEFAULT_REKEY_RECORDS: u64 = 1 << 24;
+pub const DEFAULT_REKEY_BYTES: u64 = 1 << 34;
+
+const TRAFFIC_SECRET_LEN: usize = 32;
+// The 4-byte length prefix `pad_to_bucket` writes.
+const PADDING_OVERHEAD: usize = 4;
+// The longest padded record: a full record, its length prefix and up to one block of padding.
+const MAX_PADDED_LEN: usize = RECORD_MAX_LEN + PADDING_OVERHEAD + RECORD_MAX_LEN;
+
+// The keys for one direction of the record layer.
+struct TrafficKeys {
+    secret: Zeroizing<[u8; TRAFFIC_SECRET_LEN]>,
+    cipher: Aes128Gcm,
+    iv: [u8; NONCE_LEN],
+    // How many times the keys have been changed.
+    epoch: u64,
+    // The sequence number of the next record, and the plaintext bytes so far, within the epoch.
+    sequence: u64,
+    bytes: u64,
+}
+
+impl TrafficKeys {
+    fn new(secret: Zeroizing<[u8; TRAFFIC_SECRET_LEN]>, epoch: u64) -> Self {
+        let hkdf = Hkdf::<Sha256>::from_prk(&secret[..]).expect("traffic secret is a full SHA-256 output");
+        let mut key = Zeroizing::new([0u8; 16]);
+        let mut iv = [0u8; NONCE_LEN];
+        hkdf.expand(b"record key", &mut key[..]).expect("16 bytes is a valid HKDF output length");
+        hkdf.expand(b"record iv", &mut iv).expect("12 bytes is a valid HKDF output length");
+        let cipher = Aes128Gcm::new_from_slice(&key[..]).expect("record key is 16 bytes");
+        TrafficKeys { secret, cipher, iv, epoch, sequence: 0, bytes: 0 }
+    }
+
+    // The first traffic secret for records sent by `role`.
+    fn initial(shared: &SharedSecret, role: Role) -> Self {
+        let label = format!("{} record traffic secret", role.label());
+        let secret = Zeroizing::new(derive_key(shared.as_biguint(), &label, TRAFFIC_SECRET_LEN));
+        TrafficKeys::new(Zeroizing::new(secret[..].try_into().expect("derived 32 bytes")), 0)
+    }
+
+    fn nonce(&self) -> [u8; NONCE_LEN] {
+        let mut nonce = self.iv;
+        for (byte, sequence_byte) in nonce[NONCE_LEN - 8..].iter_mut().zip(self.sequence.to_be_bytes()) {
+            *byte ^= sequence_byte;
+        }
+        nonce
+    }
+
+    // Count a record of `len` plaintext bytes, and move to the next traffic secret once a limit is reached.
+    fn advance(&mut self, len: usize, limits: (u64, u64)) {
+        self.sequence += 1;
+        self.bytes = self.bytes.saturating_add(len as u64);
+        if self.sequence >= limits.0 || self.bytes >= limits.1 {
+            let hkdf = Hkdf::<Sha256>::from_prk(&self.secret[..]).expect("traffic secret is a full SHA-256 output");
+            let mut next = Zeroizing::new([0u8; TRAFFIC_SECRET_LEN]);
+            hkdf.expand(b"record traffic update", &mut next[..]).expect("32 bytes is a valid HKDF output length");
+            *self = TrafficKeys::new(next, self.epoch + 1);
+        }
+    }
+}
+
+pub struct RecordLayer<S> {
+    stream: S,
+    send: TrafficKeys,
+    receive: TrafficKeys,
+    padding_block: usize,
+    // Rekey after this many records or plaintext bytes per direction.
+    rekey_limits: (u64, u64),
+}
+
+impl<S: Read + Write> RecordLayer<S> {
+    // A record layer over `stream`. Both ends pass the same secret and opposite roles.
+    pub fn new(stream: S, secret: &SharedSecret, role: Role) -> Self {
+        RecordLayer {
+            stream,
+            send: TrafficKeys::initial(secret, role),
+            receive: TrafficKeys::initial(secret, role.peer()),
+            padding_block: 1,
+            rekey_limits: (DEFAULT_REKEY_RECORDS, DEFAULT_REKEY_BYTES),
+        }
+    }
+
+    // Pad every outgoing record to a multiple of `block_size` bytes. The peer doesn't need the same setting. Block
+    // sizes of zero or above `RECORD_MAX_LEN` are `DhError::InvalidBucketSize`.
+    pub fn with_padding(mut self, block_size: usize) -> Result<Self, DhError> {
+        if block_size == 0 || block_size > RECORD_MAX_LEN {
+            return Err(DhError::InvalidBucketSize);
+        }
+        self.padding_block = block_size;
+        Ok(self)
+    }
+
+    // Change keys after `records` records or `bytes` bytes of padded plaintext in a direction, whichever comes first.
+    // The peer has to use the same limits. Zero limits count as one.
+    pub fn with_rekey_limits(mut self, records: u64, bytes: u64) -> Self {
+        self.rekey_limits = (records.max(1), bytes.max(1));
+        self
+    }
+
+    // Encrypt `data` as one record and write it. More than `RECORD_MAX_LEN` bytes is `DhError::RecordTooLong`.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), DhError> {
+        if data.len() > RECORD_MAX_LEN {
+            return Err(DhError::RecordTooLong { length: data.len(), max_length: RECORD_MAX_LEN });
+        }
+        let padded = Zeroizing::new(pad_to_bucket(data, self.padding_block)?);
+        let epoch = self.send.epoch.to_be_bytes();
+        let record = self
+            .send
+            .cipher
+            .encrypt(Nonce::from_slice(&self.send.nonce()), Payload { msg: &padded, aad: &epoch })
+            .map_err(|_| DhError::EncryptionFailed)?;
+        send_frame(&mut self.stream, &record)?;
+        self.send.advance(padded.len(), self.rekey_limits);
+        Ok(())
+    }
+
+    // Read and decrypt the next record. Gives `Ok(None)` if the peer hangs up between records. A record that doesn't
+    // decrypt under the current keys, e.g. because it was tampered with, replayed or reordered, is
+    // `DhError::DecryptionFailed`. The stream can't be trusted after that, so the connection should be dropped.
+    pub fn receive(&mut self) -> Result<Option<Vec<u8>>, DhError> {
+        let Some(record) = receive_frame(&mut self.stream, MAX_PADDED_LEN + TAG_LEN)? else {
+            return Ok(None);
+        };
+        let epoch = self.receive.epoch.to_be_bytes();
+        let padded = Zeroizing::new(
+            self.receive
+                .cipher
+                .decrypt(Nonce::from_slice(&self.receive.nonce()), Payload { msg: &record, aad: &epoch })
+                .map_err(|_| DhError::DecryptionFailed)?,
+        );
+        self.receive.advance(padded.len(), self.rekey_limits);
+        Ok(Some(strip_bucket_padding(&padded)?))
+    }
+
+    // How many times each direction has changed keys, as (sending, receiving).
+    pub fn epochs(&self) -> (u64, u64) {
+        (self.send.epoch, self.receive.epoch)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn secret() -> SharedSecret {
+        SharedSecret::from_bytes(&[9; 32])
+    }
+
+    // Send each record through `layer` and hand back the bytes it wrote.
+    fn sent(mut layer: RecordLayer<Cursor<Vec<u8>>>, records: &[&[u8]]) -> Vec<u8> {
+        for record in records {
+            layer.send(record).unwrap();
+        }
+        layer.into_inner().into_inner()
+    }
+
+    fn receiver(wire: Vec<u8>) -> RecordLayer<Cursor<Vec<u8>>> {
+        RecordLayer::new(Cursor::new(wire), &secret(), Role::Responder)
+    }
+
+    // The length prefixes of the records on the wire.
+    fn record_lengths(mut wire: &[u8]) -> Vec<usize> {
+        let mut lengths = Vec::new();
+        while let Some((length, rest)) = wire.split_first_chunk::<4>() {
+            let length = u32::from_be_bytes(*length) as usize;
+            lengths.push(length);
+            wire = &rest[length..];
+        }
+        lengths
+    }
+
+    #[test]
+    fn padded_records_round_trip_and_hide_their_lengths() {
+        let records: [&[u8]; 4] = [b"", b"short", &[7; 60], &[8; 200]];
+        let sender = RecordLayer::new(Cursor::new(Vec::new()), &secret(), Role::Initiator).with_padding(64).unwrap();
+        let wire = sent(sender, &records);
+        assert_eq!(record_lengths(&wire), [64 + TAG_LEN, 64 + TAG_LEN, 64 + TAG_LEN, 256 + TAG_LEN]);
+
+        let mut receiver = receiver(wire);
+        for record in records {
+            assert_eq!(receiver.receive().unwrap().unwrap(), record);
+        }
+        assert!(receiver.receive().unwrap().is_none());
+    }
+
+    #[test]
+    fn both_ends_rekey_after_the_record_limit() {
+        let records = [&b"record"[..]; 7];
+        let sender =
+            RecordLayer::new(Cursor::new(Vec::new()), &secret(), Role::Initiator).with_rekey_limits(3, u64::MAX);
+        let wire = sent(sender, &records);
+
+        let mut matching = receiver(wire.clone()).with_rekey_limits(3, u64::MAX);
+        for record in records {
+            assert_eq!(matching.receive().unwrap().unwrap(), record);
+        }
+        assert_eq!(matching.epochs(), (0, 2));
+
+        // A peer with other limits stays on the old keys and fails at the first record after the switch.
+        let mut mismatched = receiver(wire);
+        for _ in 0..3 {
+            mismatched.receive().unwrap().unwrap();
+        }
+        assert!(matches!(mismatched.receive(), Err(DhError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn byte_limit_also_rekeys() {
+        let mut sender =
+            RecordLayer::new(Cursor::new(Vec::new()), &secret(), Role::Initiator).with_rekey_limits(u64::MAX, 100);
+        // Each 60-byte record pads to 64 bytes, so every second record reaches the limit.
+        for expected_epoch in [0, 0, 1, 1, 2] {
+            assert_eq!(sender.epochs().0, expected_epoch);
+            sender.send(&[0; 60]).unwrap();
+        }
+        assert_eq!(sender.epochs(), (2, 0));
+    }
+
+    #[test]
+    fn reordered_or_tampered_records_fail() {
+        let sender = RecordLayer::new(Cursor::new(Vec::new()), &secret(), Role::Initiator);
+        let wire = sent(sender, &[b"first", b"second"]);
+        let first_len = 4 + record_lengths(&wire)[0];
+        let (first, second) = wire.split_at(first_len);
+
+        let mut reordered = receiver([second, first].concat());
+        assert!(matches!(reordered.receive(), Err(DhError::DecryptionFailed)));
+
+        let mut tampered = wire.clone();
+        tampered[first_len - 1] ^= 1;
+        assert!(matches!(receiver(tampered).receive(), Err(DhError::DecryptionFailed)));
+
+        // Records from our own direction don't decrypt either.
+        let mut reflected = RecordLayer::new(Cursor::new(wire), &secret(), Role::Initiator);
+        assert!(matches!(reflected.receive(), Err(DhError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn oversized_records_and_bad_block_sizes_are_refused() {
+        let mut layer = RecordLayer::new(Cursor::new(Vec::new()), &secret(), Role::Initiator);
+        let result = layer.send(&vec![0; RECORD_MAX_LEN + 1]);
+        assert!(matches!(
+            result,
+            Err(DhError::RecordTooLong { length, max_length: RECORD_MAX_LEN }) if length == RECORD_MAX_LEN + 1
+        ));
+        layer.send(&vec![0; RECORD_MAX_LEN]).unwrap();
+        assert!(matches!(layer.with_padding(0), Err(DhError::InvalidBucketSize)));
+        let layer = RecordLayer::new(Cursor::new(Vec::new()), &secret(), Role::Initiator);
+        assert!(matches!(layer.with_padding(RECORD_MAX_LEN + 1), Err(DhError::InvalidBucketSize)));
+    }
+}
@@ -0,0 +1,71 @@
+// Look up the built-in groups by name, so tools can list what is available and turn a name from a config file or the
+// command line into parameters. Each group is known by its short name (e.g. "modp2048" or "ffdhe2048"), and the MODP
+// groups also by their RFC 3526 ID (e.g. "rfc3526-14" or "group14"); lookups ignore case.
+
+use std::sync::OnceLock;
+
+use crate::params::{DhParameters, Group};
+
+pub struct ParametersRegistry;
+
+struct Entry {
+    group: Group,
+    params: DhParameters,
+}
+
+// Parsing the primes is done once, on first use.
+fn entries() -> &'static [Entry] {
+    static ENTRIES: OnceLock<Vec<Entry>> = OnceLock::new();
+    ENTRIES.get_or_init(|| Group::ALL.iter().map(|&group| Entry { group, params: group.parameters() }).collect())
+}
+
+impl ParametersRegistry {
+    // The parameters registered under `name` or one of its aliases, or None for an unknown name.
+    pub fn get(name: &str) -> Option<&'static DhParameters> {
+        let name = name.to_ascii_lowercase();
+        entries().iter().find(|entry| Self::matches(entry.group, &name)).map(|entry| &entry.params)
+    }
+
+    // The canonical names of every registered group, from smallest to largest modulus.
+    pub fn list() -> Vec<&'static str> {
+        entries().iter().map(|entry| entry.group.name()).collect()
+    }
+
+    fn matches(group: Group, name: &str) -> bool {
+        match group.rfc3526_id() {
+            Some(id) => name == group.name() || name == format!("rfc3526-{}", id) || name == format!("group{}", id),
+            None => name == group.name(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_has_every_built_in_group() {
+        let names = ParametersRegistry::list();
+        assert_eq!(names, ["modp2048", "ffdhe2048", "modp3072", "ffdhe3072", "modp4096", "ffdhe4096"]);
+        for group in Group::ALL {
+            assert!(names.contains(&group.name()), "{group:?}");
+            assert_eq!(ParametersRegistry::get(group.name()), Some(&group.parameters()));
+        }
+    }
+
+    #[test]
+    fn get_accepts_aliases_in_any_case() {
+        let modp2048 = Group::Modp2048.parameters();
+        for name in ["MODP2048", "rfc3526-14", "Group14", "group14"] {
+            assert_eq!(ParametersRegistry::get(name), Some(&modp2048), "{name}");
+        }
+        assert_eq!(ParametersRegistry::get("FFDHE3072"), Some(&Group::Ffdhe3072.parameters()));
+    }
+
+    #[test]
+    fn unknown_names_are_none() {
+        for name in ["", "modp1024", "group2", "rfc3526-99", "ffdhe8192", "rfc7919-ffdhe2048", " modp2048"] {
+            assert_eq!(ParametersRegistry::get(name), None, "{name}");
+        }
+    }
+}
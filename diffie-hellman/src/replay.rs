@@ -0,0 +1,101 @@
+// Sliding-window replay protection in the style of IPsec (RFC 4303) and DTLS. The receiver remembers the highest
+// message counter it has accepted plus a bitmap of the WINDOW_SIZE counters below it; anything already in the bitmap
+// or older than the window is rejected.
+
+use crate::error::DhError;
+
+// How many counters behind the highest accepted one are still tracked.
+pub const WINDOW_SIZE: u64 = 64;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplayWindow {
+    // The highest counter accepted so far, or None before the first message.
+    highest: Option<u64>,
+    // Bit i is set when counter `highest - i` has been accepted.
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        ReplayWindow::default()
+    }
+
+    // Check whether a message with this counter could still be accepted, without recording it. Callers should only
+    // `accept` the counter once the message has been authenticated, so forged messages can't move the window.
+    pub fn check(&self, counter: u64) -> Result<(), DhError> {
+        let Some(highest) = self.highest else {
+            return Ok(());
+        };
+        if counter > highest {
+            return Ok(());
+        }
+        let age = highest - counter;
+        if age >= WINDOW_SIZE {
+            return Err(DhError::StaleMessage { counter });
+        }
+        if self.bitmap & (1 << age) != 0 {
+            return Err(DhError::ReplayedMessage { counter });
+        }
+        Ok(())
+    }
+
+    // Record a counter as accepted, sliding the window forward if it is the newest one seen.
+    pub fn accept(&mut self, counter: u64) {
+        match self.highest {
+            Some(highest) if counter <= highest => {
+                self.bitmap |= 1 << (highest - counter);
+            }
+            Some(highest) => {
+                let shift = counter - highest;
+                self.bitmap = if shift >= WINDOW_SIZE { 0 } else { self.bitmap << shift };
+                self.bitmap |= 1;
+                self.highest = Some(counter);
+            }
+            None => {
+                self.bitmap = 1;
+                self.highest = Some(counter);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_are_rejected_and_new_counters_accepted() {
+        let mut window = ReplayWindow::new();
+        for counter in [0, 1, 5, 3] {
+            window.check(counter).unwrap();
+            window.accept(counter);
+        }
+        for counter in [0, 1, 3, 5] {
+            assert!(matches!(window.check(counter), Err(DhError::ReplayedMessage { counter: c }) if c == counter));
+        }
+        window.check(2).unwrap();
+        window.check(4).unwrap();
+        window.check(6).unwrap();
+    }
+
+    #[test]
+    fn counters_behind_the_window_are_stale() {
+        let mut window = ReplayWindow::new();
+        window.accept(10);
+        window.accept(10 + WINDOW_SIZE);
+        assert!(matches!(window.check(10), Err(DhError::StaleMessage { counter: 10 })));
+        window.check(11).unwrap();
+        window.accept(10 + 3 * WINDOW_SIZE);
+        assert!(matches!(window.check(10 + WINDOW_SIZE), Err(DhError::StaleMessage { .. })));
+    }
+
+    #[test]
+    fn check_alone_does_not_move_the_window() {
+        let mut window = ReplayWindow::new();
+        window.check(7).unwrap();
+        window.check(7).unwrap();
+        window.accept(7);
+        assert!(window.check(7).is_err());
+    }
+}
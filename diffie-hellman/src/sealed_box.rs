@@ -0,0 +1,116 @@
+// Anonymous one-shot encryption to a recipient's static public key, in the spirit of libsodium's sealed boxes. The
+// sender generates a throwaway keypair per message, agrees a secret with the recipient's public key and sends its
+// ephemeral public key along with the ciphertext, so the recipient can decrypt without knowing who sent it. There is
+// no sender authentication: anyone who has the recipient's public key can produce a valid box.
+//
+// Box layout: ephemeral public key length (4 bytes, big-endian) || ephemeral public key || ciphertext || GCM tag.
+// The AES-128-GCM key and nonce are derived from the shared secret with both public keys as HKDF context. Every box has
+// a fresh ephemeral key, so a key and nonce pair is never used twice.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::kdf::derive_key_with_context;
+use crate::keys::{generate_keypair, PrivateKey, PublicKey, SharedSecret};
+use crate::nonce::NONCE_LEN;
+use crate::params::DhParameters;
+
+const LENGTH_LEN: usize = 4;
+const KEY_LEN: usize = 16;
+
+// The cipher and nonce for one box, bound to both public keys so a box can't be re-addressed.
+fn box_cipher(
+    secret: &SharedSecret,
+    ephemeral_public: &[u8],
+    recipient_public: &PublicKey,
+) -> (Aes128Gcm, [u8; NONCE_LEN], Vec<u8>) {
+    let mut context = (ephemeral_public.len() as u32).to_be_bytes().to_vec();
+    context.extend_from_slice(ephemeral_public);
+    context.extend_from_slice(&recipient_public.to_wire_bytes());
+    let material = derive_key_with_context(secret.as_biguint(), "sealed box", &context, KEY_LEN + NONCE_LEN);
+    let material = Zeroizing::new(material);
+    let cipher = Aes128Gcm::new_from_slice(&material[..KEY_LEN]).expect("derived key is 16 bytes");
+    let nonce = material[KEY_LEN..].try_into().expect("derived nonce is 12 bytes");
+    (cipher, nonce, context)
+}
+
+// Encrypt `plain_text` so only the holder of the private key for `recipient_public` can read it. The recipient's key
+// is validated first.
+pub fn seal_anonymous(
+    plain_text: &[u8],
+    recipient_public: &PublicKey,
+    params: &DhParameters,
+) -> Result<Vec<u8>, DhError> {
+    recipient_public.validate_in_group(params)?;
+    let (ephemeral_private, ephemeral_public) = generate_keypair(params)?;
+    let secret = ephemeral_private.agree(recipient_public, params);
+    let ephemeral_bytes = ephemeral_public.to_wire_bytes();
+
+    let (cipher, nonce, context) = box_cipher(&secret, &ephemeral_bytes, recipient_public);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain_text, aad: &context })
+        .map_err(|_| DhError::EncryptionFailed)?;
+
+    let mut sealed = context[..LENGTH_LEN + ephemeral_bytes.len()].to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+// Decrypt a box made by `seal_anonymous` for our public key. A malformed box, an invalid ephemeral key, tampering or
+// the wrong private key all fail; tampering and the wrong key as `DhError::DecryptionFailed`.
+pub fn open_anonymous(
+    sealed: &[u8],
+    recipient_private: &PrivateKey,
+    params: &DhParameters,
+) -> Result<Vec<u8>, DhError> {
+    let (length_bytes, rest) = sealed.split_first_chunk::<LENGTH_LEN>().ok_or(DhError::MalformedMessage)?;
+    let length = u32::from_be_bytes(*length_bytes) as usize;
+    if length > rest.len() {
+        return Err(DhError::MalformedMessage);
+    }
+    let (ephemeral_bytes, ciphertext) = rest.split_at(length);
+    let ephemeral_public = PublicKey::from_wire_bytes(ephemeral_bytes, params)?;
+    ephemeral_public.validate_in_group(params)?;
+
+    let secret = recipient_private.agree(&ephemeral_public, params);
+    let recipient_public = recipient_private.public_key(params);
+    let (cipher, nonce, context) = box_cipher(&secret, ephemeral_bytes, &recipient_public);
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: ciphertext, aad: &context })
+        .map_err(|_| DhError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    #[test]
+    fn sealed_box_opens_only_for_the_recipient() {
+        let params = Group::Ffdhe2048.parameters();
+        let recipient = PrivateKey::from_bytes_be(&[0x42; 28]);
+        let recipient_public = recipient.public_key(&params);
+
+        let sealed = seal_anonymous(b"for your eyes only", &recipient_public, &params).unwrap();
+        assert_eq!(open_anonymous(&sealed, &recipient, &params).unwrap(), b"for your eyes only");
+        // Each box has its own ephemeral key.
+        assert_ne!(seal_anonymous(b"for your eyes only", &recipient_public, &params).unwrap(), sealed);
+
+        let someone_else = PrivateKey::from_bytes_be(&[0x17; 28]);
+        assert!(matches!(open_anonymous(&sealed, &someone_else, &params), Err(DhError::DecryptionFailed)));
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(open_anonymous(&tampered, &recipient, &params), Err(DhError::DecryptionFailed)));
+        assert!(matches!(open_anonymous(&sealed[..3], &recipient, &params), Err(DhError::MalformedMessage)));
+        assert!(matches!(open_anonymous(&sealed[..100], &recipient, &params), Err(DhError::MalformedMessage)));
+    }
+
+    #[test]
+    fn sealing_to_an_invalid_key_is_refused() {
+        let params = Group::Ffdhe2048.parameters();
+        let result = seal_anonymous(b"nobody", &PublicKey::from_biguint(1u32.into()), &params);
+        assert!(matches!(result, Err(DhError::InvalidPublicKey(_))));
+    }
+}
@@ -0,0 +1,199 @@
+// Serde support for the types that get sent to a peer or stored: `DhParameters`, `PublicKey`, `PrivateKey`,
+// `StaticKeyPair` and `EncryptedMessage`. Human-readable formats such as JSON get lowercase hex, with the parameters
+// and envelopes as objects of hex fields, e.g. `{"generator":"2","modulus":"ffff..."}`. Binary formats get the compact
+// byte encodings instead: `DhParameters::to_bytes`, `PublicKey::to_wire_bytes`, `PrivateKey::to_bytes_be` and
+// `EncryptedMessage::to_bytes`.
+//
+// Deserialized parameters go through `DhParameters::new`, so an insecure group is refused. A public key can't be
+// checked against its group while it is being read, so run `validate_public_key` on it before use.
+
+use std::fmt;
+
+use num_bigint::BigUint;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::envelope::EncryptedMessage;
+use crate::ephemeral::StaticKeyPair;
+use crate::keys::{PrivateKey, PublicKey};
+use crate::params::DhParameters;
+
+#[derive(Serialize, Deserialize)]
+struct ParametersFields {
+    generator: String,
+    modulus: String,
+}
+
+// A static keypair is stored as its parameters and private key; the public key is computed again on loading.
+#[derive(Serialize)]
+struct KeyPairFieldsRef<'a> {
+    params: &'a DhParameters,
+    private_key: &'a PrivateKey,
+}
+
+#[derive(Deserialize)]
+struct KeyPairFields {
+    params: DhParameters,
+    private_key: PrivateKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EnvelopeFields {
+    nonce: String,
+    ciphertext: String,
+    tag: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_bytes<E: de::Error>(field: &str, hex: &str) -> Result<Vec<u8>, E> {
+    let invalid = || E::custom(format!("{} is not an even number of hex digits", field));
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid())).collect()
+}
+
+fn parse_hex_number<E: de::Error>(field: &str, hex: &str) -> Result<BigUint, E> {
+    BigUint::parse_bytes(hex.as_bytes(), 16).ok_or_else(|| E::custom(format!("{} is not a hex number", field)))
+}
+
+// Reads a byte string from a binary format. Some formats hand bytes over as a sequence, so that is accepted too.
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+impl Serialize for DhParameters {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.to_bytes());
+        }
+        ParametersFields { generator: self.generator.to_str_radix(16), modulus: self.modulus.to_str_radix(16) }
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DhParameters {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return DhParameters::from_bytes(&deserialize_bytes(deserializer)?).map_err(de::Error::custom);
+        }
+        let fields = ParametersFields::deserialize(deserializer)?;
+        let generator = parse_hex_number("generator", &fields.generator)?;
+        let modulus = parse_hex_number("modulus", &fields.modulus)?;
+        DhParameters::new(generator, modulus).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            serializer.serialize_bytes(&self.to_wire_bytes())
+        }
+    }
+}
+
+// The binary form has to be canonical, as with `PublicKey::from_wire_bytes`: leading zero bytes are refused.
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            return PublicKey::from_hex(&hex).map_err(de::Error::custom);
+        }
+        let bytes = deserialize_bytes(deserializer)?;
+        if bytes.is_empty() || bytes[0] == 0 {
+            return Err(de::Error::custom("public key is not canonically encoded"));
+        }
+        Ok(PublicKey::from_biguint(BigUint::from_bytes_be(&bytes)))
+    }
+}
+
+// The output holds the private key in the clear: keep it somewhere only its owner can read.
+impl Serialize for PrivateKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.0.to_str_radix(16))
+        } else {
+            serializer.serialize_bytes(&self.to_bytes_be())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrivateKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            return Ok(PrivateKey::from_biguint(parse_hex_number("private key", &hex)?));
+        }
+        Ok(PrivateKey::from_bytes_be(&deserialize_bytes(deserializer)?))
+    }
+}
+
+impl Serialize for StaticKeyPair {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        KeyPairFieldsRef { params: self.params(), private_key: self.private_key() }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StaticKeyPair {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = KeyPairFields::deserialize(deserializer)?;
+        StaticKeyPair::from_private_key(fields.private_key, &fields.params).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for EncryptedMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.to_bytes());
+        }
+        EnvelopeFields { nonce: to_hex(&self.nonce), ciphertext: to_hex(&self.ciphertext), tag: to_hex(&self.tag) }
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncryptedMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return EncryptedMessage::from_bytes(&deserialize_bytes(deserializer)?).map_err(de::Error::custom);
+        }
+        let fields = EnvelopeFields::deserialize(deserializer)?;
+        let nonce = parse_hex_bytes("nonce", &fields.nonce)?;
+        let tag = parse_hex_bytes("tag", &fields.tag)?;
+        Ok(EncryptedMessage {
+            nonce: nonce.try_into().map_err(|_| de::Error::custom("nonce has the wrong length"))?,
+            ciphertext: parse_hex_bytes("ciphertext", &fields.ciphertext)?,
+            tag: tag.try_into().map_err(|_| de::Error::custom("tag has the wrong length"))?,
+        })
+    }
+}
@@ -0,0 +1,697 @@
+// A multi-message session on top of an agreed secret. Each direction has its own AES-128-GCM key and counter derived
+// from the secret, so the two ends can send whenever they like, in any interleaving, and `Session::split` can hand the
+// two directions to separate threads. Every message carries its counter in the clear so the receiver can rebuild the
+// nonce and reject replays.
+//
+// Message layout: counter (8 bytes, big-endian) || ciphertext || GCM tag (16 bytes). The plaintext starts with a
+// one-byte record type, as in TLS 1.3, so the close marker is authenticated like any other message.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::kdf::derive_key;
+use crate::keys::SharedSecret;
+use crate::nonce::NonceSequence;
+use crate::replay::ReplayWindow;
+use crate::session_audit::{AuditEvent, SessionAudit};
+
+const COUNTER_LEN: usize = 8;
+const RECORD_TYPE_LEN: usize = 1;
+const DATA_RECORD: u8 = 0;
+const CLOSE_RECORD: u8 = 1;
+pub const TICKET_LEN: usize = 32;
+
+// Which end of the exchange a session belongs to. Each end sends under its own key and nonce sequence and receives
+// under the peer's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+impl Role {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Role::Initiator => "initiator",
+            Role::Responder => "responder",
+        }
+    }
+
+    pub(crate) fn peer(self) -> Role {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+}
+
+// A decrypted incoming message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Data(String),
+    // The peer closed the session; nothing more will follow.
+    Close,
+}
+
+// The key and nonce sequence for messages sent by `role`.
+fn direction(secret: &SharedSecret, salt: &[u8], role: Role) -> (Aes128Gcm, NonceSequence) {
+    let key = Zeroizing::new(derive_key(secret.as_biguint(), &format!("{} session encryption key", role.label()), 16));
+    let cipher = Aes128Gcm::new_from_slice(&key).expect("derived key is 16 bytes");
+    (cipher, NonceSequence::new(&[salt, role.label().as_bytes()].concat()))
+}
+
+// The sending direction of a session.
+pub struct SessionSender {
+    cipher: Aes128Gcm,
+    nonces: NonceSequence,
+    counter: u64,
+    // Set once we have sent our close message.
+    closed: bool,
+}
+
+// The receiving direction of a session.
+pub struct SessionReceiver {
+    cipher: Aes128Gcm,
+    nonces: NonceSequence,
+    replay_window: ReplayWindow,
+    // How many messages have been accepted.
+    received: u64,
+    // Set once the peer's close message has been received.
+    peer_closed: bool,
+    // Incoming messages that would decrypt to more than this many bytes are rejected unread.
+    max_output_len: usize,
+}
+
+// A resumption secret exported from an established session, from which both ends can later start a fresh session
+// without another handshake. It is as sensitive as the shared secret itself.
+pub struct SessionTicket([u8; TICKET_LEN]);
+
+impl SessionTicket {
+    // Restore a ticket saved with `as_bytes`.
+    pub fn from_bytes(bytes: [u8; TICKET_LEN]) -> Self {
+        SessionTicket(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; TICKET_LEN] {
+        &self.0
+    }
+}
+
+pub struct Session {
+    sender: SessionSender,
+    receiver: SessionReceiver,
+    // HKDF keyed on the master secret and session salt, for `export_keying_material`.
+    exporter: Hkdf<Sha256>,
+    // The audit log, if one was asked for.
+    audit: Option<SessionAudit>,
+    // What the session was created from. The ciphers can't hand their keys back, so `serialize` saves these instead
+    // and `deserialize` derives everything again.
+    #[cfg(feature = "serde")]
+    origin: (Zeroizing<Vec<u8>>, Vec<u8>, Role),
+}
+
+// Everything `Session::serialize` saves.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SessionState {
+    secret: Zeroizing<Vec<u8>>,
+    salt: Vec<u8>,
+    role: Role,
+    send_counter: u64,
+    closed: bool,
+    replay_window: ReplayWindow,
+    #[serde(default)]
+    received: u64,
+    peer_closed: bool,
+    max_output_len: usize,
+}
+
+impl Session {
+    // Start a session from the agreed secret and the salt exchanged during the handshake. Both ends pass the same
+    // secret and salt, and opposite roles.
+    pub fn new(secret: &SharedSecret, salt: &[u8], role: Role) -> Self {
+        let (send_cipher, send_nonces) = direction(secret, salt, role);
+        let (receive_cipher, receive_nonces) = direction(secret, salt, role.peer());
+        Session {
+            sender: SessionSender { cipher: send_cipher, nonces: send_nonces, counter: 0, closed: false },
+            receiver: SessionReceiver {
+                cipher: receive_cipher,
+                nonces: receive_nonces,
+                replay_window: ReplayWindow::new(),
+                received: 0,
+                peer_closed: false,
+                max_output_len: usize::MAX,
+            },
+            exporter: Hkdf::new(Some(salt), &Zeroizing::new(secret.as_biguint().to_bytes_be())),
+            audit: None,
+            #[cfg(feature = "serde")]
+            origin: (Zeroizing::new(secret.as_biguint().to_bytes_be()), salt.to_vec(), role),
+        }
+    }
+
+    // Save the whole session, keys included, so it can be picked up again after a restart with `deserialize`.
+    //
+    // The output contains the shared secret in the clear: anyone who reads it can decrypt and forge every message of
+    // the session. Encrypt it at rest, never log it, and delete it once the session is over. Restoring the same saved
+    // state twice and sending from both copies reuses nonces, which breaks GCM completely, so treat a saved state as
+    // consumed once it has been restored.
+    #[cfg(feature = "serde")]
+    pub fn serialize(&self) -> serde_json::Result<Vec<u8>> {
+        let (secret, salt, role) = self.origin.clone();
+        serde_json::to_vec(&SessionState {
+            secret,
+            salt,
+            role,
+            send_counter: self.sender.counter,
+            closed: self.sender.closed,
+            replay_window: self.receiver.replay_window.clone(),
+            received: self.receiver.received,
+            peer_closed: self.receiver.peer_closed,
+            max_output_len: self.receiver.max_output_len,
+        })
+    }
+
+    // Restore a session saved with `serialize`. It carries on from the saved counters and replay window.
+    #[cfg(feature = "serde")]
+    pub fn deserialize(bytes: &[u8]) -> serde_json::Result<Session> {
+        let state: SessionState = serde_json::from_slice(bytes)?;
+        let mut session = Session::new(&SharedSecret::from_bytes(&state.secret), &state.salt, state.role);
+        session.sender.counter = state.send_counter;
+        session.sender.nonces.resume_at(state.send_counter);
+        session.sender.closed = state.closed;
+        session.receiver.replay_window = state.replay_window;
+        session.receiver.received = state.received;
+        session.receiver.peer_closed = state.peer_closed;
+        session.receiver.max_output_len = state.max_output_len;
+        Ok(session)
+    }
+
+    // A ticket for resuming this session later with `Session::resume`. Both ends derive the same ticket.
+    pub fn ticket(&self) -> SessionTicket {
+        let material = self.export_keying_material("session resumption", &[], TICKET_LEN);
+        SessionTicket(material.try_into().expect("exported TICKET_LEN bytes"))
+    }
+
+    // Start a new session from a ticket instead of a fresh handshake, which saves the exponentiations for short
+    // follow-up conversations. Both ends pass their copy of the ticket, a new salt shared between them, and their
+    // roles; reusing the previous salt would repeat nonces under the same keys.
+    //
+    // This gives up forward secrecy: anyone who later steals the ticket, or the original secret it came from, can
+    // decrypt every resumed session. Run a full handshake whenever that matters, and discard tickets when done.
+    pub fn resume(ticket: &SessionTicket, salt: &[u8], role: Role) -> Self {
+        Session::new(&SharedSecret::from_bytes(&ticket.0), salt, role)
+    }
+
+    // Limit incoming messages to `max_output_len` bytes of plaintext; longer ones fail with `DhError::OutputTooLarge`
+    // before being decrypted. There is no limit by default.
+    pub fn with_max_output_len(mut self, max_output_len: usize) -> Self {
+        self.receiver.max_output_len = max_output_len;
+        self
+    }
+
+    // Keep an audit log of the session in `audit`, see session_audit.rs. Events before this call aren't in it.
+    pub fn with_audit_log(mut self, audit: SessionAudit) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    // Note an event the session can't see itself, such as a layer on top changing keys. Does nothing without a log.
+    pub fn log_event(&mut self, event: AuditEvent) {
+        if let Some(audit) = &mut self.audit {
+            audit.events.push(event);
+        }
+    }
+
+    // The audit log so far, with the current message counts, or None if the session doesn't keep one.
+    pub fn audit_log(&self) -> Option<SessionAudit> {
+        let mut audit = self.audit.clone()?;
+        audit.messages_sent = self.messages_sent();
+        audit.messages_received = self.messages_received();
+        Some(audit)
+    }
+
+    // The audit log as JSON, see `audit_log`. It holds no secrets, so it can go to a log file or a bug report.
+    #[cfg(feature = "serde")]
+    pub fn export_transcript(&self) -> Option<String> {
+        self.audit_log().map(|audit| serde_json::to_string_pretty(&audit).expect("audit logs serialize to JSON"))
+    }
+
+    // Separate the two directions, e.g. to send from one thread while another receives. The halves keep all their
+    // state, so messages sent or received before the split are accounted for.
+    pub fn split(self) -> (SessionSender, SessionReceiver) {
+        (self.sender, self.receiver)
+    }
+
+    // Derive `len` bytes of keying material bound to this session, in the spirit of TLS exporters (RFC 5705), for
+    // protocols layered on top of the session. Both ends get the same bytes for the same label and context, and any
+    // change to either gives unrelated output. The label and context are length-prefixed so they can't run together.
+    // Panics if `len` is larger than HKDF-SHA256 can produce (255 * 32 bytes).
+    pub fn export_keying_material(&self, label: &str, context: &[u8], len: usize) -> Vec<u8> {
+        let mut info = b"diffie-hellman exporter".to_vec();
+        info.extend_from_slice(&(label.len() as u32).to_be_bytes());
+        info.extend_from_slice(label.as_bytes());
+        info.extend_from_slice(&(context.len() as u32).to_be_bytes());
+        info.extend_from_slice(context);
+
+        let mut material = vec![0u8; len];
+        self.exporter
+            .expand(&info, &mut material)
+            .expect("HKDF-SHA256 can derive at most 8160 bytes");
+        material
+    }
+
+    // See `SessionSender::messages_sent`.
+    pub fn messages_sent(&self) -> u64 {
+        self.sender.messages_sent()
+    }
+
+    // See `SessionReceiver::messages_received`.
+    pub fn messages_received(&self) -> u64 {
+        self.receiver.messages_received()
+    }
+
+    // See `SessionSender::encrypt_next`.
+    pub fn encrypt_next(&mut self, plain_text: &str) -> Result<Vec<u8>, DhError> {
+        self.sender.encrypt_next(plain_text)
+    }
+
+    // See `SessionSender::close`.
+    pub fn close(&mut self) -> Result<Vec<u8>, DhError> {
+        let message = self.sender.close()?;
+        self.log_event(AuditEvent::Closed { messages_sent: self.messages_sent() });
+        Ok(message)
+    }
+
+    // See `SessionReceiver::decrypt_next`.
+    pub fn decrypt_next(&mut self, message: &[u8]) -> Result<String, DhError> {
+        match self.receive(message)? {
+            Record::Data(plain_text) => Ok(plain_text),
+            Record::Close => Err(DhError::SessionClosed),
+        }
+    }
+
+    // See `SessionReceiver::receive`.
+    pub fn receive(&mut self, message: &[u8]) -> Result<Record, DhError> {
+        let record = self.receiver.receive(message);
+        let messages_received = self.messages_received();
+        match &record {
+            Ok(Record::Data(_)) => {}
+            Ok(Record::Close) => self.log_event(AuditEvent::PeerClosed { messages_received }),
+            Err(error) => self.log_event(AuditEvent::Rejected { messages_received, reason: error.to_string() }),
+        }
+        record
+    }
+
+    // See `SessionReceiver::end_of_stream`.
+    pub fn end_of_stream(&self) -> Result<(), DhError> {
+        self.receiver.end_of_stream()
+    }
+}
+
+impl SessionSender {
+    // How many messages this direction has encrypted, the close message included. Each used up one nonce, so this is
+    // what a rekey policy ("rekey after N messages") should look at.
+    pub fn messages_sent(&self) -> u64 {
+        self.counter
+    }
+
+    // Encrypt the next outgoing message.
+    pub fn encrypt_next(&mut self, plain_text: &str) -> Result<Vec<u8>, DhError> {
+        self.encrypt_record(DATA_RECORD, plain_text.as_bytes())
+    }
+
+    // Produce the message that tells the peer this end is done, in the spirit of TLS close_notify. After this no more
+    // messages can be sent. The peer uses it to tell a finished stream from one an attacker cut short.
+    pub fn close(&mut self) -> Result<Vec<u8>, DhError> {
+        let message = self.encrypt_record(CLOSE_RECORD, &[])?;
+        self.closed = true;
+        Ok(message)
+    }
+
+    fn encrypt_record(&mut self, record_type: u8, payload: &[u8]) -> Result<Vec<u8>, DhError> {
+        if self.closed {
+            return Err(DhError::SessionClosed);
+        }
+        let counter = self.counter;
+        let nonce = self.nonces.next_nonce()?;
+        let mut plain_bytes = Vec::with_capacity(RECORD_TYPE_LEN + payload.len());
+        plain_bytes.push(record_type);
+        plain_bytes.extend_from_slice(payload);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plain_bytes.as_slice())
+            .map_err(|_| DhError::EncryptionFailed)?;
+        self.counter += 1;
+
+        let mut message = Vec::with_capacity(COUNTER_LEN + ciphertext.len());
+        message.extend_from_slice(&counter.to_be_bytes());
+        message.extend_from_slice(&ciphertext);
+        Ok(message)
+    }
+}
+
+impl SessionReceiver {
+    // How many messages from the peer have been decrypted and accepted, the close message included. Rejected messages,
+    // replays among them, don't count.
+    pub fn messages_received(&self) -> u64 {
+        self.received
+    }
+
+    // Decrypt an incoming data message. A close message from the peer is reported as `DhError::SessionClosed`; use
+    // `receive` to handle it as a normal outcome instead.
+    pub fn decrypt_next(&mut self, message: &[u8]) -> Result<String, DhError> {
+        match self.receive(message)? {
+            Record::Data(plain_text) => Ok(plain_text),
+            Record::Close => Err(DhError::SessionClosed),
+        }
+    }
+
+    // Decrypt an incoming message of either kind. Messages may arrive out of order, but one whose counter was already
+    // accepted, or that has fallen behind the replay window, is rejected, as is anything after the peer's close.
+    pub fn receive(&mut self, message: &[u8]) -> Result<Record, DhError> {
+        if self.peer_closed {
+            return Err(DhError::SessionClosed);
+        }
+        let overhead = COUNTER_LEN + TAG_LEN + RECORD_TYPE_LEN;
+        if message.len() < overhead {
+            return Err(DhError::DecryptionFailed);
+        }
+        let output_len = message.len() - overhead;
+        if output_len > self.max_output_len {
+            return Err(DhError::OutputTooLarge { length: output_len, max_length: self.max_output_len });
+        }
+        let (counter_bytes, ciphertext) = message.split_at(COUNTER_LEN);
+        let counter = u64::from_be_bytes(counter_bytes.try_into().expect("counter is 8 bytes"));
+        self.replay_window.check(counter)?;
+
+        let nonce = self.nonces.nonce_for(counter);
+        let mut plain_bytes = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(|_| DhError::DecryptionFailed)?;
+        self.replay_window.accept(counter);
+        self.received += 1;
+
+        let payload = plain_bytes.split_off(RECORD_TYPE_LEN);
+        match plain_bytes[0] {
+            DATA_RECORD => String::from_utf8(payload).map(Record::Data).map_err(|_| DhError::InvalidUtf8),
+            CLOSE_RECORD if payload.is_empty() => {
+                self.peer_closed = true;
+                Ok(Record::Close)
+            }
+            _ => Err(DhError::DecryptionFailed),
+        }
+    }
+
+    // Call when the underlying transport reaches end of file. Succeeds only if the peer closed the session first;
+    // otherwise the stream was cut off and `DhError::Truncated` is returned.
+    pub fn end_of_stream(&self) -> Result<(), DhError> {
+        if self.peer_closed {
+            Ok(())
+        } else {
+            Err(DhError::Truncated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::PublicKey;
+    use crate::params::DhParameters;
+    use crate::replay::WINDOW_SIZE;
+    use crate::suite::CipherSuite;
+    use crate::CONTROL_CHARACTER_TEXT;
+
+    #[test]
+    fn control_characters_round_trip() {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let mut alice = Session::new(&secret, b"session test", Role::Initiator);
+        let mut bob = Session::new(&secret, b"session test", Role::Responder);
+        let to_bob = alice.encrypt_next(CONTROL_CHARACTER_TEXT).unwrap();
+        assert_eq!(bob.decrypt_next(&to_bob).unwrap(), CONTROL_CHARACTER_TEXT);
+        let to_alice = bob.encrypt_next(CONTROL_CHARACTER_TEXT).unwrap();
+        assert_eq!(alice.decrypt_next(&to_alice).unwrap(), CONTROL_CHARACTER_TEXT);
+    }
+
+    fn session_pair() -> (Session, Session) {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let salt = b"session test";
+        (Session::new(&secret, salt, Role::Initiator), Session::new(&secret, salt, Role::Responder))
+    }
+
+    #[test]
+    fn replayed_messages_are_rejected() {
+        let (mut alice, mut bob) = session_pair();
+        let first = alice.encrypt_next("first").unwrap();
+        assert_eq!(bob.decrypt_next(&first).unwrap(), "first");
+        assert!(matches!(bob.decrypt_next(&first), Err(DhError::ReplayedMessage { counter: 0 })));
+        let second = alice.encrypt_next("second").unwrap();
+        assert_eq!(bob.decrypt_next(&second).unwrap(), "second");
+        assert!(matches!(bob.decrypt_next(&second), Err(DhError::ReplayedMessage { counter: 1 })));
+        assert_eq!(bob.messages_received(), 2);
+    }
+
+    #[test]
+    fn late_messages_pass_within_the_window_only() {
+        let (mut alice, mut bob) = session_pair();
+        let messages: Vec<Vec<u8>> =
+            (0..WINDOW_SIZE + 2).map(|i| alice.encrypt_next(&i.to_string()).unwrap()).collect();
+        assert_eq!(bob.decrypt_next(&messages[3]).unwrap(), "3");
+        assert_eq!(bob.decrypt_next(&messages[1]).unwrap(), "1");
+        assert!(matches!(bob.decrypt_next(&messages[3]), Err(DhError::ReplayedMessage { counter: 3 })));
+        bob.decrypt_next(messages.last().unwrap()).unwrap();
+        assert!(matches!(bob.decrypt_next(&messages[0]), Err(DhError::StaleMessage { counter: 0 })));
+        assert!(matches!(bob.decrypt_next(&messages[1]), Err(DhError::StaleMessage { counter: 1 })));
+        assert_eq!(bob.decrypt_next(&messages[5]).unwrap(), "5");
+    }
+
+    #[test]
+    fn exported_material_depends_on_label_and_context() {
+        let (alice, bob) = session_pair();
+        let material = alice.export_keying_material("sub-protocol", b"context", 32);
+        assert_eq!(material.len(), 32);
+        assert_eq!(bob.export_keying_material("sub-protocol", b"context", 32), material);
+        assert_eq!(alice.export_keying_material("sub-protocol", b"context", 32), material);
+        assert_ne!(alice.export_keying_material("other", b"context", 32), material);
+        assert_ne!(alice.export_keying_material("sub-protocol", b"other", 32), material);
+        assert_ne!(alice.export_keying_material("sub-protocol", b"", 32), material);
+        assert_ne!(alice.export_keying_material("ab", b"c", 32), alice.export_keying_material("a", b"bc", 32));
+
+        let other_secret = SharedSecret::from_bytes(&[10; 32]);
+        let other = Session::new(&other_secret, b"session test", Role::Initiator);
+        assert_ne!(other.export_keying_material("sub-protocol", b"context", 32), material);
+    }
+
+    #[test]
+    fn sessions_enforce_their_output_limit() {
+        let (mut alice, bob) = session_pair();
+        let mut bob = bob.with_max_output_len(16);
+        let short = alice.encrypt_next("fits in sixteen").unwrap();
+        assert_eq!(bob.decrypt_next(&short).unwrap(), "fits in sixteen");
+        let long = alice.encrypt_next(&"x".repeat(10_000)).unwrap();
+        assert!(matches!(bob.decrypt_next(&long), Err(DhError::OutputTooLarge { max_length: 16, .. })));
+    }
+
+    #[test]
+    fn a_clean_close_is_recognized() {
+        let (mut alice, mut bob) = session_pair();
+        let data = alice.encrypt_next("last words").unwrap();
+        let close = alice.close().unwrap();
+        assert!(matches!(alice.encrypt_next("too late"), Err(DhError::SessionClosed)));
+
+        assert_eq!(bob.receive(&data).unwrap(), Record::Data("last words".to_string()));
+        assert_eq!(bob.receive(&close).unwrap(), Record::Close);
+        bob.end_of_stream().unwrap();
+        assert!(matches!(bob.receive(&data), Err(DhError::SessionClosed)));
+    }
+
+    #[test]
+    fn a_stream_cut_off_before_the_close_is_truncated() {
+        let (mut alice, mut bob) = session_pair();
+        assert!(matches!(bob.end_of_stream(), Err(DhError::Truncated)));
+
+        let data = alice.encrypt_next("first").unwrap();
+        let close = alice.close().unwrap();
+        assert_eq!(bob.decrypt_next(&data).unwrap(), "first");
+        assert!(matches!(bob.end_of_stream(), Err(DhError::Truncated)));
+
+        // A forged close doesn't authenticate, so it can't stand in for the real one.
+        let mut forged = close.clone();
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(matches!(bob.receive(&forged), Err(DhError::DecryptionFailed)));
+        assert!(matches!(bob.end_of_stream(), Err(DhError::Truncated)));
+        assert!(matches!(bob.decrypt_next(&close), Err(DhError::SessionClosed)));
+        bob.end_of_stream().unwrap();
+    }
+
+    #[test]
+    fn interleaved_sends_in_both_directions_stay_in_step() {
+        let (mut alice, mut bob) = session_pair();
+        for (index, alice_sends) in [true, true, false, true, false].into_iter().enumerate() {
+            let text = format!("message {index}");
+            if alice_sends {
+                assert_eq!(bob.decrypt_next(&alice.encrypt_next(&text).unwrap()).unwrap(), text);
+            } else {
+                assert_eq!(alice.decrypt_next(&bob.encrypt_next(&text).unwrap()).unwrap(), text);
+            }
+        }
+        assert_eq!((alice.messages_sent(), alice.messages_received()), (3, 2));
+        assert_eq!((bob.messages_sent(), bob.messages_received()), (2, 3));
+
+        // Both sides' first messages use counter 0, but under different keys, so neither reads as the other's.
+        let (mut alice, mut bob) = session_pair();
+        let from_alice = alice.encrypt_next("crossing").unwrap();
+        let from_bob = bob.encrypt_next("crossing").unwrap();
+        assert_ne!(from_alice, from_bob);
+        assert!(matches!(alice.decrypt_next(&from_alice), Err(DhError::DecryptionFailed)));
+        assert_eq!(alice.decrypt_next(&from_bob).unwrap(), "crossing");
+        assert_eq!(bob.decrypt_next(&from_alice).unwrap(), "crossing");
+    }
+
+    #[test]
+    fn resumed_sessions_exchange_messages_without_a_handshake() {
+        let (alice, bob) = session_pair();
+        let alice_ticket = alice.ticket();
+        let bob_ticket = SessionTicket::from_bytes(*bob.ticket().as_bytes());
+        assert_eq!(alice_ticket.as_bytes(), bob_ticket.as_bytes());
+
+        let mut alice = Session::resume(&alice_ticket, b"resumed", Role::Initiator);
+        let mut bob = Session::resume(&bob_ticket, b"resumed", Role::Responder);
+        assert_eq!(bob.decrypt_next(&alice.encrypt_next("hello again").unwrap()).unwrap(), "hello again");
+        assert_eq!(alice.decrypt_next(&bob.encrypt_next("welcome back").unwrap()).unwrap(), "welcome back");
+
+        // The resumed keys are neither the original session's nor another ticket's.
+        let (mut original, _) = session_pair();
+        let mut original_bob = session_pair().1;
+        let from_alice = alice.encrypt_next("resumed only").unwrap();
+        assert!(matches!(original_bob.decrypt_next(&from_alice), Err(DhError::DecryptionFailed)));
+        let mut stranger = Session::resume(&SessionTicket::from_bytes([0; TICKET_LEN]), b"resumed", Role::Responder);
+        assert!(matches!(stranger.decrypt_next(&from_alice), Err(DhError::DecryptionFailed)));
+        let mut resumed_bob = Session::resume(&bob_ticket, b"resumed", Role::Responder);
+        let old_keys = original.encrypt_next("old keys").unwrap();
+        assert!(matches!(resumed_bob.decrypt_next(&old_keys), Err(DhError::DecryptionFailed)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn restored_sessions_carry_on_from_their_counters() {
+        let (mut alice, bob) = session_pair();
+        let mut bob = bob.with_max_output_len(64);
+        let sent: Vec<Vec<u8>> = (0..3).map(|i| alice.encrypt_next(&format!("before {i}")).unwrap()).collect();
+        assert_eq!(bob.decrypt_next(&sent[0]).unwrap(), "before 0");
+        assert_eq!(bob.decrypt_next(&sent[2]).unwrap(), "before 2");
+
+        let mut alice = Session::deserialize(&alice.serialize().unwrap()).unwrap();
+        let mut bob = Session::deserialize(&bob.serialize().unwrap()).unwrap();
+        assert_eq!((alice.messages_sent(), bob.messages_received()), (3, 2));
+
+        let next = alice.encrypt_next("after").unwrap();
+        assert_eq!(next[..COUNTER_LEN], 3u64.to_be_bytes());
+        assert_eq!(bob.decrypt_next(&next).unwrap(), "after");
+        assert_eq!(bob.decrypt_next(&sent[1]).unwrap(), "before 1");
+        assert!(matches!(bob.decrypt_next(&sent[2]), Err(DhError::ReplayedMessage { counter: 2 })));
+        let long = alice.encrypt_next(&"x".repeat(65)).unwrap();
+        assert!(matches!(bob.decrypt_next(&long), Err(DhError::OutputTooLarge { max_length: 64, .. })));
+
+        let reply = bob.encrypt_next("reply").unwrap();
+        assert_eq!(alice.decrypt_next(&reply).unwrap(), "reply");
+        let close = alice.close().unwrap();
+        assert_eq!(bob.receive(&close).unwrap(), Record::Close);
+        let mut alice = Session::deserialize(&alice.serialize().unwrap()).unwrap();
+        let bob = Session::deserialize(&bob.serialize().unwrap()).unwrap();
+        assert!(matches!(alice.encrypt_next("closed"), Err(DhError::SessionClosed)));
+        bob.end_of_stream().unwrap();
+    }
+
+    #[test]
+    fn message_counters_track_each_direction() {
+        let (mut alice, mut bob) = session_pair();
+        assert_eq!((alice.messages_sent(), alice.messages_received()), (0, 0));
+        for i in 0..3 {
+            let message = alice.encrypt_next(&format!("to bob {i}")).unwrap();
+            bob.decrypt_next(&message).unwrap();
+        }
+        let reply = bob.encrypt_next("to alice").unwrap();
+        alice.decrypt_next(&reply).unwrap();
+        assert_eq!((alice.messages_sent(), alice.messages_received()), (3, 1));
+        assert_eq!((bob.messages_sent(), bob.messages_received()), (1, 3));
+
+        // A rejected replay doesn't count, a close does.
+        assert!(alice.decrypt_next(&reply).is_err());
+        assert_eq!(alice.messages_received(), 1);
+        let close = bob.close().unwrap();
+        alice.receive(&close).unwrap();
+        assert_eq!((alice.messages_received(), bob.messages_sent()), (2, 2));
+
+        let (sender, receiver) = alice.split();
+        assert_eq!((sender.messages_sent(), receiver.messages_received()), (3, 2));
+    }
+
+    fn audit_log(role: Role) -> SessionAudit {
+        let mut keys = [PublicKey::from_biguint(4u32.into()), PublicKey::from_biguint(9u32.into())];
+        if role == Role::Responder {
+            keys.reverse();
+        }
+        let [local, peer] = keys;
+        SessionAudit::new(&DhParameters::modp_2048(), role, &local, &peer)
+    }
+
+    #[test]
+    fn audited_sessions_log_closes_rejections_and_noted_rekeys() {
+        let (alice, bob) = session_pair();
+        let mut alice = alice.with_audit_log(audit_log(Role::Initiator).with_suite(CipherSuite::Aes256GcmSha512));
+        let mut bob = bob.with_audit_log(audit_log(Role::Responder));
+        let message = alice.encrypt_next("hello").unwrap();
+        bob.decrypt_next(&message).unwrap();
+        assert!(bob.decrypt_next(&message).is_err());
+        bob.log_event(AuditEvent::Rekeyed { epoch: 1, messages_sent: 0, messages_received: 1 });
+        bob.receive(&alice.close().unwrap()).unwrap();
+
+        let alice_log = alice.audit_log().unwrap();
+        assert_eq!(alice_log.events, [AuditEvent::Started, AuditEvent::Closed { messages_sent: 2 }]);
+        assert_eq!(alice_log.suite.as_deref(), Some("AES-256-GCM-SHA512"));
+        let bob_log = bob.audit_log().unwrap();
+        assert_eq!((bob_log.messages_sent, bob_log.messages_received), (0, 2));
+        assert_eq!(bob_log.group, Some("modp2048"));
+        assert_eq!((bob_log.local_public_key.as_str(), bob_log.peer_public_key.as_str()), ("09", "04"));
+        assert_eq!(bob_log.events.len(), 4);
+        assert!(matches!(bob_log.events[1], AuditEvent::Rejected { messages_received: 1, .. }));
+        assert_eq!(bob_log.events[2], AuditEvent::Rekeyed { epoch: 1, messages_sent: 0, messages_received: 1 });
+        assert_eq!(bob_log.events[3], AuditEvent::PeerClosed { messages_received: 2 });
+
+        // Both ends agree on everything but their own side.
+        assert_eq!((alice_log.modulus, alice_log.generator), (bob_log.modulus, bob_log.generator));
+    }
+
+    #[test]
+    fn sessions_without_a_log_record_nothing() {
+        let (mut alice, mut bob) = session_pair();
+        alice.log_event(AuditEvent::Started);
+        bob.decrypt_next(&alice.encrypt_next("hello").unwrap()).unwrap();
+        assert!(alice.audit_log().is_none() && bob.audit_log().is_none());
+        #[cfg(feature = "serde")]
+        assert!(alice.export_transcript().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn the_transcript_exports_as_json() {
+        let (alice, _) = session_pair();
+        let mut alice = alice.with_audit_log(audit_log(Role::Initiator));
+        alice.encrypt_next("hello").unwrap();
+        alice.close().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&alice.export_transcript().unwrap()).unwrap();
+        assert_eq!(json["group"], "modp2048");
+        assert_eq!(json["local_public_key"], "04");
+        assert_eq!(json["suite"], serde_json::Value::Null);
+        assert_eq!(json["messages_sent"], 2);
+        assert_eq!(json["events"][1], serde_json::json!({ "event": "closed", "messages_sent": 2 }));
+    }
+}
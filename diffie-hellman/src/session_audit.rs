@@ -0,0 +1,81 @@
+// An audit log for one session, for operators debugging interop between two implementations. It records what both
+// ends should agree on, without anything secret: the group, both public keys, the cipher suite if one was negotiated,
+// the message counts, and the events of the session's life. Compare the two ends' logs and the first field that
+// differs says where they parted: a different group or key points at the handshake, equal counts with a rejected
+// message at the record format.
+//
+// Logging is off unless a session is given a log with `Session::with_audit_log`, or started with
+// `Handshake::into_audited_session`, which fills in the handshake's side. The session appends closes, the peer's close
+// and rejected messages itself. A `Session` never changes its keys, so layers on top that do, such as a `RecordLayer`
+// or a resumption with `Session::resume`, note it with `Session::log_event` and `AuditEvent::Rekeyed`. The log stays
+// with the `Session`: the halves from `Session::split` don't log, and `Session::serialize` doesn't save it.
+// `Session::export_transcript` gives the log as JSON.
+
+use crate::keys::PublicKey;
+use crate::params::DhParameters;
+use crate::registry::ParametersRegistry;
+use crate::session::Role;
+use crate::suite::CipherSuite;
+
+// Something that happened during the session, with the counts at the time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "event", rename_all = "kebab-case"))]
+pub enum AuditEvent {
+    // The log was started, at the beginning of the session.
+    Started,
+    // This end sent its close message.
+    Closed { messages_sent: u64 },
+    // The peer's close message arrived.
+    PeerClosed { messages_received: u64 },
+    // An incoming message was rejected, for the reason given. Replays and messages after a close count too.
+    Rejected { messages_received: u64, reason: String },
+    // The keys were changed to those of `epoch`, as noted by the layer that changed them.
+    Rekeyed { epoch: u64, messages_sent: u64, messages_received: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SessionAudit {
+    // The group's name in `ParametersRegistry`, if it is one of the named groups.
+    pub group: Option<&'static str>,
+    pub modulus_bits: u64,
+    // The generator and modulus, in hex.
+    pub generator: String,
+    pub modulus: String,
+    pub role: Role,
+    // Both public keys in hex, as `PublicKey::to_hex` writes them.
+    pub local_public_key: String,
+    pub peer_public_key: String,
+    // The suite's name, e.g. "AES-128-GCM-SHA256", for sessions whose suite was negotiated.
+    pub suite: Option<String>,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub events: Vec<AuditEvent>,
+}
+
+impl SessionAudit {
+    // A log for a session in `role` over `params`, between `local_public` and `peer_public`.
+    pub fn new(params: &DhParameters, role: Role, local_public: &PublicKey, peer_public: &PublicKey) -> Self {
+        let group = ParametersRegistry::list()
+            .into_iter()
+            .find(|name| ParametersRegistry::get(name).is_some_and(|named| named.is_compatible_with(params)));
+        SessionAudit {
+            group,
+            modulus_bits: params.bit_length(),
+            generator: params.generator.to_str_radix(16),
+            modulus: params.modulus.to_str_radix(16),
+            role,
+            local_public_key: local_public.to_hex(),
+            peer_public_key: peer_public.to_hex(),
+            suite: None,
+            messages_sent: 0,
+            messages_received: 0,
+            events: vec![AuditEvent::Started],
+        }
+    }
+
+    pub fn with_suite(mut self, suite: CipherSuite) -> Self {
+        self.suite = Some(suite.to_string());
+        self
+    }
+}
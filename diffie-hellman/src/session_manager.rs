@@ -0,0 +1,172 @@
+// Server-side bookkeeping for many handshakes and sessions at once. `begin` starts a handshake with a fresh keypair
+// and hands back a random session ID and our public key for the client. `complete` takes the client's public key and
+// turns the handshake into an established `SecureChannel`, in the responder role; the client runs the initiator side
+// itself. After that `seal` and `open` encrypt for and decrypt from that client by session ID.
+//
+// The manager is `Send + Sync`, so one instance can be shared between threads, e.g. in an `Arc`. Everything sits behind
+// one mutex, but key generation and agreement happen outside it, so slow exponentiations for one client don't hold up
+// the others. Handshakes and sessions that go unused for longer than the time to live are dropped, either when next
+// looked up or by `expire`, which a server can call periodically.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+
+use crate::channel::SecureChannel;
+use crate::error::DhError;
+use crate::group_context::GroupContext;
+use crate::keys::{PrivateKey, PublicKey};
+use crate::params::DhParameters;
+use crate::session::Role;
+
+pub const SESSION_ID_LEN: usize = 16;
+
+// Identifies one handshake and the session that follows it. IDs are random, so one client can't guess another's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId([u8; SESSION_ID_LEN]);
+
+impl SessionId {
+    // Restore an ID received from the client or saved with `as_bytes`.
+    pub fn from_bytes(bytes: [u8; SESSION_ID_LEN]) -> Self {
+        SessionId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SESSION_ID_LEN] {
+        &self.0
+    }
+}
+
+// Lowercase hex, e.g. for logs.
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+enum State {
+    // Our private key, waiting for the client's public key.
+    Pending(PrivateKey),
+    Established(Box<SecureChannel>),
+}
+
+struct Entry {
+    state: State,
+    last_used: Instant,
+}
+
+pub struct SessionManager {
+    group: GroupContext,
+    ttl: Duration,
+    entries: Mutex<HashMap<SessionId, Entry>>,
+}
+
+impl SessionManager {
+    // A manager running exchanges in `params`, which drops handshakes and sessions left unused for `ttl`. Every
+    // exchange is in the same group, so the group's precomputation is done once here, see `GroupContext`.
+    pub fn new(params: DhParameters, ttl: Duration) -> Self {
+        SessionManager { group: GroupContext::new(&params), ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    // A lock poisoned by a panic in another thread still holds consistent state: every update is a single insert or
+    // remove, or a call into a channel that leaves it unchanged when it fails.
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, Entry>> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Start a handshake with a fresh keypair. Send the ID and public key to the client, and pass its public key to
+    // `complete`.
+    pub fn begin(&self) -> Result<(SessionId, PublicKey), DhError> {
+        let (private_key, public_key) = self.group.generate_keypair()?;
+        let mut id = [0u8; SESSION_ID_LEN];
+        rand::thread_rng().fill_bytes(&mut id);
+        let id = SessionId(id);
+        self.entries().insert(id, Entry { state: State::Pending(private_key), last_used: Instant::now() });
+        tracing::debug!(session = %id, "handshake started");
+        Ok((id, public_key))
+    }
+
+    // Finish the handshake `id` with the client's public key. A key that fails validation is rejected with the
+    // handshake left waiting, so the client can try again. An ID with no waiting handshake, because it never existed,
+    // has expired or has already been completed, is `DhError::UnknownSession`.
+    pub fn complete(&self, id: SessionId, peer_public: &PublicKey) -> Result<(), DhError> {
+        peer_public.validate_in_group(self.group.params())?;
+        let private_key = {
+            let mut entries = self.entries();
+            match entries.remove(&id) {
+                Some(Entry { state: State::Pending(private_key), last_used }) if last_used.elapsed() <= self.ttl => {
+                    private_key
+                }
+                Some(entry @ Entry { state: State::Established(_), .. }) => {
+                    entries.insert(id, entry);
+                    return Err(DhError::UnknownSession);
+                }
+                _ => return Err(DhError::UnknownSession),
+            }
+        };
+        let channel = SecureChannel::new(&self.group.agree(&private_key, peer_public), Role::Responder);
+        self.entries().insert(id, Entry { state: State::Established(Box::new(channel)), last_used: Instant::now() });
+        tracing::debug!(session = %id, "session established");
+        Ok(())
+    }
+
+    // Run `f` on the established channel for `id`, refreshing its time to live.
+    fn with_channel<T>(
+        &self,
+        id: SessionId,
+        f: impl FnOnce(&mut SecureChannel) -> Result<T, DhError>,
+    ) -> Result<T, DhError> {
+        let mut entries = self.entries();
+        let entry = entries.get_mut(&id).ok_or(DhError::UnknownSession)?;
+        if entry.last_used.elapsed() > self.ttl {
+            entries.remove(&id);
+            return Err(DhError::UnknownSession);
+        }
+        let State::Established(channel) = &mut entry.state else {
+            return Err(DhError::NoSharedSecret);
+        };
+        let result = f(channel)?;
+        entry.last_used = Instant::now();
+        Ok(result)
+    }
+
+    // Encrypt the next message to the client of session `id`. An unknown or expired ID is `DhError::UnknownSession`,
+    // and one whose handshake hasn't been completed `DhError::NoSharedSecret`.
+    pub fn seal(&self, id: SessionId, plain_bytes: &[u8]) -> Result<Vec<u8>, DhError> {
+        self.with_channel(id, |channel| channel.seal(plain_bytes))
+    }
+
+    // Decrypt the next message from the client of session `id`, with the errors of `seal` and of
+    // `SecureChannel::open`.
+    pub fn open(&self, id: SessionId, message: &[u8]) -> Result<Vec<u8>, DhError> {
+        self.with_channel(id, |channel| channel.open(message))
+    }
+
+    // End a handshake or session early. Returns whether `id` was known.
+    pub fn remove(&self, id: SessionId) -> bool {
+        self.entries().remove(&id).is_some()
+    }
+
+    // Drop every handshake and session unused for longer than the time to live, and return how many went.
+    pub fn expire(&self) -> usize {
+        let mut entries = self.entries();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.last_used.elapsed() <= self.ttl);
+        let expired = before - entries.len();
+        if expired > 0 {
+            tracing::debug!(expired, "dropped idle sessions");
+        }
+        expired
+    }
+
+    // How many handshakes and sessions are held, including any that have expired but not been dropped yet.
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+}
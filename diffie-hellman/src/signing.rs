@@ -0,0 +1,66 @@
+// Integrity without secrecy: an HMAC-SHA256 tag over a message sent in the clear, for control messages that the peer
+// must be able to trust but that don't need hiding. The tag is keyed from the `DerivedKeys` of `agree_and_derive` or
+// `derive_session_keys`, so only the two parties to the exchange can make or check one.
+//
+// The tag isn't keyed with `mac_key` itself, which `seal_with_keys` already uses for its encrypt-then-MAC tags. With
+// one key for both, the tag on a sealed message would also pass as the tag on its IV and ciphertext sent as a signed
+// message. Instead the signing key is expanded from `mac_key` with HKDF under its own label.
+//
+// Wire format: the tag is 32 bytes, sent alongside the message however the protocol frames it.
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::keys::DerivedKeys;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNING_LABEL: &str = "hmac-sha256 message signing key";
+
+// An HMAC-SHA256 tag from `DerivedKeys::sign`. Tags are public, so they can be logged and compared freely; equality
+// is still checked in constant time.
+#[derive(Clone, Copy, Debug, Eq)]
+pub struct Tag([u8; Tag::LEN]);
+
+impl Tag {
+    pub const LEN: usize = 32;
+
+    // A tag received from the peer. Anything but `Tag::LEN` bytes is `DhError::MalformedMessage`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DhError> {
+        Ok(Tag(bytes.try_into().map_err(|_| DhError::MalformedMessage)?))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; Tag::LEN] {
+        &self.0
+    }
+}
+
+impl PartialEq for Tag {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl DerivedKeys {
+    // Authenticate `data` without encrypting it. The peer checks the tag with `verify` and the same keys.
+    pub fn sign(&self, data: &[u8]) -> Tag {
+        Tag(self.signing_mac().chain_update(data).finalize().into_bytes().into())
+    }
+
+    // Check a tag made by `sign` over `data`, in constant time. A tag that doesn't match, because the data was altered
+    // or signed under other keys, is `DhError::InvalidTag`.
+    pub fn verify(&self, data: &[u8], tag: &Tag) -> Result<(), DhError> {
+        self.signing_mac().chain_update(data).verify_slice(tag.as_bytes()).map_err(|_| DhError::InvalidTag)
+    }
+
+    fn signing_mac(&self) -> HmacSha256 {
+        let hkdf = Hkdf::<Sha256>::from_prk(&self.mac_key).expect("MAC key is a full SHA-256 output");
+        let mut key = Zeroizing::new([0u8; 32]);
+        hkdf.expand(SIGNING_LABEL.as_bytes(), &mut key[..]).expect("32 bytes is a valid HKDF output length");
+        HmacSha256::new_from_slice(&key[..]).expect("HMAC takes keys of any length")
+    }
+}
@@ -0,0 +1,366 @@
+// Chunked authenticated encryption for data too large to encrypt as one GCM message. `StreamSealer` encrypts a stream
+// chunk by chunk and `StreamOpener` verifies and decrypts the chunks as they arrive, so a reader can start on the data
+// before the end of the stream. The key is derived from the shared secret and the nonces from a per-stream salt, as in
+// `Session`, and each chunk's nonce comes from its sequence number, so chunks can't be reordered, dropped or replayed
+// without failing authentication. `EncryptingWriter` and `DecryptingReader` wrap the two around `Write` and `Read`
+// streams.
+//
+// Each sealed chunk is the GCM output for a flag byte followed by the chunk's data. The flag marks the last chunk, so a
+// stream cut short at a chunk boundary is reported as truncated instead of ending quietly. On the wire the writer puts
+// a 4-byte big-endian length in front of every chunk.
+
+use std::io::{self, Read, Write};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::kdf::derive_key;
+use crate::keys::SharedSecret;
+use crate::nonce::NonceSequence;
+
+// Plaintext bytes per chunk. The writer buffers this much before encrypting, and the reader needs as much memory.
+pub const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+const LENGTH_LEN: usize = 4;
+const FLAG_LEN: usize = 1;
+const MORE_CHUNKS: u8 = 0;
+const LAST_CHUNK: u8 = 1;
+
+fn stream_cipher(secret: &SharedSecret, salt: &[u8]) -> (Aes128Gcm, NonceSequence) {
+    let key = Zeroizing::new(derive_key(secret.as_biguint(), "stream encryption key", 16));
+    let cipher = Aes128Gcm::new_from_slice(&key).expect("derived key is 16 bytes");
+    (cipher, NonceSequence::new(salt))
+}
+
+// Encrypts a stream one chunk at a time. Chunks may be of any size; `seal_last` ends the stream.
+pub struct StreamSealer {
+    cipher: Aes128Gcm,
+    nonces: NonceSequence,
+}
+
+impl StreamSealer {
+    // Both ends pass the same secret and salt; a salt must never be used for two streams under one secret, since that
+    // would repeat nonces.
+    pub fn new(secret: &SharedSecret, salt: &[u8]) -> Self {
+        let (cipher, nonces) = stream_cipher(secret, salt);
+        StreamSealer { cipher, nonces }
+    }
+
+    // Encrypt the next chunk of the stream.
+    pub fn seal_chunk(&mut self, chunk: &[u8]) -> Result<Vec<u8>, DhError> {
+        self.seal_with_flag(MORE_CHUNKS, chunk)
+    }
+
+    // Encrypt the final chunk, which may be empty. Without it the opener reports the stream as truncated.
+    pub fn seal_last(mut self, chunk: &[u8]) -> Result<Vec<u8>, DhError> {
+        self.seal_with_flag(LAST_CHUNK, chunk)
+    }
+
+    fn seal_with_flag(&mut self, flag: u8, chunk: &[u8]) -> Result<Vec<u8>, DhError> {
+        let nonce = self.nonces.next_nonce()?;
+        let mut plain_bytes = Vec::with_capacity(FLAG_LEN + chunk.len());
+        plain_bytes.push(flag);
+        plain_bytes.extend_from_slice(chunk);
+        self.cipher.encrypt(Nonce::from_slice(&nonce), plain_bytes.as_slice()).map_err(|_| DhError::EncryptionFailed)
+    }
+}
+
+// Verifies and decrypts the chunks of a `StreamSealer` stream in order. A chunk that is tampered with, out of place or
+// from another stream fails with `DhError::DecryptionFailed`, and the opener still expects the chunk it was waiting
+// for. Call `finish` at the end of the input to catch a stream whose last chunk never arrived.
+pub struct StreamOpener {
+    cipher: Aes128Gcm,
+    nonces: NonceSequence,
+    counter: u64,
+    finished: bool,
+}
+
+impl StreamOpener {
+    // With the secret and salt the stream was sealed with.
+    pub fn new(secret: &SharedSecret, salt: &[u8]) -> Self {
+        let (cipher, nonces) = stream_cipher(secret, salt);
+        StreamOpener { cipher, nonces, counter: 0, finished: false }
+    }
+
+    // Decrypt the next chunk. Anything after the last chunk is `DhError::MalformedMessage`.
+    pub fn open_chunk(&mut self, sealed: &[u8]) -> Result<Vec<u8>, DhError> {
+        if self.finished {
+            return Err(DhError::MalformedMessage);
+        }
+        let nonce = self.nonces.nonce_for(self.counter);
+        let mut plain_bytes =
+            self.cipher.decrypt(Nonce::from_slice(&nonce), sealed).map_err(|_| DhError::DecryptionFailed)?;
+        let chunk = plain_bytes.split_off(FLAG_LEN.min(plain_bytes.len()));
+        match plain_bytes.first() {
+            Some(&MORE_CHUNKS) => {}
+            Some(&LAST_CHUNK) => self.finished = true,
+            _ => return Err(DhError::DecryptionFailed),
+        }
+        self.counter += 1;
+        Ok(chunk)
+    }
+
+    // Whether the last chunk has been opened.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Check that the stream was complete: `DhError::Truncated` if its last chunk hasn't been opened.
+    pub fn finish(self) -> Result<(), DhError> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(DhError::Truncated)
+        }
+    }
+}
+
+fn invalid_data(error: DhError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// End of file in the middle of a stream means it was cut off.
+fn truncated(error: io::Error) -> io::Error {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, DhError::Truncated),
+        _ => error,
+    }
+}
+
+// Encrypts everything written to it and writes the chunks to the inner writer. Call `finish` when done: it writes the
+// last chunk, without which the reader reports the stream as truncated. Dropping the writer without `finish` discards
+// anything still buffered.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    sealer: StreamSealer,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    // Wrap `inner`. Both ends pass the same secret and salt; a salt must never be used for two streams under one
+    // secret, since that would repeat nonces.
+    pub fn new(inner: W, secret: &SharedSecret, salt: &[u8]) -> Self {
+        let sealer = StreamSealer::new(secret, salt);
+        EncryptingWriter { inner, sealer, buffer: Vec::with_capacity(STREAM_CHUNK_LEN) }
+    }
+
+    // Write out whatever is buffered as the last chunk, flush, and hand back the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(LAST_CHUNK)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_chunk(&mut self, flag: u8) -> io::Result<()> {
+        let ciphertext = self.sealer.seal_with_flag(flag, &self.buffer).map_err(invalid_data)?;
+        self.buffer.clear();
+        self.inner.write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        self.inner.write_all(&ciphertext)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == STREAM_CHUNK_LEN {
+            self.write_chunk(MORE_CHUNKS)?;
+        }
+        let len = buf.len().min(STREAM_CHUNK_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    // Sends what is buffered as a (short) chunk right away, e.g. so an interactive peer sees it.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_chunk(MORE_CHUNKS)?;
+        }
+        self.inner.flush()
+    }
+}
+
+// Reads chunks written by `EncryptingWriter` from the inner reader and returns their plaintext. A chunk that fails
+// authentication is an `InvalidData` error; a stream that ends before its last chunk is an `UnexpectedEof` error
+// wrapping `DhError::Truncated`.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    opener: StreamOpener,
+    // Decrypted bytes not yet returned, and how far into them we are.
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    // Wrap `inner`, with the secret and salt the stream was written with.
+    pub fn new(inner: R, secret: &SharedSecret, salt: &[u8]) -> Self {
+        DecryptingReader { inner, opener: StreamOpener::new(secret, salt), pending: Vec::new(), position: 0 }
+    }
+
+    // Hand back the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut length_bytes = [0u8; LENGTH_LEN];
+        self.inner.read_exact(&mut length_bytes).map_err(truncated)?;
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        let max_length = FLAG_LEN + STREAM_CHUNK_LEN + TAG_LEN;
+        if length > max_length {
+            return Err(invalid_data(DhError::FrameTooLarge { length, max_length }));
+        }
+        let mut ciphertext = vec![0u8; length];
+        self.inner.read_exact(&mut ciphertext).map_err(truncated)?;
+
+        self.pending = self.opener.open_chunk(&ciphertext).map_err(invalid_data)?;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.pending.len() {
+            if self.opener.is_finished() {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+        let len = buf.len().min(self.pending.len() - self.position);
+        buf[..len].copy_from_slice(&self.pending[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CONTROL_CHARACTER_TEXT;
+
+    #[test]
+    fn control_characters_round_trip() {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let (first, last) = CONTROL_CHARACTER_TEXT.as_bytes().split_at(6);
+        let mut sealer = StreamSealer::new(&secret, b"stream test");
+        let sealed = [sealer.seal_chunk(first).unwrap(), sealer.seal_last(last).unwrap()];
+        let mut opener = StreamOpener::new(&secret, b"stream test");
+        let opened: Vec<u8> = sealed.iter().flat_map(|chunk| opener.open_chunk(chunk).unwrap()).collect();
+        opener.finish().unwrap();
+        assert_eq!(String::from_utf8(opened).unwrap(), CONTROL_CHARACTER_TEXT);
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &secret, b"stream test");
+        writer.write_all(CONTROL_CHARACTER_TEXT.as_bytes()).unwrap();
+        let sealed = writer.finish().unwrap();
+        let mut text = String::new();
+        DecryptingReader::new(&sealed[..], &secret, b"stream test").read_to_string(&mut text).unwrap();
+        assert_eq!(text, CONTROL_CHARACTER_TEXT);
+    }
+
+    fn written(data: &[u8], flush_at: Option<usize>) -> Vec<u8> {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let mut writer = EncryptingWriter::new(Vec::new(), &secret, b"stream test");
+        let (first, second) = data.split_at(flush_at.unwrap_or(0));
+        writer.write_all(first).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(second).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn read_back(sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plain_bytes = Vec::new();
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        DecryptingReader::new(sealed, &secret, b"stream test").read_to_end(&mut plain_bytes)?;
+        Ok(plain_bytes)
+    }
+
+    #[test]
+    fn data_piped_through_the_writer_and_reader_comes_back() {
+        let data: Vec<u8> = (0..STREAM_CHUNK_LEN * 7 / 2).map(|i| (i * 31 % 251) as u8).collect();
+        for flush_at in [None, Some(1), Some(STREAM_CHUNK_LEN + 5)] {
+            assert_eq!(read_back(&written(&data, flush_at)).unwrap(), data, "{flush_at:?}");
+        }
+        assert!(read_back(&written(&[], None)).unwrap().is_empty());
+
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let mut reader = DecryptingReader::new(io::Cursor::new(written(&data, None)), &secret, b"stream test");
+        let mut copied = Vec::new();
+        io::copy(&mut reader, &mut copied).unwrap();
+        assert_eq!(copied, data);
+        assert_eq!(reader.into_inner().position() as usize, written(&data, None).len());
+    }
+
+    #[test]
+    fn cut_or_altered_streams_fail() {
+        let data = vec![0x42; STREAM_CHUNK_LEN + 100];
+        let sealed = written(&data, None);
+        let first_chunk_len = LENGTH_LEN + FLAG_LEN + STREAM_CHUNK_LEN + TAG_LEN;
+
+        let truncated = read_back(&sealed[..first_chunk_len]).unwrap_err();
+        assert_eq!(truncated.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read_back(&sealed[..sealed.len() - 1]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut tampered = sealed.clone();
+        tampered[first_chunk_len + LENGTH_LEN + 3] ^= 1;
+        assert_eq!(read_back(&tampered).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut oversized = sealed;
+        oversized[..LENGTH_LEN].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(read_back(&oversized).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn sealed_chunks(count: usize) -> Vec<Vec<u8>> {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let mut sealer = StreamSealer::new(&secret, b"chunk test");
+        let mut chunks: Vec<_> = (0..count - 1).map(|i| sealer.seal_chunk(&[i as u8; 100]).unwrap()).collect();
+        chunks.push(sealer.seal_last(b"end").unwrap());
+        chunks
+    }
+
+    fn opener() -> StreamOpener {
+        StreamOpener::new(&SharedSecret::from_bytes(&[9; 32]), b"chunk test")
+    }
+
+    #[test]
+    fn many_chunks_open_in_order() {
+        let chunks = sealed_chunks(50);
+        let mut opener = opener();
+        for (i, chunk) in chunks[..49].iter().enumerate() {
+            assert_eq!(opener.open_chunk(chunk).unwrap(), [i as u8; 100]);
+            assert!(!opener.is_finished());
+        }
+        assert_eq!(opener.open_chunk(&chunks[49]).unwrap(), b"end");
+        assert!(opener.is_finished());
+        assert!(matches!(opener.open_chunk(&chunks[0]), Err(DhError::MalformedMessage)));
+        opener.finish().unwrap();
+    }
+
+    #[test]
+    fn reordered_dropped_or_missing_chunks_are_detected() {
+        let chunks = sealed_chunks(10);
+
+        let mut swapped = opener();
+        swapped.open_chunk(&chunks[0]).unwrap();
+        assert!(matches!(swapped.open_chunk(&chunks[2]), Err(DhError::DecryptionFailed)));
+        // The opener is still waiting for the chunk that was skipped.
+        swapped.open_chunk(&chunks[1]).unwrap();
+
+        let mut dropped = opener();
+        for chunk in chunks.iter().take(4) {
+            dropped.open_chunk(chunk).unwrap();
+        }
+        assert!(chunks[5..].iter().all(|chunk| matches!(dropped.open_chunk(chunk), Err(DhError::DecryptionFailed))));
+
+        let mut replayed = opener();
+        replayed.open_chunk(&chunks[0]).unwrap();
+        assert!(matches!(replayed.open_chunk(&chunks[0]), Err(DhError::DecryptionFailed)));
+
+        let mut cut_short = opener();
+        for chunk in &chunks[..9] {
+            cut_short.open_chunk(chunk).unwrap();
+        }
+        assert!(matches!(cut_short.finish(), Err(DhError::Truncated)));
+    }
+}
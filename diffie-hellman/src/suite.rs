@@ -0,0 +1,405 @@
+// Negotiating the symmetric cipher and KDF hash during the handshake instead of fixing them in advance. The initiator
+// sends a `SuiteOffer` listing the cipher suites it accepts, most preferred first. The responder picks one with
+// `SuiteOffer::select` and sends back its two-byte ID, which the initiator checks with `SuiteOffer::accept`. The
+// responder's preference order decides, as a TLS server's usually does; a responder that would rather follow the
+// initiator can pass `SuiteOffer::suites` as its preferences.
+//
+// An attacker who strips the strong suites out of the offer in transit could push both sides onto the weakest one
+// they share. To stop that, both sides record the offer and the choice in the handshake transcript with
+// `SuiteOffer::bind`, and `SuiteChannel` derives its keys through the transcript. If the offer was tampered with, the
+// two sides end up with different keys and the first message fails to decrypt. To find out before sending anything,
+// confirm the key with `confirmation_tag` over the same transcript.
+//
+// Offer layout: count (1 byte) || suite IDs (2 bytes each, big-endian). Each ID is a cipher byte (1 AES-128-GCM,
+// 2 AES-256-GCM, 3 ChaCha20-Poly1305) followed by a hash byte (1 SHA-256, 2 SHA-512). IDs this end doesn't know are
+// passed over, so a newer peer can offer suites an older one hasn't heard of, but they still count for the transcript.
+//
+// `SuiteChannel` message layout: ciphertext || tag (16 bytes). The nonce is a per-direction IV XORed with the
+// message's sequence number, so messages have to arrive in order, as with `SecureChannel`.
+
+use std::fmt;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Nonce};
+use chacha20poly1305::ChaCha20Poly1305;
+use zeroize::Zeroizing;
+
+use crate::error::DhError;
+use crate::gcm::TAG_LEN;
+use crate::kdf::{derive_key_with_hash_and_salt, HkdfHash};
+use crate::keys::SharedSecret;
+use crate::nonce::NonceSequence;
+use crate::session::Role;
+use crate::transcript::Transcript;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherSuite {
+    Aes128GcmSha256,
+    Aes128GcmSha512,
+    Aes256GcmSha256,
+    Aes256GcmSha512,
+    ChaCha20Poly1305Sha256,
+    ChaCha20Poly1305Sha512,
+}
+
+impl CipherSuite {
+    // Every suite, strongest first. This is the order `SuiteOffer::default` offers them in.
+    pub const ALL: [CipherSuite; 6] = [
+        CipherSuite::Aes256GcmSha512,
+        CipherSuite::ChaCha20Poly1305Sha512,
+        CipherSuite::Aes256GcmSha256,
+        CipherSuite::ChaCha20Poly1305Sha256,
+        CipherSuite::Aes128GcmSha512,
+        CipherSuite::Aes128GcmSha256,
+    ];
+
+    // The ID the suite goes by on the wire.
+    pub fn id(self) -> u16 {
+        match self {
+            CipherSuite::Aes128GcmSha256 => 0x0101,
+            CipherSuite::Aes128GcmSha512 => 0x0102,
+            CipherSuite::Aes256GcmSha256 => 0x0201,
+            CipherSuite::Aes256GcmSha512 => 0x0202,
+            CipherSuite::ChaCha20Poly1305Sha256 => 0x0301,
+            CipherSuite::ChaCha20Poly1305Sha512 => 0x0302,
+        }
+    }
+
+    // The suite with wire ID `id`, if this end knows it.
+    pub fn from_id(id: u16) -> Option<CipherSuite> {
+        CipherSuite::ALL.into_iter().find(|suite| suite.id() == id)
+    }
+
+    // The hash HKDF runs with when deriving the suite's keys.
+    pub fn hash(self) -> HkdfHash {
+        match self {
+            CipherSuite::Aes128GcmSha256 | CipherSuite::Aes256GcmSha256 | CipherSuite::ChaCha20Poly1305Sha256 => {
+                HkdfHash::Sha256
+            }
+            CipherSuite::Aes128GcmSha512 | CipherSuite::Aes256GcmSha512 | CipherSuite::ChaCha20Poly1305Sha512 => {
+                HkdfHash::Sha512
+            }
+        }
+    }
+
+    // The cipher's key size in bytes.
+    pub fn key_len(self) -> usize {
+        match self {
+            CipherSuite::Aes128GcmSha256 | CipherSuite::Aes128GcmSha512 => 16,
+            _ => 32,
+        }
+    }
+
+    // The accept message: the suite's ID, big-endian.
+    pub fn to_bytes(self) -> [u8; 2] {
+        self.id().to_be_bytes()
+    }
+}
+
+// Names in the style of TLS, e.g. "AES-128-GCM-SHA256".
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CipherSuite::Aes128GcmSha256 => "AES-128-GCM-SHA256",
+            CipherSuite::Aes128GcmSha512 => "AES-128-GCM-SHA512",
+            CipherSuite::Aes256GcmSha256 => "AES-256-GCM-SHA256",
+            CipherSuite::Aes256GcmSha512 => "AES-256-GCM-SHA512",
+            CipherSuite::ChaCha20Poly1305Sha256 => "CHACHA20-POLY1305-SHA256",
+            CipherSuite::ChaCha20Poly1305Sha512 => "CHACHA20-POLY1305-SHA512",
+        })
+    }
+}
+
+// The list of suites the initiator accepts, most preferred first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuiteOffer {
+    // The IDs as offered, including any this end doesn't know.
+    ids: Vec<u16>,
+}
+
+impl SuiteOffer {
+    // An offer of `preferences`, in that order. Repeats are dropped. An empty list is `DhError::NoCommonCipherSuite`.
+    pub fn new(preferences: &[CipherSuite]) -> Result<Self, DhError> {
+        let mut ids: Vec<u16> = Vec::with_capacity(preferences.len());
+        for suite in preferences {
+            if !ids.contains(&suite.id()) {
+                ids.push(suite.id());
+            }
+        }
+        if ids.is_empty() {
+            return Err(DhError::NoCommonCipherSuite);
+        }
+        Ok(SuiteOffer { ids })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.ids.len() as u8];
+        for id in &self.ids {
+            bytes.extend_from_slice(&id.to_be_bytes());
+        }
+        bytes
+    }
+
+    // Read an offer from the initiator. A length that doesn't match the count is `DhError::MalformedMessage`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DhError> {
+        let Some((&count, ids)) = bytes.split_first() else {
+            return Err(DhError::MalformedMessage);
+        };
+        if ids.len() != 2 * count as usize {
+            return Err(DhError::MalformedMessage);
+        }
+        Ok(SuiteOffer { ids: ids.chunks_exact(2).map(|id| u16::from_be_bytes([id[0], id[1]])).collect() })
+    }
+
+    // The offered suites this end knows, in the initiator's order.
+    pub fn suites(&self) -> Vec<CipherSuite> {
+        self.ids.iter().filter_map(|&id| CipherSuite::from_id(id)).collect()
+    }
+
+    // The responder's choice: the first of its `preferences` that was offered. With none in common this is
+    // `DhError::NoCommonCipherSuite`.
+    pub fn select(&self, preferences: &[CipherSuite]) -> Result<CipherSuite, DhError> {
+        preferences.iter().copied().find(|suite| self.ids.contains(&suite.id())).ok_or(DhError::NoCommonCipherSuite)
+    }
+
+    // The initiator's check of the responder's accept message. A suite that wasn't offered is
+    // `DhError::CipherSuiteNotOffered`, and a message that isn't two bytes `DhError::MalformedMessage`.
+    pub fn accept(&self, reply: &[u8]) -> Result<CipherSuite, DhError> {
+        let id = u16::from_be_bytes(reply.try_into().map_err(|_| DhError::MalformedMessage)?);
+        match CipherSuite::from_id(id) {
+            Some(suite) if self.ids.contains(&id) => Ok(suite),
+            _ => Err(DhError::CipherSuiteNotOffered { suite: id }),
+        }
+    }
+
+    // Record the offer and the chosen suite in `transcript`. Both sides call this with the same values, after the
+    // handshake messages and before deriving keys from the transcript.
+    pub fn bind(&self, transcript: &mut Transcript, suite: CipherSuite) {
+        transcript.append(&self.to_bytes());
+        transcript.append(&suite.to_bytes());
+    }
+}
+
+impl Default for SuiteOffer {
+    fn default() -> Self {
+        SuiteOffer { ids: CipherSuite::ALL.iter().map(|suite| suite.id()).collect() }
+    }
+}
+
+// The AES ciphers hold expanded key schedules several times the size of ChaCha20's key, so they are boxed.
+enum SuiteCipher {
+    Aes128Gcm(Box<Aes128Gcm>),
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl SuiteCipher {
+    fn encrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> Result<Vec<u8>, DhError> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            SuiteCipher::Aes128Gcm(cipher) => cipher.encrypt(nonce, payload),
+            SuiteCipher::Aes256Gcm(cipher) => cipher.encrypt(nonce, payload),
+            SuiteCipher::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce, payload),
+        }
+        .map_err(|_| DhError::EncryptionFailed)
+    }
+
+    fn decrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> Result<Vec<u8>, DhError> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            SuiteCipher::Aes128Gcm(cipher) => cipher.decrypt(nonce, payload),
+            SuiteCipher::Aes256Gcm(cipher) => cipher.decrypt(nonce, payload),
+            SuiteCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce, payload),
+        }
+        .map_err(|_| DhError::DecryptionFailed)
+    }
+}
+
+// The cipher and nonces for messages sent by `role`, with keys derived through the transcript under the suite's hash.
+fn direction(
+    suite: CipherSuite,
+    secret: &SharedSecret,
+    transcript: &Transcript,
+    role: Role,
+) -> (SuiteCipher, NonceSequence) {
+    let context = transcript.hash();
+    let derive = |purpose: &str, len| {
+        let label = format!("{} suite {}", role.label(), purpose);
+        Zeroizing::new(derive_key_with_hash_and_salt(secret.as_biguint(), &label, suite.hash(), Some(&context), len))
+    };
+    let key = derive("encryption key", suite.key_len());
+    let cipher = match suite {
+        CipherSuite::Aes128GcmSha256 | CipherSuite::Aes128GcmSha512 => {
+            SuiteCipher::Aes128Gcm(Box::new(Aes128Gcm::new_from_slice(&key).expect("derived key is 16 bytes")))
+        }
+        CipherSuite::Aes256GcmSha256 | CipherSuite::Aes256GcmSha512 => {
+            SuiteCipher::Aes256Gcm(Box::new(Aes256Gcm::new_from_slice(&key).expect("derived key is 32 bytes")))
+        }
+        CipherSuite::ChaCha20Poly1305Sha256 | CipherSuite::ChaCha20Poly1305Sha512 => {
+            SuiteCipher::ChaCha20Poly1305(ChaCha20Poly1305::new_from_slice(&key).expect("derived key is 32 bytes"))
+        }
+    };
+    (cipher, NonceSequence::new(&derive("nonce seed", 32)))
+}
+
+// A two-way channel encrypted with a negotiated suite.
+pub struct SuiteChannel {
+    suite: CipherSuite,
+    send_cipher: SuiteCipher,
+    send_nonces: NonceSequence,
+    receive_cipher: SuiteCipher,
+    receive_nonces: NonceSequence,
+    // The sequence number of the next message to receive.
+    receive_sequence: u64,
+}
+
+impl SuiteChannel {
+    // A channel for `suite`, with keys bound to `transcript`, which should already include the negotiation from
+    // `SuiteOffer::bind`. Both ends pass the same secret, suite and transcript, and opposite roles.
+    pub fn new(suite: CipherSuite, secret: &SharedSecret, transcript: &Transcript, role: Role) -> Self {
+        let (send_cipher, send_nonces) = direction(suite, secret, transcript, role);
+        let (receive_cipher, receive_nonces) = direction(suite, secret, transcript, role.peer());
+        SuiteChannel { suite, send_cipher, send_nonces, receive_cipher, receive_nonces, receive_sequence: 0 }
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        self.suite
+    }
+
+    // Encrypt the next outgoing message.
+    pub fn seal(&mut self, plain_bytes: &[u8]) -> Result<Vec<u8>, DhError> {
+        let nonce = self.send_nonces.next_nonce()?;
+        self.send_cipher.encrypt(&nonce, Payload { msg: plain_bytes, aad: &self.suite.to_bytes() })
+    }
+
+    // Verify and decrypt the next incoming message. Anything other than the message the peer sent next, including
+    // tampering, replays and messages under keys from a different transcript, fails with `DhError::DecryptionFailed`,
+    // after which the channel still expects that message.
+    pub fn open(&mut self, message: &[u8]) -> Result<Vec<u8>, DhError> {
+        if message.len() < TAG_LEN {
+            return Err(DhError::DecryptionFailed);
+        }
+        let nonce = self.receive_nonces.nonce_for(self.receive_sequence);
+        let plain_bytes = self.receive_cipher.decrypt(&nonce, Payload { msg: message, aad: &self.suite.to_bytes() })?;
+        self.receive_sequence += 1;
+        Ok(plain_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::DhParameters;
+
+    fn transcript() -> Transcript {
+        Transcript::new(&DhParameters::from_u64_allow_insecure(2, 23).unwrap())
+    }
+
+    // Both ends of a channel for `suite`, after binding `offer` into the same transcript.
+    fn channels(offer: &SuiteOffer, suite: CipherSuite) -> (SuiteChannel, SuiteChannel) {
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let mut transcript = transcript();
+        offer.bind(&mut transcript, suite);
+        let initiator = SuiteChannel::new(suite, &secret, &transcript, Role::Initiator);
+        let responder = SuiteChannel::new(suite, &secret, &transcript, Role::Responder);
+        (initiator, responder)
+    }
+
+    #[test]
+    fn suites_round_trip_through_their_ids() {
+        for suite in CipherSuite::ALL {
+            assert_eq!(CipherSuite::from_id(suite.id()), Some(suite));
+            assert_eq!(u16::from_be_bytes(suite.to_bytes()), suite.id());
+        }
+        assert_eq!(CipherSuite::from_id(0x0401), None);
+        assert_eq!(CipherSuite::Aes128GcmSha256.key_len(), 16);
+        assert_eq!(CipherSuite::ChaCha20Poly1305Sha512.key_len(), 32);
+        assert_eq!(CipherSuite::Aes256GcmSha256.to_string(), "AES-256-GCM-SHA256");
+    }
+
+    #[test]
+    fn the_responders_preference_decides() {
+        let offer = SuiteOffer::new(&[CipherSuite::Aes128GcmSha256, CipherSuite::ChaCha20Poly1305Sha512]).unwrap();
+        let responder = [CipherSuite::ChaCha20Poly1305Sha512, CipherSuite::Aes128GcmSha256];
+        assert_eq!(offer.select(&responder).unwrap(), CipherSuite::ChaCha20Poly1305Sha512);
+        assert_eq!(offer.select(&responder[1..]).unwrap(), CipherSuite::Aes128GcmSha256);
+        assert!(matches!(offer.select(&[CipherSuite::Aes256GcmSha512]), Err(DhError::NoCommonCipherSuite)));
+        assert!(matches!(SuiteOffer::new(&[]), Err(DhError::NoCommonCipherSuite)));
+    }
+
+    #[test]
+    fn accept_refuses_a_suite_that_was_not_offered() {
+        let offer = SuiteOffer::new(&[CipherSuite::Aes256GcmSha512]).unwrap();
+        assert_eq!(offer.accept(&CipherSuite::Aes256GcmSha512.to_bytes()).unwrap(), CipherSuite::Aes256GcmSha512);
+        assert!(matches!(
+            offer.accept(&CipherSuite::Aes128GcmSha256.to_bytes()),
+            Err(DhError::CipherSuiteNotOffered { suite: 0x0101 })
+        ));
+        assert!(matches!(offer.accept(&[0x02]), Err(DhError::MalformedMessage)));
+    }
+
+    #[test]
+    fn offers_round_trip_and_skip_unknown_ids() {
+        let offer = SuiteOffer::new(&[CipherSuite::Aes128GcmSha512, CipherSuite::Aes128GcmSha512]).unwrap();
+        assert_eq!(offer.to_bytes(), [1, 0x01, 0x02]);
+        assert_eq!(SuiteOffer::from_bytes(&offer.to_bytes()).unwrap(), offer);
+        assert_eq!(SuiteOffer::default().suites(), CipherSuite::ALL);
+
+        let received = SuiteOffer::from_bytes(&[2, 0x09, 0x09, 0x03, 0x01]).unwrap();
+        assert_eq!(received.suites(), [CipherSuite::ChaCha20Poly1305Sha256]);
+        assert_eq!(received.to_bytes(), [2, 0x09, 0x09, 0x03, 0x01]);
+        assert!(matches!(SuiteOffer::from_bytes(&[2, 0x01, 0x01]), Err(DhError::MalformedMessage)));
+        assert!(matches!(SuiteOffer::from_bytes(&[]), Err(DhError::MalformedMessage)));
+    }
+
+    #[test]
+    fn every_suite_carries_messages_both_ways() {
+        let offer = SuiteOffer::default();
+        for suite in CipherSuite::ALL {
+            let (mut initiator, mut responder) = channels(&offer, suite);
+            let first = initiator.seal(b"hello").unwrap();
+            let second = initiator.seal(b"again").unwrap();
+            assert_eq!(responder.open(&first).unwrap(), b"hello");
+            assert_eq!(responder.open(&second).unwrap(), b"again");
+            assert_eq!(initiator.open(&responder.seal(b"reply").unwrap()).unwrap(), b"reply");
+            assert_eq!(responder.suite(), suite);
+        }
+    }
+
+    #[test]
+    fn tampering_replay_and_reordering_fail() {
+        let (mut initiator, mut responder) = channels(&SuiteOffer::default(), CipherSuite::Aes256GcmSha512);
+        let first = initiator.seal(b"first").unwrap();
+        let second = initiator.seal(b"second").unwrap();
+        assert!(matches!(responder.open(&second), Err(DhError::DecryptionFailed)));
+        let mut altered = first.clone();
+        altered[0] ^= 1;
+        assert!(matches!(responder.open(&altered), Err(DhError::DecryptionFailed)));
+        assert!(matches!(responder.open(&first[..TAG_LEN - 1]), Err(DhError::DecryptionFailed)));
+        assert_eq!(responder.open(&first).unwrap(), b"first");
+        assert!(matches!(responder.open(&first), Err(DhError::DecryptionFailed)));
+        assert_eq!(responder.open(&second).unwrap(), b"second");
+    }
+
+    #[test]
+    fn a_downgraded_offer_gives_keys_that_do_not_match() {
+        // An attacker strips the strong suites from the offer, so the responder picks the weakest one the initiator
+        // would also have accepted. The two ends bind different offers and the first message doesn't open.
+        let secret = SharedSecret::from_bytes(&[9; 32]);
+        let offer = SuiteOffer::new(&[CipherSuite::Aes256GcmSha512, CipherSuite::Aes128GcmSha256]).unwrap();
+        let stripped = SuiteOffer::from_bytes(&SuiteOffer::new(&[CipherSuite::Aes128GcmSha256]).unwrap().to_bytes())
+            .unwrap();
+        let suite = stripped.select(&CipherSuite::ALL).unwrap();
+        assert_eq!(offer.accept(&suite.to_bytes()).unwrap(), CipherSuite::Aes128GcmSha256);
+
+        let mut initiator_transcript = transcript();
+        offer.bind(&mut initiator_transcript, suite);
+        let mut responder_transcript = transcript();
+        stripped.bind(&mut responder_transcript, suite);
+        assert_ne!(initiator_transcript.hash(), responder_transcript.hash());
+
+        let mut initiator = SuiteChannel::new(suite, &secret, &initiator_transcript, Role::Initiator);
+        let mut responder = SuiteChannel::new(suite, &secret, &responder_transcript, Role::Responder);
+        assert!(matches!(responder.open(&initiator.seal(b"hello").unwrap()), Err(DhError::DecryptionFailed)));
+    }
+}
@@ -0,0 +1,84 @@
+// Helpers for crates building on this library to check their own parameter sets in their test suites.
+
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::keys::generate_keypair;
+use crate::params::{DhParameters, Group};
+
+// A seeded random number generator for reproducible tests and fuzzing: the same seed gives the same private keys,
+// nonces and salts on every run and every platform, through any of the `_with_rng` functions. The stream is ChaCha20
+// keyed with the seed, so it stays fixed across rand releases, unlike `StdRng`. Never use it for real keys: anyone who
+// knows or guesses the seed can recompute them.
+#[derive(Debug, Clone)]
+pub struct DeterministicRng(ChaCha20Rng);
+
+impl DeterministicRng {
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        DeterministicRng(ChaCha20Rng::from_seed(seed))
+    }
+
+    // A generator from a small seed, e.g. a test case number or the first bytes of a fuzzer input.
+    pub fn from_u64(seed: u64) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        Self::from_seed(bytes)
+    }
+}
+
+impl RngCore for DeterministicRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+// Claims the guarantees of a cryptographic generator so it can stand in for one in the key generation functions.
+// The output is only unpredictable to someone who doesn't know the seed.
+impl CryptoRng for DeterministicRng {}
+
+// Run a full two-party exchange over the given parameters and report whether both sides derived the same secret.
+pub fn assert_parameters_agreement(params: &DhParameters) -> bool {
+    let Ok((alice_private, alice_public)) = generate_keypair(params) else {
+        return false;
+    };
+    let Ok((bob_private, bob_public)) = generate_keypair(params) else {
+        return false;
+    };
+    let alice_secret = alice_private.agree(&bob_public, params);
+    let bob_secret = bob_private.agree(&alice_public, params);
+    alice_secret == bob_secret
+}
+
+// Run a full two-party exchange over one of the standard groups and report whether both sides derived the same secret.
+pub fn assert_agreement(group: Group) -> bool {
+    assert_parameters_agreement(&group.parameters())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_standard_group_agrees() {
+        for group in Group::ALL {
+            assert!(assert_agreement(group), "{group:?}");
+        }
+    }
+
+    #[test]
+    fn custom_parameters_agree() {
+        assert!(assert_parameters_agreement(&DhParameters::from_u64_allow_insecure(2, 2147483783).unwrap()));
+    }
+}
@@ -0,0 +1,61 @@
+// Carrying the protocol over transports that only take text, such as HTTP form fields, JSON bodies or MQTT payloads
+// read by tools that expect UTF-8. Every message becomes unpadded URL-safe base64, which passes through URLs, topic
+// names and JSON strings without escaping.
+//
+// The datagram handshake suits these transports: each side sends one message and nothing needs a stream. The
+// initiator sends `hello_text`, the responder answers with `reply_text`, and after that each application payload is
+// one `DatagramChannel::seal_text` message that decrypts on its own, in any order. Lost or duplicated deliveries, as
+// MQTT allows at its lower service levels, are handled as in datagram.rs.
+
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+
+use crate::datagram::{DatagramChannel, DatagramInitiator, DatagramResponder};
+use crate::error::DhError;
+use crate::params::DhParameters;
+
+// Protocol bytes as text for the transport.
+pub fn envelope_to_text(bytes: &[u8]) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Undo `envelope_to_text`. Whitespace around the text, such as a trailing newline in an HTTP body, is ignored.
+// Anything that isn't URL-safe base64 is `DhError::MalformedMessage`.
+pub fn envelope_from_text(text: &str) -> Result<Vec<u8>, DhError> {
+    BASE64_URL_SAFE_NO_PAD.decode(text.trim()).map_err(|_| DhError::MalformedMessage)
+}
+
+impl DatagramChannel {
+    // `seal` as text.
+    pub fn seal_text(&mut self, plain_bytes: &[u8]) -> Result<String, DhError> {
+        Ok(envelope_to_text(&self.seal(plain_bytes)?))
+    }
+
+    // `open` for a message from `seal_text`.
+    pub fn open_text(&mut self, message: &str) -> Result<Vec<u8>, DhError> {
+        self.open(&envelope_from_text(message)?)
+    }
+}
+
+impl DatagramInitiator {
+    // `hello` as text.
+    pub fn hello_text(&self) -> String {
+        envelope_to_text(self.hello())
+    }
+
+    // `finish` for a reply from `DatagramResponder::reply_text`.
+    pub fn finish_text(&self, reply: &str) -> Result<DatagramChannel, DhError> {
+        self.finish(&envelope_from_text(reply)?)
+    }
+}
+
+impl DatagramResponder {
+    // `respond` to a hello from `DatagramInitiator::hello_text`.
+    pub fn respond_text(params: &DhParameters, hello: &str) -> Result<(DatagramResponder, DatagramChannel), DhError> {
+        DatagramResponder::respond(params, &envelope_from_text(hello)?)
+    }
+
+    // `reply` as text.
+    pub fn reply_text(&self) -> String {
+        envelope_to_text(self.reply())
+    }
+}
@@ -0,0 +1,78 @@
+// The worked example found in most introductions to Diffie-Hellman, including Wikipedia's: p = 23, g = 5, Alice picks
+// a = 6 and Bob b = 15. The numbers are small enough to check every step with a pocket calculator, so learners can
+// compare the crate's arithmetic with the textbook. Nothing here is secure.
+
+use num_bigint::BigUint;
+
+use crate::keys::{run_exchange_with_keys, PrivateKey};
+use crate::params::DhParameters;
+
+pub const TEXTBOOK_GENERATOR: u64 = 5;
+pub const TEXTBOOK_MODULUS: u64 = 23;
+pub const TEXTBOOK_ALICE_PRIVATE: u64 = 6;
+pub const TEXTBOOK_BOB_PRIVATE: u64 = 15;
+
+// Every intermediate value of one exchange. With the classic private keys Alice sends 8, Bob sends 19, and both
+// arrive at 2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextbookSteps {
+    pub alice_private: BigUint,
+    pub bob_private: BigUint,
+    // A = g^a mod p.
+    pub alice_public: BigUint,
+    // B = g^b mod p.
+    pub bob_public: BigUint,
+    // B^a mod p.
+    pub alice_secret: BigUint,
+    // A^b mod p.
+    pub bob_secret: BigUint,
+}
+
+// INSECURE, for teaching only: the parameters p = 23, g = 5.
+pub fn textbook_parameters() -> DhParameters {
+    DhParameters::from_u64_allow_insecure(TEXTBOOK_GENERATOR, TEXTBOOK_MODULUS)
+        .expect("the textbook parameters are well-formed")
+}
+
+// Run the textbook exchange with the given private keys, e.g. `TEXTBOOK_ALICE_PRIVATE` and `TEXTBOOK_BOB_PRIVATE`,
+// and return every value along the way.
+pub fn textbook_exchange(alice_private: u64, bob_private: u64) -> TextbookSteps {
+    let params = textbook_parameters();
+    let alice_key = PrivateKey::from_biguint(BigUint::from(alice_private));
+    let bob_key = PrivateKey::from_biguint(BigUint::from(bob_private));
+    let (alice_secret, bob_secret) = run_exchange_with_keys(&alice_key, &bob_key, &params);
+    TextbookSteps {
+        alice_private: BigUint::from(alice_private),
+        bob_private: BigUint::from(bob_private),
+        alice_public: alice_key.public_key(&params).as_biguint().clone(),
+        bob_public: bob_key.public_key(&params).as_biguint().clone(),
+        alice_secret: alice_secret.as_biguint().clone(),
+        bob_secret: bob_secret.as_biguint().clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_classic_keys_give_the_textbook_values() {
+        let params = textbook_parameters();
+        assert_eq!((params.generator(), params.modulus()), (&BigUint::from(5u32), &BigUint::from(23u32)));
+
+        let steps = textbook_exchange(TEXTBOOK_ALICE_PRIVATE, TEXTBOOK_BOB_PRIVATE);
+        assert_eq!((steps.alice_private, steps.bob_private), (BigUint::from(6u32), BigUint::from(15u32)));
+        assert_eq!(steps.alice_public, BigUint::from(8u32));
+        assert_eq!(steps.bob_public, BigUint::from(19u32));
+        assert_eq!(steps.alice_secret, BigUint::from(2u32));
+        assert_eq!(steps.bob_secret, BigUint::from(2u32));
+    }
+
+    #[test]
+    fn other_keys_agree_as_computed_by_hand() {
+        // 5^4 = 625 = 4 mod 23, 5^9 = 11 mod 23, and 5^36 = 5^14 = 13 mod 23.
+        let steps = textbook_exchange(4, 9);
+        assert_eq!((steps.alice_public, steps.bob_public), (BigUint::from(4u32), BigUint::from(11u32)));
+        assert_eq!((steps.alice_secret, steps.bob_secret), (BigUint::from(13u32), BigUint::from(13u32)));
+    }
+}
@@ -0,0 +1,89 @@
+// A running SHA-256 hash over everything exchanged during the handshake. Keys derived through the transcript are
+// bound to the exact messages both sides saw: if an attacker altered any of them in transit, the two transcripts (and
+// so the derived keys) no longer match, even though each side still computed a valid shared secret.
+
+use alloc::vec::Vec;
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+use crate::kdf::derive_key_with_context;
+use crate::params::DhParameters;
+
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    hasher: Sha256,
+}
+
+impl Transcript {
+    // Start a transcript for a handshake over the given parameters, which are recorded first.
+    pub fn new(params: &DhParameters) -> Self {
+        let mut transcript = Transcript { hasher: Sha256::new_with_prefix(b"diffie-hellman transcript") };
+        transcript.append(&params.generator.to_bytes_be());
+        transcript.append(&params.modulus.to_bytes_be());
+        transcript
+    }
+
+    // Record one handshake message. Each message is length-prefixed so two transcripts can only match if they were
+    // built from the same sequence of messages.
+    pub fn append(&mut self, message: &[u8]) {
+        self.hasher.update((message.len() as u64).to_be_bytes());
+        self.hasher.update(message);
+    }
+
+    // The hash of everything recorded so far. More messages can still be appended afterwards.
+    pub fn hash(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+
+    // Derive a key from the shared secret with the transcript hash as context, see `derive_key_with_context`.
+    pub fn derive_key(&self, secret: &BigUint, label: &str, len: usize) -> Vec<u8> {
+        derive_key_with_context(secret, label, &self.hash(), len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Group;
+
+    fn transcript_of(messages: &[&[u8]]) -> Transcript {
+        let mut transcript = Transcript::new(&Group::Ffdhe2048.parameters());
+        for message in messages {
+            transcript.append(message);
+        }
+        transcript
+    }
+
+    #[test]
+    fn a_flipped_handshake_byte_changes_the_derived_key() {
+        let secret = BigUint::from(0xdead_beefu32);
+        let alice_key = [0x11; 256];
+        let bob_key = [0x22; 256];
+        let key = transcript_of(&[&alice_key, &bob_key]).derive_key(&secret, "session", 16);
+        assert_eq!(transcript_of(&[&alice_key, &bob_key]).derive_key(&secret, "session", 16), key);
+
+        for index in [0, 128, 255] {
+            let mut tampered = bob_key;
+            tampered[index] ^= 0x01;
+            assert_ne!(transcript_of(&[&alice_key, &tampered]).derive_key(&secret, "session", 16), key, "{index}");
+        }
+        assert_ne!(transcript_of(&[&bob_key, &alice_key]).derive_key(&secret, "session", 16), key);
+    }
+
+    #[test]
+    fn transcripts_differ_with_the_parameters_and_message_boundaries() {
+        assert_ne!(transcript_of(&[b"ab", b"c"]).hash(), transcript_of(&[b"a", b"bc"]).hash());
+        assert_ne!(transcript_of(&[b"abc"]).hash(), transcript_of(&[b"abc", b""]).hash());
+
+        let mut other_group = Transcript::new(&Group::Ffdhe3072.parameters());
+        other_group.append(b"abc");
+        assert_ne!(other_group.hash(), transcript_of(&[b"abc"]).hash());
+
+        let mut growing = transcript_of(&[b"abc"]);
+        let before = growing.hash();
+        assert_eq!(growing.hash(), before);
+        growing.append(b"def");
+        assert_eq!(growing.hash(), transcript_of(&[b"abc", b"def"]).hash());
+    }
+}
@@ -0,0 +1,104 @@
+// An in-memory transport for running the handshake without sockets, e.g. in tests. `DuplexTransport::pair` returns
+// two connected halves: bytes written to one can be read from the other, in both directions.
+// Reads block until the other half writes something; once it is dropped, reads report end of stream.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+pub struct DuplexTransport {
+    outgoing: Sender<Vec<u8>>,
+    incoming: Receiver<Vec<u8>>,
+    // Bytes received but not yet read.
+    pending: Vec<u8>,
+}
+
+impl DuplexTransport {
+    // Create two connected halves, one for each party.
+    pub fn pair() -> (DuplexTransport, DuplexTransport) {
+        let (a_to_b, b_from_a) = channel();
+        let (b_to_a, a_from_b) = channel();
+        let a = DuplexTransport { outgoing: a_to_b, incoming: a_from_b, pending: Vec::new() };
+        let b = DuplexTransport { outgoing: b_to_a, incoming: b_from_a, pending: Vec::new() };
+        (a, b)
+    }
+}
+
+impl Read for DuplexTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.incoming.recv() {
+                Ok(chunk) => self.pending = chunk,
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for DuplexTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.outgoing
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other half of the transport was dropped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::net::{handshake, receive_frame, send_frame};
+    use crate::params::Group;
+    use crate::session::{Role, Session};
+
+    const SALT: &[u8] = b"transport test";
+
+    #[test]
+    fn handshake_and_messages_over_the_transport() {
+        let params = Group::Ffdhe2048.parameters();
+        let (mut alice_stream, mut bob_stream) = DuplexTransport::pair();
+        let bob_params = params.clone();
+        let bob = thread::spawn(move || {
+            let secret = handshake(&mut bob_stream, &bob_params).unwrap();
+            let mut session = Session::new(&secret, SALT, Role::Responder);
+            while let Some(message) = receive_frame(&mut bob_stream, 1 << 16).unwrap() {
+                let text = session.decrypt_next(&message).unwrap();
+                send_frame(&mut bob_stream, &session.encrypt_next(&text.to_uppercase()).unwrap()).unwrap();
+            }
+            session.messages_received()
+        });
+
+        let secret = handshake(&mut alice_stream, &params).unwrap();
+        let mut session = Session::new(&secret, SALT, Role::Initiator);
+        for text in ["hello bob", "second message"] {
+            send_frame(&mut alice_stream, &session.encrypt_next(text).unwrap()).unwrap();
+            let reply = receive_frame(&mut alice_stream, 1 << 16).unwrap().unwrap();
+            assert_eq!(session.decrypt_next(&reply).unwrap(), text.to_uppercase());
+        }
+        drop(alice_stream);
+        assert_eq!(bob.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn reads_end_once_the_other_half_is_dropped() {
+        let (mut a, mut b) = DuplexTransport::pair();
+        b.write_all(b"last words").unwrap();
+        drop(b);
+        let mut received = Vec::new();
+        a.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"last words");
+        assert_eq!(a.write(b"anyone?").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,76 @@
+// Bindings for JavaScript, e.g. a browser demo. The manifest builds only an rlib, since a cdylib can't be linked
+// without `std`, so build the module with
+// `cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib` and run
+// `wasm-bindgen` on the result. Everything crosses the boundary as bytes, which wasm-bindgen hands over as
+// `Uint8Array`s: keys in the encodings `keygen` and `derive` use on the command line, the shared secret as its
+// big-endian bytes, and messages in the `encrypt_gcm` layout. Groups are named as for `ParametersRegistry::get`.
+// Errors become JavaScript exceptions carrying the `DhError` message.
+//
+// Randomness comes from the browser's `crypto.getRandomValues`, through getrandom's `js` backend.
+
+use wasm_bindgen::prelude::*;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::gcm::{decrypt_gcm, encrypt_gcm};
+use crate::keys::{generate_keypair, PrivateKey, PublicKey, SharedSecret};
+use crate::params::DhParameters;
+use crate::registry::ParametersRegistry;
+
+// A keypair from `keygen`. The private key is wiped when the object is freed on the JavaScript side.
+#[wasm_bindgen]
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct KeyPair {
+    private_key: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl KeyPair {
+    // The private exponent's big-endian bytes. Keep it in the page; never send it anywhere.
+    #[wasm_bindgen(getter, js_name = privateKey)]
+    pub fn private_key(&self) -> Vec<u8> {
+        self.private_key.clone()
+    }
+
+    // The public key in its wire encoding, to send to the peer.
+    #[wasm_bindgen(getter, js_name = publicKey)]
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public_key.clone()
+    }
+}
+
+// A fresh keypair for the named group.
+#[wasm_bindgen]
+pub fn keygen(group: &str) -> Result<KeyPair, JsError> {
+    let params = lookup(group)?;
+    let (private_key, public_key) = generate_keypair(&params)?;
+    Ok(KeyPair { private_key: private_key.to_bytes_be(), public_key: public_key.to_wire_bytes() })
+}
+
+// The secret shared with the owner of `peer_public_key`, after checking both keys against the group.
+#[wasm_bindgen]
+pub fn derive(group: &str, private_key: &[u8], peer_public_key: &[u8]) -> Result<Vec<u8>, JsError> {
+    let params = lookup(group)?;
+    let peer_public = PublicKey::from_wire_bytes(peer_public_key, &params)?;
+    peer_public.validate_in_group(&params)?;
+    let private_key = PrivateKey::from_bytes_be(private_key);
+    private_key.validate(&params)?;
+    let secret = private_key.agree(&peer_public, &params);
+    Ok(secret.dangerous_into_bytes().to_vec())
+}
+
+// Encrypt `plaintext` with AES-128-GCM under a key derived from `secret`, see `encrypt_gcm`.
+#[wasm_bindgen]
+pub fn encrypt(secret: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(encrypt_gcm(plaintext, SharedSecret::from_bytes(secret).as_biguint())?)
+}
+
+// Decrypt a message from `encrypt`. A wrong secret or a tampered message throws.
+#[wasm_bindgen]
+pub fn decrypt(secret: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, JsError> {
+    Ok(decrypt_gcm(ciphertext, SharedSecret::from_bytes(secret).as_biguint())?)
+}
+
+fn lookup(group: &str) -> Result<DhParameters, JsError> {
+    ParametersRegistry::get(group).cloned().ok_or_else(|| JsError::new(&format!("unknown group {}", group)))
+}
@@ -0,0 +1,95 @@
+// Agreement with the `openssl` command-line tool, as in examples/openssl_interop.rs. OpenSSL generates a keypair in
+// ffdhe2048 and derives the secret with `openssl pkeyutl -derive -pkeyopt dh_pad:1`; the crate's side has to give the
+// same bytes once padded to the modulus length. The tests pass without checking anything when `openssl` isn't on the
+// PATH.
+
+#![cfg(feature = "pkcs8")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use diffie_hellman::{generate_keypair, DhParameters, Group, PrivateKey, PublicKey};
+
+// A directory of its own for one test, removed when the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("diffie-hellman-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn openssl_available() -> bool {
+    let found = Command::new("openssl").arg("version").output().is_ok_and(|output| output.status.success());
+    if !found {
+        eprintln!("skipping: openssl is not on the PATH");
+    }
+    found
+}
+
+// Run `openssl` with the given arguments in `dir` and return what it wrote to stdout.
+fn openssl(dir: &Path, args: &[&str]) -> Vec<u8> {
+    let output = Command::new("openssl").args(args).current_dir(dir).output().unwrap();
+    assert!(output.status.success(), "openssl {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+// A keypair generated by OpenSSL in `params`, its private key left in openssl_private.pem.
+fn openssl_keypair(dir: &Path, params: &DhParameters) -> PublicKey {
+    fs::write(dir.join("params.pem"), params.to_pkcs3_pem()).unwrap();
+    openssl(dir, &["genpkey", "-paramfile", "params.pem", "-out", "openssl_private.pem"]);
+    openssl(dir, &["pkey", "-in", "openssl_private.pem", "-pubout", "-out", "openssl_public.pem"]);
+    let (public_key, key_params) =
+        PublicKey::from_spki_pem(&fs::read_to_string(dir.join("openssl_public.pem")).unwrap()).unwrap();
+    assert!(key_params.is_compatible_with(params));
+    public_key
+}
+
+fn derive(dir: &Path, private_key_file: &str, peer_key_file: &str) -> Vec<u8> {
+    openssl(dir, &["pkeyutl", "-derive", "-inkey", private_key_file, "-peerkey", peer_key_file, "-pkeyopt", "dh_pad:1"])
+}
+
+#[test]
+fn both_sides_derive_the_same_padded_secret() {
+    if !openssl_available() {
+        return;
+    }
+    let dir = TempDir::new("openssl-compat-derive");
+    let params = Group::Ffdhe2048.parameters();
+    let openssl_public = openssl_keypair(&dir.0, &params);
+    let (private_key, public_key) = generate_keypair(&params).unwrap();
+    fs::write(dir.0.join("rust_public.pem"), public_key.to_spki_pem(&params)).unwrap();
+
+    let ours = private_key.agree(&openssl_public, &params).to_padded_bytes(&params);
+    let theirs = derive(&dir.0, "openssl_private.pem", "rust_public.pem");
+    assert_eq!(theirs.len(), params.modulus_len());
+    assert_eq!(ours[..], theirs[..]);
+}
+
+#[test]
+fn openssl_reads_our_private_key() {
+    if !openssl_available() {
+        return;
+    }
+    let dir = TempDir::new("openssl-compat-private-key");
+    let params = Group::Ffdhe2048.parameters();
+    let openssl_public = openssl_keypair(&dir.0, &params);
+    let (private_key, public_key) = generate_keypair(&params).unwrap();
+    fs::write(dir.0.join("rust_private.pem"), private_key.to_pkcs8_pem(&params)).unwrap();
+    fs::write(dir.0.join("rust_public.pem"), public_key.to_spki_pem(&params)).unwrap();
+
+    let from_our_key = derive(&dir.0, "rust_private.pem", "openssl_public.pem");
+    let from_theirs = derive(&dir.0, "openssl_private.pem", "rust_public.pem");
+    assert_eq!(from_our_key, from_theirs);
+    let (read_back, _) = PrivateKey::from_pkcs8_pem(&private_key.to_pkcs8_pem(&params)).unwrap();
+    assert!(read_back.agree(&openssl_public, &params) == private_key.agree(&openssl_public, &params));
+}